@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+fn yuyv_to_rgba(c: &mut Criterion) {
+    let pixels = (WIDTH * HEIGHT) as usize;
+    // a gradient, so the conversion can't be skipped for constant samples
+    let src: Vec<u8> = (0..pixels * 2).map(|i| i as u8).collect();
+    let mut dst = vec![255; pixels * 4];
+    let mut luma = vec![0; pixels];
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("yuyv 1080p", |b| {
+        b.iter(|| bevy_v4l::convert_frame(*b"YUYV", WIDTH, &src, &mut dst).unwrap())
+    });
+    // the Y samples of inputs with ImageEncoding::Luma, without any color math
+    group.bench_function("yuyv 1080p luma", |b| {
+        b.iter(|| bevy_v4l::convert_frame_luma(*b"YUYV", WIDTH, &src, &mut luma).unwrap())
+    });
+    group.finish();
+}
+
+/// 4K frames on one thread and in bands across the compute task pool
+fn yuyv_4k(c: &mut Criterion) {
+    let (width, height) = (3840, 2160);
+    let pixels = width as usize * height;
+    let src: Vec<u8> = (0..pixels * 2).map(|i| i as u8).collect();
+    let mut serial = vec![255; pixels * 4];
+    let mut parallel = vec![255; pixels * 4];
+
+    bevy_v4l::convert_frame(*b"YUYV", width, &src, &mut serial).unwrap();
+    bevy_v4l::convert_frame_parallel(*b"YUYV", width, &src, &mut parallel).unwrap();
+    assert!(
+        serial == parallel,
+        "parallel conversion differs from the serial one"
+    );
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("yuyv 4k", |b| {
+        b.iter(|| bevy_v4l::convert_frame(*b"YUYV", width, &src, &mut serial).unwrap())
+    });
+    group.bench_function("yuyv 4k parallel", |b| {
+        b.iter(|| bevy_v4l::convert_frame_parallel(*b"YUYV", width, &src, &mut parallel).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, yuyv_to_rgba, yuyv_4k);
+criterion_main!(benches);
@@ -1,8 +1,8 @@
 use argh::FromArgs;
 use bevy::{prelude::*, window::ExitCondition};
-use bevy_v4l::{Input, Output, V4lPlugin};
+use bevy_v4l::{AlphaMode, Format, FrameInfo, Input, Output, PendingInput, V4lPlugin};
 
-#[derive(FromArgs)]
+#[derive(FromArgs, Resource)]
 /// Simple input capture
 struct Args {
     /// input device id
@@ -12,6 +12,10 @@ struct Args {
     /// output device id
     #[argh(positional)]
     output_device: usize,
+
+    /// color the frames fade into from left to right, black or white
+    #[argh(option, default = "String::from(\"black\")")]
+    background: String,
 }
 
 fn main() {
@@ -22,35 +26,67 @@ fn main() {
                 exit_condition: ExitCondition::DontExit,
                 close_when_requested: false,
             }),
-            V4lPlugin,
+            V4lPlugin::default(),
         ))
+        .insert_resource(argh::from_env::<Args>())
         .add_systems(Startup, setup)
+        .add_systems(Update, open_output)
         .run();
 }
 
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    let args: Args = argh::from_env();
-    let mut input = Input::new(args.input_device, &mut images).unwrap();
-    let image = input.clone_image(&mut images);
-
-    let output = Output::new(args.output_device, input.image().clone(), input.format()).unwrap();
-
+fn setup(mut commands: Commands, args: Res<Args>) {
     commands.spawn((
-        SpriteBundle {
-            texture: input.image().clone(),
-            ..default()
-        },
-        input,
+        SpriteBundle::default(),
+        PendingInput::open(args.input_device),
     ));
+}
 
-    commands.spawn((
-        Camera2dBundle {
-            camera: Camera {
-                target: image.clone().into(),
+/// Opens the output once the input is open, it is sized like the input
+fn open_output(
+    mut commands: Commands,
+    mut inputs: Query<&mut Input, Added<Input>>,
+    mut images: ResMut<Assets<Image>>,
+    args: Res<Args>,
+) {
+    for mut input in inputs.iter_mut() {
+        let image = input.clone_image(&mut images);
+
+        // outputs don't need an input, the format only has to match the size of the image
+        let size = input.size();
+        let format = Format::new(size.width, size.height, b"YUYV").unwrap();
+        let background = match args.background.as_str() {
+            "white" => Color::WHITE,
+            _ => Color::BLACK,
+        };
+        // the camera renders the input into the image, the output reads it back
+        let output = Output::builder(args.output_device)
+            .format(format)
+            .gpu_readback()
+            .processor(fade_out)
+            .alpha_mode(AlphaMode::PremultiplyOverColor(background))
+            .build(image.clone())
+            .unwrap();
+
+        commands.spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    target: image.clone().into(),
+                    ..default()
+                },
                 ..default()
             },
-            ..default()
-        },
-        output,
-    ));
+            output,
+        ));
+    }
+}
+
+/// Makes the frames more transparent from left to right, the output composites them
+/// over the background
+fn fade_out(frame: &mut [u8], info: &FrameInfo) {
+    let width = info.width as usize;
+    for row in frame.chunks_exact_mut(info.stride as usize) {
+        for (x, pixel) in row.chunks_exact_mut(4).take(width).enumerate() {
+            pixel[3] = (255 - x * 255 / width.max(1)) as u8;
+        }
+    }
 }
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use argh::FromArgs;
+use bevy::prelude::*;
+use bevy_v4l::{FrameReceived, Input, V4lPlugin};
+
+#[derive(FromArgs)]
+/// Logs the frame rate of an input, measured from the timestamps of its frames
+struct Args {
+    /// input device id
+    #[argh(positional)]
+    device: usize,
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, log_fps)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let args: Args = argh::from_env();
+    let input = Input::new(args.device, &mut images).unwrap();
+
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        SpriteBundle {
+            texture: input.image().clone(),
+            ..default()
+        },
+        input,
+    ));
+}
+
+/// Timestamp of the first frame of the current second, and frames since
+#[derive(Default)]
+struct Window {
+    start: Option<Duration>,
+    frames: u32,
+}
+
+fn log_fps(mut frames: EventReader<FrameReceived>, mut window: Local<Window>) {
+    for frame in frames.read() {
+        let time = frame.timestamp.time;
+        let start = *window.start.get_or_insert(time);
+        window.frames += 1;
+
+        let elapsed = time.saturating_sub(start);
+        if elapsed >= Duration::from_secs(1) {
+            let fps = (window.frames - 1) as f32 / elapsed.as_secs_f32();
+            info!(sequence = frame.frame.sequence, "{fps:.1} fps");
+            *window = Window {
+                start: Some(time),
+                frames: 1,
+            };
+        }
+    }
+}
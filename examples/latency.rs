@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use argh::FromArgs;
+use bevy::prelude::*;
+use bevy_v4l::{Input, TimestampSource, V4lPlugin};
+
+#[derive(FromArgs)]
+/// Shows an input with the time from capture of its frames to their swap into the image
+struct Args {
+    /// input device id
+    #[argh(positional)]
+    device: usize,
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, show_latency)
+        .run();
+}
+
+#[derive(Component)]
+struct Overlay;
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let args: Args = argh::from_env();
+    let input = Input::new(args.device, &mut images).unwrap();
+
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        SpriteBundle {
+            texture: input.image().clone(),
+            ..default()
+        },
+        input,
+    ));
+    commands.spawn((
+        TextBundle::from_section("waiting for frames", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..default()
+        }),
+        Overlay,
+    ));
+}
+
+/// Latency of the frames of the last second, the worst one is shown with the average
+#[derive(Default)]
+struct Window {
+    start: Option<Duration>,
+    total: Duration,
+    worst: Duration,
+    frames: u32,
+}
+
+fn show_latency(
+    inputs: Query<&Input>,
+    mut overlay: Query<&mut Text, With<Overlay>>,
+    mut window: Local<Window>,
+    mut last: Local<Option<Duration>>,
+) {
+    let (Ok(input), Ok(mut text)) = (inputs.get_single(), overlay.get_single_mut()) else {
+        return;
+    };
+    // updates faster than the frame rate see the same frame again
+    let Some(presented) = input
+        .last_presented()
+        .filter(|presented| Some(presented.at) != *last)
+    else {
+        return;
+    };
+    *last = Some(presented.at);
+    let Some(latency) = presented.latency() else {
+        text.sections[0].value = "timestamps of an unknown clock, no latency".to_string();
+        return;
+    };
+
+    let start = *window.start.get_or_insert(presented.at);
+    window.total += latency;
+    window.worst = window.worst.max(latency);
+    window.frames += 1;
+    if presented.at.saturating_sub(start) < Duration::from_secs(1) {
+        return;
+    }
+
+    // drivers without monotonic timestamps only show the time since the dequeue
+    let measured = match presented.timestamp.source {
+        TimestampSource::Monotonic => "capture",
+        _ => "dequeue",
+    };
+    let average = window.total / window.frames;
+    text.sections[0].value = format!(
+        "{measured} to present: {:.1} ms average, {:.1} ms worst",
+        average.as_secs_f32() * 1000.0,
+        window.worst.as_secs_f32() * 1000.0,
+    );
+    *window = Window::default();
+}
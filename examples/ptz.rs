@@ -0,0 +1,60 @@
+use argh::FromArgs;
+use bevy::prelude::*;
+use bevy_v4l::{Input, V4lPlugin};
+
+#[derive(FromArgs)]
+/// Zooms a PTZ camera with the up and down arrow keys
+struct Args {
+    /// input device id
+    #[argh(positional)]
+    input_device: usize,
+}
+
+/// Zoom changed per second while a key is held, of the whole range
+const ZOOM_SPEED: f32 = 0.5;
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, zoom)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let args: Args = argh::from_env();
+    let input = Input::new(args.input_device, &mut images).unwrap();
+
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        SpriteBundle {
+            texture: input.image().clone(),
+            ..default()
+        },
+        input,
+    ));
+}
+
+fn zoom(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    inputs: Query<&Input>,
+    mut level: Local<f32>,
+) {
+    let direction = match (
+        keys.pressed(KeyCode::ArrowUp),
+        keys.pressed(KeyCode::ArrowDown),
+    ) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => return,
+    };
+    *level = (*level + direction * ZOOM_SPEED * time.delta_seconds()).clamp(0.0, 1.0);
+
+    for input in inputs.iter() {
+        match input.set_zoom(*level) {
+            Ok(value) => info!("zoom {value}"),
+            Err(err) => warn!("failed to zoom: {err}"),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use argh::FromArgs;
 use bevy::prelude::*;
-use bevy_v4l::{Input, V4lPlugin};
+use bevy_v4l::{V4lInputBundle, V4lPlugin};
 
 #[derive(FromArgs)]
 /// Simple input capture
@@ -12,7 +12,7 @@ struct Args {
 
 fn main() {
     App::new()
-        .add_plugins((DefaultPlugins, V4lPlugin))
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
         .add_systems(Startup, setup)
         .run();
 }
@@ -20,12 +20,5 @@ fn main() {
 fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
     let args: Args = argh::from_env();
     commands.spawn(Camera2dBundle::default());
-    let device = Input::new(args.device, &mut images).unwrap();
-    commands.spawn((
-        SpriteBundle {
-            texture: device.image().clone(),
-            ..default()
-        },
-        device,
-    ));
+    commands.spawn(V4lInputBundle::new(args.device, &mut images).unwrap());
 }
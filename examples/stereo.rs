@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use argh::FromArgs;
+use bevy::prelude::*;
+use bevy_v4l::{CaptureGroup, Input, SyncedFrames, V4lPlugin};
+
+/// Width the images of both cameras are shown at
+const VIEW_WIDTH: f32 = 640.0;
+
+#[derive(FromArgs)]
+/// Shows two inputs side by side, captured together, with the skew of their frames
+struct Args {
+    /// device id of the left camera
+    #[argh(positional)]
+    left: usize,
+    /// device id of the right camera
+    #[argh(positional)]
+    right: usize,
+    /// milliseconds frames may be apart to be paired
+    #[argh(option, default = "5")]
+    tolerance: u64,
+}
+
+/// The text showing the skew
+#[derive(Component)]
+struct SkewText;
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, show_skew)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let args: Args = argh::from_env();
+    commands.spawn(Camera2dBundle::default());
+
+    let mut members = Vec::new();
+    for (device, x) in [(args.left, -0.5), (args.right, 0.5)] {
+        let input = Input::new(device, &mut images).unwrap();
+        let size = input.size();
+        let height = VIEW_WIDTH * size.height as f32 / size.width as f32;
+        let sprite = SpriteBundle {
+            texture: input.image().clone(),
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(VIEW_WIDTH, height)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x * VIEW_WIDTH, 0.0, 0.0),
+            ..default()
+        };
+        members.push(commands.spawn((sprite, input)).id());
+    }
+    commands.spawn(CaptureGroup::new(
+        members,
+        Duration::from_millis(args.tolerance),
+    ));
+
+    commands.spawn((
+        TextBundle::from_section("waiting for synced frames", TextStyle::default()).with_style(
+            Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(12.0),
+                left: Val::Px(12.0),
+                ..default()
+            },
+        ),
+        SkewText,
+    ));
+}
+
+fn show_skew(
+    mut synced: EventReader<SyncedFrames>,
+    groups: Query<&CaptureGroup>,
+    mut texts: Query<&mut Text, With<SkewText>>,
+) {
+    let Some(frames) = synced.read().last() else {
+        return;
+    };
+    let Ok(group) = groups.get(frames.group) else {
+        return;
+    };
+    let sequences: Vec<_> = frames
+        .frames
+        .iter()
+        .map(|frame| frame.frame.sequence.to_string())
+        .collect();
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!(
+            "skew {:.2} ms, frames {}, {} synced, {} unmatched",
+            frames.skew.as_secs_f64() * 1000.0,
+            sequences.join(" / "),
+            group.synced(),
+            group.unmatched(),
+        );
+    }
+}
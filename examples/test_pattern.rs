@@ -0,0 +1,36 @@
+use argh::FromArgs;
+use bevy::prelude::*;
+use bevy_v4l::{Input, TestPattern, V4lPlugin};
+
+#[derive(FromArgs)]
+/// Shows a test pattern, no v4l device needed
+struct Args {
+    /// show a moving box instead of color bars
+    #[argh(switch)]
+    moving: bool,
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, V4lPlugin::default()))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let args: Args = argh::from_env();
+    let pattern = match args.moving {
+        true => TestPattern::MovingBox,
+        false => TestPattern::Bars,
+    };
+
+    commands.spawn(Camera2dBundle::default());
+    let device = Input::test_pattern(pattern, 640, 480, *b"YUYV", 30.0, &mut images).unwrap();
+    commands.spawn((
+        SpriteBundle {
+            texture: device.image().clone(),
+            ..default()
+        },
+        device,
+    ));
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::render::view::ViewVisibility;
+
+use crate::Input;
+
+/// Whether an input converts the frames it dequeues, see [`Input::set_active`].
+///
+/// The flag is shared with the io task instead of living behind the lock, so
+/// toggling it never waits for a frame. The task reads it once per frame, a frame
+/// converted before the input went inactive is still shown.
+pub(crate) struct Activity {
+    active: Arc<AtomicBool>,
+    /// Converts a frame this often while inactive, see [`InputBuilder::keepalive`](crate::InputBuilder::keepalive)
+    keepalive: Option<Duration>,
+    last: Option<Instant>,
+    /// Frames dequeued without converting them since the last [`FrameStats`](crate::FrameStats)
+    pub(crate) skipped: u32,
+}
+
+impl Activity {
+    pub(crate) fn new(active: Arc<AtomicBool>, keepalive: Option<Duration>) -> Self {
+        Self {
+            active,
+            keepalive,
+            last: None,
+            skipped: 0,
+        }
+    }
+
+    /// Whether the frame just dequeued should be converted
+    pub(crate) fn convert(&mut self) -> bool {
+        let due = self.active.load(Ordering::Relaxed)
+            || match (self.keepalive, self.last) {
+                (Some(interval), Some(last)) => last.elapsed() >= interval,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+
+        match due {
+            true => self.last = Some(Instant::now()),
+            false => self.skipped += 1,
+        }
+        due
+    }
+}
+
+/// Activates inputs built with [`InputBuilder::throttle_hidden`](crate::InputBuilder::throttle_hidden)
+/// while any entity with a [`ViewVisibility`] shows their image or preview
+pub(crate) fn sync_visibility(
+    mut inputs: Query<&mut Input>,
+    users: Query<(&Handle<Image>, &ViewVisibility)>,
+) {
+    for mut input in inputs.iter_mut() {
+        if !input.throttle_hidden {
+            continue;
+        }
+
+        let image = input.image().id();
+        let preview = input.preview().map(Handle::id);
+        let visible = users.iter().any(|(handle, visibility)| {
+            visibility.get() && (handle.id() == image || Some(handle.id()) == preview)
+        });
+
+        // only borrowed mutably on changes, so change detection on inputs stays useful
+        if input.is_active() != visible {
+            input.set_active(visible);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use v4l::v4l2;
+use v4l::v4l2::vidioc;
+use v4l::v4l_sys::v4l2_cropcap;
+
+/// V4L2_BUF_TYPE_VIDEO_CAPTURE
+const BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+
+/// Width of a pixel relative to its height, like 10:11 for NTSC sources.
+/// See [`Input::display_size`](crate::Input::display_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelAspect {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl PixelAspect {
+    pub const SQUARE: Self = Self {
+        numerator: 1,
+        denominator: 1,
+    };
+
+    pub fn ratio(&self) -> f32 {
+        self.numerator as f32 / self.denominator.max(1) as f32
+    }
+
+    /// Pixel aspect the driver reports with VIDIOC_CROPCAP, `None` for drivers that
+    /// don't implement it or report nonsense
+    pub(crate) fn query(dev: &v4l::Device) -> Option<Self> {
+        let aspect = unsafe {
+            let mut cropcap: v4l2_cropcap = mem::zeroed();
+            cropcap.type_ = BUF_TYPE_VIDEO_CAPTURE;
+            v4l2::ioctl(
+                dev.handle().fd() as c_int,
+                vidioc::VIDIOC_CROPCAP,
+                &mut cropcap as *mut v4l2_cropcap as *mut c_void,
+            )
+            .ok()?;
+            cropcap.pixelaspect
+        };
+
+        (aspect.numerator != 0 && aspect.denominator != 0).then_some(Self {
+            numerator: aspect.numerator,
+            denominator: aspect.denominator,
+        })
+    }
+}
+
+impl Default for PixelAspect {
+    fn default() -> Self {
+        Self::SQUARE
+    }
+}
+
+impl fmt::Display for PixelAspect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.numerator, self.denominator)
+    }
+}
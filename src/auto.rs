@@ -0,0 +1,220 @@
+use std::time::{Duration, Instant};
+
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::futures;
+use tracing::{debug, warn};
+
+use crate::devices::{self, enumerate_devices, DeviceInfo, DeviceSelector};
+use crate::memory::Buffers;
+use crate::open::OpenedInput;
+use crate::{Input, Result, TestPattern};
+
+/// How often an [`AutoInput`] looks for its device
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frame rate of [`AutoInput::placeholder`] patterns, animated ones only need to move
+const PLACEHOLDER_FPS: f32 = 15.0;
+
+/// Attaches an [`Input`] to its entity whenever a capture device whose name contains
+/// `name_pattern` is plugged in, and removes it again when the device disappears.
+///
+/// The input always streams into [`AutoInput::image`], so it can be shown before the
+/// device is there.
+#[derive(Component)]
+pub struct AutoInput {
+    name_pattern: String,
+    image: Handle<Image>,
+    phase: AutoInputPhase,
+    task: Option<AutoTask>,
+    next_scan: Instant,
+    /// ID of the device while streaming
+    device: Option<usize>,
+    /// Pattern, width and height shown while no device is streaming
+    placeholder: Option<(TestPattern, u32, u32)>,
+    /// Whether the attached [`Input`] is the placeholder
+    showing_placeholder: bool,
+}
+
+/// Where an [`AutoInput`] is at, for showing in a UI.
+/// Until it is [`AutoInputPhase::Streaming`] the [`AutoInput::placeholder`] is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoInputPhase {
+    /// No matching device was found yet
+    Waiting,
+    /// A matching device was found and is being opened
+    Opening,
+    /// The [`Input`] is attached
+    Streaming,
+    /// The device disappeared, waiting for it to come back
+    Lost,
+}
+
+enum AutoTask {
+    Scan(Task<Option<DeviceInfo>>),
+    Open(Task<Result<OpenedInput>>),
+}
+
+impl AutoInput {
+    /// `name_pattern` is matched like [`Input::first_by_name`]
+    pub fn new(name_pattern: impl Into<String>, images: &mut Assets<Image>) -> Self {
+        let image = images.add(Image::new(
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![255; 4],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::all(),
+        ));
+
+        Self {
+            name_pattern: name_pattern.into(),
+            image,
+            phase: AutoInputPhase::Waiting,
+            task: None,
+            next_scan: Instant::now(),
+            device: None,
+            placeholder: None,
+            showing_placeholder: false,
+        }
+    }
+
+    /// Attaches an [`Input::test_pattern`] of `pattern` streaming into the image while no
+    /// device is streaming, so the app runs the same without a camera. Animated patterns
+    /// like [`TestPattern::MovingBox`] show that the app isn't frozen.
+    pub fn placeholder(mut self, pattern: TestPattern, width: u32, height: u32) -> Self {
+        self.placeholder = Some((pattern, width, height));
+        self
+    }
+
+    /// Whether the attached [`Input`] is the placeholder rather than the device
+    pub fn showing_placeholder(&self) -> bool {
+        self.showing_placeholder
+    }
+
+    /// Handle to bevy image, kept when the device is replugged
+    pub fn image(&self) -> &Handle<Image> {
+        &self.image
+    }
+
+    pub fn phase(&self) -> AutoInputPhase {
+        self.phase
+    }
+
+    /// Attaches the placeholder input, if there is one and it isn't attached yet
+    fn show_placeholder(&mut self, entity: &mut EntityCommands, images: &mut Assets<Image>) {
+        let Some((pattern, width, height)) = self.placeholder else {
+            return;
+        };
+        if self.showing_placeholder {
+            return;
+        }
+
+        match OpenedInput::test_pattern(pattern, width, height, *b"AB24", PLACEHOLDER_FPS) {
+            Ok(opened) => {
+                entity.insert(opened.into_input_at(self.image.clone(), images));
+                self.showing_placeholder = true;
+            }
+            Err(err) => {
+                warn!(
+                    "failed to generate placeholder for {}: {err}",
+                    self.name_pattern
+                );
+                self.placeholder = None;
+            }
+        }
+    }
+
+    fn scan(&self) -> AutoTask {
+        let name = self.name_pattern.clone();
+        AutoTask::Scan(AsyncComputeTaskPool::get().spawn(async move {
+            let devices = enumerate_devices();
+            devices::find_by_name(&devices, &name, true).ok().cloned()
+        }))
+    }
+
+    fn open(&self, info: DeviceInfo) -> AutoTask {
+        let selector = DeviceSelector::name(self.name_pattern.clone());
+        AutoTask::Open(AsyncComputeTaskPool::get().spawn(async move {
+            let dev = devices::open_path(&info.path)?;
+            OpenedInput::new(dev, info.id, selector, None, None, Buffers::default(), true)
+        }))
+    }
+}
+
+pub(crate) fn drive_auto_inputs(
+    mut commands: Commands,
+    mut autos: Query<(Entity, &mut AutoInput)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let now = Instant::now();
+
+    for (entity, mut auto) in autos.iter_mut() {
+        if auto.device.is_none() {
+            auto.show_placeholder(&mut commands.entity(entity), &mut images);
+        }
+
+        let next_scan = auto.next_scan;
+        match auto.task.as_mut() {
+            Some(AutoTask::Scan(task)) => {
+                let Some(found) = futures::check_ready(task) else {
+                    continue;
+                };
+                auto.task = None;
+
+                if let Some(info) = found {
+                    debug!(
+                        "found v4l device {} for {}",
+                        info.path.display(),
+                        auto.name_pattern
+                    );
+                    auto.task = Some(auto.open(info));
+                    auto.phase = AutoInputPhase::Opening;
+                }
+            }
+            Some(AutoTask::Open(task)) => {
+                let Some(result) = futures::check_ready(task) else {
+                    continue;
+                };
+                auto.task = None;
+
+                match result {
+                    Ok(opened) => {
+                        let input = opened.into_input_at(auto.image.clone(), &mut images);
+                        auto.device = Some(input.id());
+                        auto.phase = AutoInputPhase::Streaming;
+                        auto.showing_placeholder = false;
+                        // replaces the placeholder
+                        commands.entity(entity).insert(input);
+                    }
+                    Err(err) => {
+                        warn!("failed to open v4l input for {}: {err}", auto.name_pattern);
+                        auto.phase = AutoInputPhase::Waiting;
+                    }
+                }
+            }
+            None if now < next_scan => {}
+            None => {
+                auto.next_scan = now + RESCAN_INTERVAL;
+
+                match auto.device {
+                    // the device node goes away when it is unplugged
+                    Some(id) if !crate::device_path(id).exists() => {
+                        debug!("v4l device {id} for {} disappeared", auto.name_pattern);
+                        commands.entity(entity).remove::<Input>();
+                        auto.device = None;
+                        auto.phase = AutoInputPhase::Lost;
+                    }
+                    Some(_) => {}
+                    None => auto.task = Some(auto.scan()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+/// White balance and black level applied while demosaicing 8 bit Bayer formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BayerConfig {
+    /// Subtracted from every sample, the rest is stretched back to the full range
+    pub black_level: u8,
+    /// Red, green and blue gains, ignored while `auto_white_balance` is set
+    pub gains: [f32; 3],
+    /// Recalculates the gains every this many frames assuming the scene averages
+    /// to grey
+    pub auto_white_balance: Option<u32>,
+    pub demosaic: Demosaic,
+}
+
+impl Default for BayerConfig {
+    fn default() -> Self {
+        Self {
+            black_level: 0,
+            gains: [1.0; 3],
+            auto_white_balance: None,
+            demosaic: Demosaic::default(),
+        }
+    }
+}
+
+/// How the missing channels of every Bayer sample are filled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Demosaic {
+    /// Every 2x2 block becomes one color, which is cheap and good enough for previews
+    #[default]
+    Blocks,
+    /// Every channel is averaged from the nearest samples of it, with the frame mirrored
+    /// at the edges. Full resolution, at a few times the cost.
+    Bilinear,
+}
+
+/// Red, green and blue
+type Channel = usize;
+const R: Channel = 0;
+const G: Channel = 1;
+const B: Channel = 2;
+
+/// Channels of the top left, top right, bottom left and bottom right samples
+/// of every 2x2 block
+fn pattern(fourcc: &[u8; 4]) -> Option<[Channel; 4]> {
+    match fourcc {
+        b"RGGB" => Some([R, G, G, B]),
+        b"BA81" => Some([B, G, G, R]),
+        b"GBRG" => Some([G, B, R, G]),
+        b"GRBG" => Some([G, R, B, G]),
+        _ => None,
+    }
+}
+
+/// Fourccs of the patterns above
+pub(crate) const FOURCCS: [&[u8; 4]; 4] = [b"RGGB", b"BA81", b"GBRG", b"GRBG"];
+
+pub(crate) fn is_bayer(fourcc: &[u8; 4]) -> bool {
+    pattern(fourcc).is_some()
+}
+
+/// Demosaics Bayer frames, keeping the gains of the auto white balance between frames
+pub(crate) struct Bayer {
+    config: BayerConfig,
+    gains: [f32; 3],
+    /// Frames and per channel sums since the gains were last recalculated
+    frames: u32,
+    sums: [u64; 3],
+}
+
+impl Bayer {
+    pub(crate) fn new(config: BayerConfig) -> Self {
+        Self {
+            config,
+            gains: config.gains,
+            frames: 0,
+            sums: [0; 3],
+        }
+    }
+
+    /// Gains applied to the latest frame
+    pub(crate) fn gains(&self) -> [f32; 3] {
+        self.gains
+    }
+
+    /// Converts the Bayer `src` into the rgba `dst`, see [`Demosaic`]
+    pub(crate) fn demosaic(
+        &mut self,
+        fourcc: &[u8; 4],
+        width: usize,
+        height: usize,
+        src: &[u8],
+        dst: &mut [u8],
+    ) {
+        let Some(pattern) = pattern(fourcc) else {
+            return;
+        };
+
+        let black = self.config.black_level;
+        let stretch = 255.0 / (255 - black.min(254)) as f32;
+        // 8.8 fixed point, so the loop stays in integers
+        let scales = self.gains.map(|gain| (gain * stretch * 256.0) as u32);
+        let auto = self.config.auto_white_balance.is_some();
+
+        let mut sums = [0_u64; 3];
+        let mut finish = |rgb: [u32; 3]| {
+            if auto {
+                for (sum, value) in sums.iter_mut().zip(rgb) {
+                    *sum += value as u64;
+                }
+            }
+
+            [0, 1, 2].map(|channel| ((rgb[channel] * scales[channel]) >> 8).min(255) as u8)
+        };
+
+        match self.config.demosaic {
+            Demosaic::Blocks => blocks(pattern, black, width, height, src, dst, &mut finish),
+            Demosaic::Bilinear => bilinear(pattern, black, width, height, src, dst, &mut finish),
+        }
+
+        if let Some(interval) = self.config.auto_white_balance {
+            self.balance(sums, interval);
+        }
+    }
+
+    /// Grey world white balance, recalculated every `interval` frames
+    fn balance(&mut self, sums: [u64; 3], interval: u32) {
+        for (total, sum) in self.sums.iter_mut().zip(sums) {
+            *total += sum;
+        }
+        self.frames += 1;
+
+        if self.frames < interval.max(1) {
+            return;
+        }
+
+        let [r, g, b] = self.sums.map(|sum| sum.max(1) as f32);
+        self.gains = [g / r, 1.0, g / b];
+        self.frames = 0;
+        self.sums = [0; 3];
+    }
+}
+
+/// Every 2x2 block becomes the color of its samples, `finish` white balances
+/// the black level corrected color of a pixel
+fn blocks(
+    pattern: [Channel; 4],
+    black: u8,
+    width: usize,
+    height: usize,
+    src: &[u8],
+    dst: &mut [u8],
+    finish: &mut impl FnMut([u32; 3]) -> [u8; 3],
+) {
+    for y in (0..height & !1).step_by(2) {
+        let (Some(top), Some(bottom)) = (
+            src.get(y * width..(y + 1) * width),
+            src.get((y + 1) * width..(y + 2) * width),
+        ) else {
+            break;
+        };
+
+        for x in (0..width & !1).step_by(2) {
+            let samples = [top[x], top[x + 1], bottom[x], bottom[x + 1]];
+
+            let mut rgb = [0_u32; 3];
+            for (channel, sample) in pattern.iter().zip(samples) {
+                rgb[*channel] += sample.saturating_sub(black) as u32;
+            }
+            // two green samples per block
+            rgb[G] /= 2;
+
+            let rgb = finish(rgb);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let i = ((y + dy) * width + x + dx) * 4;
+                if let Some(pixel) = dst.get_mut(i..i + 3) {
+                    pixel.copy_from_slice(&rgb);
+                }
+            }
+        }
+    }
+}
+
+/// Every pixel keeps its own sample, its other channels are the average of the samples
+/// of them among its 8 neighbours
+fn bilinear(
+    pattern: [Channel; 4],
+    black: u8,
+    width: usize,
+    height: usize,
+    src: &[u8],
+    dst: &mut [u8],
+    finish: &mut impl FnMut([u32; 3]) -> [u8; 3],
+) {
+    if width < 2 || height < 2 || src.len() < width * height {
+        return;
+    }
+
+    // mirroring keeps the channel of the missing sample, -1 becomes 1 and len becomes len - 2
+    let mirror = |value: usize, len: usize| match value {
+        0 => 1,
+        value if value > len => len - 2,
+        value => value - 1,
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let own = pattern[(y % 2) * 2 + x % 2];
+            let mut rgb = [0_u32; 3];
+            let mut counts = [0_u32; 3];
+
+            // offset by one, so the neighbours start at 0
+            for sy in (y..y + 3).map(|sy| mirror(sy, height)) {
+                for sx in (x..x + 3).map(|sx| mirror(sx, width)) {
+                    let channel = pattern[(sy % 2) * 2 + sx % 2];
+                    if channel == own && (sx, sy) != (x, y) {
+                        continue;
+                    }
+
+                    rgb[channel] += src[sy * width + sx].saturating_sub(black) as u32;
+                    counts[channel] += 1;
+                }
+            }
+
+            let rgb = finish([0, 1, 2].map(|channel| rgb[channel] / counts[channel].max(1)));
+            let i = (y * width + x) * 4;
+            if let Some(pixel) = dst.get_mut(i..i + 3) {
+                pixel.copy_from_slice(&rgb);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+/// Weight of the previous conversions in the measured conversion time, out of 8
+const AVERAGE_WEIGHT: u32 = 7;
+
+/// Longest time without a converted frame, no matter how far behind the input is
+const MAX_GAP: Duration = Duration::from_secs(1);
+
+/// Most frames dequeued per converted frame
+const MAX_INTERVAL: u32 = 30;
+
+/// Sent when an input with [`InputBuilder::conversion_budget`](crate::InputBuilder::conversion_budget)
+/// changed how many of its frames it converts
+#[derive(Event, Debug, Clone)]
+pub struct ConversionThrottled {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Every `interval`th frame is converted, 1 when the input caught up
+    pub interval: u32,
+    /// Average time converting a frame
+    pub conversion: Duration,
+    pub budget: Duration,
+}
+
+/// Skips converting frames of an input while converting takes longer than its budget
+pub(crate) struct Budget {
+    budget: Duration,
+    /// Average of the latest conversions
+    pub(crate) average: Option<Duration>,
+    interval: u32,
+    /// Frames to skip before the next conversion
+    countdown: u32,
+    last_converted: Instant,
+    started: Option<Instant>,
+    /// Frames skipped since the last [`FrameStats`](crate::FrameStats)
+    pub(crate) skipped: u32,
+    /// New interval, sent as a [`ConversionThrottled`] once the task is done
+    pub(crate) throttled: Option<u32>,
+}
+
+impl Budget {
+    pub(crate) fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            average: None,
+            interval: 1,
+            countdown: 0,
+            last_converted: Instant::now(),
+            started: None,
+            skipped: 0,
+            throttled: None,
+        }
+    }
+
+    pub(crate) fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// Whether to convert the frame that was just dequeued, starts timing it if so
+    pub(crate) fn convert(&mut self) -> bool {
+        if self.countdown > 0 && self.last_converted.elapsed() < MAX_GAP {
+            self.countdown -= 1;
+            self.skipped += 1;
+            return false;
+        }
+
+        let now = Instant::now();
+        self.countdown = self.interval - 1;
+        self.last_converted = now;
+        self.started = Some(now);
+        true
+    }
+
+    /// Ends the timing of a converted frame. `backlog` is how long the frame waited
+    /// in the driver's queue, which grows while the input falls behind.
+    pub(crate) fn converted(&mut self, backlog: Option<Duration>) {
+        let Some(started) = self.started.take() else {
+            return;
+        };
+
+        let elapsed = started.elapsed();
+        let average = match self.average {
+            Some(average) => (average * AVERAGE_WEIGHT + elapsed) / 8,
+            None => elapsed,
+        };
+        self.average = Some(average);
+
+        let behind = average.max(backlog.unwrap_or_default());
+        let interval = (behind.as_secs_f32() / self.budget.as_secs_f32().max(f32::EPSILON))
+            .ceil()
+            .clamp(1.0, MAX_INTERVAL as f32) as u32;
+        if interval != self.interval {
+            self.interval = interval;
+            self.throttled = Some(interval);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+#[cfg(feature = "bundles")]
+use bevy::render::render_asset::RenderAssetUsages;
+#[cfg(feature = "bundles")]
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+
+#[cfg(feature = "bundles")]
+use crate::{Format, Output};
+use crate::{Input, Result};
+
+/// An [`Input`] with a sprite showing its image
+#[cfg(feature = "bundles")]
+#[derive(Bundle)]
+pub struct V4lInputBundle {
+    pub input: Input,
+    pub sprite: SpriteBundle,
+}
+
+#[cfg(feature = "bundles")]
+impl V4lInputBundle {
+    /// Opens the v4l video device (/dev/video{id}) like [`Input::new`]
+    pub fn new(device_id: usize, images: &mut ResMut<Assets<Image>>) -> Result<Self> {
+        Ok(Self::from(Input::new(device_id, images)?))
+    }
+}
+
+#[cfg(feature = "bundles")]
+impl From<Input> for V4lInputBundle {
+    fn from(input: Input) -> Self {
+        Self {
+            sprite: SpriteBundle {
+                texture: input.image().clone(),
+                ..default()
+            },
+            input,
+        }
+    }
+}
+
+/// An [`Input`] with the handle of its image, for entities that show it some other way
+/// than a sprite
+#[derive(Bundle)]
+pub struct V4lBareInputBundle {
+    pub input: Input,
+    pub image: Handle<Image>,
+}
+
+impl V4lBareInputBundle {
+    /// Opens the v4l video device (/dev/video{id}) like [`Input::new`]
+    pub fn new(device_id: usize, images: &mut ResMut<Assets<Image>>) -> Result<Self> {
+        Ok(Self::from(Input::new(device_id, images)?))
+    }
+}
+
+impl From<Input> for V4lBareInputBundle {
+    fn from(input: Input) -> Self {
+        Self {
+            image: input.image().clone(),
+            input,
+        }
+    }
+}
+
+/// An [`Output`] with a camera rendering into its image, so everything the camera
+/// sees is written to the device
+#[cfg(feature = "bundles")]
+#[derive(Bundle)]
+pub struct V4lOutputBundle {
+    pub output: Output,
+    pub camera: Camera2dBundle,
+}
+
+#[cfg(feature = "bundles")]
+impl V4lOutputBundle {
+    /// Opens the v4l video device (/dev/video{id}) with `format`, the camera renders
+    /// at the size of the format
+    pub fn new(
+        device_id: usize,
+        format: Format,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> Result<Self> {
+        let image = images.add(target_image(format.width(), format.height()));
+        let output = Output::new(device_id, image.clone(), format)?;
+
+        Ok(Self {
+            output,
+            camera: Camera2dBundle {
+                camera: Camera {
+                    target: image.into(),
+                    ..default()
+                },
+                ..default()
+            },
+        })
+    }
+}
+
+#[cfg(feature = "bundles")]
+fn target_image(width: u32, height: u32) -> Image {
+    Image {
+        data: vec![255_u8; (width * height * 4) as usize],
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        asset_usage: RenderAssetUsages::all(),
+        ..default()
+    }
+}
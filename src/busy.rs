@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use tracing::debug;
+
+use crate::{Error, Result};
+
+const EBUSY: i32 = 16;
+/// Time between the attempts of [`OpenPolicy::WaitUntilFree`]
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What an input does when another process holds its device, see
+/// [`InputBuilder::open_policy`](crate::InputBuilder::open_policy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenPolicy {
+    /// Fails with [`Error::DeviceBusy`] right away
+    #[default]
+    Exclusive,
+    /// Tries again until the device is free or `timeout` passed, like at startup while
+    /// the previous instance of the app still shuts down. Building blocks meanwhile,
+    /// [`PendingInput`](crate::PendingInput)s send a [`DeviceBusyWaiting`] for every
+    /// attempt.
+    WaitUntilFree { timeout: Duration },
+}
+
+/// Sent while a [`PendingInput`](crate::PendingInput) waits for another process to free
+/// its device, see [`OpenPolicy::WaitUntilFree`]
+#[derive(Event, Debug, Clone)]
+pub struct DeviceBusyWaiting {
+    pub entity: Entity,
+    pub path: PathBuf,
+    /// Processes holding the device as "name (pid)", when /proc can be read
+    pub holders: Vec<String>,
+    /// Attempts that failed so far
+    pub attempt: u32,
+    /// Time since the first attempt
+    pub waited: Duration,
+    pub timeout: Duration,
+}
+
+/// Turns EBUSY into [`Error::DeviceBusy`], naming the processes holding the device
+pub(crate) fn check(err: io::Error, path: &Path) -> Error {
+    if !is_busy(&err) {
+        return err.into();
+    }
+
+    Error::DeviceBusy {
+        path: path.to_path_buf(),
+        holders: holders(path),
+    }
+}
+
+pub(crate) fn is_busy(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(EBUSY)
+}
+
+/// Opens with `open` again while it fails with a busy device, as `policy` allows.
+/// `waiting` is called before every retry.
+pub(crate) fn retry<T>(
+    policy: OpenPolicy,
+    mut waiting: impl FnMut(DeviceBusyWaiting),
+    mut open: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let OpenPolicy::WaitUntilFree { timeout } = policy else {
+        return open();
+    };
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let err = match open() {
+            Ok(opened) => return Ok(opened),
+            Err(err) => err,
+        };
+        let waited = started.elapsed();
+        let Some((path, holders)) = busy_device(&err) else {
+            return Err(err);
+        };
+        if waited + RETRY_INTERVAL > timeout {
+            return Err(err);
+        }
+        attempt += 1;
+        debug!(
+            path = %path.display(),
+            attempt,
+            ?waited,
+            "v4l device busy{}, trying again",
+            describe_holders(holders)
+        );
+        waiting(DeviceBusyWaiting {
+            entity: Entity::PLACEHOLDER,
+            path: path.to_path_buf(),
+            holders: holders.to_vec(),
+            attempt,
+            waited,
+            timeout,
+        });
+        std::thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// The busy device `err` failed on, also one of several selectors that failed
+fn busy_device(err: &Error) -> Option<(&Path, &[String])> {
+    match err {
+        Error::DeviceBusy { path, holders } => Some((path, holders)),
+        Error::NoDeviceAvailable { failures } => {
+            failures.iter().find_map(|(_, err)| busy_device(err))
+        }
+        _ => None,
+    }
+}
+
+/// Processes that have `path` open, as "name (pid)".
+/// Best effort, processes whose fds can't be read are skipped.
+fn holders(path: &Path) -> Vec<String> {
+    let Ok(path) = path.canonicalize() else {
+        return Vec::new();
+    };
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let own = std::process::id().to_string();
+
+    procs
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().into_string().ok()?;
+            if pid == own || !pid.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+
+            let holds = fs::read_dir(entry.path().join("fd"))
+                .ok()?
+                .flatten()
+                .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == path));
+            if !holds {
+                return None;
+            }
+
+            let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            Some(format!("{} ({pid})", name.trim()))
+        })
+        .collect()
+}
+
+/// Lists the holders found by [`check`] for error messages
+pub(crate) fn describe_holders(holders: &[String]) -> String {
+    match holders {
+        [] => String::new(),
+        holders => format!(", held by {}", holders.join(", ")),
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use v4l::frameinterval::FrameIntervalEnum;
+use v4l::framesize::FrameSizeEnum;
+use v4l::video::Capture;
+use v4l::{FourCC, Fraction};
+
+use crate::{can_decode, Result};
+
+/// Capture formats, frame sizes and frame intervals a device offers,
+/// see [`enumerate_capabilities`]
+#[derive(Debug, Clone)]
+pub struct DeviceCapabilities {
+    pub formats: Vec<FormatCapabilities>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatCapabilities {
+    /// Four ascii characters, like `*b"YUYV"`
+    pub fourcc: [u8; 4],
+    /// Name of the format as reported by the driver
+    pub description: String,
+    /// Whether inputs convert the format on the cpu, the others need an m2m device
+    pub decodable: bool,
+    pub sizes: Vec<FrameSizes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameSizes {
+    Discrete {
+        width: u32,
+        height: u32,
+        intervals: Vec<FrameIntervals>,
+    },
+    /// Every size from the min to the max in steps, the intervals are those of the max size
+    Stepwise {
+        min_width: u32,
+        max_width: u32,
+        step_width: u32,
+        min_height: u32,
+        max_height: u32,
+        step_height: u32,
+        intervals: Vec<FrameIntervals>,
+    },
+}
+
+/// Time between frames, the inverse of the frame rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameIntervals {
+    Discrete(Duration),
+    Stepwise {
+        min: Duration,
+        max: Duration,
+        step: Duration,
+    },
+}
+
+/// Lists the capture formats of the v4l video device (/dev/video{id}) with their frame
+/// sizes and intervals, without streaming from it. Sizes and intervals a driver can't
+/// enumerate are left empty.
+pub fn enumerate_capabilities(device_id: usize) -> Result<DeviceCapabilities> {
+    let dev = crate::devices::open_path(&crate::device_path(device_id))?;
+
+    let formats = dev
+        .enum_formats()?
+        .into_iter()
+        .map(|format| {
+            let fourcc = format.fourcc;
+            let sizes = dev.enum_framesizes(fourcc).unwrap_or_default();
+            FormatCapabilities {
+                fourcc: fourcc.repr,
+                description: format.description,
+                decodable: can_decode(&fourcc.repr),
+                sizes: sizes
+                    .into_iter()
+                    .map(|size| frame_sizes(&dev, fourcc, size.size))
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(DeviceCapabilities { formats })
+}
+
+fn frame_sizes(dev: &v4l::Device, fourcc: FourCC, size: FrameSizeEnum) -> FrameSizes {
+    let intervals = |width, height| {
+        dev.enum_frameintervals(fourcc, width, height)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|interval| match interval.interval {
+                FrameIntervalEnum::Discrete(interval) => {
+                    FrameIntervals::Discrete(duration(interval))
+                }
+                FrameIntervalEnum::Stepwise(interval) => FrameIntervals::Stepwise {
+                    min: duration(interval.min),
+                    max: duration(interval.max),
+                    step: duration(interval.step),
+                },
+            })
+            .collect()
+    };
+
+    match size {
+        FrameSizeEnum::Discrete(size) => FrameSizes::Discrete {
+            width: size.width,
+            height: size.height,
+            intervals: intervals(size.width, size.height),
+        },
+        FrameSizeEnum::Stepwise(size) => FrameSizes::Stepwise {
+            min_width: size.min_width,
+            max_width: size.max_width,
+            step_width: size.step_width,
+            min_height: size.min_height,
+            max_height: size.max_height,
+            step_height: size.step_height,
+            intervals: intervals(size.max_width, size.max_height),
+        },
+    }
+}
+
+/// Frame interval of a fraction of seconds
+pub(crate) fn duration(fraction: Fraction) -> Duration {
+    Duration::from_secs_f64(fraction.numerator as f64 / fraction.denominator.max(1) as f64)
+}
@@ -0,0 +1,722 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+
+use crate::{bayer, Decoder};
+
+/// V4L2_COLORSPACE_*, as drivers report them
+const COLORSPACE_REC709: u32 = 3;
+const COLORSPACE_JPEG: u32 = 7;
+const COLORSPACE_BT2020: u32 = 10;
+const COLORSPACE_DCI_P3: u32 = 12;
+
+/// V4L2_QUANTIZATION_*
+const QUANTIZATION_FULL_RANGE: u32 = 1;
+const QUANTIZATION_LIM_RANGE: u32 = 2;
+
+/// How the rgba in the image of an [`Input`](crate::Input) relates to the frames of the
+/// device, for shaders that care about color. See [`Input::color`](crate::Input::color).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub struct ColorMetadata {
+    /// Colorspace the driver reported, as its raw v4l2 value.
+    /// 0 means the default of the pixel format.
+    pub colorspace: u32,
+    /// Transfer function the driver reported, as its raw v4l2 value
+    pub transfer: u32,
+    /// Quantization the driver reported, as its raw v4l2 value
+    pub quantization: u32,
+    /// Colorimetry of the format as the driver reported it, see
+    /// [`InputBuilder::colorimetry`](crate::InputBuilder::colorimetry) for drivers that
+    /// report the wrong one
+    pub detected: Colorimetry,
+    /// How the frames were turned into rgb
+    pub conversion: YcbcrConversion,
+    /// How the rgb values are stored in the image
+    pub encoding: ImageEncoding,
+}
+
+/// How a frame was turned into rgb
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum YcbcrConversion {
+    /// The frames were rgb already
+    None,
+    /// Converted on the cpu or the gpu with the matrix and range expansion of this
+    /// colorimetry. JPEG frames are always full range BT.601.
+    Ycbcr(Colorimetry),
+    /// Converted by an m2m device, which picks the matrix and range itself
+    M2m,
+}
+
+impl ColorMetadata {
+    pub(crate) fn new(
+        format: &v4l::Format,
+        decoder: Decoder,
+        encoding: ImageEncoding,
+        colorimetry: Option<Colorimetry>,
+    ) -> Self {
+        let conversion = match (decoder, &format.fourcc.repr) {
+            (Decoder::M2m { .. }, _) => YcbcrConversion::M2m,
+            (Decoder::Cpu, b"AB24" | b"RGB3" | b"BGR3" | b"RGBP" | b"GREY" | b"Y16 ") => {
+                YcbcrConversion::None
+            }
+            (Decoder::Cpu, fourcc) if bayer::is_bayer(fourcc) => YcbcrConversion::None,
+            (Decoder::Cpu, b"MJPG" | b"JPEG") => YcbcrConversion::Ycbcr(Colorimetry::JPEG),
+            (Decoder::Cpu | Decoder::Gpu, _) => {
+                YcbcrConversion::Ycbcr(Colorimetry::resolve(format, colorimetry))
+            }
+        };
+
+        Self {
+            colorspace: format.colorspace as u32,
+            transfer: format.transfer as u32,
+            quantization: format.quantization as u32,
+            detected: Colorimetry::detect(format),
+            conversion,
+            encoding,
+        }
+    }
+}
+
+/// Matrix between the rgb and the YCbCr samples of a format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum YcbcrMatrix {
+    /// Standard definition video, JPEG and most webcams
+    #[default]
+    Bt601,
+    /// HD video, like from HDMI capture cards
+    Bt709,
+}
+
+impl YcbcrMatrix {
+    /// Weights of red and blue in the luma
+    fn weights(self) -> (f32, f32) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// Values the YCbCr samples of a format take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum YcbcrRange {
+    /// All of 0 to 255, like JPEG
+    Full,
+    /// Luma from 16 to 235 and chroma from 16 to 240, the default of uncompressed video
+    #[default]
+    Limited,
+}
+
+impl YcbcrRange {
+    /// Scales of the luma and the chroma from samples to full range, and the offset of
+    /// the luma
+    fn expansion(self) -> (f32, f32, i32) {
+        match self {
+            Self::Full => (1.0, 1.0, 0),
+            Self::Limited => (255.0 / 219.0, 255.0 / 224.0, 16),
+        }
+    }
+}
+
+/// Matrix and range of the YCbCr samples of a format. Inputs convert frames with the
+/// one the driver reports and outputs write frames with it, unless set with
+/// [`InputBuilder::colorimetry`](crate::InputBuilder::colorimetry) or
+/// [`OutputBuilder::colorimetry`](crate::OutputBuilder::colorimetry).
+///
+/// The default is limited range BT.601, what the v4l2 spec defines for uncompressed
+/// formats and what [`convert_frame`](crate::convert_frame) uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Colorimetry {
+    pub matrix: YcbcrMatrix,
+    pub range: YcbcrRange,
+}
+
+impl Colorimetry {
+    /// Full range BT.601, how JPEG frames are encoded
+    pub const JPEG: Self = Self {
+        matrix: YcbcrMatrix::Bt601,
+        range: YcbcrRange::Full,
+    };
+
+    /// The colorimetry the driver reported for `format`. Fields left at their default
+    /// get the one of the v4l2 spec: BT.709 for HD colorspaces, BT.601 otherwise, and
+    /// limited range unless the colorspace is JPEG.
+    pub(crate) fn detect(format: &v4l::Format) -> Self {
+        let colorspace = format.colorspace as u32;
+        let matrix = match colorspace {
+            // BT.2020 isn't supported, BT.709 is the closest
+            COLORSPACE_REC709 | COLORSPACE_DCI_P3 | COLORSPACE_BT2020 => YcbcrMatrix::Bt709,
+            _ => YcbcrMatrix::Bt601,
+        };
+        let range = match format.quantization as u32 {
+            QUANTIZATION_FULL_RANGE => YcbcrRange::Full,
+            QUANTIZATION_LIM_RANGE => YcbcrRange::Limited,
+            _ if colorspace == COLORSPACE_JPEG => YcbcrRange::Full,
+            _ => YcbcrRange::Limited,
+        };
+        Self { matrix, range }
+    }
+
+    /// `colorimetry` if it was set, the detected one otherwise
+    pub(crate) fn resolve(format: &v4l::Format, colorimetry: Option<Colorimetry>) -> Self {
+        colorimetry.unwrap_or_else(|| Self::detect(format))
+    }
+
+    pub(crate) fn to_rgb(self) -> ToRgb {
+        let (kr, kb) = self.matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let (luma, chroma, offset) = self.range.expansion();
+        let even = |value: f32, unit: f32| ((value * unit / 2.0).round() * 2.0) as i16;
+
+        ToRgb {
+            luma_offset: offset as i16,
+            y: even(luma, 2048.0),
+            r_v: even(2.0 * (1.0 - kr) * chroma, 1024.0),
+            g_u: even(2.0 * (1.0 - kb) * kb / kg * chroma, 1024.0),
+            g_v: even(2.0 * (1.0 - kr) * kr / kg * chroma, 1024.0),
+            b_u: even(2.0 * (1.0 - kb) * chroma, 1024.0),
+        }
+    }
+
+    pub(crate) fn to_ycbcr(self) -> ToYcbcr {
+        let (kr, kb) = self.matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let (luma, chroma, offset) = self.range.expansion();
+        let fixed = |scale: f32| move |value: f32| (value / scale * 65536.0).round() as i32;
+
+        ToYcbcr {
+            luma_offset: offset,
+            y: [kr, kg, kb].map(fixed(luma)),
+            u: [-kr / (1.0 - kb) / 2.0, -kg / (1.0 - kb) / 2.0, 0.5].map(fixed(chroma)),
+            v: [0.5, -kg / (1.0 - kr) / 2.0, -kb / (1.0 - kr) / 2.0].map(fixed(chroma)),
+        }
+    }
+}
+
+/// Fixed point coefficients converting the YCbCr of a [`Colorimetry`] into rgb in 1/4.
+/// Chroma terms are `(c * k) >> 16` of the chroma `c` shifted left by 8, the luma is
+/// `(y * k) >> 16` of the luma less its offset shifted left by 7. Every coefficient is
+/// even so the doubling multiply of NEON can take half of it, the vector paths of the
+/// `simd` feature convert to the same bytes as [`ToRgb::pair`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ToRgb {
+    pub(crate) luma_offset: i16,
+    pub(crate) y: i16,
+    pub(crate) r_v: i16,
+    pub(crate) g_u: i16,
+    pub(crate) g_v: i16,
+    pub(crate) b_u: i16,
+}
+
+impl ToRgb {
+    /// Converts two pixels sharing their chroma into 8 bytes of rgba
+    #[inline]
+    pub(crate) fn pair(&self, dst: &mut [u8], y0: u8, y1: u8, u: u8, v: u8) {
+        let terms = self.chroma(u, v);
+        dst[..4].copy_from_slice(&self.pixel(y0, terms));
+        dst[4..8].copy_from_slice(&self.pixel(y1, terms));
+    }
+
+    /// Scales the chroma terms by `saturation`, keeping the coefficients even
+    pub(crate) fn saturate(self, saturation: f32) -> Self {
+        let scale = |k: i16| ((k as f32 * saturation / 2.0).round() * 2.0) as i16;
+        Self {
+            r_v: scale(self.r_v),
+            g_u: scale(self.g_u),
+            g_v: scale(self.g_v),
+            b_u: scale(self.b_u),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub(crate) fn rgb(&self, y: u8, u: u8, v: u8) -> [u8; 4] {
+        self.pixel(y, self.chroma(u, v))
+    }
+
+    /// Terms of the chroma added to the luma of every channel
+    #[inline]
+    fn chroma(&self, u: u8, v: u8) -> [i32; 3] {
+        let (u, v) = ((u as i32 - 128) << 8, (v as i32 - 128) << 8);
+        [
+            mulhi(v, self.r_v),
+            -mulhi(u, self.g_u) - mulhi(v, self.g_v),
+            mulhi(u, self.b_u),
+        ]
+    }
+
+    #[inline]
+    fn pixel(&self, y: u8, [r, g, b]: [i32; 3]) -> [u8; 4] {
+        let y = mulhi((y as i32 - self.luma_offset as i32) << 7, self.y);
+        let channel = |term: i32| ((y + term + 2) >> 2).clamp(0, 255) as u8;
+        [channel(r), channel(g), channel(b), 255]
+    }
+}
+
+#[inline]
+fn mulhi(value: i32, k: i16) -> i32 {
+    (value * k as i32) >> 16
+}
+
+/// Coefficients converting rgb into the YCbCr of a [`Colorimetry`], in 1/65536
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ToYcbcr {
+    luma_offset: i32,
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+}
+
+impl ToYcbcr {
+    #[inline]
+    pub(crate) fn luma(&self, rgb: [i32; 3]) -> u8 {
+        (dot(self.y, rgb) + self.luma_offset).clamp(0, 255) as u8
+    }
+
+    /// Cb and Cr of `rgb`
+    #[inline]
+    pub(crate) fn chroma(&self, rgb: [i32; 3]) -> [u8; 2] {
+        [self.u, self.v].map(|k| (dot(k, rgb) + 128).clamp(0, 255) as u8)
+    }
+
+    /// Y, Cb and Cr of the rgb in the first 3 bytes of `rgba`
+    #[inline]
+    pub(crate) fn ycbcr(&self, rgba: &[u8]) -> [u8; 3] {
+        let rgb = [0, 1, 2].map(|channel| rgba[channel] as i32);
+        let [u, v] = self.chroma(rgb);
+        [self.luma(rgb), u, v]
+    }
+}
+
+#[inline]
+fn dot(k: [i32; 3], [r, g, b]: [i32; 3]) -> i32 {
+    (k[0] * r + k[1] * g + k[2] * b + (1 << 15)) >> 16
+}
+
+/// How the image of an [`Input`](crate::Input) stores colors, see
+/// [`InputBuilder::image_encoding`](crate::InputBuilder::image_encoding).
+///
+/// Converted frames are gamma encoded like the video they came from, which is close
+/// enough to sRGB to be stored as is. The texture format and what is stored in it go
+/// together:
+///
+/// - [`ImageEncoding::Srgb`] stores the gamma encoded bytes in an sRGB texture,
+///   sprites show them right and shaders sample linear values
+/// - [`ImageEncoding::Linear`] decodes the transfer function into a unorm texture, so
+///   shaders doing color math sample linear values without the sampler's help
+/// - [`ImageEncoding::Unorm`] stores the gamma encoded bytes in a unorm texture, shaders
+///   sample them as they are, like the raw frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ImageEncoding {
+    /// Rgba8UnormSrgb, stored as converted. Shaders sample linear values.
+    #[default]
+    Srgb,
+    /// Rgba8Unorm with the sRGB EOTF applied, loses precision in dark areas
+    Linear,
+    /// Rgba8Unorm stored as converted. Shaders sample the gamma encoded values, for
+    /// materials that decode them themselves or don't need linear colors.
+    Unorm,
+    /// Rgba16Float with the sRGB EOTF applied
+    LinearHalf,
+    /// Rgba16Float stored as converted, from 0 to 1. Sources with more than 8 bits
+    /// per sample, like Y16, keep their precision.
+    Half,
+    /// Rgba16Unorm stored as converted. Sources with more than 8 bits per sample,
+    /// like Y16, keep their precision.
+    Unorm16,
+    /// R8Unorm with only the luma of every pixel, for computer vision. YUV formats
+    /// copy their Y samples without any color math, rgb ones are weighted with BT.601.
+    ///
+    /// Only for packed 4:2:2, NV12, NV21, GREY, Y16 and rgb formats. Frame processors get
+    /// one byte per pixel and the temporal filter is skipped.
+    Luma,
+}
+
+impl ImageEncoding {
+    pub fn texture_format(self) -> TextureFormat {
+        match self {
+            Self::Srgb => TextureFormat::Rgba8UnormSrgb,
+            Self::Linear | Self::Unorm => TextureFormat::Rgba8Unorm,
+            Self::LinearHalf | Self::Half => TextureFormat::Rgba16Float,
+            Self::Unorm16 => TextureFormat::Rgba16Unorm,
+            Self::Luma => TextureFormat::R8Unorm,
+        }
+    }
+
+    pub(crate) fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Srgb | Self::Linear | Self::Unorm => 4,
+            Self::LinearHalf | Self::Half | Self::Unorm16 => 8,
+            Self::Luma => 1,
+        }
+    }
+}
+
+/// Applies the sRGB EOTF to converted frames or widens them to 16 bits per channel,
+/// with a lookup table per encoding
+pub(crate) enum Linearize {
+    Unorm(Box<[u8; 256]>),
+    /// For the encodings with 8 bytes per pixel
+    Wide {
+        encoding: ImageEncoding,
+        color: Box<[u16; 256]>,
+        alpha: Box<[u16; 256]>,
+        /// Every 16 bit sample for [`ImageEncoding::LinearHalf`], the other encodings
+        /// are cheap enough to compute
+        samples: Vec<u16>,
+    },
+}
+
+impl Linearize {
+    /// `None` for encodings that store frames as converted
+    pub(crate) fn new(encoding: ImageEncoding) -> Option<Self> {
+        let linear = |value: usize| srgb_eotf(value as f32 / 255.0);
+
+        match encoding {
+            ImageEncoding::Srgb | ImageEncoding::Unorm | ImageEncoding::Luma => None,
+            ImageEncoding::Linear => Some(Self::Unorm(Box::new(std::array::from_fn(|value| {
+                (linear(value) * 255.0).round() as u8
+            })))),
+            ImageEncoding::LinearHalf | ImageEncoding::Half | ImageEncoding::Unorm16 => {
+                let samples = match encoding {
+                    ImageEncoding::LinearHalf => {
+                        (0..=u16::MAX).map(|value| wide(encoding, value)).collect()
+                    }
+                    _ => Vec::new(),
+                };
+
+                Some(Self::Wide {
+                    encoding,
+                    color: Box::new(std::array::from_fn(|value| {
+                        wide(encoding, value as u16 * 257)
+                    })),
+                    alpha: Box::new(std::array::from_fn(|value| match encoding {
+                        ImageEncoding::Unorm16 => value as u16 * 257,
+                        _ => f16_bits(value as f32 / 255.0),
+                    })),
+                    samples,
+                })
+            }
+        }
+    }
+
+    /// Whether frames end up with 16 bits per channel
+    pub(crate) fn is_wide(&self) -> bool {
+        matches!(self, Self::Wide { .. })
+    }
+
+    /// Writes 16 bit little endian grey samples to `dst` with 8 bytes per pixel,
+    /// without reducing them to 8 bits first
+    pub(crate) fn widen_y16(&self, src: &[u8], dst: &mut [u8]) {
+        let Self::Wide {
+            encoding,
+            alpha,
+            samples,
+            ..
+        } = self
+        else {
+            return;
+        };
+
+        for (dst, src) in dst.chunks_exact_mut(8).zip(src.chunks_exact(2)) {
+            let value = u16::from_le_bytes([src[0], src[1]]);
+            let value = match samples.get(value as usize) {
+                Some(&value) => value,
+                None => wide(*encoding, value),
+            };
+
+            for channel in dst[..6].chunks_exact_mut(2) {
+                channel.copy_from_slice(&value.to_le_bytes());
+            }
+            dst[6..].copy_from_slice(&alpha[255].to_le_bytes());
+        }
+    }
+
+    /// Converts the first `pixels` rgba8 pixels of `buffer` in place.
+    /// For the encodings with 8 bytes per pixel the buffer has to fit them.
+    pub(crate) fn apply(&self, buffer: &mut [u8], pixels: usize) {
+        match self {
+            Self::Unorm(table) => {
+                for pixel in buffer.chunks_exact_mut(4).take(pixels) {
+                    for value in &mut pixel[..3] {
+                        *value = table[*value as usize];
+                    }
+                }
+            }
+            Self::Wide { color, alpha, .. } => {
+                let pixels = pixels.min(buffer.len() / 8);
+
+                // back to front, so every pixel is read before it is overwritten
+                for i in (0..pixels).rev() {
+                    let [r, g, b, a]: [u8; 4] = buffer[i * 4..i * 4 + 4].try_into().unwrap();
+
+                    let dst = &mut buffer[i * 8..i * 8 + 8];
+                    for (dst, bits) in dst.chunks_exact_mut(2).zip([
+                        color[r as usize],
+                        color[g as usize],
+                        color[b as usize],
+                        alpha[a as usize],
+                    ]) {
+                        dst.copy_from_slice(&bits.to_le_bytes());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 16 bit value of a sample from 0 to 65535 in an encoding with 8 bytes per pixel
+fn wide(encoding: ImageEncoding, value: u16) -> u16 {
+    let normalized = value as f32 / 65535.0;
+    match encoding {
+        ImageEncoding::Unorm16 => value,
+        ImageEncoding::LinearHalf => f16_bits(srgb_eotf(normalized)),
+        _ => f16_bits(normalized),
+    }
+}
+
+fn srgb_eotf(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of [`srgb_eotf`], for reading linear images back as gamma encoded
+#[cfg(feature = "snapshot")]
+pub(crate) fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Value of the bits of a half float, for the finite values the encodings store
+#[cfg(feature = "snapshot")]
+pub(crate) fn f16_value(bits: u16) -> f32 {
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    let magnitude = match exponent {
+        0 => mantissa * 2_f32.powi(-24),
+        _ => (1.0 + mantissa / 1024.0) * 2_f32.powi(exponent - 15),
+    };
+    match bits & 0x8000 {
+        0 => magnitude,
+        _ => -magnitude,
+    }
+}
+
+/// Bits of the half float closest below `value`, for values in 0..=1
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    match exponent {
+        ..=-11 => 0,
+        // subnormal, with the implicit leading bit
+        -10..=0 => ((mantissa | 0x80_0000) >> (14 - exponent)) as u16,
+        _ => ((exponent as u16) << 10) | (mantissa >> 13) as u16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Limited range Y of the sRGB mid grey 128, with neutral chroma
+    const MID_GREY: [u8; 4] = [126, 128, 126, 128];
+
+    /// The rgba bytes a YUYV pixel pair of `yuyv` converts to
+    fn converted(yuyv: [u8; 4]) -> [u8; 8] {
+        let mut rgba = [0; 8];
+        crate::convert_frame(*b"YUYV", 2, &yuyv, &mut rgba).unwrap();
+        rgba
+    }
+
+    /// Value of the bits of a half float of 0 to 1
+    fn half(bits: u16) -> f32 {
+        let (exponent, mantissa) = ((bits >> 10) as i32, (bits & 0x3ff) as f32);
+        (1.0 + mantissa / 1024.0) * 2_f32.powi(exponent - 15)
+    }
+
+    #[test]
+    fn srgb_images_store_the_gamma_encoded_bytes() {
+        assert_eq!(
+            ImageEncoding::Srgb.texture_format(),
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert!(Linearize::new(ImageEncoding::Srgb).is_none());
+        // the sampler decodes 128 to the linear 0.216
+        let rgba = converted(MID_GREY);
+        for &value in &rgba[..3] {
+            assert!(value.abs_diff(128) <= 1, "mid grey converted to {rgba:?}");
+        }
+    }
+
+    #[test]
+    fn linear_images_store_the_decoded_mid_grey() {
+        let gamma = converted(MID_GREY)[0];
+        // the sRGB EOTF, from IEC 61966-2-1
+        let expected = ((gamma as f32 / 255.0 + 0.055) / 1.055).powf(2.4);
+        assert!((expected - 0.216).abs() < 0.005, "{expected}");
+
+        let mut rgba = converted(MID_GREY);
+        Linearize::new(ImageEncoding::Linear)
+            .unwrap()
+            .apply(&mut rgba, 2);
+        assert_eq!(rgba, [55, 55, 55, 255, 55, 55, 55, 255]);
+        assert_eq!(
+            ImageEncoding::Linear.texture_format(),
+            TextureFormat::Rgba8Unorm
+        );
+
+        let mut wide = converted(MID_GREY).to_vec();
+        wide.resize(16, 0);
+        Linearize::new(ImageEncoding::LinearHalf)
+            .unwrap()
+            .apply(&mut wide, 2);
+        let channels: Vec<_> = wide
+            .chunks_exact(2)
+            .map(|bits| half(u16::from_le_bytes([bits[0], bits[1]])))
+            .collect();
+        for (i, &value) in channels.iter().enumerate() {
+            let expected = if i % 4 == 3 { 1.0 } else { expected };
+            // half floats keep 11 bits, rounded down
+            assert!((value - expected).abs() < 0.001, "{channels:?}");
+        }
+    }
+
+    #[test]
+    fn unorm_images_keep_the_gamma_encoded_bytes() {
+        assert!(Linearize::new(ImageEncoding::Unorm).is_none());
+        let mut wide = converted(MID_GREY).to_vec();
+        wide.resize(16, 0);
+        Linearize::new(ImageEncoding::Half)
+            .unwrap()
+            .apply(&mut wide, 2);
+        let gamma = converted(MID_GREY)[0] as f32 / 255.0;
+        let value = half(u16::from_le_bytes([wide[0], wide[1]]));
+        assert!((value - gamma).abs() < 0.001, "{value} instead of {gamma}");
+    }
+
+    /// YCbCr of red, green, blue, white and black in every colorimetry, from the float
+    /// equations of BT.601 and BT.709
+    const VECTORS: [(YcbcrMatrix, YcbcrRange, [[u8; 3]; 5]); 4] = [
+        (
+            YcbcrMatrix::Bt601,
+            YcbcrRange::Limited,
+            [
+                [81, 90, 240],
+                [145, 54, 34],
+                [41, 240, 110],
+                [235, 128, 128],
+                [16, 128, 128],
+            ],
+        ),
+        (
+            YcbcrMatrix::Bt601,
+            YcbcrRange::Full,
+            [
+                [76, 85, 255],
+                [150, 44, 21],
+                [29, 255, 107],
+                [255, 128, 128],
+                [0, 128, 128],
+            ],
+        ),
+        (
+            YcbcrMatrix::Bt709,
+            YcbcrRange::Limited,
+            [
+                [63, 102, 240],
+                [173, 42, 26],
+                [32, 240, 118],
+                [235, 128, 128],
+                [16, 128, 128],
+            ],
+        ),
+        (
+            YcbcrMatrix::Bt709,
+            YcbcrRange::Full,
+            [
+                [54, 99, 255],
+                [182, 30, 12],
+                [18, 255, 116],
+                [255, 128, 128],
+                [0, 128, 128],
+            ],
+        ),
+    ];
+
+    const COLORS: [[u8; 3]; 5] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255; 3], [0; 3]];
+
+    #[test]
+    fn reference_vectors_decode_to_their_colors() {
+        for (matrix, range, vectors) in VECTORS {
+            let k = Colorimetry { matrix, range }.to_rgb();
+            for ([y, u, v], color) in vectors.into_iter().zip(COLORS) {
+                let rgb = k.rgb(y, u, v);
+                // chroma clamped at 255 in full range decodes a little short
+                let close = rgb.iter().zip(color).all(|(&a, b)| a.abs_diff(b) <= 2);
+                assert!(
+                    close,
+                    "{matrix:?} {range:?}: {:?} decoded as {rgb:?}",
+                    [y, u, v]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn colors_encode_to_the_reference_vectors() {
+        for (matrix, range, vectors) in VECTORS {
+            let k = Colorimetry { matrix, range }.to_ycbcr();
+            for (vector, [r, g, b]) in vectors.into_iter().zip(COLORS) {
+                let ycbcr = k.ycbcr(&[r, g, b, 255]);
+                let close = ycbcr.iter().zip(vector).all(|(&a, b)| a.abs_diff(b) <= 1);
+                assert!(
+                    close,
+                    "{matrix:?} {range:?}: {ycbcr:?} instead of {vector:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn colorimetry_is_detected_from_the_format() {
+        use v4l::format::{Colorspace, Quantization};
+
+        let detected = |colorspace, quantization| {
+            let mut format = v4l::Format::new(1920, 1080, v4l::FourCC::new(b"YUYV"));
+            (format.colorspace, format.quantization) = (colorspace, quantization);
+            let Colorimetry { matrix, range } = Colorimetry::detect(&format);
+            (matrix, range)
+        };
+        assert_eq!(
+            detected(Colorspace::Default, Quantization::Default),
+            (YcbcrMatrix::Bt601, YcbcrRange::Limited)
+        );
+        assert_eq!(
+            detected(Colorspace::Rec709, Quantization::Default),
+            (YcbcrMatrix::Bt709, YcbcrRange::Limited)
+        );
+        assert_eq!(
+            detected(Colorspace::Rec709, Quantization::FullRange),
+            (YcbcrMatrix::Bt709, YcbcrRange::Full)
+        );
+        assert_eq!(
+            detected(Colorspace::JPEG, Quantization::Default),
+            (YcbcrMatrix::Bt601, YcbcrRange::Full)
+        );
+        assert_eq!(
+            detected(Colorspace::JPEG, Quantization::LimitedRange),
+            (YcbcrMatrix::Bt601, YcbcrRange::Limited)
+        );
+    }
+}
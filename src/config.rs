@@ -0,0 +1,100 @@
+use std::sync::RwLock;
+
+use bevy::prelude::*;
+
+use crate::{V4lError, BUFFER_COUNT};
+
+/// What happens to errors of devices after they were opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorPolicy {
+    /// Sent as [`V4lError`] events
+    #[default]
+    Event,
+    /// Logged instead of sent
+    Log,
+    /// Panics the app, for tests and kiosks that rather restart than freeze
+    Panic,
+}
+
+/// Defaults of every device, set from the [`V4lPlugin`](crate::V4lPlugin). Settings
+/// of a device, like [`InputBuilder::buffer_count`](crate::InputBuilder::buffer_count),
+/// win over them.
+///
+/// Devices opened before the plugin is added or while this changes get the
+/// defaults of that time.
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct V4lConfig {
+    /// Stream buffers requested from drivers, 4 by default
+    pub default_buffer_count: u32,
+    pub error_policy: ErrorPolicy,
+    /// Switches inputs whose current format can't be converted to one that can,
+    /// otherwise opening them fails. Formats requested by the app are never replaced.
+    pub auto_negotiate: bool,
+    /// Logs a trace line for every frame read or written
+    pub log_frames: bool,
+    /// Adds diagnostics of every device to the
+    /// [`DiagnosticsStore`](bevy::diagnostic::DiagnosticsStore), like `v4l/input0/fps`,
+    /// `v4l/input0/dropped` and `v4l/input0/conversion` for the frame rate, dropped
+    /// frames per second and conversion time of an input. Outputs have their frame rate,
+    /// like `v4l/output1/fps`. `LogDiagnosticsPlugin` prints them with the others.
+    /// Off by default, the io tasks only count frames for them while it is on. Devices
+    /// opened before it is turned on have none.
+    pub diagnostics: bool,
+}
+
+impl V4lConfig {
+    const DEFAULT: Self = Self {
+        default_buffer_count: BUFFER_COUNT,
+        error_policy: ErrorPolicy::Event,
+        auto_negotiate: true,
+        log_frames: true,
+        diagnostics: false,
+    };
+}
+
+impl Default for V4lConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Read by constructors, which can't reach the resource
+static CURRENT: RwLock<V4lConfig> = RwLock::new(V4lConfig::DEFAULT);
+
+pub(crate) fn current() -> V4lConfig {
+    CURRENT
+        .read()
+        .map_or(V4lConfig::DEFAULT, |config| config.clone())
+}
+
+pub(crate) fn set(config: V4lConfig) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = config;
+    }
+}
+
+/// Keeps the defaults constructors see in sync with the resource
+pub(crate) fn sync_config(config: Res<V4lConfig>) {
+    if config.is_changed() {
+        set(config.clone());
+    }
+}
+
+/// Applies the [`ErrorPolicy`] to an error that would be sent
+pub(crate) fn report(
+    config: &V4lConfig,
+    errors: &mut EventWriter<V4lError>,
+    event: Option<V4lError>,
+) {
+    let Some(event) = event else {
+        return;
+    };
+    match config.error_policy {
+        ErrorPolicy::Event => {
+            errors.send(event);
+        }
+        ErrorPolicy::Log => error!(device = %event.label, error = %event.error, "v4l error"),
+        ErrorPolicy::Panic => panic!("v4l error on {}: {}", event.label, event.error),
+    }
+}
@@ -0,0 +1,593 @@
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+
+use bevy::prelude::*;
+use tracing::{debug, warn};
+use v4l::control::{Control, Description, Flags, MenuItem, Type, Value};
+use v4l::v4l2;
+use v4l::v4l2::vidioc;
+use v4l::v4l_sys::v4l2_query_ext_ctrl;
+
+use crate::{Error, Input, Result};
+
+const CID_BASE: u32 = 0x00980900;
+const CID_CAMERA_CLASS_BASE: u32 = 0x009a0900;
+
+/// A v4l control, like the exposure of a camera. Controls the crate doesn't name are
+/// set with [`ControlId::Raw`] and their id from linux/v4l2-controls.h.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlId {
+    Brightness,
+    Contrast,
+    Saturation,
+    Gain,
+    /// Menu of auto exposure modes, 1 is manual and 3 is aperture priority on uvc cameras
+    ExposureAuto,
+    /// Exposure time in units of 100µs, only taken while [`ControlId::ExposureAuto`]
+    /// is manual
+    ExposureAbsolute,
+    WhiteBalanceAuto,
+    /// Only taken while [`ControlId::WhiteBalanceAuto`] is off
+    WhiteBalanceTemperature,
+    /// Menu of anti flicker modes, 1 for 50Hz and 2 for 60Hz
+    PowerLineFrequency,
+    /// Pan in arc seconds, positive turns right
+    PanAbsolute,
+    /// Tilt in arc seconds, positive turns up
+    TiltAbsolute,
+    ZoomAbsolute,
+    /// Turns by this many arc seconds, write-only on most cameras
+    PanRelative,
+    /// Tilts by this many arc seconds, write-only on most cameras
+    TiltRelative,
+    /// Zooms by this many steps, write-only on most cameras
+    ZoomRelative,
+    /// Keeps panning at this speed until set to 0, negative turns left
+    PanSpeed,
+    /// Keeps tilting at this speed until set to 0, negative turns down
+    TiltSpeed,
+    Raw(u32),
+}
+
+impl ControlId {
+    /// Id the driver knows the control by
+    pub fn id(self) -> u32 {
+        match self {
+            Self::Brightness => CID_BASE,
+            Self::Contrast => CID_BASE + 1,
+            Self::Saturation => CID_BASE + 2,
+            Self::WhiteBalanceAuto => CID_BASE + 12,
+            Self::Gain => CID_BASE + 19,
+            Self::PowerLineFrequency => CID_BASE + 24,
+            Self::WhiteBalanceTemperature => CID_BASE + 26,
+            Self::ExposureAuto => CID_CAMERA_CLASS_BASE + 1,
+            Self::ExposureAbsolute => CID_CAMERA_CLASS_BASE + 2,
+            Self::PanRelative => CID_CAMERA_CLASS_BASE + 4,
+            Self::TiltRelative => CID_CAMERA_CLASS_BASE + 5,
+            Self::PanAbsolute => CID_CAMERA_CLASS_BASE + 8,
+            Self::TiltAbsolute => CID_CAMERA_CLASS_BASE + 9,
+            Self::ZoomAbsolute => CID_CAMERA_CLASS_BASE + 13,
+            Self::ZoomRelative => CID_CAMERA_CLASS_BASE + 14,
+            Self::PanSpeed => CID_CAMERA_CLASS_BASE + 32,
+            Self::TiltSpeed => CID_CAMERA_CLASS_BASE + 33,
+            Self::Raw(id) => id,
+        }
+    }
+}
+
+impl From<u32> for ControlId {
+    /// Named controls for the ids the crate knows, [`ControlId::Raw`] otherwise
+    fn from(id: u32) -> Self {
+        [
+            Self::Brightness,
+            Self::Contrast,
+            Self::Saturation,
+            Self::Gain,
+            Self::ExposureAuto,
+            Self::ExposureAbsolute,
+            Self::WhiteBalanceAuto,
+            Self::WhiteBalanceTemperature,
+            Self::PowerLineFrequency,
+            Self::PanAbsolute,
+            Self::TiltAbsolute,
+            Self::ZoomAbsolute,
+            Self::PanRelative,
+            Self::TiltRelative,
+            Self::ZoomRelative,
+            Self::PanSpeed,
+            Self::TiltSpeed,
+        ]
+        .into_iter()
+        .find(|control| control.id() == id)
+        .unwrap_or(Self::Raw(id))
+    }
+}
+
+/// Value of a control. Menus take the index of their item as an integer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlValue {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl From<i64> for ControlValue {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<bool> for ControlValue {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<String> for ControlValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+/// A control a device exposes, with what it takes, see
+/// [`Input::enumerate_controls`](crate::Input::enumerate_controls)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlDescriptor {
+    pub id: ControlId,
+    /// Name the driver gives it, like "Exposure Time, Absolute"
+    pub name: String,
+    pub kind: ControlKind,
+    pub min: i64,
+    pub max: i64,
+    pub step: u64,
+    pub default: i64,
+    pub flags: ControlFlags,
+    /// Items of menus, empty for other kinds. Drivers leave out items they don't take.
+    pub menu: Vec<ControlMenuItem>,
+}
+
+/// Type of the value of a control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlKind {
+    Integer,
+    Integer64,
+    Boolean,
+    /// Takes the index of one of [`ControlDescriptor::menu`]
+    Menu,
+    /// Like [`ControlKind::Menu`], but items are numbers instead of names
+    IntegerMenu,
+    /// Triggers an action when set to any value, like a one-shot white balance
+    Button,
+    String,
+    Bitmask,
+    /// Arrays and structs, only reachable through
+    /// [`Input::with_raw_device`](crate::Input::with_raw_device)
+    Compound,
+}
+
+/// State of a control, from the flags the driver reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlFlags {
+    /// Not supported by the device, even though the driver lists it
+    pub disabled: bool,
+    /// Can't be set right now, like by another process
+    pub grabbed: bool,
+    pub read_only: bool,
+    pub write_only: bool,
+    /// Set but without effect until another control changes, like the exposure time
+    /// while auto exposure is on
+    pub inactive: bool,
+    /// Changes on its own, like the gain while auto gain is on
+    pub volatile: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlMenuItem {
+    /// Value that selects the item
+    pub index: u32,
+    /// Name of the item, the value formatted for integer menus
+    pub name: String,
+    /// Value of the item of integer menus
+    pub value: Option<i64>,
+}
+
+impl ControlDescriptor {
+    /// `None` for the headers of control classes, which aren't controls
+    fn new(description: Description) -> Option<Self> {
+        let kind = match description.typ {
+            Type::Integer => ControlKind::Integer,
+            Type::Integer64 => ControlKind::Integer64,
+            Type::Boolean => ControlKind::Boolean,
+            Type::Menu => ControlKind::Menu,
+            Type::IntegerMenu => ControlKind::IntegerMenu,
+            Type::Button => ControlKind::Button,
+            Type::String => ControlKind::String,
+            Type::Bitmask => ControlKind::Bitmask,
+            Type::CtrlClass => return None,
+            _ => ControlKind::Compound,
+        };
+        let flags = description.flags;
+
+        Some(Self {
+            id: ControlId::from(description.id),
+            name: description.name,
+            kind,
+            min: description.minimum,
+            max: description.maximum,
+            step: description.step,
+            default: description.default,
+            flags: ControlFlags {
+                disabled: flags.contains(Flags::DISABLED),
+                grabbed: flags.contains(Flags::GRABBED),
+                read_only: flags.contains(Flags::READ_ONLY),
+                write_only: flags.contains(Flags::WRITE_ONLY),
+                inactive: flags.contains(Flags::INACTIVE),
+                volatile: flags.contains(Flags::VOLATILE),
+            },
+            menu: description
+                .items
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(index, item)| match item {
+                    MenuItem::Name(name) => ControlMenuItem {
+                        index,
+                        name,
+                        value: None,
+                    },
+                    MenuItem::Value(value) => ControlMenuItem {
+                        index,
+                        name: value.to_string(),
+                        value: Some(value),
+                    },
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Every control of the device, disabled ones included
+pub(crate) fn enumerate(dev: &v4l::Device) -> Result<Vec<ControlDescriptor>> {
+    Ok(dev
+        .query_controls()?
+        .into_iter()
+        .filter_map(ControlDescriptor::new)
+        .collect())
+}
+
+/// Range of one control without enumerating all of them, menus are left empty
+fn query(dev: &v4l::Device, id: ControlId) -> Result<ControlDescriptor> {
+    let description = unsafe {
+        let mut query: v4l2_query_ext_ctrl = mem::zeroed();
+        query.id = id.id();
+        v4l2::ioctl(
+            dev.handle().fd(),
+            vidioc::VIDIOC_QUERY_EXT_CTRL,
+            &mut query as *mut v4l2_query_ext_ctrl as *mut c_void,
+        )?;
+        Description::from(query)
+    };
+    ControlDescriptor::new(description).ok_or(Error::UnsupportedControl(id))
+}
+
+/// Sets `position` from 0 to 1 across the range of the control, rounded to its step.
+/// Returns the value set without reading it back, since relative controls are
+/// write-only on many cameras.
+pub(crate) fn set_normalized(dev: &v4l::Device, id: ControlId, position: f32) -> Result<i64> {
+    let control = query(dev, id)?;
+    let step = control.step.max(1) as f64;
+    let steps =
+        ((control.max - control.min) as f64 * position.clamp(0.0, 1.0) as f64 / step).round();
+    let value = (control.min + (steps * step) as i64).min(control.max);
+    set(dev, id, ControlValue::Integer(value))?;
+    Ok(value)
+}
+
+pub(crate) fn get(dev: &v4l::Device, id: ControlId) -> Result<ControlValue> {
+    match dev.control(id.id())?.value {
+        Value::Integer(value) => Ok(ControlValue::Integer(value)),
+        Value::Boolean(value) => Ok(ControlValue::Boolean(value)),
+        Value::String(value) => Ok(ControlValue::String(value)),
+        _ => Err(Error::UnsupportedControl(id)),
+    }
+}
+
+/// Sets the control as is, drivers fail with ERANGE or EINVAL for values out of range
+/// instead of the crate clamping them. Works while streaming, since controls don't
+/// touch the buffers.
+pub(crate) fn set(dev: &v4l::Device, id: ControlId, value: ControlValue) -> Result<()> {
+    debug!(?id, ?value, "setting v4l control");
+    let value = match value {
+        ControlValue::Integer(value) => Value::Integer(value),
+        ControlValue::Boolean(value) => Value::Boolean(value),
+        ControlValue::String(value) => Value::String(value),
+    };
+    dev.set_control(Control { id: id.id(), value })?;
+    Ok(())
+}
+
+/// Exposure set with [`Input::set_exposure`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Exposure {
+    /// Full auto exposure, or aperture priority on uvc cameras that don't offer it
+    Auto,
+    Manual {
+        time_100us: u32,
+    },
+}
+
+/// White balance set with [`Input::set_white_balance`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhiteBalance {
+    Auto,
+    Manual { kelvin: u32 },
+}
+
+/// The part of [`Input::set_exposure`] or [`Input::set_white_balance`] that failed,
+/// see [`ControlError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlStep {
+    /// Setting the auto control
+    Auto,
+    /// Reading the auto control back, it has to hold the value just set
+    Verify,
+    /// Setting the manual value
+    Manual,
+}
+
+#[derive(Debug)]
+pub struct ControlError {
+    pub step: ControlStep,
+    pub error: Error,
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} step failed: {}", self.step, self.error)
+    }
+}
+
+fn fail(step: ControlStep) -> impl FnOnce(Error) -> ControlError {
+    move |error| ControlError { step, error }
+}
+
+/// Sets the auto control and checks the driver took it, drivers that don't know a
+/// value may ignore it instead of failing
+fn set_auto(
+    dev: &v4l::Device,
+    id: ControlId,
+    value: ControlValue,
+) -> std::result::Result<(), ControlError> {
+    set(dev, id, value.clone()).map_err(fail(ControlStep::Auto))?;
+    let current = get(dev, id).map_err(fail(ControlStep::Verify))?;
+    let took = match (&current, &value) {
+        (ControlValue::Boolean(on), ControlValue::Integer(value))
+        | (ControlValue::Integer(value), ControlValue::Boolean(on)) => (*value != 0) == *on,
+        _ => current == value,
+    };
+    if !took {
+        return Err(fail(ControlStep::Verify)(Error::ControlNotApplied {
+            control: id,
+            requested: value,
+            current,
+        }));
+    }
+    Ok(())
+}
+
+/// Switches to manual exposure before setting the time, which drivers ignore or
+/// reject while auto exposure is on
+pub(crate) fn set_exposure(
+    dev: &v4l::Device,
+    exposure: Exposure,
+) -> std::result::Result<(), ControlError> {
+    match exposure {
+        Exposure::Auto => {
+            let auto = ControlValue::Integer(ExposureMode::Auto as i64);
+            if let Err(err) = set_auto(dev, ControlId::ExposureAuto, auto) {
+                // uvc cameras only offer manual and aperture priority
+                debug!(%err, "full auto exposure failed, trying aperture priority");
+                let priority = ControlValue::Integer(ExposureMode::AperturePriority as i64);
+                set_auto(dev, ControlId::ExposureAuto, priority)?;
+            }
+            Ok(())
+        }
+        Exposure::Manual { time_100us } => {
+            let manual = ControlValue::Integer(ExposureMode::Manual as i64);
+            set_auto(dev, ControlId::ExposureAuto, manual)?;
+            set(
+                dev,
+                ControlId::ExposureAbsolute,
+                ControlValue::Integer(time_100us.into()),
+            )
+            .map_err(fail(ControlStep::Manual))
+        }
+    }
+}
+
+/// Turns auto white balance off before setting the temperature
+pub(crate) fn set_white_balance(
+    dev: &v4l::Device,
+    white_balance: WhiteBalance,
+) -> std::result::Result<(), ControlError> {
+    let auto = matches!(white_balance, WhiteBalance::Auto);
+    set_auto(
+        dev,
+        ControlId::WhiteBalanceAuto,
+        ControlValue::Boolean(auto),
+    )?;
+    match white_balance {
+        WhiteBalance::Auto => Ok(()),
+        WhiteBalance::Manual { kelvin } => set(
+            dev,
+            ControlId::WhiteBalanceTemperature,
+            ControlValue::Integer(kelvin.into()),
+        )
+        .map_err(fail(ControlStep::Manual)),
+    }
+}
+
+/// Auto exposure modes of [`ControlId::ExposureAuto`]
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExposureMode {
+    Auto = 0,
+    Manual = 1,
+    ShutterPriority = 2,
+    AperturePriority = 3,
+}
+
+/// Controls of the device of the [`Input`] on the same entity. Changed fields are set
+/// on the device, and replaced with the values the driver took, like when it rounded
+/// them to its step. Fields left `None` aren't touched.
+#[derive(Component, Reflect, Debug, Clone, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraControls {
+    pub brightness: Option<i64>,
+    pub contrast: Option<i64>,
+    pub saturation: Option<i64>,
+    pub gain: Option<i64>,
+    pub exposure_mode: Option<ExposureMode>,
+    /// In units of 100µs, only taken with [`ExposureMode::Manual`]
+    pub exposure_time: Option<i64>,
+    pub auto_white_balance: Option<bool>,
+    /// In kelvin, only taken while `auto_white_balance` is off
+    pub white_balance_temperature: Option<i64>,
+    /// 1 for 50Hz and 2 for 60Hz, 0 turns anti flicker off
+    pub power_line_frequency: Option<i64>,
+}
+
+impl CameraControls {
+    /// Fields in the order they are set, modes before the values they unlock
+    fn values(&self) -> [(ControlId, Option<ControlValue>); 9] {
+        let integer = |value: Option<i64>| value.map(ControlValue::Integer);
+        [
+            (ControlId::Brightness, integer(self.brightness)),
+            (ControlId::Contrast, integer(self.contrast)),
+            (ControlId::Saturation, integer(self.saturation)),
+            (ControlId::Gain, integer(self.gain)),
+            (
+                ControlId::ExposureAuto,
+                integer(self.exposure_mode.map(|mode| mode as i64)),
+            ),
+            (ControlId::ExposureAbsolute, integer(self.exposure_time)),
+            (
+                ControlId::WhiteBalanceAuto,
+                self.auto_white_balance.map(ControlValue::Boolean),
+            ),
+            (
+                ControlId::WhiteBalanceTemperature,
+                integer(self.white_balance_temperature),
+            ),
+            (
+                ControlId::PowerLineFrequency,
+                integer(self.power_line_frequency),
+            ),
+        ]
+    }
+
+    /// Stores a value read from the device, values of other types are dropped
+    fn set(&mut self, id: ControlId, value: ControlValue) {
+        let value = match value {
+            ControlValue::Integer(value) => value,
+            ControlValue::Boolean(on) => on as i64,
+            ControlValue::String(_) => return,
+        };
+        match id {
+            ControlId::Brightness => self.brightness = Some(value),
+            ControlId::Contrast => self.contrast = Some(value),
+            ControlId::Saturation => self.saturation = Some(value),
+            ControlId::Gain => self.gain = Some(value),
+            ControlId::ExposureAuto => {
+                self.exposure_mode = match value {
+                    0 => Some(ExposureMode::Auto),
+                    1 => Some(ExposureMode::Manual),
+                    2 => Some(ExposureMode::ShutterPriority),
+                    3 => Some(ExposureMode::AperturePriority),
+                    _ => return,
+                }
+            }
+            ControlId::ExposureAbsolute => self.exposure_time = Some(value),
+            ControlId::WhiteBalanceAuto => self.auto_white_balance = Some(value != 0),
+            ControlId::WhiteBalanceTemperature => self.white_balance_temperature = Some(value),
+            ControlId::PowerLineFrequency => self.power_line_frequency = Some(value),
+            // moved with the ptz methods of Input, CameraControls has no fields for them
+            ControlId::PanAbsolute
+            | ControlId::TiltAbsolute
+            | ControlId::ZoomAbsolute
+            | ControlId::PanRelative
+            | ControlId::TiltRelative
+            | ControlId::ZoomRelative
+            | ControlId::PanSpeed
+            | ControlId::TiltSpeed
+            | ControlId::Raw(_) => {}
+        }
+    }
+}
+
+/// What was last set on the device, so only fields changed since are set again
+#[derive(Component)]
+pub(crate) struct AppliedControls(CameraControls);
+
+/// Inputs whose controls changed or that just opened
+type ControlsChanged = Or<(Changed<CameraControls>, Added<Input>)>;
+
+/// Sets the changed fields of [`CameraControls`], and the whole component once the
+/// input opened. Controls that fail are logged and skipped, the rest are still set.
+pub(crate) fn sync_camera_controls(
+    mut commands: Commands,
+    mut inputs: Query<
+        (
+            Entity,
+            &Input,
+            &mut CameraControls,
+            Option<&mut AppliedControls>,
+        ),
+        ControlsChanged,
+    >,
+) {
+    for (entity, input, mut controls, applied) in inputs.iter_mut() {
+        let _span = input.device.span.enter();
+        let previous = applied.as_ref().map(|applied| applied.0.values());
+        let mut granted = controls.clone();
+
+        for (index, (id, value)) in controls.values().into_iter().enumerate() {
+            let Some(value) = value else {
+                continue;
+            };
+            if previous
+                .as_ref()
+                .is_some_and(|previous| previous[index].1.as_ref() == Some(&value))
+            {
+                continue;
+            }
+
+            if let Err(err) = input.set_control(id, value) {
+                warn!(?id, %err, "setting v4l control failed");
+                continue;
+            }
+            match input.control(id) {
+                Ok(value) => granted.set(id, value),
+                Err(err) => debug!(?id, %err, "reading back v4l control failed"),
+            }
+        }
+
+        // written back without change detection, or it would be set again next frame
+        controls.bypass_change_detection().clone_from(&granted);
+        match applied {
+            Some(mut applied) => applied.0 = granted,
+            None => {
+                commands.entity(entity).insert(AppliedControls(granted));
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::encode::{self, FrameEncoder};
+use crate::scale::ScaledFrame;
+use crate::{can_decode, unpad_frame, Colorimetry, Error, Format, FrameInfo, Result, SizePolicy};
+
+/// Converts frames of pixel formats the crate doesn't, like vendor specific ones, see
+/// [`InputBuilder::converter`](crate::InputBuilder::converter) and
+/// [`OutputBuilder::converter`](crate::OutputBuilder::converter). Converters are asked
+/// before the built-in conversions, the ones of [`BuiltinConverter`].
+///
+/// Frames are converted on the io task of the device, like the built-in formats.
+pub trait PixelConverter: Send {
+    /// Whether frames of `fourcc` are converted by this converter
+    fn converts(&self, fourcc: [u8; 4]) -> bool;
+
+    /// Converts `src`, a frame of `format` as the driver dequeued it with rows padded to
+    /// [`Format::stride`], into the rgba `dst` of `format.width() * 4` bytes per row.
+    /// Orientation, deinterlacing and processors are applied afterwards.
+    fn decode(
+        &mut self,
+        src: &[u8],
+        format: &Format,
+        dst: &mut [u8],
+        info: &FrameInfo,
+    ) -> Result<()> {
+        let _ = (src, dst, info);
+        Err(unsupported(format))
+    }
+
+    /// Encodes the rgba `src` of `format.width() * 4` bytes per row into `dst`, a buffer
+    /// of a device streaming `format`, and returns the bytes used
+    fn encode(&mut self, src: &[u8], format: &Format, dst: &mut [u8]) -> Result<usize> {
+        let _ = (src, dst);
+        Err(unsupported(format))
+    }
+}
+
+fn unsupported(format: &Format) -> Error {
+    Error::UnsupportedFormat {
+        fourcc: format.fourcc().into(),
+    }
+}
+
+/// The conversions of the crate as a [`PixelConverter`], for converters that handle a
+/// few formats and leave the others to these
+#[derive(Default)]
+pub struct BuiltinConverter {
+    unpadded: Vec<u8>,
+}
+
+impl PixelConverter for BuiltinConverter {
+    fn converts(&self, fourcc: [u8; 4]) -> bool {
+        can_decode(&fourcc) || encode::can_encode(&fourcc)
+    }
+
+    fn decode(&mut self, src: &[u8], format: &Format, dst: &mut [u8], _: &FrameInfo) -> Result<()> {
+        let (fourcc, width) = (format.fourcc(), format.width());
+        let src = unpad_frame(
+            &fourcc,
+            src,
+            format.0.stride,
+            width,
+            format.height(),
+            &mut self.unpadded,
+        );
+        crate::convert_frame(fourcc, width, src, dst)
+    }
+
+    fn encode(&mut self, src: &[u8], format: &Format, dst: &mut [u8]) -> Result<usize> {
+        let Some(mut encoder) =
+            encode::for_format(&format.0, Colorimetry::resolve(&format.0, None))
+        else {
+            return Err(unsupported(format));
+        };
+        let size = (format.width(), format.height());
+        encoder.encode(&ScaledFrame::new(src, size, size, SizePolicy::Error)?, dst)
+    }
+}
+
+/// Writes the frames of an output with the [`PixelConverter`] of the app
+pub(crate) struct Encoder {
+    converter: Box<dyn PixelConverter>,
+    format: Format,
+    /// The scaled and turned frame, like the image of the size of the format
+    frame: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new(converter: Box<dyn PixelConverter>, format: v4l::Format) -> Self {
+        Self {
+            converter,
+            format: Format(format),
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl FrameEncoder for Encoder {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        self.frame.clear();
+        for y in 0..src.height() {
+            let row = src.row(y);
+            for x in 0..src.width() {
+                self.frame.extend_from_slice(&src.pixel(row, x));
+            }
+        }
+        self.converter.encode(&self.frame, &self.format, dst)
+    }
+}
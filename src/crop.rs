@@ -0,0 +1,334 @@
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::os::raw::{c_int, c_void};
+
+use bevy::math::URect;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension};
+use tracing::{debug, info};
+use v4l::format::FieldOrder;
+use v4l::v4l2;
+use v4l::video::Capture;
+use v4l::FourCC;
+
+use crate::ioctl::{Rect, Selection, VIDIOC_G_SELECTION, VIDIOC_S_SELECTION};
+use crate::profile::{self, Profile};
+use crate::source::IoStream;
+use crate::{bayer, exchange, late, validate, Decoder, Error, Format, Input, Io, Result};
+
+/// V4L2_BUF_TYPE_VIDEO_CAPTURE, drivers of multi-planar devices take it for selections too
+const BUF_TYPE_CAPTURE: u32 = 1;
+/// V4L2_SEL_TGT_CROP and V4L2_SEL_TGT_CROP_DEFAULT
+const TARGET_CROP: u32 = 0;
+const TARGET_CROP_DEFAULT: u32 = 1;
+
+/// Region of the frames of an [`Input`] its image shows, see [`Input::set_crop`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    /// The region the driver granted, or the one converted
+    pub rect: URect,
+    /// Whether the driver crops, otherwise frames are captured whole and only the
+    /// region is converted
+    pub driver: bool,
+}
+
+/// Region of the frames an io task cuts out before converting them, for crops the
+/// driver didn't take
+#[derive(Clone, Copy)]
+pub(crate) struct Region {
+    rect: URect,
+    /// Size of the captured frames
+    pub(crate) source: (u32, u32),
+}
+
+impl Region {
+    /// Copies the region out of a frame of `fourcc` with rows of `stride` bytes, its
+    /// rows one after the other like [`unpad`](crate::unpad) leaves them
+    pub(crate) fn cut<'a>(
+        &self,
+        fourcc: &[u8; 4],
+        src: &[u8],
+        stride: u32,
+        dst: &'a mut Vec<u8>,
+    ) -> &'a [u8] {
+        let row = |width| validate::row_bytes(fourcc, width).unwrap_or(0);
+        let (width, height) = self.source;
+        let stride = (stride as usize).max(row(width));
+        let columns = row(self.rect.min.x)..row(self.rect.max.x);
+        let rows = self.rect.min.y as usize..self.rect.max.y as usize;
+        // the region starts on even rows and columns of subsampled formats
+        let chroma_rows = rows.start / 2..rows.end / 2;
+
+        dst.clear();
+        copy(src, stride, columns.clone(), rows, dst);
+        let (_, chroma) = src.split_at((stride * height as usize).min(src.len()));
+        match fourcc {
+            b"NV12" | b"NV21" => {
+                let stride = validate::chroma_stride(fourcc, stride);
+                copy(chroma, stride, columns, chroma_rows, dst)
+            }
+            b"YU12" => {
+                let stride = validate::chroma_stride(fourcc, stride);
+                let plane = stride * (height as usize).div_ceil(2);
+                let (cb, cr) = chroma.split_at(plane.min(chroma.len()));
+                let columns = columns.start / 2..columns.end / 2;
+                copy(cb, stride, columns.clone(), chroma_rows.clone(), dst);
+                copy(cr, stride, columns, chroma_rows, dst);
+            }
+            _ => {}
+        }
+        dst
+    }
+}
+
+/// Appends `columns` bytes of `rows` of a plane with rows of `stride` bytes to `dst`
+fn copy(plane: &[u8], stride: usize, columns: Range<usize>, rows: Range<usize>, dst: &mut Vec<u8>) {
+    for y in rows {
+        if let Some(row) = plane.get(y * stride + columns.start..y * stride + columns.end) {
+            dst.extend_from_slice(row);
+        }
+    }
+}
+
+/// Crops the frames of `input` to `rect`, in the driver when it crops
+pub(crate) fn set(input: &mut Input, rect: URect, images: &mut Assets<Image>) -> Result<Crop> {
+    let span = input.device.span.clone();
+    let _span = span.enter();
+    let native = input.device.io.lock().is_ok_and(|io| io.native);
+    if input.decoder == Decoder::Gpu || native {
+        return Err(invalid(
+            rect,
+            "frames of gpu converted and native inputs are shown as they are dequeued",
+        ));
+    }
+
+    // drivers that don't crop fail with EINVAL or ENOTTY, like most webcams
+    let crops = input
+        .device
+        .dev
+        .as_ref()
+        .is_some_and(|dev| selection(dev, TARGET_CROP).is_ok());
+    if crops {
+        match driver(input, Some(rect), images) {
+            Ok(granted) => {
+                let crop = Crop {
+                    rect: granted,
+                    driver: true,
+                };
+                info!(requested = ?rect, granted = ?crop.rect, "v4l driver crops");
+                input.crop = Some(crop);
+                return Ok(crop);
+            }
+            Err(err) => debug!(%err, "v4l driver failed to crop, converting the region"),
+        }
+    }
+
+    software(input, Some(rect), images)?;
+    let crop = Crop {
+        rect,
+        driver: false,
+    };
+    input.crop = Some(crop);
+    Ok(crop)
+}
+
+/// Shows the whole frames of `input` again
+pub(crate) fn clear(input: &mut Input, images: &mut Assets<Image>) -> Result<()> {
+    let span = input.device.span.clone();
+    let _span = span.enter();
+    match input.crop {
+        None => return Ok(()),
+        Some(Crop { driver: true, .. }) => {
+            driver(input, None, images)?;
+        }
+        Some(_) => software(input, None, images)?,
+    }
+    input.crop = None;
+    Ok(())
+}
+
+/// Sets the crop of the driver to `rect`, or back to its default, and the stream up
+/// again with the format of the cropped frames. Returns the region the driver granted.
+fn driver(input: &mut Input, rect: Option<URect>, images: &mut Assets<Image>) -> Result<URect> {
+    let unsupported = || Error::Io(io::ErrorKind::Unsupported.into());
+    let Some(dev) = input.device.dev.as_ref() else {
+        return Err(unsupported());
+    };
+    // the crop can't change while buffers are allocated
+    match input.device.io.lock() {
+        Ok(mut io) => io.stream = IoStream::Closed,
+        Err(_) => return Err(unsupported()),
+    }
+
+    let granted = match rect {
+        Some(rect) => select(dev, rect),
+        None => selection(dev, TARGET_CROP_DEFAULT).and_then(|rect| select(dev, rect)),
+    };
+    // the previous format again when the driver rejected the crop, to restart the stream
+    let format = match granted {
+        Ok(_) => Capture::format(dev)?,
+        Err(_) => input.device.format,
+    };
+
+    let profile = Profile {
+        format: Format(format),
+        controls: Vec::new(),
+    };
+    profile::apply(input, &profile, "the crop", images).map_err(|err| err.error)?;
+    Ok(granted?)
+}
+
+/// Converts the region `rect` of the frames of `input`, the whole frames for `None`
+fn software(input: &mut Input, rect: Option<URect>, images: &mut Assets<Image>) -> Result<()> {
+    let (encoding, late_upload, orientation) =
+        (input.encoding, input.late_upload, input.orientation);
+    let device = &mut input.device;
+    let Ok(mut io) = device.io.lock() else {
+        return Err(Error::Io(io::ErrorKind::Unsupported.into()));
+    };
+    let format = &device.format;
+    let size = match rect {
+        Some(rect) => {
+            let fourcc = io.overrides.fourcc(format.fourcc.repr);
+            check(format, &fourcc, rect, io.m2m.is_some())?;
+            io.crop = Some(Region {
+                rect,
+                source: (format.width, format.height),
+            });
+            (rect.width(), rect.height())
+        }
+        None => {
+            io.crop = None;
+            (format.width, format.height)
+        }
+    };
+
+    let (width, height) = orientation.size(size.0, size.1);
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    resize(&mut io, &device.exchange, size, encoding.bytes_per_pixel());
+    let image = Image::new(
+        size,
+        TextureDimension::D2,
+        io.buffer.clone(),
+        encoding.texture_format(),
+        late::image_usage(late_upload),
+    );
+    images.insert(&device.image, image);
+    device.size = size;
+    Ok(())
+}
+
+/// Sizes the buffers of `io` for frames of `size`, targets and the average of the
+/// temporal filter of the previous region are removed
+fn resize(io: &mut Io, exchange: &exchange::Exchange, size: Extent3d, bytes_per_pixel: usize) {
+    let len = (size.width * size.height) as usize * bytes_per_pixel;
+    io.buffer = vec![255; len];
+    io.targets.clear();
+    io.fresh = false;
+    exchange.reset(len);
+    if let Some(denoise) = io.denoise.as_mut() {
+        denoise.reset();
+    }
+}
+
+/// Fails for regions outside the frames of `format`, or that split the blocks of
+/// subsampled chroma
+fn check(format: &v4l::Format, fourcc: &[u8; 4], rect: URect, m2m: bool) -> Result<()> {
+    let fail = |reason: String| Err(invalid(rect, &reason));
+    if rect.is_empty() {
+        return fail("the region is empty".to_string());
+    }
+    if rect.max.x > format.width || rect.max.y > format.height {
+        return fail(format!("the frames are {}x{}", format.width, format.height));
+    }
+    if m2m || validate::row_bytes(fourcc, 1).is_none() {
+        return fail(format!(
+            "only the driver crops frames of {} converted elsewhere",
+            FourCC::new(fourcc)
+        ));
+    }
+    let interleaved = match format.field_order {
+        FieldOrder::Alternate | FieldOrder::SequentialTB | FieldOrder::SequentialBT => {
+            return fail("only the driver crops fields that aren't interleaved".to_string());
+        }
+        FieldOrder::Interlaced | FieldOrder::InterlacedTB | FieldOrder::InterlacedBT => true,
+        _ => false,
+    };
+
+    let (even_columns, even_rows) = match fourcc {
+        b"YUYV" | b"UYVY" | b"YVYU" => (true, false),
+        b"NV12" | b"NV21" | b"YU12" => (true, true),
+        // the region starts on the same color as the pattern
+        fourcc if bayer::is_bayer(fourcc) => (true, true),
+        _ => (false, false),
+    };
+    let odd = |start: u32, len: u32| start % 2 == 1 || len % 2 == 1;
+    if even_columns && odd(rect.min.x, rect.width()) {
+        return fail(format!(
+            "{} needs an even left and width",
+            FourCC::new(fourcc)
+        ));
+    }
+    // both fields of interleaved lines keep their rows
+    if (even_rows || interleaved) && odd(rect.min.y, rect.height()) {
+        return fail(format!(
+            "{} needs an even top and height",
+            FourCC::new(fourcc)
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(rect: URect, reason: &str) -> Error {
+    Error::InvalidCrop {
+        rect,
+        reason: reason.to_string(),
+    }
+}
+
+/// The rectangle of `target` of the capture crop of `dev`
+fn selection(dev: &v4l::Device, target: u32) -> io::Result<URect> {
+    let mut selection: Selection = unsafe { mem::zeroed() };
+    selection.typ = BUF_TYPE_CAPTURE;
+    selection.target = target;
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            VIDIOC_G_SELECTION,
+            &mut selection as *mut Selection as *mut c_void,
+        )?;
+    }
+    Ok(from_v4l2(selection.r))
+}
+
+/// Crops the frames of `dev` to `rect`, returns the rectangle the driver adjusted it to
+fn select(dev: &v4l::Device, rect: URect) -> io::Result<URect> {
+    let mut selection: Selection = unsafe { mem::zeroed() };
+    selection.typ = BUF_TYPE_CAPTURE;
+    selection.target = TARGET_CROP;
+    selection.r = Rect {
+        left: rect.min.x as i32,
+        top: rect.min.y as i32,
+        width: rect.width(),
+        height: rect.height(),
+    };
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            VIDIOC_S_SELECTION,
+            &mut selection as *mut Selection as *mut c_void,
+        )?;
+    }
+    Ok(from_v4l2(selection.r))
+}
+
+/// Rectangles of some sensors start left of or above the active pixels
+fn from_v4l2(rect: Rect) -> URect {
+    let (left, top) = (rect.left.max(0) as u32, rect.top.max(0) as u32);
+    URect::new(left, top, left + rect.width, top + rect.height)
+}
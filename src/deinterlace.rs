@@ -0,0 +1,117 @@
+use v4l::format::FieldOrder;
+
+/// How an [`Input`](crate::Input) of an interlaced format combines the two fields of a
+/// frame, see [`InputBuilder::deinterlace`](crate::InputBuilder::deinterlace).
+/// Progressive formats are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Deinterlace {
+    /// Interleaves the lines of both fields into full resolution frames, moving
+    /// objects comb. Frames of devices that alternate fields are made of the latest
+    /// two, every field updates its lines.
+    #[default]
+    Weave,
+    /// Shows one field with every line doubled. Halves the vertical resolution, but
+    /// nothing combs. Devices that alternate fields show every field, the others the
+    /// top one.
+    Bob,
+}
+
+/// Deinterlaces the converted frames of an input for the field order of its format
+pub(crate) struct Deinterlacer {
+    pub(crate) mode: Deinterlace,
+    pub(crate) order: FieldOrder,
+    /// Latest two fields of devices that alternate them, woven
+    woven: Vec<u8>,
+    /// Copy of a frame while its sequential fields are woven
+    scratch: Vec<u8>,
+}
+
+impl Deinterlacer {
+    pub(crate) fn new(mode: Deinterlace, order: FieldOrder) -> Self {
+        Self {
+            mode,
+            order,
+            woven: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Lines of the buffers the device fills, a field of frames `height` high for
+    /// devices that alternate fields
+    pub(crate) fn lines(&self, height: u32) -> u32 {
+        match self.order {
+            FieldOrder::Alternate => height / 2,
+            _ => height,
+        }
+    }
+
+    /// Whether [`Deinterlacer::apply`] changes the frames of the field order
+    pub(crate) fn changes_frames(&self) -> bool {
+        match self.order {
+            FieldOrder::Alternate | FieldOrder::SequentialTB | FieldOrder::SequentialBT => true,
+            FieldOrder::Interlaced | FieldOrder::InterlacedTB | FieldOrder::InterlacedBT => {
+                self.mode == Deinterlace::Bob
+            }
+            _ => false,
+        }
+    }
+
+    /// Deinterlaces a `frame` of rows `row` bytes long in place. Devices that alternate
+    /// fields hold the field in the first [`Deinterlacer::lines`] rows, `bottom` tells
+    /// which one it is.
+    pub(crate) fn apply(&mut self, frame: &mut [u8], row: usize, bottom: bool) {
+        let rows = frame.len() / row;
+        match (self.order, self.mode) {
+            (FieldOrder::Alternate, Deinterlace::Weave) => {
+                if self.woven.len() != frame.len() {
+                    self.woven = frame.to_vec();
+                }
+                let lines = self.woven.chunks_exact_mut(row).skip(bottom as usize);
+                for (line, field) in lines.step_by(2).zip(frame.chunks_exact(row)) {
+                    line.copy_from_slice(field);
+                }
+                frame.copy_from_slice(&self.woven);
+            }
+            (
+                FieldOrder::Interlaced | FieldOrder::InterlacedTB | FieldOrder::InterlacedBT,
+                Deinterlace::Bob,
+            ) => {
+                for pair in frame.chunks_exact_mut(row * 2) {
+                    let (top, bottom) = pair.split_at_mut(row);
+                    bottom.copy_from_slice(top);
+                }
+            }
+            (FieldOrder::SequentialTB | FieldOrder::SequentialBT, Deinterlace::Weave) => {
+                self.scratch.clear();
+                self.scratch.extend_from_slice(frame);
+                let (first, second) = self.scratch.split_at(rows / 2 * row);
+                let (top, bottom) = match self.order {
+                    FieldOrder::SequentialBT => (second, first),
+                    _ => (first, second),
+                };
+                for (index, line) in frame.chunks_exact_mut(row).enumerate() {
+                    let field = if index % 2 == 0 { top } else { bottom };
+                    let start = index / 2 * row;
+                    if let Some(field) = field.get(start..start + row) {
+                        line.copy_from_slice(field);
+                    }
+                }
+            }
+            (
+                FieldOrder::Alternate | FieldOrder::SequentialTB | FieldOrder::SequentialBT,
+                Deinterlace::Bob,
+            ) => double(frame, row, rows / 2),
+            _ => {}
+        }
+    }
+}
+
+/// Spreads the first `lines` rows of `frame` over twice as many, from the bottom up so
+/// no row is overwritten before it was copied
+fn double(frame: &mut [u8], row: usize, lines: usize) {
+    for line in (0..lines).rev() {
+        frame.copy_within(line * row..(line + 1) * row, 2 * line * row);
+        frame.copy_within(2 * line * row..(2 * line + 1) * row, (2 * line + 1) * row);
+    }
+}
@@ -0,0 +1,78 @@
+/// Mean difference of a sampled pixel, in 8 bit levels, above which a frame
+/// counts as a scene cut and replaces the average instead of being blended in
+const SCENE_CUT: u32 = 48;
+
+/// Only every SAMPLE_STEP-th byte is compared when looking for scene cuts
+const SAMPLE_STEP: usize = 61;
+
+/// Exponential moving average over converted frames, see
+/// [`InputBuilder::denoise`](crate::InputBuilder::denoise)
+pub(crate) struct TemporalFilter {
+    /// Weight of the average against a new frame, 0 to 1
+    strength: f32,
+    /// Running average of every rgb byte, 8.8 fixed point.
+    /// Empty until the first frame, or after a reset.
+    average: Vec<u16>,
+}
+
+impl TemporalFilter {
+    pub(crate) fn new(strength: f32) -> Self {
+        Self {
+            strength: strength.clamp(0.0, 1.0),
+            average: Vec::new(),
+        }
+    }
+
+    /// Keeps the average, so changing the strength doesn't flash
+    pub(crate) fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+
+    /// Starts over from the next frame
+    pub(crate) fn reset(&mut self) {
+        self.average.clear();
+    }
+
+    /// Blends the rgba `frame` into the average and replaces it with the result
+    pub(crate) fn apply(&mut self, frame: &mut [u8]) {
+        if self.average.len() != frame.len() || self.is_scene_cut(frame) {
+            self.average.clear();
+            self.average
+                .extend(frame.iter().map(|&value| (value as u16) << 8));
+            return;
+        }
+
+        // fraction of the new frame in the result, 1 to 256
+        let weight = ((1.0 - self.strength) * 256.0).round().max(1.0) as i32;
+
+        for (pixel, average) in frame
+            .chunks_exact_mut(4)
+            .zip(self.average.chunks_exact_mut(4))
+        {
+            // alpha is left alone
+            for (value, average) in pixel[..3].iter_mut().zip(&mut average[..3]) {
+                let target = (*value as i32) << 8;
+                let current = *average as i32;
+                let next = current + (((target - current) * weight) >> 8);
+
+                *average = next as u16;
+                *value = ((next + 128) >> 8).min(255) as u8;
+            }
+        }
+    }
+
+    fn is_scene_cut(&self, frame: &[u8]) -> bool {
+        let (difference, samples) = frame.iter().zip(&self.average).step_by(SAMPLE_STEP).fold(
+            (0, 0),
+            |(difference, samples), (&value, &average)| {
+                let average = (average >> 8) as i32;
+                (
+                    difference + (value as i32 - average).unsigned_abs(),
+                    samples + 1,
+                )
+            },
+        );
+
+        samples > 0 && difference / samples > SCENE_CUT
+    }
+}
@@ -0,0 +1,486 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::{Component, Resource};
+use v4l::capability::Flags;
+use v4l::video::Capture;
+
+use crate::{can_decode, Error, Result, V4lDeviceEvent};
+
+/// A v4l device node found on the system
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// ID of the v4l video device (/dev/video{id})
+    pub id: usize,
+    pub path: PathBuf,
+    /// Name of the device as reported by the driver
+    pub card: String,
+    pub driver: String,
+    pub bus_info: String,
+    /// Node can capture video frames
+    pub capture: bool,
+    /// Node can output video frames
+    pub output: bool,
+    /// Node only carries metadata, UVC creates one of these next to every camera
+    pub metadata: bool,
+    /// Memory-to-memory node, like a hardware scaler or codec
+    pub m2m: bool,
+}
+
+impl DeviceInfo {
+    /// Queries the capabilities of a single device node
+    pub fn query(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let dev = v4l::Device::with_path(path)?;
+        Self::from_device(id, path, &dev)
+    }
+
+    pub(crate) fn from_device(id: usize, path: &Path, dev: &v4l::Device) -> Result<Self> {
+        let caps = dev.query_caps()?;
+        let flags = caps.capabilities;
+
+        Ok(Self {
+            id,
+            path: path.to_path_buf(),
+            card: caps.card,
+            driver: caps.driver,
+            bus_info: caps.bus,
+            capture: flags.intersects(Flags::VIDEO_CAPTURE | Flags::VIDEO_CAPTURE_MPLANE),
+            output: flags.intersects(Flags::VIDEO_OUTPUT | Flags::VIDEO_OUTPUT_MPLANE),
+            metadata: flags.contains(Flags::META_CAPTURE),
+            m2m: flags.contains(Flags::VIDEO_M2M),
+        })
+    }
+
+    /// Card name followed by the device path, used in error messages
+    fn describe(&self) -> String {
+        format!("{} ({})", self.card, self.path.display())
+    }
+}
+
+/// What a v4l device reports about itself, see [`Input::query_caps`](crate::Input::query_caps)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub driver: String,
+    pub card: String,
+    pub bus_info: String,
+    /// Kernel version of the driver as (major, minor, patch)
+    pub version: (u8, u8, u8),
+    pub capture: bool,
+    pub output: bool,
+    /// Only supports the multi-planar api. Inputs and outputs stream these with it,
+    /// devices that support both apis are streamed single planar.
+    pub mplane: bool,
+    pub m2m: bool,
+    pub metadata: bool,
+    /// Supports streaming io, which the crate uses
+    pub streaming: bool,
+    /// Supports read() and write()
+    pub read_write: bool,
+    /// Node of the v4l2loopback driver, like a virtual camera
+    pub loopback: bool,
+}
+
+impl Capabilities {
+    pub(crate) fn query(dev: &v4l::Device) -> Result<Self> {
+        let caps = dev.query_caps()?;
+        let flags = caps.capabilities;
+        let single = Flags::VIDEO_CAPTURE | Flags::VIDEO_OUTPUT | Flags::VIDEO_M2M;
+        let multi =
+            Flags::VIDEO_CAPTURE_MPLANE | Flags::VIDEO_OUTPUT_MPLANE | Flags::VIDEO_M2M_MPLANE;
+
+        Ok(Self {
+            loopback: caps.driver == "v4l2 loopback",
+            driver: caps.driver,
+            card: caps.card,
+            bus_info: caps.bus,
+            version: caps.version,
+            capture: flags.intersects(Flags::VIDEO_CAPTURE | Flags::VIDEO_CAPTURE_MPLANE),
+            output: flags.intersects(Flags::VIDEO_OUTPUT | Flags::VIDEO_OUTPUT_MPLANE),
+            mplane: flags.intersects(multi) && !flags.intersects(single),
+            m2m: flags.intersects(Flags::VIDEO_M2M | Flags::VIDEO_M2M_MPLANE),
+            metadata: flags.contains(Flags::META_CAPTURE),
+            streaming: flags.contains(Flags::STREAMING),
+            read_write: flags.contains(Flags::READ_WRITE),
+        })
+    }
+}
+
+/// Lists the v4l device nodes on the system, ordered by id.
+/// Nodes that cannot be queried are skipped.
+pub fn enumerate_devices() -> Vec<DeviceInfo> {
+    let mut devices: Vec<_> = v4l::context::enum_devices()
+        .into_iter()
+        .filter_map(|node| DeviceInfo::query(node.index(), node.path()).ok())
+        .collect();
+
+    devices.sort_by_key(|info| info.id);
+    devices
+}
+
+/// The v4l device nodes on the system, listed by [`V4lPlugin`](crate::V4lPlugin) at
+/// startup, for camera pickers. Nodes are only queried for their capabilities, which
+/// doesn't disturb devices that are streaming.
+///
+/// The plugin keeps it up to date as devices are plugged in and out, see
+/// [`V4lDeviceEvent`]. [`V4lDevices::refresh`] rescans right away.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct V4lDevices {
+    devices: Vec<DeviceInfo>,
+}
+
+impl V4lDevices {
+    pub fn scan() -> Self {
+        Self {
+            devices: enumerate_devices(),
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        self.devices = enumerate_devices();
+    }
+
+    /// Every node, ordered by id
+    pub fn all(&self) -> &[DeviceInfo] {
+        &self.devices
+    }
+
+    /// Nodes an [`Input`](crate::Input) can capture from, without the metadata and m2m ones
+    pub fn capture(&self) -> impl Iterator<Item = &DeviceInfo> {
+        self.devices
+            .iter()
+            .filter(|info| info.capture && !info.metadata && !info.m2m)
+    }
+
+    /// Nodes an [`Output`](crate::Output) can write to
+    pub fn output(&self) -> impl Iterator<Item = &DeviceInfo> {
+        self.devices.iter().filter(|info| info.output && !info.m2m)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&DeviceInfo> {
+        self.devices.iter().find(|info| info.id == id)
+    }
+
+    /// Adds or removes the node of a hotplug event, keeping the nodes ordered by id
+    pub(crate) fn apply(&mut self, event: &V4lDeviceEvent) {
+        let info = event.info();
+        self.devices.retain(|known| known.id != info.id);
+        if let V4lDeviceEvent::Connected(info) = event {
+            let index = self.devices.partition_point(|known| known.id < info.id);
+            self.devices.insert(index, info.clone());
+        }
+    }
+}
+
+/// Capture devices that stream at least one format the crate can convert, ordered by id.
+/// Metadata and m2m nodes are skipped.
+pub(crate) fn default_candidates() -> Vec<DeviceInfo> {
+    enumerate_devices()
+        .into_iter()
+        .filter(|info| info.capture && !info.metadata && !info.m2m)
+        .filter(|info| {
+            let Ok(dev) = v4l::Device::with_path(&info.path) else {
+                return false;
+            };
+            dev.enum_formats()
+                .is_ok_and(|formats| formats.iter().any(|format| can_decode(&format.fourcc.repr)))
+        })
+        .collect()
+}
+
+/// Finds the capture device whose card name contains `name` or whose bus info is
+/// `name`, ignoring case. Metadata nodes and nodes that cannot capture are never matched.
+///
+/// When several devices match, one whose card name or bus info is exactly `name` wins,
+/// so identical cameras can be told apart by their bus info. Otherwise `first` picks
+/// the one with the lowest id instead of failing.
+pub(crate) fn find_by_name<'a>(
+    devices: &'a [DeviceInfo],
+    name: &str,
+    first: bool,
+) -> Result<&'a DeviceInfo> {
+    let needle = name.to_lowercase();
+    let capture = devices.iter().filter(|info| info.capture && !info.metadata);
+
+    let exact = |info: &&DeviceInfo| {
+        info.card.to_lowercase() == needle || info.bus_info.to_lowercase() == needle
+    };
+    let mut matches: Vec<_> = capture
+        .clone()
+        .filter(|info| info.card.to_lowercase().contains(&needle) || exact(info))
+        .collect();
+    if matches.len() > 1 && matches.iter().filter(|info| exact(info)).count() == 1 {
+        matches.retain(exact);
+    }
+
+    match matches.as_slice() {
+        [] => Err(Error::NoMatchingDevice {
+            name: name.to_string(),
+            available: capture.map(DeviceInfo::describe).collect(),
+        }),
+        [info] => Ok(*info),
+        [info, ..] if first => Ok(*info),
+        _ => Err(Error::AmbiguousDevice {
+            name: name.to_string(),
+            candidates: matches.iter().map(|info| info.describe()).collect(),
+        }),
+    }
+}
+
+/// Identifies a v4l device to open
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeviceSelector {
+    /// ID of the v4l video device (/dev/video{id})
+    Index(usize),
+    /// Path to the device node, including udev symlinks like /dev/v4l/by-id/*
+    Path(PathBuf),
+    /// Case-insensitive substring of the card name, or the bus info like
+    /// "usb-0000:00:14.0-1". The lowest numbered capture device wins when several match.
+    Name(String),
+}
+
+impl DeviceSelector {
+    pub fn name(name: impl Into<String>) -> Self {
+        Self::Name(name.into())
+    }
+
+    /// Opens the selected device node, returning it with its id
+    pub(crate) fn open(&self) -> Result<(v4l::Device, usize)> {
+        match self {
+            Self::Index(id) => Ok((open_path(&crate::device_path(*id))?, *id)),
+            Self::Path(path) => {
+                let dev = open_path(path)?;
+                let id = index_from_path(path).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is not a v4l device node", path.display()),
+                    )
+                })?;
+                Ok((dev, id))
+            }
+            Self::Name(name) => {
+                let devices = enumerate_devices();
+                let info = find_by_name(&devices, name, true)?;
+                Ok((open_path(&info.path)?, info.id))
+            }
+        }
+    }
+}
+
+/// Opens the device node at `path`. Errors like ENOENT don't name the path
+/// themselves, the common ones get variants that do.
+pub(crate) fn open_path(path: &Path) -> Result<v4l::Device> {
+    v4l::Device::with_path(path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => Error::DeviceNotFound {
+            path: path.to_path_buf(),
+        },
+        std::io::ErrorKind::PermissionDenied => Error::PermissionDenied {
+            path: path.to_path_buf(),
+            source: err,
+        },
+        _ => match crate::busy::check(err, path) {
+            Error::Io(err) => {
+                std::io::Error::new(err.kind(), format!("{}: {err}", path.display())).into()
+            }
+            err => err,
+        },
+    })
+}
+
+impl DeviceSelector {
+    /// Path of the node opened as `id`, selected paths are kept as they are so logs
+    /// show the udev symlinks users picked
+    pub(crate) fn node_path(&self, id: usize) -> PathBuf {
+        match self {
+            Self::Path(path) => path.clone(),
+            _ => crate::device_path(id),
+        }
+    }
+}
+
+impl From<usize> for DeviceSelector {
+    fn from(id: usize) -> Self {
+        Self::Index(id)
+    }
+}
+
+impl From<PathBuf> for DeviceSelector {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for DeviceSelector {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_path_buf())
+    }
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(id) => write!(f, "/dev/video{id}"),
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Name(name) => write!(f, "\"{name}\""),
+        }
+    }
+}
+
+/// Outcome of opening a device from a list of [`DeviceSelector`]s
+#[derive(Component, Debug)]
+pub struct Selection {
+    /// Selector of the device that was opened
+    pub selector: DeviceSelector,
+    /// Selectors tried before it, with the reason each one was skipped
+    pub skipped: Vec<(DeviceSelector, Error)>,
+}
+
+/// Resolves symlinks and extracts N from /dev/videoN
+fn index_from_path(path: &Path) -> Option<usize> {
+    let path = path.canonicalize().ok()?;
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("video")?
+        .parse()
+        .ok()
+}
+
+/// Joins the reasons devices failed to open, used in error messages
+pub(crate) fn describe_failures(failures: &[(DeviceSelector, Error)]) -> String {
+    failures
+        .iter()
+        .map(|(selector, err)| format!("{selector}: {err}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Node of the enumeration, UVC cameras come with a metadata node after them
+    fn node(id: usize, card: &str, bus_info: &str) -> DeviceInfo {
+        DeviceInfo {
+            id,
+            path: crate::device_path(id),
+            card: card.to_string(),
+            driver: "uvcvideo".to_string(),
+            bus_info: bus_info.to_string(),
+            capture: true,
+            output: false,
+            metadata: false,
+            m2m: false,
+        }
+    }
+
+    fn metadata(id: usize, card: &str, bus_info: &str) -> DeviceInfo {
+        DeviceInfo {
+            metadata: true,
+            ..node(id, card, bus_info)
+        }
+    }
+
+    /// Two identical webcams, a capture card and a hardware scaler
+    fn enumeration() -> Vec<DeviceInfo> {
+        vec![
+            node(0, "HD Webcam C270", "usb-0000:00:14.0-1"),
+            metadata(1, "HD Webcam C270", "usb-0000:00:14.0-1"),
+            node(2, "HD Webcam C270", "usb-0000:00:14.0-2"),
+            metadata(3, "HD Webcam C270", "usb-0000:00:14.0-2"),
+            node(4, "USB3 Video Capture", "usb-0000:00:14.0-3"),
+            DeviceInfo {
+                output: true,
+                m2m: true,
+                driver: "bcm2835-codec".to_string(),
+                ..node(10, "bcm2835-codec-isp", "platform:bcm2835-codec")
+            },
+        ]
+    }
+
+    #[test]
+    fn names_match_card_substrings_ignoring_case() {
+        let devices = enumeration();
+        assert_eq!(find_by_name(&devices, "usb3 video", false).unwrap().id, 4);
+        assert_eq!(find_by_name(&devices, "CAPTURE", false).unwrap().id, 4);
+    }
+
+    #[test]
+    fn bus_info_tells_identical_cameras_apart() {
+        let devices = enumeration();
+        let second = find_by_name(&devices, "USB-0000:00:14.0-2", false).unwrap();
+        assert_eq!(second.id, 2);
+        // bus info only matches whole, never as a substring
+        assert!(find_by_name(&devices, "14.0-2", false).is_err());
+    }
+
+    #[test]
+    fn ambiguous_names_fail_unless_the_first_is_taken() {
+        let devices = enumeration();
+        match find_by_name(&devices, "c270", false) {
+            Err(Error::AmbiguousDevice { candidates, .. }) => assert_eq!(
+                candidates,
+                [
+                    "HD Webcam C270 (/dev/video0)",
+                    "HD Webcam C270 (/dev/video2)"
+                ]
+            ),
+            other => panic!("expected an ambiguous name, got {other:?}"),
+        }
+        assert_eq!(find_by_name(&devices, "c270", true).unwrap().id, 0);
+    }
+
+    #[test]
+    fn an_exact_card_name_wins_over_substrings() {
+        let mut devices = enumeration();
+        devices.push(node(6, "HD Webcam C270 Pro", "usb-0000:00:14.0-4"));
+        devices.retain(|info| info.id != 2);
+        let exact = find_by_name(&devices, "hd webcam c270", false).unwrap();
+        assert_eq!(exact.id, 0);
+    }
+
+    #[test]
+    fn metadata_nodes_are_never_matched() {
+        let devices = enumeration();
+        match find_by_name(&devices, "scaler", false) {
+            Err(Error::NoMatchingDevice { available, .. }) => assert_eq!(
+                available,
+                [
+                    "HD Webcam C270 (/dev/video0)",
+                    "HD Webcam C270 (/dev/video2)",
+                    "USB3 Video Capture (/dev/video4)",
+                    "bcm2835-codec-isp (/dev/video10)",
+                ]
+            ),
+            other => panic!("expected no match, got {other:?}"),
+        }
+        let only_metadata = [metadata(1, "HD Webcam C270", "usb-0000:00:14.0-1")];
+        assert!(find_by_name(&only_metadata, "c270", true).is_err());
+    }
+
+    #[test]
+    fn capture_and_output_lists_skip_other_nodes() {
+        let devices = V4lDevices {
+            devices: enumeration(),
+        };
+        let capture: Vec<_> = devices.capture().map(|info| info.id).collect();
+        assert_eq!(capture, [0, 2, 4]);
+        assert_eq!(devices.output().count(), 0);
+        assert_eq!(devices.get(10).map(|info| info.m2m), Some(true));
+    }
+
+    #[test]
+    fn hotplug_events_keep_the_nodes_ordered() {
+        let mut devices = V4lDevices {
+            devices: enumeration(),
+        };
+        devices.apply(&V4lDeviceEvent::Connected(node(5, "Cam Link 4K", "usb-2")));
+        devices.apply(&V4lDeviceEvent::Disconnected(node(2, "", "")));
+        let ids: Vec<_> = devices.all().iter().map(|info| info.id).collect();
+        assert_eq!(ids, [0, 1, 3, 4, 5, 10]);
+
+        // a node reconnected with another card replaces the previous one
+        devices.apply(&V4lDeviceEvent::Connected(node(4, "Capture 2", "usb-3")));
+        assert_eq!(devices.all().len(), 6);
+        assert_eq!(devices.get(4).unwrap().card, "Capture 2");
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+use bevy::diagnostic::{Diagnostic, DiagnosticMeasurement, DiagnosticPath, DiagnosticsStore};
+use bevy::prelude::*;
+
+use crate::Input;
+
+/// Frame rates are measured over at least this long, a measurement per frame would
+/// be 0 or the rate of a single frame
+const RATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Diagnostics of a device, see [`V4lConfig::diagnostics`](crate::V4lConfig::diagnostics).
+/// Counted by the io tasks and added to the [`DiagnosticsStore`] by `poll_io_tasks`.
+pub(crate) struct Recorder {
+    kind: Kind,
+    id: usize,
+    /// Built once the entity of the device is known
+    paths: Option<Paths>,
+    /// Frames converted or written since `since`
+    frames: u32,
+    /// Frames dropped since `since`, requeued stale or corrupt ones
+    dropped: u32,
+    since: Instant,
+    /// Time the io task spent converting its latest frame
+    pub(crate) conversion: Option<Duration>,
+    /// Rate an output is paced to, set before every measurement
+    pub(crate) target_fps: Option<f32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Input,
+    Output,
+}
+
+struct Paths {
+    fps: DiagnosticPath,
+    dropped: DiagnosticPath,
+    conversion: DiagnosticPath,
+    target_fps: DiagnosticPath,
+}
+
+impl Recorder {
+    /// Recorder of an input of the device `id`, `None` while diagnostics are off
+    pub(crate) fn input(id: usize) -> Option<Self> {
+        Self::new(Kind::Input, id)
+    }
+
+    /// Like [`Recorder::input`] for outputs, which measure their frame rate and the
+    /// rate they are paced to
+    pub(crate) fn output(id: usize) -> Option<Self> {
+        Self::new(Kind::Output, id)
+    }
+
+    fn new(kind: Kind, id: usize) -> Option<Self> {
+        crate::config::current().diagnostics.then(|| Self {
+            kind,
+            id,
+            paths: None,
+            frames: 0,
+            dropped: 0,
+            since: Instant::now(),
+            conversion: None,
+            target_fps: None,
+        })
+    }
+
+    /// Counts a converted or written frame, and the ones dropped before it
+    pub(crate) fn frame(&mut self, dropped: u32) {
+        self.frames += 1;
+        self.dropped += dropped;
+    }
+
+    /// Adds the measurements since the last one to `store`, registering the diagnostics
+    /// of the device with the first
+    pub(crate) fn record(&mut self, entity: Entity, store: &mut DiagnosticsStore) {
+        let paths = self
+            .paths
+            .get_or_insert_with(|| Paths::new(self.kind, self.id, entity));
+        if store.get(&paths.fps).is_none() {
+            store.add(Diagnostic::new(paths.fps.clone()).with_suffix(" fps"));
+            match self.kind {
+                Kind::Input => {
+                    store.add(Diagnostic::new(paths.dropped.clone()).with_suffix("/s"));
+                    store.add(Diagnostic::new(paths.conversion.clone()).with_suffix(" ms"));
+                }
+                Kind::Output => {
+                    store.add(Diagnostic::new(paths.target_fps.clone()).with_suffix(" fps"));
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(conversion) = self.conversion.take() {
+            measure(
+                store,
+                &paths.conversion,
+                now,
+                conversion.as_secs_f64() * 1000.0,
+            );
+        }
+
+        let elapsed = now - self.since;
+        if elapsed < RATE_INTERVAL {
+            return;
+        }
+        let rate = |count: u32| count as f64 / elapsed.as_secs_f64();
+        measure(store, &paths.fps, now, rate(self.frames));
+        measure(store, &paths.dropped, now, rate(self.dropped));
+        if let Some(fps) = self.target_fps {
+            measure(store, &paths.target_fps, now, fps as f64);
+        }
+        self.frames = 0;
+        self.dropped = 0;
+        self.since = now;
+    }
+}
+
+impl Paths {
+    /// Paths like `v4l/input0/fps`, virtual inputs are told apart by their entity
+    fn new(kind: Kind, id: usize, entity: Entity) -> Self {
+        let device = match (kind, id) {
+            (Kind::Input, Input::VIRTUAL_ID) => format!("v4l/virtual{}", entity.index()),
+            (Kind::Input, id) => format!("v4l/input{id}"),
+            (Kind::Output, id) => format!("v4l/output{id}"),
+        };
+        Self {
+            fps: DiagnosticPath::new(format!("{device}/fps")),
+            dropped: DiagnosticPath::new(format!("{device}/dropped")),
+            conversion: DiagnosticPath::new(format!("{device}/conversion")),
+            target_fps: DiagnosticPath::new(format!("{device}/target_fps")),
+        }
+    }
+}
+
+/// Diagnostics the app disabled, or that aren't registered for the kind of device,
+/// take no measurements
+fn measure(store: &mut DiagnosticsStore, path: &DiagnosticPath, time: Instant, value: f64) {
+    if let Some(diagnostic) = store
+        .get_mut(path)
+        .filter(|diagnostic| diagnostic.is_enabled)
+    {
+        diagnostic.add_measurement(DiagnosticMeasurement { time, value });
+    }
+}
@@ -0,0 +1,89 @@
+/// How converters reduce sources with more than 8 bits per sample to the 8 bit image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Drop the low bits, smooth gradients show bands
+    None,
+    /// Add a 4x4 Bayer pattern before dropping the low bits
+    #[default]
+    Ordered,
+}
+
+/// 4x4 Bayer matrix, thresholds in sixteenths of an 8 bit step
+const BAYER: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+impl Dither {
+    /// Reduces the 16 bit `value` of the pixel at `x`, `y` to 8 bits
+    #[inline]
+    pub(crate) fn reduce(self, value: u16, x: usize, y: usize) -> u8 {
+        let threshold = match self {
+            Self::None => 0,
+            // centered in its sixteenth, so the mean is kept
+            Self::Ordered => BAYER[y % 4][x % 4] * 16 + 8,
+        };
+
+        ((value as u32 + threshold as u32) >> 8).min(255) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 256;
+    const HEIGHT: usize = 8;
+
+    /// A 16 bit gradient rising by 3 8 bit steps across the frame, too slow for 8 bits
+    fn gradient() -> Vec<u16> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| 100 * 256 + (i % WIDTH * 3 * 256 / WIDTH) as u16)
+            .collect()
+    }
+
+    /// Mean distance in 8 bit steps between the 4x4 blocks of `reduced` and of the source
+    fn block_error(src: &[u16], reduced: &[u8]) -> f64 {
+        let mut error = 0.0;
+        for y in (0..HEIGHT).step_by(4) {
+            for x in (0..WIDTH).step_by(4) {
+                let block = (y..y + 4).flat_map(|y| (x..x + 4).map(move |x| y * WIDTH + x));
+                let (mut source, mut output) = (0.0, 0.0);
+                for i in block {
+                    source += src[i] as f64 / 256.0;
+                    output += reduced[i] as f64;
+                }
+                error += (source - output).abs() / 16.0;
+            }
+        }
+        error / (WIDTH * HEIGHT / 16) as f64
+    }
+
+    #[test]
+    fn ordered_dither_tracks_a_shallow_gradient() {
+        let src = gradient();
+        let truncated: Vec<_> = src
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Dither::None.reduce(value, i % WIDTH, i / WIDTH))
+            .collect();
+
+        // through the Y16 converter, which dithers by default
+        let bytes: Vec<_> = src.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let mut rgba = vec![0; WIDTH * HEIGHT * 4];
+        crate::convert_frame(*b"Y16 ", WIDTH as u32, &bytes, &mut rgba).unwrap();
+        let dithered: Vec<_> = rgba.chunks_exact(4).map(|pixel| pixel[0]).collect();
+
+        let (truncated, dithered) = (block_error(&src, &truncated), block_error(&src, &dithered));
+        // truncation is half a step too dark on average, the pattern a fraction of one
+        assert!(truncated > 0.4, "{truncated}");
+        assert!(dithered < truncated / 4.0, "{dithered} against {truncated}");
+    }
+
+    #[test]
+    fn ordered_dither_keeps_the_extremes() {
+        for x in 0..4 {
+            for y in 0..4 {
+                assert_eq!(Dither::Ordered.reduce(0, x, y), 0);
+                assert_eq!(Dither::Ordered.reduce(u16::MAX, x, y), 255);
+            }
+        }
+    }
+}
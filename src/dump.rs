@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::Result;
+
+/// Starts every frame header in a dump
+pub(crate) const FRAME_MAGIC: [u8; 4] = *b"V4LF";
+
+/// Length of a frame header, the frame bytes follow it
+pub(crate) const HEADER_LEN: usize = 36;
+
+/// Frames waiting for the writer thread before new ones are dropped
+const MAX_QUEUED_FRAMES: usize = 8;
+
+/// How much of the stream a recording keeps, see
+/// [`Input::start_recording`](crate::Input::start_recording)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Every frame until the recording is stopped
+    Continuous,
+    /// The latest `max_frames`, kept in memory and written once the recording is
+    /// stopped. For catching a glitch after it happened.
+    Ring { max_frames: usize },
+}
+
+/// Writes dequeued buffers to a file on a separate thread.
+///
+/// Every frame starts with a little endian header:
+/// `"V4LF"`, fourcc, width, height, stride, bytesused and sequence as `u32`,
+/// and the timestamp in microseconds as `u64`, followed by `bytesused` bytes.
+pub(crate) struct Dumper {
+    format: v4l::Format,
+    frames: SyncSender<Vec<u8>>,
+    /// Frames left to write, `None` until the dump is dropped
+    remaining: Option<usize>,
+}
+
+impl Dumper {
+    /// Dump of the first `max_frames` buffers
+    pub(crate) fn new(path: &Path, format: v4l::Format, max_frames: usize) -> Result<Self> {
+        Self::spawn(path, format, Some(max_frames), None)
+    }
+
+    /// Dump with the buffers of `mode`, until it is dropped
+    pub(crate) fn recording(path: &Path, format: v4l::Format, mode: RecordMode) -> Result<Self> {
+        match mode {
+            RecordMode::Continuous => Self::spawn(path, format, None, None),
+            RecordMode::Ring { max_frames } => {
+                Self::spawn(path, format, None, Some(max_frames.max(1)))
+            }
+        }
+    }
+
+    fn spawn(
+        path: &Path,
+        format: v4l::Format,
+        remaining: Option<usize>,
+        ring: Option<usize>,
+    ) -> Result<Self> {
+        let file = File::create(path)?;
+        let (frames, receiver) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+
+        let path = path.display().to_string();
+        std::thread::Builder::new()
+            .name("v4l dump".to_string())
+            .spawn({
+                let span = tracing::Span::current();
+                move || span.in_scope(|| write_frames(BufWriter::new(file), receiver, &path, ring))
+            })?;
+
+        Ok(Self {
+            format,
+            frames,
+            remaining,
+        })
+    }
+
+    /// Queues a frame for writing.
+    /// Returns false once the dump is finished and can be dropped.
+    pub(crate) fn push(&mut self, buf: &[u8], sequence: u32, timestamp: Duration) -> bool {
+        if self.remaining == Some(0) {
+            return false;
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + buf.len());
+        frame.extend_from_slice(&FRAME_MAGIC);
+        frame.extend_from_slice(&self.format.fourcc.repr);
+        for value in [
+            self.format.width,
+            self.format.height,
+            self.format.stride,
+            buf.len() as u32,
+            sequence,
+        ] {
+            frame.extend_from_slice(&value.to_le_bytes());
+        }
+        frame.extend_from_slice(&(timestamp.as_micros() as u64).to_le_bytes());
+        frame.extend_from_slice(buf);
+
+        match self.frames.try_send(frame) {
+            Ok(()) => match self.remaining.as_mut() {
+                Some(remaining) => {
+                    *remaining -= 1;
+                    *remaining > 0
+                }
+                None => true,
+            },
+            Err(TrySendError::Full(_)) => {
+                warn!("v4l dump can't keep up, dropping frame {sequence}");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Writes the frames as they come, or the latest `ring` of them once the dump is dropped
+fn write_frames(
+    mut file: BufWriter<File>,
+    frames: Receiver<Vec<u8>>,
+    path: &str,
+    ring: Option<usize>,
+) {
+    let mut written = 0;
+    let mut kept = VecDeque::with_capacity(ring.unwrap_or(0));
+
+    let mut write = |frame: Vec<u8>| match file.write_all(&frame) {
+        Ok(()) => {
+            written += 1;
+            true
+        }
+        Err(err) => {
+            warn!("failed to write v4l dump {path}: {err}");
+            false
+        }
+    };
+    for frame in frames {
+        match ring {
+            Some(max_frames) => {
+                if kept.len() == max_frames {
+                    kept.pop_front();
+                }
+                kept.push_back(frame);
+            }
+            None => {
+                if !write(frame) {
+                    return;
+                }
+            }
+        }
+    }
+    for frame in kept {
+        if !write(frame) {
+            return;
+        }
+    }
+
+    match file.flush() {
+        Ok(()) => debug!("wrote {written} frames to v4l dump {path}"),
+        Err(err) => warn!("failed to write v4l dump {path}: {err}"),
+    }
+}
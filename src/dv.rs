@@ -0,0 +1,285 @@
+//! Digital video timings and EDID of capture devices with an hdmi or dvi receiver, like
+//! HDMI to CSI bridges. The v4l crate has no wrappers for them, they are set with the
+//! ioctls of the kernel headers.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use bevy::prelude::*;
+use bevy::tasks::block_on;
+use tracing::{debug, info, warn};
+use v4l::v4l2;
+use v4l::v4l2::vidioc::_IOC_TYPE;
+use v4l::video::Capture;
+
+use crate::ioctl::{iowr, BtTimings, RawTimings, VIDIOC_QUERY_DV_TIMINGS, VIDIOC_S_DV_TIMINGS};
+use crate::profile::{self, Profile};
+use crate::source::IoStream;
+use crate::{Error, Format, Input, Result};
+
+const VIDIOC_S_EDID: _IOC_TYPE = iowr(b'V', 41, mem::size_of::<Edid>());
+
+/// V4L2_DV_BT_656_1120, the only type of timings the kernel defines
+const DV_BT_656_1120: u32 = 0;
+/// Bytes of an EDID block
+const EDID_BLOCK: usize = 128;
+/// Receivers fail to query the timings of a source without a signal with ENOLINK
+const ENOLINK: i32 = 67;
+
+/// struct v4l2_edid
+#[repr(C)]
+struct Edid {
+    pad: u32,
+    start_block: u32,
+    blocks: u32,
+    reserved: [u32; 5],
+    edid: *mut u8,
+}
+
+/// Timings of the signal of a digital video receiver, the BT.656/1120 timings of the
+/// kernel, see [`Input::query_dv_timings`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DvTimings {
+    /// Active pixels per line
+    pub width: u32,
+    /// Active lines per frame, both fields of interlaced signals
+    pub height: u32,
+    pub interlaced: bool,
+    /// V4L2_DV_VSYNC_POS_POL (1) and V4L2_DV_HSYNC_POS_POL (2)
+    pub polarities: u32,
+    /// Pixels per second
+    pub pixelclock: u64,
+    pub hfrontporch: u32,
+    pub hsync: u32,
+    pub hbackporch: u32,
+    pub vfrontporch: u32,
+    pub vsync: u32,
+    pub vbackporch: u32,
+    /// Vertical blanking of the bottom field of interlaced signals
+    pub il_vfrontporch: u32,
+    pub il_vsync: u32,
+    pub il_vbackporch: u32,
+    /// V4L2_DV_BT_STD_* bits of the standards the timings belong to, like CEA-861
+    pub standards: u32,
+    /// V4L2_DV_FL_* bits
+    pub flags: u32,
+    /// Aspect ratio of the picture as numerator and denominator, 0 when square
+    pub picture_aspect: (u32, u32),
+    /// Video identification codes of CEA-861 and HDMI, 0 when unknown
+    pub cea861_vic: u8,
+    pub hdmi_vic: u8,
+}
+
+impl DvTimings {
+    /// Frames per second of the signal, fields per second of interlaced ones. `None`
+    /// when the receiver didn't measure the pixel clock.
+    pub fn fps(&self) -> Option<f64> {
+        let total_width = self.width + self.hfrontporch + self.hsync + self.hbackporch;
+        let mut total_height = self.height + self.vfrontporch + self.vsync + self.vbackporch;
+        if self.interlaced {
+            total_height += self.il_vfrontporch + self.il_vsync + self.il_vbackporch;
+        }
+        let pixels = total_width as u64 * total_height as u64;
+        if self.pixelclock == 0 || pixels == 0 {
+            return None;
+        }
+        let fields = if self.interlaced { 2.0 } else { 1.0 };
+        Some(self.pixelclock as f64 / pixels as f64 * fields)
+    }
+
+    fn from_raw(raw: &RawTimings) -> Self {
+        let bt = raw.bt;
+        Self {
+            width: bt.width,
+            height: bt.height,
+            interlaced: bt.interlaced != 0,
+            polarities: bt.polarities,
+            pixelclock: bt.pixelclock,
+            hfrontporch: bt.hfrontporch,
+            hsync: bt.hsync,
+            hbackporch: bt.hbackporch,
+            vfrontporch: bt.vfrontporch,
+            vsync: bt.vsync,
+            vbackporch: bt.vbackporch,
+            il_vfrontporch: bt.il_vfrontporch,
+            il_vsync: bt.il_vsync,
+            il_vbackporch: bt.il_vbackporch,
+            standards: bt.standards,
+            flags: bt.flags,
+            picture_aspect: (bt.picture_aspect[0], bt.picture_aspect[1]),
+            cea861_vic: bt.cea861_vic,
+            hdmi_vic: bt.hdmi_vic,
+        }
+    }
+
+    fn to_raw(self) -> RawTimings {
+        RawTimings {
+            typ: DV_BT_656_1120,
+            bt: BtTimings {
+                width: self.width,
+                height: self.height,
+                interlaced: self.interlaced as u32,
+                polarities: self.polarities,
+                pixelclock: self.pixelclock,
+                hfrontporch: self.hfrontporch,
+                hsync: self.hsync,
+                hbackporch: self.hbackporch,
+                vfrontporch: self.vfrontporch,
+                vsync: self.vsync,
+                vbackporch: self.vbackporch,
+                il_vfrontporch: self.il_vfrontporch,
+                il_vsync: self.il_vsync,
+                il_vbackporch: self.il_vbackporch,
+                standards: self.standards,
+                flags: self.flags,
+                picture_aspect: [self.picture_aspect.0, self.picture_aspect.1],
+                cea861_vic: self.cea861_vic,
+                hdmi_vic: self.hdmi_vic,
+                reserved: [0; 46],
+            },
+            reserved: [0; 1],
+        }
+    }
+}
+
+impl fmt::Display for DvTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scan = if self.interlaced { "i" } else { "p" };
+        write!(f, "{}x{}{scan}", self.width, self.height)?;
+        if let Some(fps) = self.fps() {
+            write!(f, "{fps:.2}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Timings the receiver of `dev` detects on its input
+pub(crate) fn query(dev: &v4l::Device) -> Result<DvTimings> {
+    let mut raw: RawTimings = unsafe { mem::zeroed() };
+    let queried = unsafe {
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            VIDIOC_QUERY_DV_TIMINGS,
+            &mut raw as *mut RawTimings as *mut c_void,
+        )
+    };
+    match queried {
+        Ok(()) => Ok(DvTimings::from_raw(&raw)),
+        Err(err) if err.raw_os_error() == Some(ENOLINK) => Err(Error::NoSignal {
+            reason: "the receiver detects no timings".to_string(),
+        }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Sets the timings of `dev`, only while no buffers are allocated. Returns the timings
+/// the driver took.
+pub(crate) fn apply(dev: &v4l::Device, timings: DvTimings) -> io::Result<DvTimings> {
+    let mut raw = timings.to_raw();
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            VIDIOC_S_DV_TIMINGS,
+            &mut raw as *mut RawTimings as *mut c_void,
+        )?;
+    }
+    Ok(DvTimings::from_raw(&raw))
+}
+
+/// Sets the timings the receiver of `dev` detects, for opening it in the resolution of
+/// the source. `None` when it has no receiver or detects no timings.
+pub(crate) fn detect(dev: &v4l::Device) -> Option<DvTimings> {
+    let detected = match query(dev) {
+        Ok(timings) => timings,
+        Err(err) => {
+            debug!(%err, "no dv timings detected");
+            return None;
+        }
+    };
+    match apply(dev, detected) {
+        Ok(applied) => {
+            info!(%applied, "v4l dv timings set");
+            Some(applied)
+        }
+        Err(err) => {
+            warn!(%err, %detected, "failed to set the detected dv timings");
+            None
+        }
+    }
+}
+
+/// Sets the timings of `input` and the stream up again with the format the driver
+/// reports for them, like after a source change
+pub(crate) fn set(input: &mut Input, timings: DvTimings, images: &mut Assets<Image>) -> Result<()> {
+    let unsupported = || Error::Io(io::ErrorKind::Unsupported.into());
+    if input.device.dev.is_none() {
+        return Err(unsupported());
+    }
+    // the frame of an unfinished task is dropped with the stream
+    if let Some(task) = input.device.task.take() {
+        block_on(task);
+    }
+
+    let span = input.device.span.clone();
+    let _span = span.enter();
+    // the timings can't change while buffers are allocated
+    match input.device.io.lock() {
+        Ok(mut io) => io.stream = IoStream::Closed,
+        Err(_) => return Err(unsupported()),
+    }
+    let Some(dev) = input.device.dev.as_ref() else {
+        return Err(unsupported());
+    };
+    let applied = apply(dev, timings);
+    // the previous format again when the driver rejected the timings, to restart the
+    // stream
+    let format = match &applied {
+        Ok(applied) => {
+            info!(requested = %timings, %applied, "v4l dv timings set");
+            Capture::format(dev)?
+        }
+        Err(_) => input.device.format,
+    };
+
+    let previous = UVec2::new(input.device.size.width, input.device.size.height);
+    let profile = Profile {
+        format: Format(format),
+        controls: Vec::new(),
+    };
+    profile::apply(input, &profile, "the dv timings", images).map_err(|err| err.error)?;
+    applied?;
+    input.format_changed = Some(previous);
+    Ok(())
+}
+
+/// Sets the EDID `dev` advertises to sources on its first pad
+pub(crate) fn set_edid(dev: &v4l::Device, edid: &[u8]) -> Result<()> {
+    if !edid.len().is_multiple_of(EDID_BLOCK) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "an EDID is made of {EDID_BLOCK} byte blocks, this one has {} bytes",
+                edid.len()
+            ),
+        )));
+    }
+    // drivers only read the blocks, the kernel copies them in
+    let mut blocks = edid.to_vec();
+    let mut raw = Edid {
+        pad: 0,
+        start_block: 0,
+        blocks: (edid.len() / EDID_BLOCK) as u32,
+        reserved: [0; 5],
+        edid: blocks.as_mut_ptr(),
+    };
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            VIDIOC_S_EDID,
+            &mut raw as *mut Edid as *mut c_void,
+        )?;
+    }
+    Ok(())
+}
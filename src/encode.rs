@@ -0,0 +1,400 @@
+use crate::color::ToYcbcr;
+use crate::scale::ScaledFrame;
+use crate::{validate, Colorimetry, Result};
+
+/// Converts rgba frames into the buffers of an output format, chosen once when the
+/// output is opened, see [`for_format`]
+pub(crate) trait FrameEncoder: Send {
+    /// Writes `src` into the buffer `dst`, returning the bytes used
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize>;
+}
+
+/// Encoder for buffers of `format` with YCbCr samples of `colorimetry`, None for formats
+/// that can't be written on the cpu. MJPEG outputs have their own, with the quality of
+/// the output.
+pub(crate) fn for_format(
+    format: &v4l::Format,
+    colorimetry: Colorimetry,
+) -> Option<Box<dyn FrameEncoder>> {
+    let k = colorimetry.to_ycbcr();
+    // rows are padded to the bytesperline of the driver, the stride of planar formats
+    // is the one of their luma plane
+    let stride =
+        |bytes_per_pixel| (format.stride as usize).max(format.width as usize * bytes_per_pixel);
+    // rows of packed 4:2:2 end on a whole macropixel
+    let pairs = stride(2).max(format.width.next_multiple_of(2) as usize * 2);
+    let encoder: Box<dyn FrameEncoder> = match &format.fourcc.repr {
+        b"YUYV" => Box::new(Packed422::<0, 2, 1, 3>(pairs, k)),
+        // hdmi output bridges often only take this one
+        b"UYVY" => Box::new(Packed422::<1, 3, 0, 2>(pairs, k)),
+        b"YVYU" => Box::new(Packed422::<0, 2, 3, 1>(pairs, k)),
+        // rgba, only negotiated by encoders
+        b"AB24" => Box::new(Rgba(stride(4))),
+        // packed rgb, consumers that take it skip the yuv conversion
+        b"RGB3" => Box::new(Packed(
+            stride(3),
+            Box::new(|rgba| [rgba[0], rgba[1], rgba[2]]),
+        )),
+        b"IYU2" => Box::new(Packed(
+            stride(3),
+            Box::new(move |rgba| {
+                let [y, u, v] = k.ycbcr(rgba);
+                [u, y, v]
+            }),
+        )),
+        b"GREY" => Box::new(Grey(stride(1))),
+        b"NV12" => Box::new(Nv12 {
+            stride: stride(1),
+            k,
+        }),
+        b"YU12" => Box::new(Yu12 {
+            stride: stride(1),
+            k,
+        }),
+        _ => return None,
+    };
+    Some(encoder)
+}
+
+/// Whether outputs can write frames of this format, with [`for_format`] or on a
+/// multi-planar stream
+pub(crate) fn can_encode(fourcc: &[u8; 4]) -> bool {
+    let format = v4l::Format::new(1, 1, v4l::FourCC::new(fourcc));
+    for_format(&format, Colorimetry::default()).is_some()
+        || crate::mplane::can_encode(fourcc)
+        || cfg!(feature = "mjpeg-encode") && fourcc == b"MJPG"
+}
+
+/// Packed 4:2:2 with the samples of two pixels at the given byte offsets, in rows of
+/// the stride
+struct Packed422<const Y0: usize, const Y1: usize, const U: usize, const V: usize>(usize, ToYcbcr);
+
+impl<const Y0: usize, const Y1: usize, const U: usize, const V: usize> FrameEncoder
+    for Packed422<Y0, Y1, U, V>
+{
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        encode_yuv422::<Y0, Y1, U, V>(src, dst, self.0, &self.1);
+        Ok(self.0 * src.height())
+    }
+}
+
+struct Rgba(usize);
+
+impl FrameEncoder for Rgba {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        Ok(encode_rows(src, dst, self.0, 4, |rgba, dst| {
+            dst.copy_from_slice(rgba)
+        }))
+    }
+}
+
+/// Turns the rgba of a pixel into its 3 bytes
+type PackPixel = Box<dyn Fn(&[u8]) -> [u8; 3] + Send>;
+
+/// 3 bytes per pixel, taken from the rgba of the pixel
+struct Packed(usize, PackPixel);
+
+impl FrameEncoder for Packed {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        Ok(encode_rows(src, dst, self.0, 3, |rgba, dst| {
+            dst.copy_from_slice(&(self.1)(rgba))
+        }))
+    }
+}
+
+/// Full range luma with the BT.601 weights, like the luma of captured rgb formats
+struct Grey(usize);
+
+impl FrameEncoder for Grey {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        Ok(encode_rows(src, dst, self.0, 1, |rgba, dst| {
+            let [r, g, b] = [0, 1, 2].map(|channel| rgba[channel] as u32);
+            dst[0] = ((77 * r + 150 * g + 29 * b) >> 8) as u8;
+        }))
+    }
+}
+
+struct Nv12 {
+    stride: usize,
+    k: ToYcbcr,
+}
+
+impl FrameEncoder for Nv12 {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        let y_len = self.stride * src.height();
+        let uv_stride = validate::chroma_stride(b"NV12", self.stride);
+        let (y, uv) = dst.split_at_mut(y_len.min(dst.len()));
+        encode_nv12(src, (y, self.stride), (uv, uv_stride), &self.k);
+        Ok(y_len + uv_stride * src.height().div_ceil(2))
+    }
+}
+
+struct Yu12 {
+    stride: usize,
+    k: ToYcbcr,
+}
+
+impl FrameEncoder for Yu12 {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        let y_len = self.stride * src.height();
+        let chroma_stride = validate::chroma_stride(b"YU12", self.stride);
+        let chroma_len = chroma_stride * src.height().div_ceil(2);
+        let (y, chroma) = dst.split_at_mut(y_len.min(dst.len()));
+        let (u, v) = chroma.split_at_mut(chroma_len.min(chroma.len()));
+        encode_yu12(src, (y, self.stride), ([u, v], chroma_stride), &self.k);
+        Ok(y_len + chroma_len * 2)
+    }
+}
+
+/// Converts the rgba `src` into YUYV with YCbCr samples of `k`, in rows of `stride` bytes
+pub(crate) fn encode_yuyv(src: &ScaledFrame, dst: &mut [u8], stride: usize, k: &ToYcbcr) {
+    encode_yuv422::<0, 2, 1, 3>(src, dst, stride, k);
+}
+
+/// Converts the rgba `src` into packed 4:2:2 with the samples of two pixels at the given
+/// byte offsets, each row starting `stride` bytes after the previous one. The chroma
+/// is the one of the average color of both pixels, rows of odd widths end on a pair
+/// of the last pixel twice.
+fn encode_yuv422<const Y0: usize, const Y1: usize, const U: usize, const V: usize>(
+    src: &ScaledFrame,
+    dst: &mut [u8],
+    stride: usize,
+    k: &ToYcbcr,
+) {
+    for (y, dst) in dst.chunks_mut(stride).take(src.height()).enumerate() {
+        let row = src.row(y);
+        let last = src.width().saturating_sub(1);
+        let pairs = dst.chunks_exact_mut(4).take(src.width().div_ceil(2));
+        for (x, dst) in pairs.enumerate() {
+            // buffer is rgba, skip alpha channel
+            let rgb =
+                |x: usize| [0, 1, 2].map(|channel| src.pixel(row, x.min(last))[channel] as i32);
+            let (first, second) = (rgb(x * 2), rgb(x * 2 + 1));
+            let [u, v] = k.chroma([0, 1, 2].map(|i| (first[i] + second[i] + 1) / 2));
+            dst[Y0] = k.luma(first);
+            dst[Y1] = k.luma(second);
+            dst[U] = u;
+            dst[V] = v;
+        }
+    }
+}
+
+/// Converts the rgba `src` into a luma plane and an interleaved CbCr plane at half
+/// resolution, both given with their stride. Odd sizes end on blocks of a single
+/// column or row.
+pub(crate) fn encode_nv12(
+    src: &ScaledFrame,
+    (y, y_stride): (&mut [u8], usize),
+    (uv, uv_stride): (&mut [u8], usize),
+    k: &ToYcbcr,
+) {
+    encode_luma_plane(src, y, y_stride, k);
+
+    let rows = uv.chunks_mut(uv_stride).take(src.height().div_ceil(2));
+    for (row, dst) in rows.enumerate() {
+        for (x, dst) in dst
+            .chunks_exact_mut(2)
+            .take(src.width().div_ceil(2))
+            .enumerate()
+        {
+            dst.copy_from_slice(&block_chroma(src, x * 2, row * 2, k));
+        }
+    }
+}
+
+/// Like [`encode_nv12`], but with separate Cb and Cr planes
+fn encode_yu12(
+    src: &ScaledFrame,
+    (y, y_stride): (&mut [u8], usize),
+    ([u, v], chroma_stride): ([&mut [u8]; 2], usize),
+    k: &ToYcbcr,
+) {
+    encode_luma_plane(src, y, y_stride, k);
+
+    let rows = u.chunks_mut(chroma_stride).zip(v.chunks_mut(chroma_stride));
+    for (row, (u, v)) in rows.take(src.height().div_ceil(2)).enumerate() {
+        let pixels = u.iter_mut().zip(v.iter_mut());
+        for (x, (u, v)) in pixels.take(src.width().div_ceil(2)).enumerate() {
+            [*u, *v] = block_chroma(src, x * 2, row * 2, k);
+        }
+    }
+}
+
+/// Y of every pixel, in rows of `stride` bytes
+fn encode_luma_plane(src: &ScaledFrame, y: &mut [u8], stride: usize, k: &ToYcbcr) {
+    for (row, dst) in y.chunks_mut(stride).take(src.height()).enumerate() {
+        let row = src.row(row);
+        for (x, dst) in dst.iter_mut().take(src.width()).enumerate() {
+            *dst = k.luma([0, 1, 2].map(|channel| src.pixel(row, x)[channel] as i32));
+        }
+    }
+}
+
+/// Cb and Cr of the average color of the 2x2 block at `x`, `y`. Blocks past the last
+/// column or row of odd sizes repeat it.
+fn block_chroma(src: &ScaledFrame, x: usize, y: usize, k: &ToYcbcr) -> [u8; 2] {
+    let [right, bottom] = [src.width(), src.height()].map(|size| size.saturating_sub(1));
+    let mut sum = [0_i32; 3];
+    for row in [src.row(y), src.row((y + 1).min(bottom))] {
+        for x in [x, (x + 1).min(right)] {
+            let rgba = src.pixel(row, x);
+            for (sum, &value) in sum.iter_mut().zip(&rgba[..3]) {
+                *sum += value as i32;
+            }
+        }
+    }
+
+    k.chroma(sum.map(|sum| (sum + 2) / 4))
+}
+
+/// Writes every pixel with `pixel`, which gets its rgba and its `bytes_per_pixel` bytes,
+/// in rows of `stride` bytes. Returns the bytes used, padding included.
+fn encode_rows(
+    src: &ScaledFrame,
+    dst: &mut [u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    pixel: impl Fn(&[u8], &mut [u8]),
+) -> usize {
+    let mut len = 0;
+    for (y, dst) in dst.chunks_mut(stride).take(src.height()).enumerate() {
+        let row = src.row(y);
+        let pixels = dst.chunks_exact_mut(bytes_per_pixel).take(src.width());
+        for (x, dst) in pixels.enumerate() {
+            pixel(&src.pixel(row, x), dst);
+        }
+        len += dst.len();
+    }
+    len
+}
+
+/// Copies the rgba `src` into `dst`, returning the bytes written
+pub(crate) fn encode_rgba(src: &ScaledFrame, dst: &mut [u8]) -> usize {
+    encode_rows(src, dst, src.width() * 4, 4, |rgba, dst| {
+        dst.copy_from_slice(rgba)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SizePolicy;
+
+    /// Encodes the rgba `src` of `size` into a buffer of `format` filled with 0xaa,
+    /// returning the bytes used and the buffer
+    fn encode(format: &v4l::Format, src: &[u8], size: (u32, u32)) -> (usize, Vec<u8>) {
+        let mut encoder = for_format(format, Colorimetry::default()).unwrap();
+        let frame = ScaledFrame::new(src, size, size, SizePolicy::Error).unwrap();
+        let mut dst = vec![0xaa; src.len() * 2];
+        let used = encoder.encode(&frame, &mut dst).unwrap();
+        (used, dst)
+    }
+
+    #[test]
+    fn grey_weights_the_primaries_like_bt601() {
+        let src = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]].concat();
+        let format = v4l::Format::new(3, 1, v4l::FourCC::new(b"GREY"));
+        let (used, dst) = encode(&format, &src, (3, 1));
+        assert_eq!(used, 3);
+        assert_eq!(dst[..3], [76, 149, 28]);
+
+        let src = [[255, 255, 255, 255], [0, 0, 0, 255], [128, 128, 128, 0]].concat();
+        let (_, dst) = encode(&format, &src, (3, 1));
+        assert_eq!(dst[..3], [255, 0, 128]);
+    }
+
+    #[test]
+    fn grey_rows_keep_the_stride() {
+        let src = [255; 3 * 2 * 4];
+        let mut format = v4l::Format::new(3, 2, v4l::FourCC::new(b"GREY"));
+        format.stride = 4;
+        let (used, dst) = encode(&format, &src, (3, 2));
+        assert_eq!(used, 8);
+        assert_eq!(dst[..8], [255, 255, 255, 0xaa, 255, 255, 255, 0xaa]);
+    }
+
+    /// A 4x4 rgba image of 2x2 blocks of red, white, a mixed color and blue, which
+    /// chroma subsampling keeps as they are
+    fn blocks() -> Vec<u8> {
+        let colors = [
+            [255, 0, 0, 255],
+            [255; 4],
+            [40, 160, 90, 255],
+            [0, 0, 255, 255],
+        ];
+        (0..16)
+            .flat_map(|i| colors[i / 8 * 2 + i % 4 / 2])
+            .collect()
+    }
+
+    #[test]
+    fn encoders_round_trip_through_the_decoders() {
+        let src = blocks();
+        for (fourcc, used) in [
+            (b"YUYV", 32),
+            (b"UYVY", 32),
+            (b"YVYU", 32),
+            (b"IYU2", 48),
+            (b"NV12", 24),
+            (b"YU12", 24),
+            (b"RGB3", 48),
+            (b"AB24", 64),
+        ] {
+            let format = v4l::Format::new(4, 4, v4l::FourCC::new(fourcc));
+            let (bytes, dst) = encode(&format, &src, (4, 4));
+            assert_eq!(bytes, used, "{}", crate::FourCC::from(*fourcc));
+
+            let mut rgba = vec![0; src.len()];
+            crate::convert_frame(*fourcc, 4, &dst[..bytes], &mut rgba).unwrap();
+            for (decoded, src) in rgba.chunks_exact(4).zip(src.chunks_exact(4)) {
+                let close = decoded.iter().zip(src).all(|(a, b)| a.abs_diff(*b) <= 2);
+                assert!(
+                    close,
+                    "{}: {src:?} decoded as {decoded:?}",
+                    crate::FourCC::from(*fourcc)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grey_decodes_to_its_luma() {
+        let src = blocks();
+        let format = v4l::Format::new(4, 4, v4l::FourCC::new(b"GREY"));
+        let (bytes, dst) = encode(&format, &src, (4, 4));
+        assert_eq!(bytes, 16);
+
+        let mut rgba = vec![0; src.len()];
+        crate::convert_frame(*b"GREY", 4, &dst[..bytes], &mut rgba).unwrap();
+        for (decoded, src) in rgba.chunks_exact(4).zip(src.chunks_exact(4)) {
+            let luma = 0.299 * src[0] as f32 + 0.587 * src[1] as f32 + 0.114 * src[2] as f32;
+            assert!(
+                (decoded[0] as f32 - luma).abs() <= 1.5,
+                "{decoded:?} of {src:?}"
+            );
+            assert_eq!(decoded[..3], [decoded[0]; 3]);
+        }
+    }
+
+    #[test]
+    fn planar_encoders_keep_their_strides() {
+        let src = blocks();
+        let mut format = v4l::Format::new(4, 4, v4l::FourCC::new(b"NV12"));
+        format.stride = 8;
+        let (bytes, dst) = encode(&format, &src, (4, 4));
+        assert_eq!(bytes, 8 * 4 + 8 * 2);
+        assert_eq!(dst[4..8], [0xaa; 4], "luma padding is left alone");
+
+        let mut unpadded = Vec::new();
+        for (plane, len) in [(&dst[..32], 4), (&dst[32..48], 4)] {
+            for row in plane.chunks(8) {
+                unpadded.extend_from_slice(&row[..len]);
+            }
+        }
+        let mut rgba = vec![0; src.len()];
+        crate::convert_frame(*b"NV12", 4, &unpadded, &mut rgba).unwrap();
+        for (decoded, src) in rgba.chunks_exact(4).zip(src.chunks_exact(4)) {
+            assert!(decoded.iter().zip(src).all(|(a, b)| a.abs_diff(*b) <= 2));
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::VecDeque;
+use std::io;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use tracing::warn;
+use v4l::buffer::Type;
+use v4l::format::FieldOrder;
+use v4l::io::mmap::Stream;
+use v4l::io::traits::{CaptureStream, OutputStream};
+use v4l::prelude::*;
+use v4l::v4l2;
+use v4l::video::Capture;
+use v4l::FourCC;
+
+use crate::devices::DeviceSelector;
+use crate::encode;
+use crate::frame::Sequencer;
+use crate::ioctl::{ExtControl, ExtControls, VIDIOC_S_EXT_CTRLS};
+use crate::m2m::stream_off;
+use crate::source::IoStream;
+use crate::validate;
+use crate::{
+    describe_format, AlphaMode, Colorimetry, Device, Dither, Error, Format, FrameId, ImageEncoding,
+    Io, MemoryType, NegotiationReport, Orientation, Result, SizePolicy, BUFFER_COUNT,
+};
+
+/// Raw formats fed to the encoder, in order of preference.
+/// RGBA skips the cpu conversion entirely.
+const RAW_FOURCCS: [&[u8; 4]; 2] = [b"AB24", b"YUYV"];
+
+/// Encoded frames kept for [`EncodedFrame`] events before the oldest are dropped
+const MAX_PENDING_FRAMES: usize = 32;
+
+/// V4L2_CTRL_CLASS_CODEC, the class of the CID_MPEG_VIDEO controls
+const CTRL_CLASS_CODEC: u32 = 0x00990000;
+const CID_MPEG_VIDEO_GOP_SIZE: u32 = 0x009909cb;
+const CID_MPEG_VIDEO_BITRATE: u32 = 0x009909cf;
+const CID_MPEG_VIDEO_H264_PROFILE: u32 = 0x00990a6b;
+
+/// Settings for an [`EncodedOutput`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncoderSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Compressed format produced by the encoder, serialized like a [`FourCC`](crate::FourCC)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::fourcc"))]
+    pub codec: [u8; 4],
+    /// Target bitrate in bits per second
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bitrate: Option<i64>,
+    /// Frames between keyframes
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gop_size: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub h264_profile: Option<H264Profile>,
+    /// Output device the encoded stream is written to, like a v4l2loopback node
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub target: Option<DeviceSelector>,
+    /// Send every encoded frame to the app as an [`EncodedFrame`] event
+    pub events: bool,
+}
+
+impl EncoderSettings {
+    /// H.264 at the driver's default bitrate, delivered as events
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            codec: *b"H264",
+            bitrate: None,
+            gop_size: None,
+            h264_profile: None,
+            target: None,
+            events: true,
+        }
+    }
+}
+
+/// Values of V4L2_CID_MPEG_VIDEO_H264_PROFILE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum H264Profile {
+    Baseline = 0,
+    ConstrainedBaseline = 1,
+    Main = 2,
+    High = 4,
+}
+
+/// A compressed frame produced by an [`EncodedOutput`]
+#[derive(Event, Debug, Clone)]
+pub struct EncodedFrame {
+    pub entity: Entity,
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    /// Sequence reported by the encoder
+    pub frame: FrameId,
+}
+
+/// Encodes a bevy image with a memory-to-memory encoder, like the H.264 encoder
+/// on a Raspberry Pi (/dev/video11).
+///
+/// The encoded stream is written to [`EncoderSettings::target`] and/or sent as
+/// [`EncodedFrame`] events.
+#[derive(Component)]
+pub struct EncodedOutput {
+    pub(crate) device: Device,
+    /// Frames dequeued by the pump thread, waiting to be sent as events
+    frames: Arc<Mutex<VecDeque<EncodedFrame>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl EncodedOutput {
+    pub fn new(
+        encoder: impl Into<DeviceSelector>,
+        image: Handle<Image>,
+        settings: EncoderSettings,
+    ) -> Result<Self> {
+        let (dev, id) = encoder.into().open()?;
+
+        let controls = [
+            (CID_MPEG_VIDEO_BITRATE, settings.bitrate),
+            (CID_MPEG_VIDEO_GOP_SIZE, settings.gop_size),
+            (
+                CID_MPEG_VIDEO_H264_PROFILE,
+                settings.h264_profile.map(|profile| profile as i64),
+            ),
+        ];
+        let controls = controls
+            .into_iter()
+            .filter_map(|(cid, value)| Some((cid, value?)))
+            .map(|(cid, value)| {
+                let value = i32::try_from(value).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{value} doesn't fit the 32 bit encoder control {cid:#x}"),
+                    )
+                })?;
+                Ok(ExtControl::integer(cid, value))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        set_codec_controls(&dev, controls)?;
+
+        // stateful encoders expect the coded format to be set first
+        let requested = v4l::Format::new(
+            settings.width,
+            settings.height,
+            FourCC::new(&settings.codec),
+        );
+        let coded = Capture::set_format(&dev, &requested)?;
+        let path = crate::device_path(id);
+        let mut report = NegotiationReport::new(path.display());
+        report.step("set coded format", Some(&requested), &coded);
+        if coded.fourcc.repr != settings.codec {
+            return Err(Error::FormatRejected {
+                requested: describe_format(&requested),
+                granted: describe_format(&coded),
+            });
+        }
+
+        let format = negotiate_raw(&dev, settings.width, settings.height, &mut report)?;
+        validate::format(&format)?;
+
+        let target = match &settings.target {
+            Some(selector) => {
+                let (target, _) = selector.open()?;
+                v4l::video::Output::set_format(&target, &coded)?;
+                let stream = MmapStream::with_buffers(&target, Type::VideoOutput, BUFFER_COUNT)?;
+                Some(stream)
+            }
+            None => None,
+        };
+
+        let stream = MmapStream::with_buffers(&dev, Type::VideoOutput, BUFFER_COUNT)?;
+        let capture = MmapStream::with_buffers(&dev, Type::VideoCapture, BUFFER_COUNT)?;
+        report.memory = Some(MemoryType::Mmap);
+
+        let frames = Arc::new(Mutex::new(VecDeque::new()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let span = crate::device_span(&report.device, "encoder");
+
+        std::thread::Builder::new()
+            .name(format!("v4l encoder {id}"))
+            .spawn({
+                let frames = settings.events.then(|| frames.clone());
+                let running = running.clone();
+                let span = span.clone();
+                move || span.in_scope(|| pump(capture, target, frames, running))
+            })?;
+
+        let size = Extent3d {
+            width: format.width,
+            height: format.height,
+            depth_or_array_layers: 1,
+        };
+
+        Ok(Self {
+            device: Device {
+                id,
+                format,
+                image,
+                size,
+                io: Arc::new(Mutex::new(Io {
+                    buffer: vec![255_u8; (size.width * size.height * 4) as usize],
+                    stream: IoStream::Mmap(stream),
+                    m2m: None,
+                    processor: None,
+                    error: None,
+                    sequence: 0,
+                    frames: Default::default(),
+                    dequeue_timestamps: false,
+                    restarts: 0,
+                    restarted: None,
+                    paused: false,
+                    cancel: None,
+                    drain: false,
+                    corrupt: 0,
+                    lost: 0,
+                    native: false,
+                    received: None,
+                    sent: None,
+                    size_policy: SizePolicy::default(),
+                    alpha: AlphaMode::default(),
+                    orientation: Orientation::default(),
+                    crop: None,
+                    offsets: Default::default(),
+                    resampler: None,
+                    linearize: None,
+                    dither: Dither::default(),
+                    colorimetry: Colorimetry::detect(&format),
+                    denoise: None,
+                    post_process: None,
+                    bayer: None,
+                    converter: None,
+                    stats: None,
+                    counts: Default::default(),
+                    snapshot: None,
+                    encoding: ImageEncoding::default(),
+                    preview: None,
+                    targets: Vec::new(),
+                    raw: None,
+                    publisher: None,
+                    dump: None,
+                    activity: None,
+                    fresh: false,
+                    dequeued: None,
+                    exchange: Default::default(),
+                    upload_latency: None,
+                    presenter: Default::default(),
+                    stride: 0,
+                    unpadded: Vec::new(),
+                    overrides: Default::default(),
+                    watchdog: None,
+                    budget: None,
+                    signal: None,
+                    source_change: None,
+                    diagnostics: None,
+                    deinterlace: None,
+                    #[cfg(feature = "h264")]
+                    h264: None,
+                    frame_encoder: encode::for_format(&format, Colorimetry::detect(&format)),
+                    underruns: None,
+                    wait: None,
+                })),
+                task: None,
+                frame: None,
+                report,
+                span,
+                path: Some(path),
+                frame_interval: None,
+                dev: Some(dev),
+                closed: false,
+                cancel: Default::default(),
+                exchange: Default::default(),
+                paused: false,
+                errors: Default::default(),
+                health: Default::default(),
+                release_image: false,
+                released: None,
+            },
+            frames,
+            running,
+        })
+    }
+
+    /// Handle to bevy image
+    pub fn image(&self) -> &Handle<Image> {
+        &self.device.image
+    }
+
+    /// ID of the v4l encoder device (/dev/video{id})
+    pub fn id(&self) -> usize {
+        self.device.id
+    }
+
+    /// Raw format fed to the encoder
+    pub fn format(&self) -> Format {
+        Format(self.device.format)
+    }
+
+    pub fn size(&self) -> Extent3d {
+        self.device.size
+    }
+
+    /// Latest frame fed to the encoder, updated once per frame
+    pub fn last_frame(&self) -> Option<FrameId> {
+        self.device.frame
+    }
+
+    /// How the coded and raw formats were arrived at
+    pub fn negotiation(&self) -> &NegotiationReport {
+        &self.device.report
+    }
+
+    pub(crate) fn drain_frames(&self) -> Vec<EncodedFrame> {
+        match self.frames.lock() {
+            Ok(mut frames) => frames.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Drop for EncodedOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _span = self.device.span.enter();
+
+        // wakes the pump thread if it is blocked dequeuing
+        if let Some(dev) = &self.device.dev {
+            if let Err(err) = stream_off(dev, Type::VideoCapture) {
+                warn!("failed to stop encoder {}: {err}", self.device.id);
+            }
+        }
+    }
+}
+
+/// Picks the first raw format the encoder accepts at the requested size
+/// Sets `controls` of the codec class with VIDIOC_S_EXT_CTRLS, stateful encoders don't
+/// take them with VIDIOC_S_CTRL
+fn set_codec_controls(dev: &v4l::Device, mut controls: Vec<ExtControl>) -> io::Result<()> {
+    if controls.is_empty() {
+        return Ok(());
+    }
+    let mut raw = ExtControls {
+        which: CTRL_CLASS_CODEC,
+        count: controls.len() as u32,
+        error_idx: 0,
+        request_fd: 0,
+        reserved: [0],
+        controls: controls.as_mut_ptr(),
+    };
+    let set = unsafe {
+        v4l2::ioctl(
+            dev.handle().fd(),
+            VIDIOC_S_EXT_CTRLS,
+            &mut raw as *mut ExtControls as *mut c_void,
+        )
+    };
+    set.map_err(|err| match controls.get(raw.error_idx as usize) {
+        Some(control) => {
+            let id = control.id;
+            io::Error::new(err.kind(), format!("encoder control {id:#x}: {err}"))
+        }
+        None => err,
+    })
+}
+
+fn negotiate_raw(
+    dev: &v4l::Device,
+    width: u32,
+    height: u32,
+    report: &mut NegotiationReport,
+) -> Result<v4l::Format> {
+    let mut granted = None;
+
+    for fourcc in RAW_FOURCCS {
+        let requested = v4l::Format::new(width, height, FourCC::new(fourcc));
+        let format = v4l::video::Output::set_format(dev, &requested)?;
+        report.step("set raw format", Some(&requested), &format);
+
+        if format.fourcc.repr == *fourcc && format.width == width && format.height == height {
+            return Ok(format);
+        }
+
+        granted = Some((requested, format));
+    }
+
+    let (requested, format) = granted.expect("RAW_FOURCCS is not empty");
+    Err(Error::FormatRejected {
+        requested: describe_format(&requested),
+        granted: describe_format(&format),
+    })
+}
+
+/// Moves encoded frames from the capture side of the encoder to the target device
+/// and the event queue until stopped
+fn pump(
+    mut capture: Stream<'static>,
+    mut target: Option<Stream<'static>>,
+    frames: Option<Arc<Mutex<VecDeque<EncodedFrame>>>>,
+    running: Arc<AtomicBool>,
+) {
+    let mut sequencer = Sequencer::default();
+
+    while running.load(Ordering::Relaxed) {
+        let (buf, buf_meta) = match CaptureStream::next(&mut capture) {
+            Ok(next) => next,
+            Err(_) if !running.load(Ordering::Relaxed) => break,
+            Err(err) => {
+                warn!("encoder capture failed: {err}");
+                break;
+            }
+        };
+
+        let data = &buf[..(buf_meta.bytesused as usize).min(buf.len())];
+        let frame = sequencer.next(buf_meta.sequence);
+
+        if let Some(target) = target.as_mut() {
+            match OutputStream::next(target) {
+                Ok((out, out_meta)) => {
+                    let len = data.len().min(out.len());
+                    out[..len].copy_from_slice(&data[..len]);
+                    out_meta.field = FieldOrder::Progressive as u32;
+                    out_meta.bytesused = len as u32;
+                }
+                Err(err) => warn!("failed to write encoded frame: {err}"),
+            }
+        }
+
+        if let Some(frames) = &frames {
+            let Ok(mut frames) = frames.lock() else {
+                break;
+            };
+
+            if frames.len() == MAX_PENDING_FRAMES {
+                frames.pop_front();
+            }
+
+            frames.push_back(EncodedFrame {
+                // filled in by the plugin when the event is sent
+                entity: Entity::PLACEHOLDER,
+                data: data.to_vec(),
+                keyframe: buf_meta.flags.contains(v4l::buffer::Flags::KEYFRAME),
+                frame,
+            });
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+use crate::V4lError;
+
+/// Errors that repeat the last one of a device are sent at most this often
+const REPEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps a failing device from sending the same [`V4lError`] every frame, like an
+/// unplugged camera without a reconnect policy
+#[derive(Debug, Default)]
+pub(crate) struct ErrorFilter {
+    /// Message and time of the last error sent
+    last: Option<(String, Instant)>,
+    suppressed: u32,
+}
+
+impl ErrorFilter {
+    /// `None` if `event` repeats the last error sent within [`REPEAT_INTERVAL`]
+    pub(crate) fn pass(&mut self, mut event: V4lError) -> Option<V4lError> {
+        let message = event.error.to_string();
+        let now = Instant::now();
+        if let Some((last, sent)) = &self.last {
+            if *last == message && now - *sent < REPEAT_INTERVAL {
+                self.suppressed += 1;
+                return None;
+            }
+        }
+
+        self.last = Some((message, now));
+        event.repeated = std::mem::take(&mut self.suppressed);
+        Some(event)
+    }
+}
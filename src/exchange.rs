@@ -0,0 +1,108 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use crate::pool::BufferPool;
+use crate::{Presented, Timestamp};
+
+/// The middle of the three buffers of an input's frames. The io task converts into its
+/// own buffer in [`Io`](crate::Io) and swaps it in here when the frame is done, the main
+/// world swaps the image data with it. Either only holds the lock for the swap, so
+/// neither waits on the other converting, uploading or blocked on the driver.
+pub(crate) struct Exchange(Mutex<Middle>);
+
+struct Middle {
+    buffer: Vec<u8>,
+    /// When the frame in `buffer` was dequeued and its timestamp, `None` once it was taken
+    fresh: Option<(Instant, Timestamp)>,
+    /// The frame taken last
+    presented: Option<Presented>,
+}
+
+impl Exchange {
+    pub(crate) fn new(len: usize) -> Self {
+        Self(Mutex::new(Middle {
+            buffer: BufferPool::global().take(len, 255),
+            fresh: None,
+            presented: None,
+        }))
+    }
+
+    /// Drops the frame for one of a new size, see [`Exchange::new`]
+    pub(crate) fn reset(&self, len: usize) {
+        let pool = BufferPool::global();
+        let previous = std::mem::replace(
+            &mut *self.lock(),
+            Middle {
+                buffer: pool.take(len, 255),
+                fresh: None,
+                presented: None,
+            },
+        );
+        pool.give(previous.buffer);
+    }
+
+    /// Takes the buffer of a closed device for the [`BufferPool`], no frames are
+    /// swapped in anymore
+    pub(crate) fn recycle(&self) -> Vec<u8> {
+        let mut middle = self.lock();
+        middle.fresh = None;
+        std::mem::take(&mut middle.buffer)
+    }
+
+    /// Swaps a finished frame in, `buffer` gets the one to convert the next frame into.
+    /// A frame that wasn't taken yet is dropped for the newer one.
+    pub(crate) fn publish(&self, buffer: &mut Vec<u8>, dequeued: Instant, timestamp: Timestamp) {
+        let mut middle = self.lock();
+        std::mem::swap(&mut middle.buffer, buffer);
+        middle.fresh = Some((dequeued, timestamp));
+    }
+
+    pub(crate) fn is_fresh(&self) -> bool {
+        self.lock().fresh.is_some()
+    }
+
+    /// Swaps the latest frame into `front`, returns when it was dequeued or `None` when
+    /// there is no frame since the last one taken
+    pub(crate) fn take(&self, front: &mut Vec<u8>) -> Option<Instant> {
+        let mut middle = self.lock();
+        let dequeued = middle.present()?;
+        std::mem::swap(&mut middle.buffer, front);
+        Some(dequeued)
+    }
+
+    /// Like [`Exchange::take`], for readers that only need to see the frame
+    pub(crate) fn read(&self, f: impl FnOnce(&[u8])) -> Option<Instant> {
+        let mut middle = self.lock();
+        let dequeued = middle.present()?;
+        f(&middle.buffer);
+        Some(dequeued)
+    }
+
+    /// The frame taken last, and when
+    pub(crate) fn presented(&self) -> Option<Presented> {
+        self.lock().presented
+    }
+
+    /// Frames in the buffer are replaced whole, a panic can't leave one half swapped
+    fn lock(&self) -> MutexGuard<'_, Middle> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Middle {
+    /// Takes the fresh frame, recording when it was taken
+    fn present(&mut self) -> Option<Instant> {
+        let (dequeued, timestamp) = self.fresh.take()?;
+        self.presented = Some(Presented {
+            timestamp,
+            at: Timestamp::now().time,
+        });
+        Some(dequeued)
+    }
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,165 @@
+use std::ops::{Deref, DerefMut};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+use bevy::prelude::*;
+
+use crate::{read_or_restart, stream_write, swap_images, Device, FrameId, Input, Output, Result};
+
+/// An [`Input`] whose frames are dequeued by the app instead of the plugin, for driving
+/// the device from another event loop, like registering its fd with epoll.
+/// Built with [`InputBuilder::build_external`](crate::InputBuilder::build_external).
+///
+/// The plugin never spawns tasks for it, so [`ExternalInput::service`] can't race them.
+/// The [`Input`] methods are available through `Deref`.
+#[derive(Component)]
+pub struct ExternalInput {
+    input: Input,
+    fd: RawFd,
+}
+
+impl ExternalInput {
+    pub(crate) fn new(input: Input) -> Self {
+        Self {
+            fd: device_fd(&input.device),
+            input,
+        }
+    }
+
+    /// Dequeues, converts and swaps a frame into the images of the input, blocking
+    /// until a frame is there. Call when the fd is readable to not block.
+    ///
+    /// Returns the frame, or `None` when no frame was converted, like while inactive.
+    /// Errors of the frame's [`FrameProcessor`](crate::FrameProcessor) are returned too,
+    /// the frame is still shown.
+    pub fn service(&mut self, images: &mut Assets<Image>) -> Result<Option<FrameId>> {
+        let preview = self.input.preview().cloned();
+        let late_upload = self.input.late_upload;
+        let orientation = self.input.orientation;
+        let device = &mut self.input.device;
+        let Ok(mut io) = device.io.lock() else {
+            return Ok(None);
+        };
+
+        let (id, fourcc) = (device.id, device.format.fourcc.repr);
+        let (width, height) = orientation.size(device.size.width, device.size.height);
+        device
+            .span
+            .in_scope(|| read_or_restart(&mut io, id, &fourcc, width, height));
+
+        let fresh = match late_upload {
+            true => device.exchange.is_fresh(),
+            false => swap_images(&mut io, &device.image, preview.as_ref(), images),
+        };
+        device.frame = io.frames.last();
+
+        match io.error.take() {
+            Some(err) => Err(err),
+            None => Ok(device.frame.filter(|_| fresh)),
+        }
+    }
+}
+
+impl Deref for ExternalInput {
+    type Target = Input;
+
+    fn deref(&self) -> &Input {
+        &self.input
+    }
+}
+
+impl DerefMut for ExternalInput {
+    fn deref_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+}
+
+impl AsRawFd for ExternalInput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for ExternalInput {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // open for as long as the device is
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+/// An [`Output`] whose frames are written by the app instead of the plugin, see
+/// [`ExternalInput`]. Built with [`OutputBuilder::build_external`](crate::OutputBuilder::build_external).
+#[derive(Component)]
+pub struct ExternalOutput {
+    output: Output,
+    fd: RawFd,
+}
+
+impl ExternalOutput {
+    pub(crate) fn new(output: Output) -> Self {
+        Self {
+            fd: device_fd(&output.0),
+            output,
+        }
+    }
+
+    /// Writes the current contents of the image to the device, blocking until
+    /// a buffer is free. Call when the fd is writable to not block.
+    pub fn service(&mut self, images: &Assets<Image>) -> Result<Option<FrameId>> {
+        let device = &mut self.output.0;
+        let Some(image) = images.get(&device.image) else {
+            return Ok(None);
+        };
+        let Ok(mut io) = device.io.lock() else {
+            return Ok(None);
+        };
+
+        io.buffer.clone_from(&image.data);
+        let format = device.format;
+        let (width, height) = (image.width(), image.height());
+        device
+            .span
+            .in_scope(|| stream_write(&mut io, &format, width, height))?;
+        if let Some(underruns) = io.underruns.as_mut() {
+            underruns.written((width, height));
+        }
+
+        device.frame = io.frames.last();
+        Ok(device.frame)
+    }
+}
+
+impl Deref for ExternalOutput {
+    type Target = Output;
+
+    fn deref(&self) -> &Output {
+        &self.output
+    }
+}
+
+impl DerefMut for ExternalOutput {
+    fn deref_mut(&mut self) -> &mut Output {
+        &mut self.output
+    }
+}
+
+impl AsRawFd for ExternalOutput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for ExternalOutput {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // open for as long as the device is
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+/// Devices opened from selectors always have a v4l device, only virtual inputs don't
+fn device_fd(device: &Device) -> RawFd {
+    let dev = device
+        .dev
+        .as_ref()
+        .expect("external devices are opened from a selector");
+    dev.handle().fd()
+}
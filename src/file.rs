@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use tracing::debug;
+use v4l::FourCC;
+
+use crate::dump::{FRAME_MAGIC, HEADER_LEN};
+use crate::source::{FrameMeta, Pacer, VirtualSource};
+use crate::{Error, Result};
+
+/// Replays a dump or a raw file of back to back frames as a capture device
+pub(crate) struct FileSource {
+    file: BufReader<File>,
+    path: String,
+    /// Dumps start every frame with a header, raw files have a fixed frame size
+    dump: bool,
+    looping: bool,
+    finished: bool,
+    buffer: Vec<u8>,
+    pacer: Pacer,
+}
+
+impl FileSource {
+    /// Opens a file written by [`Input::dump_to`](crate::Input::dump_to),
+    /// taking the format from its first frame
+    pub(crate) fn dump(path: &Path, looping: bool, fps: f32) -> Result<(Self, v4l::Format)> {
+        let mut file = BufReader::new(File::open(path)?);
+        let Some(header) = read_header(&mut file)? else {
+            return Err(empty(path));
+        };
+        file.seek(SeekFrom::Start(0))?;
+
+        let source = Self {
+            file,
+            path: path.display().to_string(),
+            dump: true,
+            looping,
+            finished: false,
+            buffer: Vec::new(),
+            pacer: Pacer::new(fps),
+        };
+        Ok((source, header.format))
+    }
+
+    /// Opens a file of frames in `format` without any headers, like a `.yuv` file
+    pub(crate) fn raw(path: &Path, format: &v4l::Format, looping: bool, fps: f32) -> Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+
+        let frame_len = match format.size {
+            0 => {
+                (format.width * format.height) as usize
+                    * crate::bytes_per_pixel(&format.fourcc.repr)
+            }
+            size => size as usize,
+        };
+        if file.get_ref().metadata()?.len() < frame_len as u64 {
+            return Err(empty(path));
+        }
+
+        Ok(Self {
+            file,
+            path: path.display().to_string(),
+            dump: false,
+            looping,
+            finished: false,
+            buffer: vec![0; frame_len],
+            pacer: Pacer::new(fps),
+        })
+    }
+
+    /// Reads the next frame into the buffer, returns false at the end of the file
+    fn read_frame(&mut self) -> io::Result<bool> {
+        if self.dump {
+            let Some(header) = read_header(&mut self.file)? else {
+                return Ok(false);
+            };
+            self.buffer.resize(header.bytesused as usize, 0);
+        }
+
+        match self.file.read_exact(&mut self.buffer) {
+            Ok(()) => Ok(true),
+            // a truncated last frame, like a dump cut short by a crash
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl VirtualSource for FileSource {
+    fn next(&mut self) -> io::Result<(&[u8], FrameMeta)> {
+        let (sequence, timestamp) = self.pacer.wait();
+
+        // the last frame is held once a file that doesn't loop ends
+        if !self.finished && !self.read_frame()? {
+            if self.looping {
+                self.file.seek(SeekFrom::Start(0))?;
+                if !self.read_frame()? {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            } else {
+                debug!("finished replaying {}", self.path);
+                self.finished = true;
+            }
+        }
+
+        let meta = FrameMeta {
+            error: false,
+            flags: 0,
+            bottom: false,
+            bytesused: self.buffer.len() as u32,
+            sequence,
+            timestamp,
+        };
+        Ok((&self.buffer, meta))
+    }
+}
+
+struct Header {
+    format: v4l::Format,
+    bytesused: u32,
+}
+
+/// Reads a frame header of a dump, `None` at the end of the file
+fn read_header(file: &mut impl Read) -> io::Result<Option<Header>> {
+    let mut header = [0; HEADER_LEN];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    if header[..4] != FRAME_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a v4l dump, frame header is missing",
+        ));
+    }
+
+    let field = |index: usize| {
+        let start = 8 + index * 4;
+        u32::from_le_bytes(header[start..start + 4].try_into().unwrap())
+    };
+
+    let fourcc: [u8; 4] = header[4..8].try_into().unwrap();
+    let mut format = v4l::Format::new(field(0), field(1), FourCC::new(&fourcc));
+    format.stride = field(2);
+
+    Ok(Some(Header {
+        format,
+        bytesused: field(3),
+    }))
+}
+
+fn empty(path: &Path) -> Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("{} contains no frames", path.display()),
+    )
+    .into()
+}
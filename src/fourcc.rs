@@ -0,0 +1,75 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Four character code of a pixel format, like `YUYV`. Parses from and displays as its
+/// characters, so formats can be stored as strings in config files.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCC([u8; 4]);
+
+impl FourCC {
+    pub const fn new(bytes: &[u8; 4]) -> Self {
+        Self(*bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
+}
+
+impl From<[u8; 4]> for FourCC {
+    fn from(bytes: [u8; 4]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<FourCC> for [u8; 4] {
+    fn from(fourcc: FourCC) -> Self {
+        fourcc.0
+    }
+}
+
+impl FromStr for FourCC {
+    type Err = Error;
+
+    /// Takes 1 to 4 ascii characters, shorter codes are padded with spaces like `Y16 `,
+    /// which is how they are displayed
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let code = s.trim_end_matches(' ');
+        if code.is_empty() || s.len() > 4 || !code.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Err(Error::InvalidFourcc(s.to_string()));
+        }
+
+        let mut bytes = [b' '; 4];
+        bytes[..code.len()].copy_from_slice(code.as_bytes());
+        Ok(Self(bytes))
+    }
+}
+
+impl TryFrom<&str> for FourCC {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for FourCC {
+    /// Bytes that aren't printable ascii are escaped, drivers have reported garbage
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in &self.0 {
+            match byte {
+                b' ' | b'!'..=b'~' => write!(f, "{}", byte as char)?,
+                _ => write!(f, "\\x{byte:02x}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FourCC(\"{self}\")")
+    }
+}
@@ -0,0 +1,53 @@
+/// Identifies a frame of a device.
+///
+/// Drivers restart their sequence numbers whenever a stream restarts, so every
+/// restart starts a new generation. Ids order by generation first, later frames
+/// always compare greater than earlier ones of the same device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct FrameId {
+    /// Streams started on the device before the one this frame is from
+    pub generation: u32,
+    /// Sequence number reported by the driver
+    pub sequence: u32,
+}
+
+/// Assigns [`FrameId`]s to the sequence numbers of a stream
+#[derive(Debug, Default)]
+pub(crate) struct Sequencer {
+    generation: u32,
+    last: Option<FrameId>,
+}
+
+impl Sequencer {
+    pub(crate) fn next(&mut self, sequence: u32) -> FrameId {
+        // a sequence going backwards means the driver restarted the stream,
+        // or wrapped around after 2^32 frames, which is just as good a reason
+        if self.last.is_some_and(|last| sequence < last.sequence) {
+            self.generation = self.generation.wrapping_add(1);
+        }
+
+        let id = FrameId {
+            generation: self.generation,
+            sequence,
+        };
+        self.last = Some(id);
+        id
+    }
+
+    /// Frames the driver dropped before `sequence`, the gap since the latest frame less
+    /// the `dequeued` buffers that got no id, like stale ones requeued unconverted. 0
+    /// for the first frame of a stream.
+    pub(crate) fn lost(&self, sequence: u32, dequeued: u32) -> u32 {
+        match self.last {
+            Some(last) if sequence > last.sequence => {
+                (sequence - last.sequence - 1).saturating_sub(dequeued)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Id of the latest frame
+    pub(crate) fn last(&self) -> Option<FrameId> {
+        self.last
+    }
+}
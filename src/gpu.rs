@@ -0,0 +1,246 @@
+use bevy::asset::AssetId;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, uniform_buffer_sized,
+};
+use bevy::render::render_resource::{
+    BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer, BufferDescriptor,
+    BufferUsages, CachedRenderPipelineId, ColorTargetState, ColorWrites, CommandEncoderDescriptor,
+    FragmentState, LoadOp, Operations, PipelineCache, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipelineDescriptor, ShaderStages, StoreOp, TextureFormat,
+    TextureUsages, VertexState,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::Extract;
+use bevy::utils::HashMap;
+
+use crate::{Colorimetry, Decoder, ImageEncoding, Input, YcbcrMatrix, YcbcrRange};
+
+pub(crate) const SHADER: Handle<Shader> =
+    Handle::weak_from_u128(0x5f1c_2a7e_94d3_4b08_a6e1_3c90_7d24_b851);
+
+/// Whether the shader converts frames of this format into images of this encoding
+pub(crate) fn can_convert(fourcc: &[u8; 4], encoding: ImageEncoding) -> bool {
+    encoding == ImageEncoding::Srgb && layout(fourcc).is_some()
+}
+
+/// Layout and sample offsets of a format, as the shader expects them in its uniform
+fn layout(fourcc: &[u8; 4]) -> Option<[u32; 5]> {
+    match fourcc {
+        b"YUYV" => Some([0, 0, 2, 1, 3]),
+        b"UYVY" => Some([0, 1, 3, 0, 2]),
+        b"YVYU" => Some([0, 0, 2, 3, 1]),
+        b"NV12" => Some([1, 0, 0, 0, 1]),
+        b"NV21" => Some([1, 0, 0, 1, 0]),
+        b"YU12" => Some([2, 0, 0, 0, 0]),
+        _ => None,
+    }
+}
+
+/// Lets the conversion pass render into the image of an input
+pub(crate) fn render_target(mut image: Image, gpu: bool) -> Image {
+    if gpu {
+        image.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT;
+    }
+    image
+}
+
+/// Pipeline drawing a frame into the texture of an input with a fullscreen triangle
+#[derive(Resource)]
+pub(crate) struct GpuConversion {
+    layout: BindGroupLayout,
+    pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for GpuConversion {
+    fn from_world(world: &mut World) -> Self {
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            "v4l_gpu_conversion",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    storage_buffer_read_only_sized(false, None),
+                    uniform_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline =
+            world
+                .resource::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("v4l_gpu_conversion".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: Vec::new(),
+                    vertex: VertexState {
+                        shader: SHADER,
+                        shader_defs: Vec::new(),
+                        entry_point: "vertex".into(),
+                        buffers: Vec::new(),
+                    },
+                    primitive: default(),
+                    depth_stencil: None,
+                    multisample: default(),
+                    fragment: Some(FragmentState {
+                        shader: SHADER,
+                        shader_defs: Vec::new(),
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: TextureFormat::Rgba8UnormSrgb,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                });
+
+        Self { layout, pipeline }
+    }
+}
+
+/// Latest frames of the gpu converted inputs, by their image
+#[derive(Resource, Default)]
+pub(crate) struct GpuFrames(HashMap<AssetId<Image>, GpuFrame>);
+
+#[derive(Default)]
+struct GpuFrame {
+    fourcc: [u8; 4],
+    width: u32,
+    height: u32,
+    stride: u32,
+    colorimetry: Colorimetry,
+    /// As dequeued, padded to whole words for the storage buffer
+    data: Vec<u8>,
+    /// Grown with the frames, reused otherwise
+    storage: Option<Buffer>,
+    uniform: Option<Buffer>,
+    /// Set until `data` was drawn
+    fresh: bool,
+}
+
+/// Takes the latest frames of gpu converted inputs from their exchange
+pub(crate) fn extract_frames(inputs: Extract<Query<&Input>>, mut frames: ResMut<GpuFrames>) {
+    let inputs = || inputs.iter().filter(|input| input.decoder == Decoder::Gpu);
+    frames
+        .0
+        .retain(|id, _| inputs().any(|input| input.device.image.id() == *id));
+
+    for input in inputs() {
+        let device = &input.device;
+        let frame = frames.0.entry(device.image.id()).or_default();
+        let Some(dequeued) = device.exchange.take(&mut frame.data) else {
+            continue;
+        };
+        frame.data.resize(frame.data.len().next_multiple_of(4), 0);
+        frame.fourcc = device.format.fourcc.repr;
+        frame.width = device.format.width;
+        frame.height = device.format.height;
+        frame.stride = device.format.stride;
+        frame.colorimetry = Colorimetry::resolve(&device.format, input.colorimetry);
+        frame.fresh = true;
+
+        // a running task holds the io, the latency of this frame isn't reported then
+        if let Ok(mut io) = device.io.try_lock() {
+            io.upload_latency = Some(dequeued.elapsed());
+        }
+    }
+}
+
+/// Draws the fresh frames into the textures of their inputs, before the frame renders
+pub(crate) fn convert_frames(
+    mut frames: ResMut<GpuFrames>,
+    conversion: Res<GpuConversion>,
+    pipelines: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    // compiled a few frames after startup
+    let Some(pipeline) = pipelines.get_render_pipeline(conversion.pipeline) else {
+        return;
+    };
+
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("v4l_gpu_conversion"),
+    });
+    for (id, frame) in frames.0.iter_mut().filter(|(_, frame)| frame.fresh) {
+        let Some(gpu_image) = gpu_images.get(*id) else {
+            continue;
+        };
+        // profiles can switch to formats the shader doesn't know
+        let Some([layout, y0, y1, u, v]) = layout(&frame.fourcc) else {
+            continue;
+        };
+        // the gpu image is replaced when a profile switch resizes it
+        if gpu_image.size.as_uvec2() != UVec2::new(frame.width, frame.height) {
+            continue;
+        }
+        frame.fresh = false;
+
+        let len = frame.data.len() as u64;
+        let storage = match frame.storage.take() {
+            Some(storage) if storage.size() >= len => storage,
+            _ => render_device.create_buffer(&BufferDescriptor {
+                label: Some("v4l_gpu_frame"),
+                size: len,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        };
+        let uniform = frame.uniform.take().unwrap_or_else(|| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some("v4l_gpu_frame_layout"),
+                size: 40,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        let values = [
+            frame.width,
+            frame.height,
+            frame.stride,
+            layout,
+            y0,
+            y1,
+            u,
+            v,
+            (frame.colorimetry.matrix == YcbcrMatrix::Bt709) as u32,
+            (frame.colorimetry.range == YcbcrRange::Limited) as u32,
+        ];
+        let values: Vec<u8> = values.into_iter().flat_map(u32::to_le_bytes).collect();
+        queue.write_buffer(&storage, 0, &frame.data);
+        queue.write_buffer(&uniform, 0, &values);
+
+        let bind_group = render_device.create_bind_group(
+            "v4l_gpu_conversion",
+            &conversion.layout,
+            &BindGroupEntries::sequential((
+                storage.as_entire_binding(),
+                uniform.as_entire_binding(),
+            )),
+        );
+        {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("v4l_gpu_conversion"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &gpu_image.texture_view,
+                    resolve_target: None,
+                    // every pixel is drawn
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        frame.storage = Some(storage);
+        frame.uniform = Some(uniform);
+    }
+    queue.submit([encoder.finish()]);
+}
@@ -0,0 +1,97 @@
+// Converts a YUV frame as the driver dequeued it into the texture of an input, see gpu.rs
+
+struct Frame {
+    width: u32,
+    height: u32,
+    // bytes per row of the luma plane, or of the frame for packed formats
+    stride: u32,
+    // 0 packed 4:2:2, 1 semi-planar 4:2:0, 2 planar 4:2:0
+    layout: u32,
+    // byte offsets of the samples in a macropixel of packed formats, only the chroma
+    // offsets of a pair are used for semi-planar ones
+    y0: u32,
+    y1: u32,
+    u: u32,
+    v: u32,
+    // 0 BT.601, 1 BT.709
+    matrix: u32,
+    // 1 for limited range samples, expanded to full range
+    limited: u32,
+}
+
+@group(0) @binding(0) var<storage, read> data: array<u32>;
+@group(0) @binding(1) var<uniform> frame: Frame;
+
+// a triangle covering the target
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32(index >> 1u), f32(index & 1u)) * 2.0;
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+fn sample(offset: u32) -> f32 {
+    let word = data[offset / 4u];
+    return f32((word >> ((offset % 4u) * 8u)) & 0xffu) / 255.0;
+}
+
+// the target is srgb, the frame is gamma encoded already
+fn to_linear(value: vec3<f32>) -> vec3<f32> {
+    let low = value / 12.92;
+    let high = pow((value + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, value <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fragment(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let x = u32(position.x);
+    let y = u32(position.y);
+    let plane = frame.height * frame.stride;
+
+    var luma: f32;
+    var u: f32;
+    var v: f32;
+    switch frame.layout {
+        case 0u: {
+            let pair = y * frame.stride + x / 2u * 4u;
+            luma = sample(pair + select(frame.y0, frame.y1, x % 2u == 1u));
+            u = sample(pair + frame.u);
+            v = sample(pair + frame.v);
+        }
+        case 1u: {
+            luma = sample(y * frame.stride + x);
+            let pair = plane + y / 2u * frame.stride + x / 2u * 2u;
+            u = sample(pair + frame.u);
+            v = sample(pair + frame.v);
+        }
+        default: {
+            luma = sample(y * frame.stride + x);
+            let chroma = y / 2u * (frame.stride / 2u) + x / 2u;
+            let chroma_plane = (frame.height + 1u) / 2u * (frame.stride / 2u);
+            u = sample(plane + chroma);
+            v = sample(plane + chroma_plane + chroma);
+        }
+    }
+
+    u -= 0.5;
+    v -= 0.5;
+    if frame.limited == 1u {
+        luma = (luma - 16.0 / 255.0) * (255.0 / 219.0);
+        u *= 255.0 / 224.0;
+        v *= 255.0 / 224.0;
+    }
+
+    // weights of red and blue in the luma, like the cpu conversion
+    var kr = 0.299;
+    var kb = 0.114;
+    if frame.matrix == 1u {
+        kr = 0.2126;
+        kb = 0.0722;
+    }
+    let kg = 1.0 - kr - kb;
+    let rgb = vec3<f32>(
+        luma + 2.0 * (1.0 - kr) * v,
+        luma - 2.0 * (1.0 - kb) * kb / kg * u - 2.0 * (1.0 - kr) * kr / kg * v,
+        luma + 2.0 * (1.0 - kb) * u,
+    );
+    return vec4<f32>(to_linear(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0))), 1.0);
+}
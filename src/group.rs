@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use tracing::{debug, info};
+
+use crate::{FrameId, FrameReceived, Input, Timestamp};
+
+/// Frames of a member kept for matching, a few frames of latency between the cameras
+const QUEUED: usize = 4;
+
+/// Inputs captured together, like the two cameras of a stereo rig. Spawned on an entity
+/// of its own with the entities of the inputs, the plugin starts their streams in the
+/// same update once all of them are open and pairs their frames by capture time.
+/// Members paused until then are resumed.
+///
+/// A [`SyncedFrames`] is sent whenever every member has a frame within the tolerance of
+/// the others, frames left out of it are counted as unmatched. Capture times are those
+/// of [`FrameReceived::capture_time`], the timestamps of the kernel unless the inputs
+/// have a [`MetadataInput`](crate::MetadataInput).
+#[derive(Component, Debug)]
+pub struct CaptureGroup {
+    members: Vec<Member>,
+    tolerance: Duration,
+    started: bool,
+    latest: Option<SyncedFrames>,
+    synced: u64,
+    unmatched: u64,
+    /// Unmatched since the last set that was sent
+    unreported: u64,
+}
+
+#[derive(Debug)]
+struct Member {
+    entity: Entity,
+    /// Oldest first
+    frames: VecDeque<SyncedFrame>,
+}
+
+/// A frame of a member of a [`CaptureGroup`]
+#[derive(Debug, Clone)]
+pub struct SyncedFrame {
+    /// The input the frame is of
+    pub entity: Entity,
+    /// Image of the input, it shows the frame until the input receives its next one
+    pub image: Handle<Image>,
+    pub frame: FrameId,
+    /// Timestamp of the buffer
+    pub timestamp: Timestamp,
+    /// See [`FrameReceived::capture_time`]
+    pub capture_time: Duration,
+}
+
+/// Sent when every member of a [`CaptureGroup`] has a frame within its tolerance of the
+/// others
+#[derive(Event, Debug, Clone)]
+pub struct SyncedFrames {
+    pub group: Entity,
+    /// A frame of every member, in the order of the members
+    pub frames: Vec<SyncedFrame>,
+    /// Between the earliest and the latest capture time of the frames
+    pub skew: Duration,
+    /// Frames of the members left unmatched since the previous set
+    pub unmatched: u64,
+}
+
+impl CaptureGroup {
+    /// Groups the inputs on `members`, frames are matched when their capture times are
+    /// at most `tolerance` apart, a few milliseconds for cameras of the same model
+    pub fn new(members: impl IntoIterator<Item = Entity>, tolerance: Duration) -> Self {
+        Self {
+            members: members
+                .into_iter()
+                .map(|entity| Member {
+                    entity,
+                    frames: VecDeque::with_capacity(QUEUED),
+                })
+                .collect(),
+            tolerance,
+            started: false,
+            latest: None,
+            synced: 0,
+            unmatched: 0,
+            unreported: 0,
+        }
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.members.iter().map(|member| member.entity)
+    }
+
+    pub fn tolerance(&self) -> Duration {
+        self.tolerance
+    }
+
+    /// Applies from the next frame on
+    pub fn set_tolerance(&mut self, tolerance: Duration) {
+        self.tolerance = tolerance;
+    }
+
+    /// Whether the streams of the members were started, once all of them were open
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// The last set of frames that was matched, like the last [`SyncedFrames`]
+    pub fn latest_synced_frames(&self) -> Option<&SyncedFrames> {
+        self.latest.as_ref()
+    }
+
+    /// Sets of frames matched so far
+    pub fn synced(&self) -> u64 {
+        self.synced
+    }
+
+    /// Frames of the members that weren't matched so far, too far apart from the
+    /// frames of the others or superseded by newer ones
+    pub fn unmatched(&self) -> u64 {
+        self.unmatched
+    }
+
+    fn contains(&self, entity: Entity) -> bool {
+        self.members.iter().any(|member| member.entity == entity)
+    }
+
+    /// Queues `frame` and returns the set of frames it completes, the frames of the
+    /// others closest to it when all of them are within the tolerance
+    fn push(&mut self, group: Entity, frame: SyncedFrame) -> Option<SyncedFrames> {
+        let member = self
+            .members
+            .iter_mut()
+            .find(|member| member.entity == frame.entity)?;
+        if member.frames.len() == QUEUED {
+            member.frames.pop_front();
+            self.unmatched += 1;
+            self.unreported += 1;
+        }
+        let time = frame.capture_time;
+        member.frames.push_back(frame);
+
+        let picks = self
+            .members
+            .iter()
+            .map(|member| {
+                member
+                    .frames
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, frame)| distance(frame.capture_time, time))
+                    .map(|(index, frame)| (index, frame.capture_time))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let earliest = picks.iter().map(|&(_, time)| time).min()?;
+        let latest = picks.iter().map(|&(_, time)| time).max()?;
+        let skew = latest - earliest;
+        if skew > self.tolerance {
+            return None;
+        }
+
+        // frames older than the ones matched can't be matched anymore
+        let mut frames = Vec::with_capacity(picks.len());
+        for (member, (index, _)) in self.members.iter_mut().zip(picks) {
+            frames.extend(member.frames.drain(..=index).next_back());
+            self.unmatched += index as u64;
+            self.unreported += index as u64;
+        }
+        let synced = SyncedFrames {
+            group,
+            frames,
+            skew,
+            unmatched: std::mem::take(&mut self.unreported),
+        };
+        self.synced += 1;
+        self.latest = Some(synced.clone());
+        Some(synced)
+    }
+}
+
+fn distance(a: Duration, b: Duration) -> Duration {
+    a.max(b) - a.min(b)
+}
+
+/// Holds the streams of the members of groups that aren't started yet, and starts all
+/// of them once the last one is open
+pub(crate) fn start_capture_groups(
+    mut groups: Query<&mut CaptureGroup>,
+    mut inputs: Query<&mut Input>,
+) {
+    for mut group in groups.iter_mut() {
+        if group.started {
+            continue;
+        }
+        let mut open = true;
+        for member in &group.members {
+            match inputs.get_mut(member.entity) {
+                // the streams of the first members wait for the others
+                Ok(mut input) if !input.is_paused() => input.pause(),
+                Ok(_) => {}
+                Err(_) => open = false,
+            }
+        }
+        if !open {
+            continue;
+        }
+
+        for member in &group.members {
+            if let Ok(mut input) = inputs.get_mut(member.entity) {
+                input.resume();
+            }
+        }
+        group.started = true;
+        info!(members = group.members.len(), "v4l capture group started");
+    }
+}
+
+/// Matches the frames received by members of groups and sends the sets
+pub(crate) fn pair_capture_groups(
+    mut groups: Query<(Entity, &mut CaptureGroup)>,
+    inputs: Query<&Input>,
+    mut received: EventReader<FrameReceived>,
+    mut synced: EventWriter<SyncedFrames>,
+) {
+    for frame in received.read() {
+        let Ok(input) = inputs.get(frame.entity) else {
+            continue;
+        };
+        for (entity, mut group) in groups.iter_mut() {
+            if !group.contains(frame.entity) {
+                continue;
+            }
+            let frame = SyncedFrame {
+                entity: frame.entity,
+                image: input.image().clone(),
+                frame: frame.frame,
+                timestamp: frame.timestamp,
+                capture_time: frame.capture_time(),
+            };
+            if let Some(set) = group.push(entity, frame) {
+                debug!(skew = ?set.skew, unmatched = set.unmatched, "v4l frames synced");
+                synced.send(set);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use openh264::decoder::Decoder;
+use openh264::formats::YUVSource;
+use tracing::warn;
+
+use crate::{Error, Result};
+
+/// Most bytes buffered while waiting for the end of an access unit. Streams that don't
+/// split into access units, like after a corrupted start code, are dropped past this.
+const MAX_PENDING: usize = 8 << 20;
+
+/// Nal unit types
+const SLICE: u8 = 1;
+const IDR: u8 = 5;
+const SEI: u8 = 6;
+const SPS: u8 = 7;
+const PPS: u8 = 8;
+const DELIMITER: u8 = 9;
+
+/// Decodes H264 captures on the cpu. Drivers may split access units across buffers,
+/// so they are collected until the next one starts, which delays frames by one.
+pub(crate) struct H264 {
+    /// Created with the first frame
+    decoder: Option<Decoder>,
+    /// Bytes not split into nal units yet, the last one may continue in the next buffer
+    pending: Vec<u8>,
+    /// Nal units of the access unit being collected, with their start codes
+    unit: Vec<u8>,
+    has_slice: bool,
+    has_idr: bool,
+    /// Set after dropping data, nothing is decoded until the next IDR frame
+    resync: bool,
+}
+
+impl H264 {
+    pub(crate) fn new() -> Self {
+        Self {
+            decoder: None,
+            pending: Vec::new(),
+            unit: Vec::new(),
+            has_slice: false,
+            has_idr: false,
+            // captures may start in the middle of a group of pictures
+            resync: true,
+        }
+    }
+
+    /// Buffers a dequeued buffer and decodes the access units it completes into the
+    /// rgba `dst`. Returns whether `dst` holds a new frame.
+    pub(crate) fn push(&mut self, buf: &[u8], dst: &mut [u8]) -> Result<bool> {
+        self.pending.extend_from_slice(buf);
+        if self.pending.len() + self.unit.len() > MAX_PENDING {
+            warn!(
+                bytes = self.pending.len() + self.unit.len(),
+                "no h264 access unit found, dropping the buffered stream"
+            );
+            self.pending.clear();
+            self.unit.clear();
+            (self.has_slice, self.has_idr) = (false, false);
+            self.resync = true;
+            return Ok(false);
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        let starts = start_codes(&pending);
+        let (mut decoded, mut error) = (false, None);
+        for window in starts.windows(2) {
+            let nal = &pending[window[0]..window[1]];
+            if starts_unit(nal) && self.has_slice {
+                match self.decode_unit(dst) {
+                    Ok(frame) => decoded |= frame,
+                    Err(err) => error = Some(err),
+                }
+            }
+
+            match nal_type(nal) {
+                SLICE => self.has_slice = true,
+                IDR => (self.has_slice, self.has_idr) = (true, true),
+                _ => {}
+            }
+            self.unit.extend_from_slice(nal);
+        }
+
+        // garbage before the first start code is dropped with the complete nal units
+        if let Some(&last) = starts.last() {
+            pending.drain(..last);
+        }
+        self.pending = pending;
+        error.map_or(Ok(decoded), Err)
+    }
+
+    /// Decodes the collected access unit, a frame that can't be decoded drops the stream
+    /// until the next IDR frame
+    fn decode_unit(&mut self, dst: &mut [u8]) -> Result<bool> {
+        self.has_slice = false;
+        let has_idr = std::mem::take(&mut self.has_idr);
+        if self.resync && !has_idr {
+            self.unit.clear();
+            return Ok(false);
+        }
+        self.resync = false;
+
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => self.decoder.insert(Decoder::new().map_err(decode_error)?),
+        };
+        let decoded = decoder.decode(&self.unit);
+        self.unit.clear();
+
+        let yuv = match decoded {
+            Ok(Some(yuv)) => yuv,
+            Ok(None) => return Ok(false),
+            Err(err) => {
+                self.resync = true;
+                return Err(decode_error(err));
+            }
+        };
+
+        let (width, height) = yuv.dimensions();
+        if dst.len() != width * height * 4 {
+            return Err(Error::Decode(format!(
+                "h264 frame is {width}x{height}, which doesn't match the format of the device"
+            )));
+        }
+        yuv.write_rgba8(dst);
+        Ok(true)
+    }
+}
+
+fn decode_error(err: openh264::Error) -> Error {
+    Error::Decode(format!("h264: {err}"))
+}
+
+/// Offsets of the 3 byte start codes of `data`, 4 byte ones start one byte earlier
+fn start_codes(data: &[u8]) -> Vec<usize> {
+    data.windows(3)
+        .enumerate()
+        .filter(|(_, window)| window == &[0, 0, 1])
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Type of a nal unit starting with its start code
+fn nal_type(nal: &[u8]) -> u8 {
+    nal.get(3).map_or(0, |header| header & 0x1f)
+}
+
+/// Whether a nal unit can only be the first of an access unit. Slices start one when
+/// their first macroblock is 0, encoded as a single set bit.
+fn starts_unit(nal: &[u8]) -> bool {
+    match nal_type(nal) {
+        DELIMITER | SPS | PPS | SEI => true,
+        SLICE | IDR => nal.get(4).is_some_and(|first_mb| first_mb & 0x80 != 0),
+        _ => false,
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::utils::futures;
+
+use crate::config;
+use crate::{
+    is_compressed, read_or_restart, FrameId, FrameReceived, ImageEncoding, Input, MetadataInput,
+    StreamRestarted, StreamStarted, Timestamp, V4lConfig, V4lError,
+};
+
+/// Frames a [`RawInput`] keeps until they are drained, see [`RawInput::set_capacity`]
+const CAPACITY: usize = 4;
+
+/// How a [`RawInput`] stores the frames it captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameLayout {
+    /// The bytes the driver dequeued, in the fourcc of [`Input::format`] with the rows
+    /// padded like the driver does
+    Native,
+    /// Converted like the image of an [`Input`] with this encoding
+    Converted(ImageEncoding),
+}
+
+/// A frame captured by a [`RawInput`]
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub layout: FrameLayout,
+    /// Format of the driver, `data` is in it for [`FrameLayout::Native`]
+    pub fourcc: [u8; 4],
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row of `data`, 0 for compressed formats
+    pub stride: u32,
+    pub frame: FrameId,
+    /// Capture time reported by the driver
+    pub timestamp: Timestamp,
+}
+
+/// An [`Input`] that keeps its frames in memory instead of an image, for apps that
+/// process frames without showing them. No image asset is allocated, frames aren't
+/// uploaded and [`FrameLayout::Native`] frames aren't converted either.
+/// Built with [`InputBuilder::build_raw`](crate::InputBuilder::build_raw).
+///
+/// The latest frames are kept until they are drained, a [`FrameReceived`] is sent for
+/// every one. The [`Input`] methods are available through `Deref`, those of images do
+/// nothing.
+#[derive(Component)]
+pub struct RawInput {
+    input: Input,
+    layout: FrameLayout,
+    frames: VecDeque<Frame>,
+    capacity: usize,
+    /// Buffer of a dropped frame, reused for the next one
+    spare: Option<Vec<u8>>,
+}
+
+impl RawInput {
+    pub(crate) fn new(input: Input, layout: FrameLayout) -> Self {
+        Self {
+            input,
+            layout,
+            frames: VecDeque::with_capacity(CAPACITY),
+            capacity: CAPACITY,
+            spare: None,
+        }
+    }
+
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    /// Latest frame, `None` until the first one is captured or after the frames were
+    /// drained
+    pub fn latest(&self) -> Option<&Frame> {
+        self.frames.back()
+    }
+
+    /// Takes the kept frames, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        self.frames.drain(..)
+    }
+
+    /// Frames kept until they are drained, the oldest is dropped for a new one. 4 by
+    /// default, at least 1.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.frames.len() > self.capacity {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Takes the frame of the exchange of the device into a new frame, returns whether
+    /// there was one
+    fn push(&mut self, frame: FrameId, timestamp: Timestamp) -> bool {
+        let device = &self.input.device;
+        let fourcc = device.format.fourcc.repr;
+        let (width, height, stride) = match self.layout {
+            FrameLayout::Native if is_compressed(&fourcc) => {
+                (device.format.width, device.format.height, 0)
+            }
+            FrameLayout::Native => (
+                device.format.width,
+                device.format.height,
+                device.format.stride,
+            ),
+            FrameLayout::Converted(encoding) => (
+                device.size.width,
+                device.size.height,
+                device.size.width * encoding.bytes_per_pixel() as u32,
+            ),
+        };
+
+        if self.frames.len() >= self.capacity {
+            self.spare = self.frames.pop_front().map(|frame| frame.data);
+        }
+        let mut data = self.spare.take().unwrap_or_default();
+        // converted frames are written into the buffer the exchange gets, it must be
+        // of their size
+        if let FrameLayout::Converted(encoding) = self.layout {
+            let pixels = (device.size.width * device.size.height) as usize;
+            data.resize(pixels * encoding.bytes_per_pixel(), 255);
+        }
+        if device.exchange.take(&mut data).is_none() {
+            self.spare = Some(data);
+            return false;
+        }
+
+        self.frames.push_back(Frame {
+            data,
+            layout: self.layout,
+            fourcc,
+            width,
+            height,
+            stride,
+            frame,
+            timestamp,
+        });
+        true
+    }
+}
+
+impl Deref for RawInput {
+    type Target = Input;
+
+    fn deref(&self) -> &Input {
+        &self.input
+    }
+}
+
+impl DerefMut for RawInput {
+    fn deref_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+}
+
+/// Like [`spawn_input_tasks`](crate::spawn_input_tasks), without an image to size
+/// frames by
+pub(crate) fn spawn_raw_tasks(mut inputs: Query<&mut RawInput>) {
+    for mut input in inputs.iter_mut() {
+        let device = &mut input.input.device;
+        if device.closed || device.task.is_some() || device.is_paused() {
+            continue;
+        }
+
+        let id = device.id;
+        let fourcc = device.format.fourcc.repr;
+        let (width, height) = (device.size.width, device.size.height);
+        let io = device.io.clone();
+        let span = device.span.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let _span = span.enter();
+            if let Ok(mut io) = io.lock() {
+                read_or_restart(&mut io, id, &fourcc, width, height);
+            };
+        });
+
+        device.task = Some(task);
+    }
+}
+
+/// Keeps the frames of finished tasks and sends the events of their devices
+pub(crate) fn poll_raw_inputs(
+    mut inputs: Query<(Entity, &mut RawInput, Option<&MetadataInput>)>,
+    mut started: EventWriter<StreamStarted>,
+    mut received: EventWriter<FrameReceived>,
+    mut restarts: EventWriter<StreamRestarted>,
+    mut errors: EventWriter<V4lError>,
+    config: Res<V4lConfig>,
+) {
+    for (entity, mut input, metadata) in inputs.iter_mut() {
+        let input = &mut *input;
+        let Some(task) = input.input.device.task.as_mut() else {
+            continue;
+        };
+        if futures::check_ready(task).is_none() {
+            continue;
+        }
+        input.input.device.task = None;
+
+        let io = input.input.device.io.clone();
+        let Ok(mut io) = io.lock() else {
+            continue;
+        };
+
+        let device = &input.input.device;
+        started.send_batch(device.started(io.frames.last(), entity));
+        input.input.device.frame = io.frames.last();
+
+        let frame = io.received.take();
+        if let Some(frame) = frame.filter(|frame| input.push(frame.frame, frame.timestamp)) {
+            let frame = match metadata {
+                Some(metadata) => metadata.correlate(frame),
+                None => frame,
+            };
+            received.send(FrameReceived { entity, ..frame });
+        }
+
+        let device = &mut input.input.device;
+        if let Some((attempt, error)) = io.restarted.take() {
+            restarts.send(StreamRestarted {
+                entity,
+                device: device.id,
+                label: device.label().to_string(),
+                attempt,
+                error,
+            });
+        }
+        let reopened = io
+            .watchdog
+            .as_mut()
+            .and_then(|watchdog| watchdog.reopened.take());
+        if let Some(dev) = reopened {
+            device.dev = Some(dev);
+        }
+        if let Some(error) = io.error.take() {
+            let event = device.error_event(entity, error);
+            config::report(&config, &mut errors, device.errors.pass(event));
+        }
+    }
+}
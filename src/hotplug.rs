@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use tracing::{debug, warn};
+
+use crate::devices::{DeviceInfo, V4lDevices};
+
+/// How often the device nodes are listed. Listing /dev is a single directory read,
+/// nodes are only queried when they show up.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sent when a v4l device node appears or disappears, [`V4lDevices`] is updated
+/// before the event is sent
+#[derive(Event, Debug, Clone)]
+pub enum V4lDeviceEvent {
+    Connected(DeviceInfo),
+    /// With the info the node had while it was connected
+    Disconnected(DeviceInfo),
+}
+
+impl V4lDeviceEvent {
+    pub fn info(&self) -> &DeviceInfo {
+        match self {
+            Self::Connected(info) | Self::Disconnected(info) => info,
+        }
+    }
+}
+
+/// Changes found by the monitor thread, which exits once this is dropped
+#[derive(Resource)]
+pub(crate) struct Hotplug(Mutex<Receiver<V4lDeviceEvent>>);
+
+impl Hotplug {
+    /// Watches for nodes that aren't in `devices` yet or are gone from it
+    pub(crate) fn spawn(devices: &V4lDevices) -> Option<Self> {
+        let known = devices
+            .all()
+            .iter()
+            .map(|info| (info.id, info.clone()))
+            .collect();
+        let (sender, receiver) = mpsc::channel();
+
+        match std::thread::Builder::new()
+            .name("v4l hotplug".into())
+            .spawn(move || monitor(known, sender))
+        {
+            Ok(_) => Some(Self(Mutex::new(receiver))),
+            Err(err) => {
+                warn!("failed to start v4l hotplug monitor: {err}");
+                None
+            }
+        }
+    }
+}
+
+fn monitor(mut known: BTreeMap<usize, DeviceInfo>, sender: Sender<V4lDeviceEvent>) {
+    loop {
+        std::thread::sleep(SCAN_INTERVAL);
+        let nodes = v4l::context::enum_devices();
+
+        let gone: Vec<usize> = known
+            .keys()
+            .filter(|id| !nodes.iter().any(|node| node.index() == **id))
+            .copied()
+            .collect();
+        for info in gone.iter().filter_map(|id| known.remove(id)) {
+            if sender.send(V4lDeviceEvent::Disconnected(info)).is_err() {
+                return;
+            }
+        }
+
+        let new: Vec<_> = nodes
+            .iter()
+            .filter(|node| !known.contains_key(&node.index()))
+            .collect();
+        for node in new {
+            // udev may not have set the permissions of a new node yet, it is
+            // queried again on the next scan
+            let info = match DeviceInfo::query(node.index(), node.path()) {
+                Ok(info) => info,
+                Err(err) => {
+                    debug!(
+                        "can't query new v4l device {}: {err}",
+                        node.path().display()
+                    );
+                    continue;
+                }
+            };
+            known.insert(info.id, info.clone());
+            if sender.send(V4lDeviceEvent::Connected(info)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+pub(crate) fn send_device_events(
+    hotplug: Option<Res<Hotplug>>,
+    mut devices: ResMut<V4lDevices>,
+    mut events: EventWriter<V4lDeviceEvent>,
+) {
+    let Some(hotplug) = hotplug else {
+        return;
+    };
+    let Ok(receiver) = hotplug.0.lock() else {
+        return;
+    };
+
+    for event in receiver.try_iter() {
+        devices.apply(&event);
+        events.send(event);
+    }
+}
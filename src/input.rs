@@ -0,0 +1,990 @@
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(feature = "snapshot")]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::math::URect;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureUsages};
+use v4l::FourCC;
+
+use crate::bayer::Bayer;
+use crate::control::{
+    self, ControlDescriptor, ControlError, ControlId, ControlStep, ControlValue, Exposure,
+    WhiteBalance,
+};
+use crate::crop::{self, Crop};
+use crate::denoise::TemporalFilter;
+use crate::devices::{
+    self, enumerate_devices, Capabilities, DeviceInfo, DeviceSelector, Selection,
+};
+use crate::dump::{Dumper, RecordMode};
+use crate::file::FileSource;
+use crate::input_builder::InputBuilder;
+use crate::inspect::DeviceStatus;
+use crate::late;
+use crate::memory::Buffers;
+use crate::mock::{MockSource, MockStep};
+use crate::open::OpenedInput;
+use crate::pattern::TestPattern;
+use crate::preference::{self, FormatRequest};
+use crate::profile::{self, Profile};
+use crate::reconnect::Connection;
+#[cfg(feature = "snapshot")]
+use crate::snapshot::{Frame, SnapshotFormat, Snapshots};
+use crate::subscribe::{self, FrameRef, Subscribers};
+use crate::target::{Target, TargetOptions};
+use crate::{
+    ColorMetadata, Colorimetry, Deinterlace, Device, Error, Format, FrameId, FrameProcessor,
+    ImageEncoding, NegotiationReport, Orientation, PixelAspect, PixelConverter, Presented, Result,
+    SignalState, StreamStats, Timestamp,
+};
+
+/// Reflected for inspectors, which see the [`DeviceStatus`] of the device
+#[derive(Component, Reflect)]
+#[reflect(from_reflect = false)]
+pub struct Input {
+    #[reflect(ignore)]
+    pub(crate) device: Device,
+    #[reflect(ignore)]
+    pub(crate) selection: Selection,
+    #[reflect(ignore)]
+    pub(crate) info: Option<DeviceInfo>,
+    #[reflect(ignore)]
+    pub(crate) decoder: Decoder,
+    #[reflect(ignore)]
+    pub(crate) encoding: ImageEncoding,
+    /// Set with [`InputBuilder::colorimetry`]
+    #[reflect(ignore)]
+    pub(crate) colorimetry: Option<Colorimetry>,
+    #[reflect(ignore)]
+    pub(crate) preview: Option<Handle<Image>>,
+    /// Formats to switch to by name, see [`Input::switch_profile`]
+    #[reflect(ignore)]
+    pub(crate) profiles: HashMap<String, Profile>,
+    /// Switched to by the plugin before the next frame
+    #[reflect(ignore)]
+    pub(crate) pending_profile: Option<String>,
+    /// Size before [`Input::set_format`], until the plugin sent the [`FormatChanged`](crate::FormatChanged)
+    #[reflect(ignore)]
+    pub(crate) format_changed: Option<UVec2>,
+    /// Shared with the io task, see [`Input::set_active`]
+    #[reflect(ignore)]
+    pub(crate) active: Arc<AtomicBool>,
+    /// Shared with the io task, see [`Input::subscribe`]
+    #[reflect(ignore)]
+    pub(crate) subscribers: Subscribers,
+    #[reflect(ignore)]
+    pub(crate) throttle_hidden: bool,
+    /// Frames go from the io buffer to the texture, see [`InputBuilder::late_upload`]
+    #[reflect(ignore)]
+    pub(crate) late_upload: bool,
+    /// Frames are only read on request, see [`InputBuilder::single_shot`]
+    #[reflect(ignore)]
+    pub(crate) single_shot: bool,
+    /// Mirrored in [`Io`] for the task converting the frames, see [`Input::set_orientation`]
+    #[reflect(ignore)]
+    pub(crate) orientation: Orientation,
+    /// See [`Input::set_crop`]
+    #[reflect(ignore)]
+    pub(crate) crop: Option<Crop>,
+    /// See [`Input::request_snapshot`]
+    #[cfg(feature = "snapshot")]
+    #[reflect(ignore)]
+    pub(crate) snapshots: Snapshots,
+    /// Set by [`Input::request_frame`] until the frame is in the image
+    #[reflect(ignore)]
+    pub(crate) frame_requested: bool,
+    /// See [`Input::signal`], updated by the plugin
+    #[reflect(ignore)]
+    pub(crate) signal: SignalState,
+    #[reflect(ignore)]
+    pub(crate) connection: Connection,
+    pub(crate) status: DeviceStatus,
+}
+
+/// Where captured frames are converted to rgba
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoder {
+    Cpu,
+    /// Memory-to-memory device (/dev/video{id})
+    M2m {
+        id: usize,
+    },
+    /// Shader in the render world, see [`InputBuilder::gpu_conversion`]
+    Gpu,
+}
+
+impl Input {
+    /// [`Input::id`] of inputs that don't read from a v4l device
+    pub const VIRTUAL_ID: usize = usize::MAX;
+
+    /// Creates a V4lDevice for encoding a bevy image into v4l
+    pub fn new(device_id: usize, images: &mut Assets<Image>) -> Result<Self> {
+        let dev = devices::open_path(&crate::device_path(device_id))?;
+        let opened = OpenedInput::new(
+            dev,
+            device_id,
+            DeviceSelector::Index(device_id),
+            None,
+            None,
+            Buffers::default(),
+            true,
+        )?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Like [`Input::new`], but sets `format` on the device first, see
+    /// [`InputBuilder::format`]
+    pub fn with_format(
+        device_id: usize,
+        format: Format,
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        Self::builder()
+            .device(device_id)
+            .format(format)
+            .build(images)
+    }
+
+    /// Like [`Input::new`], but converts frames of the formats `converter` takes with
+    /// it, see [`InputBuilder::converter`]
+    pub fn with_converter(
+        device_id: usize,
+        converter: impl PixelConverter + 'static,
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        Self::builder()
+            .device(device_id)
+            .converter(converter)
+            .build(images)
+    }
+
+    /// Like [`Input::new`], but sets the first of `preferences` the device takes,
+    /// probing them in order. Returns the index of the one that was set, see
+    /// [`FormatRequest`].
+    ///
+    /// Fails with [`Error::NoPreferredFormat`], listing what the device offers, when
+    /// the driver takes none of them.
+    pub fn new_with_preferences(
+        device_id: usize,
+        preferences: &[FormatRequest],
+        images: &mut Assets<Image>,
+    ) -> Result<(Self, usize)> {
+        let dev = devices::open_path(&crate::device_path(device_id))?;
+        let (index, format) = preference::select(&dev, preferences)?;
+        let opened = OpenedInput::new(
+            dev,
+            device_id,
+            DeviceSelector::Index(device_id),
+            None,
+            Some(&format),
+            Buffers::default(),
+            true,
+        )?;
+        Ok((opened.into_input(images), index))
+    }
+
+    /// Opens the capture device at `path`, which may be a stable udev symlink like
+    /// /dev/v4l/by-id/usb-...-video-index0 that doesn't change between reboots
+    pub fn from_path(path: impl AsRef<Path>, images: &mut Assets<Image>) -> Result<Self> {
+        let selector = DeviceSelector::from(path.as_ref());
+        let (dev, id) = selector.open()?;
+        let opened = OpenedInput::new(dev, id, selector, None, None, Buffers::default(), true)?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Opens the capture device whose name contains `name` or whose bus info is `name`,
+    /// ignoring case. An exact match wins over devices whose name only contains `name`,
+    /// metadata nodes next to the camera are never matched.
+    ///
+    /// Fails when no device matches and when several do, listing the candidates.
+    pub fn by_name(name: &str, images: &mut Assets<Image>) -> Result<Self> {
+        let devices = enumerate_devices();
+        let info = devices::find_by_name(&devices, name, false)?;
+        let opened = OpenedInput::new(
+            devices::open_path(&info.path)?,
+            info.id,
+            DeviceSelector::name(name),
+            None,
+            None,
+            Buffers::default(),
+            true,
+        )?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Like [`Input::by_name`], but takes the lowest numbered device when
+    /// more than one matches.
+    pub fn first_by_name(name: &str, images: &mut Assets<Image>) -> Result<Self> {
+        let devices = enumerate_devices();
+        let info = devices::find_by_name(&devices, name, true)?;
+        let opened = OpenedInput::new(
+            devices::open_path(&info.path)?,
+            info.id,
+            DeviceSelector::name(name),
+            None,
+            None,
+            Buffers::default(),
+            true,
+        )?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Opens the first capture device that streams a format this crate can convert,
+    /// see [`InputBuilder::default_device`]
+    pub fn default_device(images: &mut Assets<Image>) -> Result<Self> {
+        Self::builder().default_device().build(images)
+    }
+
+    /// Tries each selector in order and opens the first device that is present,
+    /// not busy and streams a format this crate can decode.
+    ///
+    /// Use [`PendingInput`](crate::PendingInput) to do the probing without blocking the
+    /// calling system.
+    pub fn first_available(
+        selectors: &[DeviceSelector],
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        Ok(
+            OpenedInput::first_available(selectors, None, None, Buffers::default(), true, false)?
+                .into_input(images),
+        )
+    }
+
+    /// Replays a file written by [`Input::dump_to`] at `fps` frames per second.
+    /// Frames go through the same conversion as frames from a device.
+    ///
+    /// When `looping` is false the last frame is held once the file ends.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        looping: bool,
+        fps: f32,
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let (source, format) = FileSource::dump(path, looping, fps)?;
+        let opened = OpenedInput::virtual_source(source, format, DeviceSelector::from(path))?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Replays a file of headerless `width`x`height` frames in `fourcc`, like a `.yuv` file,
+    /// see [`Input::from_file`]
+    pub fn from_raw_file(
+        path: impl AsRef<Path>,
+        width: u32,
+        height: u32,
+        fourcc: [u8; 4],
+        looping: bool,
+        fps: f32,
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let format = v4l::Format::new(width, height, FourCC::new(&fourcc));
+        let source = FileSource::raw(path, &format, looping, fps)?;
+        let opened = OpenedInput::virtual_source(source, format, DeviceSelector::from(path))?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Generates `pattern` as `width`x`height` frames in `fourcc` at `fps` frames per second,
+    /// for running without a camera. YUYV and AB24 are supported.
+    pub fn test_pattern(
+        pattern: TestPattern,
+        width: u32,
+        height: u32,
+        fourcc: [u8; 4],
+        fps: f32,
+        images: &mut Assets<Image>,
+    ) -> Result<Self> {
+        let opened = OpenedInput::test_pattern(pattern, width, height, fourcc, fps)?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Dequeues `steps` at `fps` steps per second, frames of `width`x`height` in `fourcc`
+    /// or the failures of a device, for testing apps and the plugin without one. Frames
+    /// go through the same conversion and error handling as frames from a device, the
+    /// input waits for frames once the steps ran out.
+    pub fn mock<I>(
+        width: u32,
+        height: u32,
+        fourcc: [u8; 4],
+        fps: f32,
+        steps: I,
+        images: &mut Assets<Image>,
+    ) -> Result<Self>
+    where
+        I: IntoIterator<Item = MockStep>,
+        I::IntoIter: Send + 'static,
+    {
+        let format = v4l::Format::new(width, height, FourCC::new(&fourcc));
+        let source = MockSource::new(steps, fps);
+        let opened =
+            OpenedInput::virtual_source(source, format, DeviceSelector::name("mock input"))?;
+        Ok(opened.into_input(images))
+    }
+
+    /// Configures an Input before opening it
+    pub fn builder() -> InputBuilder {
+        InputBuilder::default()
+    }
+
+    /// Adds an image the size and [`ImageEncoding`] of the input's own, cameras can render
+    /// into it and outputs read it back, see [`OutputBuilder::gpu_readback`](crate::OutputBuilder::gpu_readback)
+    pub fn clone_image(&mut self, images: &mut ResMut<Assets<Image>>) -> Handle<Image> {
+        let pixels = (self.device.size.width * self.device.size.height) as usize;
+        let buffer = vec![255_u8; pixels * self.encoding.bytes_per_pixel()];
+        images.add(Image {
+            data: buffer,
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size: self.device.size,
+                dimension: TextureDimension::D2,
+                format: self.encoding.texture_format(),
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_DST
+                    | TextureUsages::COPY_SRC
+                    | TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            },
+            asset_usage: RenderAssetUsages::all(),
+            ..default()
+        })
+    }
+
+    /// Handle to bevy image
+    pub fn image(&self) -> &Handle<Image> {
+        &self.device.image
+    }
+
+    /// ID of the v4l video device (/dev/video{id}), [`Input::VIRTUAL_ID`] for
+    /// inputs that don't read from a device
+    pub fn id(&self) -> usize {
+        self.device.id
+    }
+
+    /// Path the device was opened with, like a /dev/v4l/by-id/* symlink for inputs
+    /// opened with [`Input::from_path`]. `None` for inputs that don't read from a device.
+    pub fn path(&self) -> Option<&Path> {
+        self.device.path.as_deref()
+    }
+
+    pub fn format(&self) -> Format {
+        Format(self.device.format)
+    }
+
+    /// Stream buffers the driver allocated, see [`InputBuilder::buffer_count`].
+    /// `None` for inputs that don't read from a device.
+    pub fn buffer_count(&self) -> Option<u32> {
+        self.device.report.buffers
+    }
+
+    /// Time between frames the driver granted, see [`InputBuilder::frame_interval`].
+    /// `None` for drivers and virtual inputs that don't report one.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        self.device.frame_interval
+    }
+
+    /// Size of the image, turned by the [`Orientation`] of the input
+    pub fn size(&self) -> Extent3d {
+        self.device.size
+    }
+
+    /// Latest frame captured, updated once per frame
+    pub fn last_frame(&self) -> Option<FrameId> {
+        self.device.frame
+    }
+
+    /// When the frame in the image was captured, for syncing it with other media. Check
+    /// its [`TimestampSource`](crate::TimestampSource), drivers whose clock is unknown
+    /// get the time the frame was dequeued.
+    pub fn last_frame_timestamp(&self) -> Option<Timestamp> {
+        self.last_presented().map(|presented| presented.timestamp)
+    }
+
+    /// The frame in the image, with when it was captured and swapped in
+    pub fn last_presented(&self) -> Option<Presented> {
+        self.device.exchange.presented()
+    }
+
+    /// Time from capture of the frame in the image to its swap into the image, see
+    /// [`Presented::latency`]
+    pub fn capture_latency(&self) -> Option<Duration> {
+        self.last_presented()?.latency()
+    }
+
+    /// Frames dequeued, presented and dropped, and the errors of the stream, like for
+    /// a status badge. Counted since the device was opened or reconnected.
+    pub fn stats(&self) -> StreamStats {
+        self.device.health.stats()
+    }
+
+    /// How the capture format was arrived at
+    pub fn negotiation(&self) -> &NegotiationReport {
+        &self.device.report
+    }
+
+    /// Shape of the pixels of the device, square unless the driver says otherwise
+    pub fn pixel_aspect(&self) -> PixelAspect {
+        self.device.report.pixel_aspect
+    }
+
+    /// Size to show the image at so it isn't squashed, the width scaled by the
+    /// [`PixelAspect`], the height of rotated images. Meant for `Sprite::custom_size`,
+    /// scale it to fit as needed.
+    pub fn display_size(&self) -> Vec2 {
+        let size = self.device.size;
+        let ratio = self.pixel_aspect().ratio();
+        match self.orientation.swaps_size() {
+            true => Vec2::new(size.width as f32, size.height as f32 * ratio),
+            false => Vec2::new(size.width as f32 * ratio, size.height as f32),
+        }
+    }
+
+    /// The device that was opened, `None` for virtual inputs
+    pub fn info(&self) -> Option<&DeviceInfo> {
+        self.info.as_ref()
+    }
+
+    /// Which selector the device was opened with, and why earlier ones were skipped
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    pub fn decoder(&self) -> Decoder {
+        self.decoder
+    }
+
+    /// Handle to the downscaled image, see [`InputBuilder::preview`]
+    pub fn preview(&self) -> Option<&Handle<Image>> {
+        self.preview.as_ref()
+    }
+
+    /// How the rgba in the image relates to the colors of the device
+    pub fn color(&self) -> ColorMetadata {
+        ColorMetadata::new(
+            &self.device.format,
+            self.decoder,
+            self.encoding,
+            self.colorimetry,
+        )
+    }
+
+    /// Writes the next `max_frames` buffers, exactly as they are dequeued, to `path`.
+    /// See [`InputBuilder::dump_to`].
+    pub fn dump_to(&self, path: impl AsRef<Path>, max_frames: usize) -> Result<()> {
+        let dumper = self
+            .device
+            .span
+            .in_scope(|| Dumper::new(path.as_ref(), self.device.format, max_frames))?;
+        if let Ok(mut io) = self.device.io.lock() {
+            io.dump = Some(dumper);
+        }
+        Ok(())
+    }
+
+    /// Saves the frame in the image to `path`, blocking until it is written. The image
+    /// only changes while the plugin polls, the frame is never half swapped in. Fails
+    /// for [`InputBuilder::late_upload`] and gpu converted inputs, whose frames are only
+    /// on the gpu.
+    ///
+    /// Images of linear or 16 bit encodings are written gamma encoded with 8 bits per
+    /// channel, like [`ImageEncoding::Srgb`] ones.
+    #[cfg(feature = "snapshot")]
+    pub fn save_snapshot(
+        &self,
+        images: &Assets<Image>,
+        path: impl AsRef<Path>,
+        format: SnapshotFormat,
+    ) -> Result<()> {
+        let frame = Frame::copy(self, images)?;
+        self.device
+            .span
+            .in_scope(|| frame.save(path.as_ref(), format))
+    }
+
+    /// Like [`Input::save_snapshot`], but writes the frame swapped in with the next poll
+    /// on the io task pool and sends a [`SnapshotSaved`](crate::SnapshotSaved) once it is written
+    #[cfg(feature = "snapshot")]
+    pub fn request_snapshot(&mut self, path: impl Into<PathBuf>, format: SnapshotFormat) {
+        self.snapshots.requested.push((path.into(), format));
+    }
+
+    /// Records the buffers dequeued from now on, exactly as they are dequeued, to `path`
+    /// until [`Input::stop_recording`]. Replaces a running recording or dump.
+    /// [`Input::from_file`] replays recordings through the same conversion.
+    ///
+    /// Every frame starts with a little endian header: `"V4LF"`, the fourcc, width,
+    /// height, stride, bytesused and sequence as `u32`, and the timestamp in
+    /// microseconds as `u64`, followed by the `bytesused` bytes of the buffer. Frames
+    /// are written on a separate thread, ones it can't keep up with are dropped
+    /// instead of holding up capture.
+    pub fn start_recording(&self, path: impl AsRef<Path>, mode: RecordMode) -> Result<()> {
+        let dumper = self
+            .device
+            .span
+            .in_scope(|| Dumper::recording(path.as_ref(), self.device.format, mode))?;
+        if let Ok(mut io) = self.device.io.lock() {
+            io.dump = Some(dumper);
+        }
+        Ok(())
+    }
+
+    /// Stops a recording or dump, the frames of [`RecordMode::Ring`] recordings are
+    /// written then
+    pub fn stop_recording(&self) {
+        if let Ok(mut io) = self.device.io.lock() {
+            io.dump = None;
+        }
+    }
+
+    /// Changes the strength of the temporal filter, see [`InputBuilder::denoise`].
+    /// `None` turns it off.
+    pub fn set_denoise(&mut self, strength: Option<f32>) {
+        if let Ok(mut io) = self.device.io.lock() {
+            match (strength, io.denoise.as_mut()) {
+                (Some(strength), Some(denoise)) => denoise.set_strength(strength),
+                (strength, _) => io.denoise = strength.map(TemporalFilter::new),
+            }
+        }
+    }
+
+    /// How the fields of interlaced frames are combined, see [`InputBuilder::deinterlace`]
+    pub fn deinterlace(&self) -> Deinterlace {
+        let io = self.device.io.lock().ok();
+        io.and_then(|io| io.deinterlace.as_ref().map(|deinterlace| deinterlace.mode))
+            .unwrap_or_default()
+    }
+
+    /// Changes how the fields of interlaced frames are combined from the next frame on
+    pub fn set_deinterlace(&mut self, deinterlace: Deinterlace) {
+        if let Ok(mut io) = self.device.io.lock() {
+            if let Some(deinterlacer) = io.deinterlace.as_mut() {
+                deinterlacer.mode = deinterlace;
+            }
+        }
+    }
+
+    /// How frames are turned, see [`InputBuilder::orientation`]
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Turns frames from the next one on. The image is replaced under the same handle
+    /// when its width and height are swapped, and targets sized for the previous
+    /// orientation are removed.
+    pub fn set_orientation(&mut self, orientation: Orientation, images: &mut Assets<Image>) {
+        let previous = self.orientation;
+        let device = &mut self.device;
+        let Ok(mut io) = device.io.lock() else {
+            return;
+        };
+        // the shader and native raw frames show the buffers as they are dequeued
+        if io.native || orientation == previous {
+            return;
+        }
+        io.orientation = orientation;
+        self.orientation = orientation;
+        // the average of the previous orientation would smear over the frames
+        if let Some(denoise) = io.denoise.as_mut() {
+            denoise.reset();
+        }
+        if orientation.swaps_size() == previous.swaps_size() {
+            return;
+        }
+
+        let size = Extent3d {
+            width: device.size.height,
+            height: device.size.width,
+            depth_or_array_layers: 1,
+        };
+        let len = io.buffer.len();
+        io.targets.clear();
+        io.fresh = false;
+        device.exchange.reset(len);
+        let image = Image::new(
+            size,
+            TextureDimension::D2,
+            vec![255; len],
+            self.encoding.texture_format(),
+            late::image_usage(self.late_upload),
+        );
+        images.insert(&device.image, image);
+        device.size = size;
+    }
+
+    /// Region of the frames the image shows, `None` for whole frames
+    pub fn crop(&self) -> Option<Crop> {
+        self.crop
+    }
+
+    /// Shows the region `rect` of the frames from the next one on, like the part of
+    /// a wide angle camera that's of interest. The region is of the frames as they
+    /// are captured, before they are turned. The image is replaced under the same
+    /// handle with one of the size of the region and targets are removed, like after
+    /// a profile switch.
+    ///
+    /// The driver crops when it can, it may adjust the region and gives frames of
+    /// the format it reports afterwards, scaled by some. The returned [`Crop`] holds
+    /// the region it granted. Otherwise frames are captured whole and only the region
+    /// is converted, which fails with [`Error::InvalidCrop`](crate::Error::InvalidCrop)
+    /// for regions outside the frames, ones that split the blocks of the chroma
+    /// subsampling, like an odd left edge for YUYV, and for compressed formats.
+    /// Switching the format, like to a profile, converts whole frames again, crops of
+    /// the driver last as long as the driver keeps them.
+    pub fn set_crop(&mut self, rect: URect, images: &mut Assets<Image>) -> Result<Crop> {
+        crop::set(self, rect, images)
+    }
+
+    /// Switches the device to `format` without despawning the input, like for a
+    /// resolution setting. Waits for the frame being captured, sets the stream up again
+    /// with buffers for the new format and resizes the image in place, so handles to it
+    /// show frames of the new format. A [`FormatChanged`](crate::FormatChanged) is sent
+    /// when the plugin runs next.
+    ///
+    /// When the driver rejects the format, or the stream can't be set up for it, the
+    /// input keeps streaming in the previous one. Like with [`Input::switch_profile`],
+    /// targets are removed, and inputs converting on an m2m device and virtual inputs
+    /// can't switch.
+    pub fn set_format(&mut self, format: Format, images: &mut Assets<Image>) -> Result<()> {
+        profile::set_format(self, format, images)
+    }
+
+    /// Timings the hdmi or dvi receiver of the device detects, like 1920x1080p60 of a
+    /// source plugged into an HDMI to CSI bridge. Fails with
+    /// [`Error::NoSignal`] without a source, and for devices
+    /// without a receiver. The timings aren't applied, see [`Input::set_dv_timings`].
+    #[cfg(feature = "dv-timings")]
+    pub fn query_dv_timings(&self) -> Result<crate::DvTimings> {
+        match &self.device.dev {
+            Some(dev) => crate::dv::query(dev),
+            None => Err(Error::Io(std::io::ErrorKind::Unsupported.into())),
+        }
+    }
+
+    /// Sets the timings of the receiver of the device, like the ones of
+    /// [`Input::query_dv_timings`]. Like after a source change, the stream is set up
+    /// again in the format the driver reports for the timings, the image is resized in
+    /// place and a [`FormatChanged`](crate::FormatChanged) is sent when the plugin runs
+    /// next. When the driver rejects them the input keeps streaming in the previous
+    /// format.
+    #[cfg(feature = "dv-timings")]
+    pub fn set_dv_timings(
+        &mut self,
+        timings: crate::DvTimings,
+        images: &mut Assets<Image>,
+    ) -> Result<()> {
+        crate::dv::set(self, timings, images)
+    }
+
+    /// Sets the EDID the receiver of the device advertises to sources, blocks of 128
+    /// bytes. Sources read it after the hotplug pulse most receivers send, to pick the
+    /// timings they send. An empty EDID clears it, sources see no sink until one is set.
+    #[cfg(feature = "dv-timings")]
+    pub fn set_edid(&self, edid: &[u8]) -> Result<()> {
+        match &self.device.dev {
+            Some(dev) => crate::dv::set_edid(dev, edid),
+            None => Err(Error::Io(std::io::ErrorKind::Unsupported.into())),
+        }
+    }
+
+    /// Shows the whole frames again, see [`Input::set_crop`]
+    pub fn clear_crop(&mut self, images: &mut Assets<Image>) -> Result<()> {
+        crop::clear(self, images)
+    }
+
+    /// Red, green and blue gains applied to the latest frame of Bayer inputs,
+    /// see [`BayerConfig`](crate::BayerConfig)
+    pub fn bayer_gains(&self) -> Option<[f32; 3]> {
+        let io = self.device.io.lock().ok()?;
+        io.bayer.as_ref().map(Bayer::gains)
+    }
+
+    /// Restarts the average of the temporal filter, like after a scene cut
+    pub fn reset_denoise(&mut self) {
+        if let Ok(mut io) = self.device.io.lock() {
+            if let Some(denoise) = io.denoise.as_mut() {
+                denoise.reset();
+            }
+        }
+    }
+
+    /// Also writes every frame into `image`, converted for `options`, like an sRGB input
+    /// with a linear copy for compute shaders. Conversion happens off the main thread.
+    ///
+    /// The image has to be the size of the input and have the texture format of
+    /// the encoding. Targets with the encoding of the input's own image are rejected
+    /// unless they mirror it, share its handle instead. Luma inputs can't have targets.
+    pub fn add_target(
+        &mut self,
+        image: &Handle<Image>,
+        options: TargetOptions,
+        images: &Assets<Image>,
+    ) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidTarget(reason));
+
+        if options.encoding == self.encoding && !options.mirror {
+            return invalid("same encoding as the input image, share its handle instead".into());
+        }
+        if self.encoding == ImageEncoding::Luma {
+            return invalid("luma inputs have no colors to convert".into());
+        }
+
+        let Some(target) = images.get(image) else {
+            return invalid("image doesn't exist".into());
+        };
+        let size = self.device.size;
+        if target.width() != size.width || target.height() != size.height {
+            return invalid(format!(
+                "image is {}x{} but the input is {}x{}",
+                target.width(),
+                target.height(),
+                size.width,
+                size.height
+            ));
+        }
+        let format = options.encoding.texture_format();
+        if target.texture_descriptor.format != format {
+            return invalid(format!(
+                "image is {:?} but {:?} needs {format:?}",
+                target.texture_descriptor.format, options.encoding
+            ));
+        }
+
+        let pixels = (size.width * size.height) as usize;
+        if let Ok(mut io) = self.device.io.lock() {
+            io.targets.push(Target::new(image.id(), options, pixels));
+        }
+        Ok(())
+    }
+
+    /// A copy of the image mirrored left to right, like the local preview of a video
+    /// call whose stream isn't mirrored. The frames are mirrored as they are copied into
+    /// it after the conversion, only inputs with a mirrored image pay for it, and once
+    /// every handle to it was dropped, like with the sprite showing it, it isn't
+    /// written anymore.
+    ///
+    /// It is an [`Input::add_target`] target, removed when the size of the frames
+    /// changes. Luma inputs and inputs converted on the gpu can't have one.
+    pub fn mirrored_image(&mut self, images: &mut Assets<Image>) -> Result<Handle<Image>> {
+        if self.encoding == ImageEncoding::Luma || self.decoder == Decoder::Gpu {
+            return Err(Error::InvalidTarget(
+                "only frames converted to rgba on the cpu can be mirrored".into(),
+            ));
+        }
+
+        let size = self.device.size;
+        let pixels = (size.width * size.height) as usize;
+        let image = images.add(Image::new(
+            size,
+            TextureDimension::D2,
+            vec![255; pixels * self.encoding.bytes_per_pixel()],
+            self.encoding.texture_format(),
+            RenderAssetUsages::default(),
+        ));
+        let options = TargetOptions {
+            encoding: self.encoding,
+            mirror: true,
+        };
+        if let Ok(mut io) = self.device.io.lock() {
+            io.targets.push(Target::new(image.id(), options, pixels));
+        }
+        Ok(image)
+    }
+
+    /// Stops writing frames into an image added with [`Input::add_target`]
+    pub fn remove_target(&mut self, image: &Handle<Image>) {
+        if let Ok(mut io) = self.device.io.lock() {
+            io.targets.retain(|target| target.image != image.id());
+        }
+    }
+
+    /// Replaces the [`FrameProcessor`] run on captured frames, see [`InputBuilder::processor`]
+    pub fn set_processor(&mut self, processor: Option<FrameProcessor>) {
+        if let Ok(mut io) = self.device.io.lock() {
+            io.processor = processor;
+        }
+    }
+
+    /// While inactive the stream keeps running, but frames are dequeued without being
+    /// converted and the images keep the last converted frame, so resuming is instant.
+    /// See [`InputBuilder::keepalive`] for converting the odd frame anyway.
+    ///
+    /// Inputs built with [`InputBuilder::throttle_hidden`] are set every frame by
+    /// the plugin.
+    pub fn set_active(&mut self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    /// Reads one frame into the image of a [`InputBuilder::single_shot`] input, a
+    /// [`FrameCaptured`](crate::FrameCaptured) is sent once it is there. Requests made
+    /// before it arrives are served by the same frame.
+    pub fn request_frame(&mut self) {
+        self.frame_requested = true;
+    }
+
+    /// Receives every frame the io task dequeues, right after it is dequeued and before
+    /// it is converted, for consumers that can't wait for the next frame of the app.
+    ///
+    /// Frames are copied out of the driver buffer, so receivers may keep them as long as
+    /// they like. The task never waits for receivers: one that fell 4 frames behind
+    /// misses frames until it caught up. Dropping the receiver unsubscribes it.
+    pub fn subscribe(&self) -> Receiver<FrameRef> {
+        subscribe::subscribe(&self.subscribers)
+    }
+
+    /// Stops the stream without closing the device, like for a privacy shutter. Unlike
+    /// [`Input::set_active`] the driver stops capturing, which turns off the LED of
+    /// most cameras. The image keeps the last frame.
+    pub fn pause(&mut self) {
+        self.device.pause();
+    }
+
+    /// Starts the stream stopped by [`Input::pause`] again
+    pub fn resume(&mut self) {
+        self.device.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.device.is_paused()
+    }
+
+    /// Queries the capabilities of the device, see [`Capabilities`](crate::Capabilities).
+    /// Fails for inputs that don't read from a device and after [`Input::close`].
+    pub fn query_caps(&self) -> Result<Capabilities> {
+        self.device.query_caps()
+    }
+
+    /// Controls the device exposes with their ranges and menus, like for building
+    /// sliders. Fails for inputs that don't read from a device.
+    pub fn enumerate_controls(&self) -> Result<Vec<ControlDescriptor>> {
+        control::enumerate(self.device.opened()?)
+    }
+
+    /// Current value of a control of the device, like its exposure. Fails for inputs
+    /// that don't read from a device.
+    pub fn control(&self, id: ControlId) -> Result<ControlValue> {
+        control::get(self.device.opened()?, id)
+    }
+
+    /// Sets a control of the device while it streams, like its exposure. Values out of
+    /// range fail with the error of the driver.
+    pub fn set_control(&self, id: ControlId, value: impl Into<ControlValue>) -> Result<()> {
+        control::set(self.device.opened()?, id, value.into())
+    }
+
+    /// Switches between auto and manual exposure, turning auto exposure off and
+    /// checking the driver took it before setting the time
+    pub fn set_exposure(&self, exposure: Exposure) -> std::result::Result<(), ControlError> {
+        let dev = self.device.opened().map_err(|error| ControlError {
+            step: ControlStep::Auto,
+            error,
+        })?;
+        control::set_exposure(dev, exposure)
+    }
+
+    /// Switches between auto and manual white balance, turning auto white balance off
+    /// and checking the driver took it before setting the temperature
+    pub fn set_white_balance(
+        &self,
+        white_balance: WhiteBalance,
+    ) -> std::result::Result<(), ControlError> {
+        let dev = self.device.opened().map_err(|error| ControlError {
+            step: ControlStep::Auto,
+            error,
+        })?;
+        control::set_white_balance(dev, white_balance)
+    }
+
+    /// Zooms from 0 for the widest to 1 for the closest zoom of PTZ cameras, returning
+    /// the value set on [`ControlId::ZoomAbsolute`]
+    pub fn set_zoom(&self, zoom: f32) -> Result<i64> {
+        control::set_normalized(self.device.opened()?, ControlId::ZoomAbsolute, zoom)
+    }
+
+    /// Pans from -1 for the leftmost to 1 for the rightmost position of PTZ cameras,
+    /// returning the value set on [`ControlId::PanAbsolute`]
+    pub fn set_pan(&self, pan: f32) -> Result<i64> {
+        let position = (pan + 1.0) / 2.0;
+        control::set_normalized(self.device.opened()?, ControlId::PanAbsolute, position)
+    }
+
+    /// Tilts from -1 for the lowest to 1 for the highest position of PTZ cameras,
+    /// returning the value set on [`ControlId::TiltAbsolute`]
+    pub fn set_tilt(&self, tilt: f32) -> Result<i64> {
+        let position = (tilt + 1.0) / 2.0;
+        control::set_normalized(self.device.opened()?, ControlId::TiltAbsolute, position)
+    }
+
+    /// Pans by `amount` from -1 to 1 of the largest step of [`ControlId::PanRelative`],
+    /// like for the stick of a gamepad
+    pub fn pan_by(&self, amount: f32) -> Result<i64> {
+        let position = (amount + 1.0) / 2.0;
+        control::set_normalized(self.device.opened()?, ControlId::PanRelative, position)
+    }
+
+    /// Tilts by `amount` from -1 to 1 of the largest step of
+    /// [`ControlId::TiltRelative`], like for the stick of a gamepad
+    pub fn tilt_by(&self, amount: f32) -> Result<i64> {
+        let position = (amount + 1.0) / 2.0;
+        control::set_normalized(self.device.opened()?, ControlId::TiltRelative, position)
+    }
+
+    /// Calls `f` with the device, for ioctls the crate doesn't wrap, like vendor
+    /// controls. `None` for inputs that don't read from a device.
+    ///
+    /// The stream is running, so `f` must not change the format or the buffers.
+    pub fn with_raw_device<R>(&self, f: impl FnOnce(&v4l::Device) -> R) -> Option<R> {
+        self.device.dev.as_ref().map(f)
+    }
+
+    /// Stops streaming and releases the device without despawning the input, so
+    /// another process can open it. The image keeps the last frame.
+    pub fn close(&mut self) {
+        self.device.close();
+    }
+
+    /// Whether the source delivers a picture, like a capture card with a cable plugged
+    /// in. Transitions send a [`SignalLost`](crate::SignalLost) and a
+    /// [`SignalRestored`](crate::SignalRestored). Updated once per frame by the plugin.
+    pub fn signal(&self) -> SignalState {
+        self.signal
+    }
+
+    /// Whether the device disappeared and the input stopped reading it, see
+    /// [`DeviceLost`](crate::DeviceLost) and [`InputBuilder::reconnect`]
+    pub fn is_disconnected(&self) -> bool {
+        self.connection.lost
+    }
+
+    /// Id, format and state of the device, as inspectors show them. Updated once per
+    /// frame by the plugin.
+    pub fn status(&self) -> &DeviceStatus {
+        &self.status
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Adds a profile to switch to with [`Input::switch_profile`],
+    /// replacing the profile of the same name
+    pub fn add_profile(&mut self, name: impl Into<String>, profile: Profile) {
+        self.profiles.insert(name.into(), profile);
+    }
+
+    /// Sets the format and controls of the profile `name` before the next frame is
+    /// captured, and sends a [`ProfileSwitched`](crate::ProfileSwitched) when done.
+    ///
+    /// The image is resized to the new format and [`Input::add_target`] targets are
+    /// removed. Inputs converting on an m2m device and virtual inputs can't switch.
+    pub fn switch_profile(&mut self, name: impl Into<String>) {
+        self.pending_profile = Some(name.into());
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use v4l::format::FieldOrder;
+use v4l::{FourCC, Fraction};
+
+use crate::busy::{self, DeviceBusyWaiting, OpenPolicy};
+use crate::config;
+use crate::devices::{self, DeviceSelector};
+use crate::dump::Dumper;
+use crate::external::ExternalInput;
+use crate::gpu;
+use crate::headless::{FrameLayout, RawInput};
+use crate::input::Input;
+use crate::m2m::M2m;
+use crate::memory::Buffers;
+use crate::open::{OpenedInput, PendingInput};
+use crate::profile::Profile;
+use crate::raw::RawFrames;
+use crate::reconnect::ReconnectPolicy;
+use crate::watchdog::WatchdogPolicy;
+use crate::{
+    can_decode, can_decode_luma, BayerConfig, Colorimetry, Deinterlace, Dither, Error, Format,
+    FrameInfo, FrameProcessor, ImageEncoding, MemoryType, Orientation, PixelConverter, Result,
+    WaitStrategy,
+};
+
+/// Configures how an [`Input`] is opened, see [`Input::builder`]
+#[derive(Default)]
+pub struct InputBuilder {
+    pub(crate) selectors: Vec<DeviceSelector>,
+    pub(crate) m2m: Option<M2m>,
+    pub(crate) processor: Option<FrameProcessor>,
+    pub(crate) converter: Option<Box<dyn PixelConverter>>,
+    pub(crate) raw: Option<RawFrames>,
+    pub(crate) dump: Option<(PathBuf, usize)>,
+    pub(crate) dequeue_timestamps: bool,
+    pub(crate) encoding: ImageEncoding,
+    pub(crate) dither: Dither,
+    pub(crate) colorimetry: Option<Colorimetry>,
+    pub(crate) denoise: Option<f32>,
+    pub(crate) bayer: Option<BayerConfig>,
+    pub(crate) stats: Option<usize>,
+    pub(crate) preview: Option<(u32, u32)>,
+    pub(crate) throttle_hidden: bool,
+    pub(crate) keep_image: bool,
+    pub(crate) late_upload: bool,
+    pub(crate) single_shot: bool,
+    pub(crate) every_frame: bool,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) wait: WaitStrategy,
+    pub(crate) memory: MemoryType,
+    pub(crate) buffer_count: Option<u32>,
+    pub(crate) interpret_as: Option<[u8; 4]>,
+    pub(crate) swizzle: Option<[usize; 4]>,
+    pub(crate) watchdog: Option<WatchdogPolicy>,
+    pub(crate) budget: Option<Duration>,
+    pub(crate) signal_timeout: Option<Duration>,
+    pub(crate) deinterlace: Deinterlace,
+    pub(crate) orientation: Orientation,
+    pub(crate) profiles: HashMap<String, Profile>,
+    pub(crate) format: Option<Format>,
+    pub(crate) frame_interval: Option<(u32, u32)>,
+    pub(crate) reconnect: Option<ReconnectPolicy>,
+    pub(crate) open_policy: OpenPolicy,
+    pub(crate) gpu: bool,
+    /// Only set with the dv-timings feature
+    pub(crate) auto_dv_timings: bool,
+    /// Set by [`InputBuilder::build_raw`] for [`FrameLayout::Native`]
+    pub(crate) native: bool,
+}
+
+impl InputBuilder {
+    /// Adds a device to try.
+    /// Devices are tried in the order they were added, like [`Input::first_available`].
+    pub fn device(mut self, selector: impl Into<DeviceSelector>) -> Self {
+        self.selectors.push(selector.into());
+        self
+    }
+
+    /// Adds every capture device that streams a format this crate can convert,
+    /// lowest id first. UVC metadata nodes and m2m devices are skipped.
+    ///
+    /// Which one was opened is reported by [`Input::info`].
+    pub fn default_device(mut self) -> Self {
+        let candidates = devices::default_candidates();
+        self.selectors.extend(
+            candidates
+                .into_iter()
+                .map(|info| DeviceSelector::Path(info.path)),
+        );
+        self
+    }
+
+    /// Format to set on the device before streaming, instead of its current one.
+    /// The driver may adjust it, [`Input::format`] and [`Input::size`] are what it
+    /// granted. Fails with [`Error::UnsupportedFormat`] when the granted format can't
+    /// be converted.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Asks the driver for `numerator / denominator` seconds between frames, like 1/30
+    /// for 30 fps. Drivers round it to a rate they support, see [`Input::frame_interval`].
+    pub fn frame_interval(mut self, numerator: u32, denominator: u32) -> Self {
+        self.frame_interval = Some((numerator, denominator));
+        self
+    }
+
+    /// Reopens the device at the same path when it disappears, like when its cable is
+    /// pulled, and streams into the same image again. Without this the input stops for
+    /// good after a [`DeviceLost`](crate::DeviceLost).
+    ///
+    /// The device has to come back in the same format.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Whether opening waits for a device another process holds, instead of failing
+    /// with [`Error::DeviceBusy`]. [`OpenPolicy::Exclusive`] by default.
+    pub fn open_policy(mut self, policy: OpenPolicy) -> Self {
+        self.open_policy = policy;
+        self
+    }
+
+    /// Converts frames with a memory-to-memory device instead of the CPU.
+    /// Falls back to the CPU when no m2m device can convert the capture format.
+    ///
+    /// Compressed formats like MJPEG use [`M2m::auto`] unless set otherwise.
+    pub fn m2m(mut self, m2m: M2m) -> Self {
+        self.m2m = Some(m2m);
+        self
+    }
+
+    /// Runs `processor` on every frame after it is converted to rgba,
+    /// before it is copied into the image.
+    ///
+    /// It runs off the main thread and has to be fast. A panicking processor is
+    /// sent as a [`V4lError`](crate::V4lError) and the frame is shown as is.
+    pub fn processor(
+        mut self,
+        processor: impl Fn(&mut [u8], &FrameInfo) + Send + Sync + 'static,
+    ) -> Self {
+        self.processor = Some(Box::new(processor));
+        self
+    }
+
+    /// Converts frames of the formats `converter` takes with it instead of the built-in
+    /// conversions, like a vendor specific format. Devices streaming a format neither
+    /// converts fail to open with [`Error::UnsupportedFormat`], instead of falling back
+    /// to a format the crate converts.
+    pub fn converter(mut self, converter: impl PixelConverter + 'static) -> Self {
+        self.converter = Some(Box::new(converter));
+        self
+    }
+
+    /// Sends the bytes of every frame exactly as they were dequeued as [`RawFrame`](crate::RawFrame)
+    /// events, for recording compressed streams without converting them.
+    pub fn raw_frames(mut self, mode: RawFrames) -> Self {
+        self.raw = Some(mode);
+        self
+    }
+
+    /// Writes the first `max_frames` buffers, exactly as they are dequeued, to `path`
+    /// for debugging format problems.
+    ///
+    /// Frames are written on a separate thread. Write failures are logged and
+    /// stop the dump, capture carries on.
+    pub fn dump_to(mut self, path: impl Into<PathBuf>, max_frames: usize) -> Self {
+        self.dump = Some((path.into(), max_frames));
+        self
+    }
+
+    /// Timestamps frames when they are dequeued instead of using the driver's timestamps,
+    /// for drivers that report wrong ones
+    pub fn dequeue_timestamps(mut self) -> Self {
+        self.dequeue_timestamps = true;
+        self
+    }
+
+    /// How colors are stored in the image, sRGB encoded by default
+    pub fn image_encoding(mut self, encoding: ImageEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// How sources with more than 8 bits per sample, like Y16, are reduced to
+    /// 8 bit images, [`Dither::Ordered`] by default. Unused for images with 16 bits
+    /// per channel, like [`ImageEncoding::Half`].
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Converts YUV frames with `colorimetry` instead of the one the driver reports,
+    /// see [`Input::color`] for the detected one. Drivers often leave the range at its
+    /// default, which is limited for uncompressed formats, for sensors streaming full
+    /// range. Frames converted by an m2m device or decoded from JPEG ignore it.
+    pub fn colorimetry(mut self, colorimetry: Colorimetry) -> Self {
+        self.colorimetry = Some(colorimetry);
+        self
+    }
+
+    /// Averages every frame with the previous ones to reduce noise, at the cost of
+    /// smearing motion. `strength` is the weight of the previous frames, from
+    /// 0 (off) to 1. The average restarts on scene cuts or [`Input::reset_denoise`].
+    ///
+    /// Keeps an extra frame sized buffer per device.
+    pub fn denoise(mut self, strength: f32) -> Self {
+        self.denoise = Some(strength);
+        self
+    }
+
+    /// Black level and white balance for devices streaming 8 bit Bayer formats
+    pub fn bayer(mut self, config: BayerConfig) -> Self {
+        self.bayer = Some(config);
+        self
+    }
+
+    /// Sends [`FrameStats`](crate::FrameStats) with a luma histogram of `buckets`
+    /// buckets for every frame. The luma is counted while converting where the format
+    /// has it, otherwise from the converted frame.
+    pub fn stats(mut self, buckets: usize) -> Self {
+        self.stats = Some(buckets);
+        self
+    }
+
+    /// Keeps a second image of `width` x `height` with a downscaled copy of every frame,
+    /// see [`Input::preview`]. The aspect ratio is kept, with black borders.
+    ///
+    /// Not available with [`ImageEncoding::Luma`].
+    pub fn preview(mut self, width: u32, height: u32) -> Self {
+        self.preview = Some((width, height));
+        self
+    }
+
+    /// Makes the input inactive, see [`Input::set_active`], while no entity with
+    /// a visible [`ViewVisibility`](bevy::render::view::ViewVisibility) has its image
+    /// or preview as `Handle<Image>`, like an offscreen sprite.
+    ///
+    /// Images used any other way, like in materials, aren't seen, use
+    /// [`Input::set_active`] for those.
+    pub fn throttle_hidden(mut self) -> Self {
+        self.throttle_hidden = true;
+        self
+    }
+
+    /// Leaves the image in the assets once the input is dropped, for apps that keep
+    /// showing its last frame. By default it is removed on the next update even while
+    /// materials or sprites still hold handles to it, and its data is reused by the
+    /// [`BufferPool`](crate::BufferPool).
+    pub fn keep_image(mut self) -> Self {
+        self.keep_image = true;
+        self
+    }
+
+    /// Writes frames to the texture of the image during render world extraction,
+    /// instead of swapping them into the image in [`Update`]. Frames that arrive
+    /// after [`Update`] are shown a frame earlier, see
+    /// [`FrameStats::upload_latency`](crate::FrameStats::upload_latency).
+    ///
+    /// This skips a full frame copy per frame. Swapped frames are cloned when the image
+    /// is extracted and bevy uploads them into a new texture, late uploads write the
+    /// frame from the io task's buffers into the existing texture. The main world keeps
+    /// no copy of the frame either.
+    ///
+    /// The image only lives in the render world
+    /// ([`RenderAssetUsages::RENDER_WORLD`]), its data can't be read from
+    /// [`Assets<Image>`]. The preview and [`Input::add_target`] images aren't updated.
+    ///
+    /// [`RenderAssetUsages::RENDER_WORLD`]: bevy::render::render_asset::RenderAssetUsages::RENDER_WORLD
+    pub fn late_upload(mut self) -> Self {
+        self.late_upload = true;
+        self
+    }
+
+    /// Converts YUYV, UYVY, YVYU, NV12, NV21 and YU12 frames with a shader in the render
+    /// world instead of on the cpu. Dequeued frames are uploaded as they are and drawn
+    /// into the texture of the image, [`Input::image`] stays the same.
+    ///
+    /// Implies [`InputBuilder::late_upload`], and frame processors, the temporal filter
+    /// and statistics see no frames. Inputs of other formats, with an m2m device, an
+    /// [`ImageEncoding`] other than [`ImageEncoding::Srgb`] or
+    /// [`InputBuilder::interpret_as`] are converted on the cpu, which is noted in the
+    /// [`NegotiationReport`](crate::NegotiationReport).
+    pub fn gpu_conversion(mut self) -> Self {
+        self.gpu = true;
+        self
+    }
+
+    /// Only reads a frame when one is requested with [`Input::request_frame`], like for
+    /// taking photos. The stream is stopped between frames, so the camera isn't
+    /// capturing while nobody asked for a frame.
+    pub fn single_shot(mut self) -> Self {
+        self.single_shot = true;
+        self
+    }
+
+    /// Converts every frame in the order it was captured. By default frames that
+    /// queued up in the driver while the app ran slower than the camera are requeued
+    /// unconverted, and only the newest is converted so the image stays current.
+    /// Dropped frames are counted in [`FrameReceived::dropped`](crate::FrameReceived::dropped).
+    ///
+    /// For apps that need every frame, like recording. [`Input::subscribe`], raw frames
+    /// and dumps don't see dropped frames either.
+    pub fn every_frame(mut self) -> Self {
+        self.every_frame = true;
+        self
+    }
+
+    /// Converts a frame every `interval` while the input is inactive, so its images
+    /// don't get too stale
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Adds a profile to switch to with [`Input::switch_profile`]
+    pub fn profile(mut self, name: impl Into<String>, profile: Profile) -> Self {
+        self.profiles.insert(name.into(), profile);
+        self
+    }
+
+    /// How stream buffers are shared with the device, [`MemoryType::Auto`] by default.
+    /// The type that was chosen is in
+    /// [`NegotiationReport::memory`](crate::NegotiationReport::memory).
+    pub fn memory(mut self, memory: MemoryType) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Stream buffers to ask the driver for, 4 unless the [`V4lConfig`](crate::V4lConfig)
+    /// says otherwise. Fewer save memory with large frames, more absorb jitter. Drivers
+    /// may allocate a different number, see [`Input::buffer_count`]. Opening fails for
+    /// fewer than 2.
+    pub fn buffer_count(mut self, count: u32) -> Self {
+        self.buffer_count = Some(count);
+        self
+    }
+
+    /// Skips converting frames while converting takes longer than `budget` or frames
+    /// wait longer than it in the driver's queue, like 4K MJPEG on a weak cpu, so the
+    /// input doesn't fall further and further behind. At least a frame a second is
+    /// converted. Changes are sent as [`ConversionThrottled`](crate::ConversionThrottled).
+    pub fn conversion_budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Escalates dequeues that keep failing or timing out through restarting the stream,
+    /// reopening the device and giving up, see [`WatchdogEscalated`](crate::WatchdogEscalated).
+    /// Virtual inputs have no watchdog.
+    pub fn watchdog(mut self, policy: WatchdogPolicy) -> Self {
+        self.watchdog = Some(policy);
+        self
+    }
+
+    /// Combines the fields of interlaced formats with `deinterlace`, weaving them by
+    /// default. Frames converted on the gpu are shown as the device delivers them.
+    /// See [`Input::set_deinterlace`].
+    pub fn deinterlace(mut self, deinterlace: Deinterlace) -> Self {
+        self.deinterlace = deinterlace;
+        self
+    }
+
+    /// Turns frames with `orientation` and allocates the image turned, with
+    /// [`Input::size`] reporting its size. RGBA frames are turned row by row as the
+    /// cpu converts them. Luma, Bayer and interlaced frames and those of m2m devices
+    /// are turned once they are complete. Frames converted on the gpu are shown as
+    /// the device delivers them. See [`Input::set_orientation`].
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Sends a [`SignalLost`](crate::SignalLost) once the device delivered no frame for
+    /// `timeout`, twice its frame interval by default, like when the cable of a capture
+    /// card is pulled. The device stays open and frames resume with a
+    /// [`SignalRestored`](crate::SignalRestored). Virtual inputs never lose their signal.
+    pub fn signal_timeout(mut self, timeout: Duration) -> Self {
+        self.signal_timeout = Some(timeout);
+        self
+    }
+
+    /// Queries the timings the hdmi or dvi receiver of the device detects and sets
+    /// them before the format is negotiated, so the stream starts in the resolution of
+    /// the source, like [`Input::set_dv_timings`] with [`Input::query_dv_timings`].
+    /// Devices without a receiver or a source are opened as they are. Later changes of
+    /// the source are followed like without this, see
+    /// [`FormatChanged`](crate::FormatChanged).
+    #[cfg(feature = "dv-timings")]
+    pub fn auto_dv_timings(mut self, auto: bool) -> Self {
+        self.auto_dv_timings = auto;
+        self
+    }
+
+    /// Converts frames as `fourcc` no matter what format the driver reports, for
+    /// drivers that mislabel their frames, like YUYV that is actually UYVY.
+    /// Noted in the [`NegotiationReport`](crate::NegotiationReport). Frames converted by
+    /// an m2m device are left alone.
+    pub fn interpret_as(mut self, fourcc: [u8; 4]) -> Self {
+        self.interpret_as = Some(fourcc);
+        self
+    }
+
+    /// Takes every rgba channel of the image from the converted channel at the index in
+    /// `channels`, like `[2, 1, 0, 3]` for drivers that swap red and blue. Indices over 3
+    /// are clamped. Noted in the [`NegotiationReport`](crate::NegotiationReport).
+    pub fn swizzle(mut self, channels: [usize; 4]) -> Self {
+        self.swizzle = Some(channels.map(|channel| channel.min(3)));
+        self
+    }
+
+    /// How the io task waits for frames, [`WaitStrategy::Blocking`] by default.
+    /// The strategy and the measured wake-up latency are in [`FrameStats`](crate::FrameStats).
+    pub fn wait(mut self, strategy: WaitStrategy) -> Self {
+        self.wait = strategy;
+        self
+    }
+
+    /// Like [`InputBuilder::build`], but the app dequeues frames with
+    /// [`ExternalInput::service`] instead of the plugin
+    pub fn build_external(self, images: &mut Assets<Image>) -> Result<ExternalInput> {
+        Ok(ExternalInput::new(self.build(images)?))
+    }
+
+    /// Like [`InputBuilder::build`], but frames are kept in the [`RawInput`] instead of
+    /// an image, for apps that never show them. Options for images, like
+    /// [`InputBuilder::preview`] and [`InputBuilder::gpu_conversion`], are ignored.
+    pub fn build_raw(mut self, layout: FrameLayout) -> Result<RawInput> {
+        self.gpu = false;
+        match layout {
+            FrameLayout::Native => self.native = true,
+            FrameLayout::Converted(encoding) => self.encoding = encoding,
+        }
+        let native = self.native;
+        Ok(RawInput::new(
+            self.open(|_| {})?.into_headless(native),
+            layout,
+        ))
+    }
+
+    pub fn build(self, images: &mut Assets<Image>) -> Result<Input> {
+        Ok(self.open(|_| {})?.into_input(images))
+    }
+
+    /// Opens the device on the async compute pool, see [`PendingInput`]
+    pub fn pending(self) -> PendingInput {
+        let (sender, waiting) = mpsc::channel();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            self.open(|event| {
+                let _ = sender.send(event);
+            })
+        });
+        PendingInput {
+            task,
+            waiting: Mutex::new(waiting),
+        }
+    }
+
+    fn open(mut self, waiting: impl FnMut(DeviceBusyWaiting)) -> Result<OpenedInput> {
+        // raw only and native inputs can stream formats this crate can't convert
+        let convert = self.raw != Some(RawFrames::Only) && !self.native;
+        let converter = self.converter.take();
+        let decodable = |fourcc: &[u8; 4]| {
+            can_decode(fourcc)
+                || converter
+                    .as_ref()
+                    .is_some_and(|converter| converter.converts(*fourcc))
+        };
+        let format = self.format.map(v4l::Format::from);
+        let buffers = Buffers::new(
+            self.memory,
+            self.buffer_count
+                .unwrap_or(config::current().default_buffer_count),
+        )?;
+        let mut opened = busy::retry(self.open_policy, waiting, || {
+            OpenedInput::first_available(
+                &self.selectors,
+                self.m2m.as_ref(),
+                format.as_ref(),
+                buffers,
+                // formats aren't negotiated away from the ones of the converter
+                convert && converter.is_none(),
+                self.auto_dv_timings,
+            )
+        })?;
+        opened.processor = self.processor;
+        opened.raw = self.raw;
+        opened.dequeue_timestamps = self.dequeue_timestamps;
+        opened.encoding = self.encoding;
+
+        if let Some(fourcc) = self.interpret_as {
+            if convert && opened.m2m.is_none() && !decodable(&fourcc) {
+                return Err(Error::UnsupportedFormat {
+                    fourcc: fourcc.into(),
+                });
+            }
+            opened.report.note(format!(
+                "converting frames reported as {} as {}",
+                opened.format.fourcc,
+                FourCC::new(&fourcc)
+            ));
+            opened.overrides.fourcc = Some(fourcc);
+        }
+        if let Some(channels) = self.swizzle {
+            opened
+                .report
+                .note(format!("swizzling converted rgba channels to {channels:?}"));
+            opened.overrides.channels = Some(channels);
+        }
+
+        let fourcc = match &opened.m2m {
+            Some(m2m) => m2m.format.fourcc.repr,
+            None => opened.overrides.fourcc(opened.format.fourcc.repr),
+        };
+        if convert && self.encoding == ImageEncoding::Luma && !can_decode_luma(&fourcc) {
+            return Err(Error::UnsupportedFormat {
+                fourcc: fourcc.into(),
+            });
+        }
+        if convert && opened.m2m.is_none() && !decodable(&fourcc) {
+            return Err(Error::UnsupportedFormat {
+                fourcc: fourcc.into(),
+            });
+        }
+        opened.converter = converter;
+        opened.dither = self.dither;
+        opened.colorimetry = self.colorimetry;
+        opened.denoise = self.denoise;
+        opened.bayer = self.bayer;
+        opened.stats = self.stats;
+        opened.preview = self.preview;
+        opened.throttle_hidden = self.throttle_hidden;
+        opened.keep_image = self.keep_image;
+        opened.late_upload = self.late_upload;
+        if self.gpu && convert {
+            let fourcc = opened.format.fourcc.repr;
+            if opened.m2m.is_none()
+                && self.interpret_as.is_none()
+                && gpu::can_convert(&fourcc, self.encoding)
+            {
+                opened.gpu = true;
+                opened.late_upload = true;
+            } else {
+                opened.report.note(format!(
+                    "converting {} frames on the cpu, the gpu can't convert them",
+                    opened.format.fourcc
+                ));
+            }
+        }
+        if opened.gpu && self.orientation != Orientation::None {
+            opened
+                .report
+                .note("frames converted on the gpu aren't turned");
+        } else {
+            opened.orientation = self.orientation;
+        }
+        opened.single_shot = self.single_shot;
+        opened.every_frame = self.every_frame;
+        opened.keepalive = self.keepalive;
+        opened.wait = self.wait;
+        opened.profiles = self.profiles;
+        opened.watchdog = self.watchdog;
+        opened.budget = self.budget;
+        opened.signal_timeout = self.signal_timeout;
+        opened.deinterlace = self.deinterlace;
+        if !matches!(
+            opened.format.field_order,
+            FieldOrder::Any | FieldOrder::Progressive | FieldOrder::Top | FieldOrder::Bottom
+        ) {
+            opened.report.note(format!(
+                "device streams {:?} fields, deinterlaced with {:?}",
+                opened.format.field_order, self.deinterlace
+            ));
+        }
+        opened.reconnect = self.reconnect;
+        if let Some((numerator, denominator)) = self.frame_interval {
+            opened.set_frame_interval(Fraction::new(numerator, denominator))?;
+        }
+
+        if let Some((path, max_frames)) = &self.dump {
+            let dumper = opened
+                .span
+                .in_scope(|| Dumper::new(path, opened.format, *max_frames))?;
+            opened.dump = Some(dumper);
+        }
+        Ok(opened)
+    }
+}
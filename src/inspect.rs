@@ -0,0 +1,75 @@
+use std::fmt::{self, Write};
+
+use bevy::prelude::*;
+
+use crate::{Device, FourCC, Input, Output};
+
+/// What inspectors show of an [`Input`] or [`Output`], mirrored from the device by the
+/// plugin every frame since the device itself isn't reflectable
+#[derive(Reflect, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceStatus {
+    /// ID of the v4l video device (/dev/video{id})
+    pub id: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Pixel format of the stream, like "YUYV"
+    pub fourcc: String,
+    pub width: u32,
+    pub height: u32,
+    /// Whether frames are read or written, false while paused, lost or closed
+    pub streaming: bool,
+}
+
+impl DeviceStatus {
+    fn new(device: &Device, streaming: bool) -> Self {
+        Self {
+            id: device.id,
+            label: device.label().to_string(),
+            fourcc: FourCC::new(&device.format.fourcc.repr).to_string(),
+            width: device.format.width,
+            height: device.format.height,
+            streaming: streaming && !device.closed && !device.is_paused(),
+        }
+    }
+
+    /// Whether the status is the one of `device`, without allocating one
+    fn describes(&self, device: &Device, streaming: bool) -> bool {
+        self.id == device.id
+            && self.label == device.label()
+            && displays_as(FourCC::new(&device.format.fourcc.repr), &self.fourcc)
+            && (self.width, self.height) == (device.format.width, device.format.height)
+            && self.streaming == (streaming && !device.closed && !device.is_paused())
+    }
+}
+
+/// Whether `value` is displayed as `expected`, compared while it is formatted
+fn displays_as(value: impl fmt::Display, expected: &str) -> bool {
+    struct Rest<'a>(&'a str);
+
+    impl Write for Rest<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 = self.0.strip_prefix(s).ok_or(fmt::Error)?;
+            Ok(())
+        }
+    }
+
+    let mut rest = Rest(expected);
+    write!(rest, "{value}").is_ok() && rest.0.is_empty()
+}
+
+/// Only writes changed statuses, so `Changed<Input>` isn't set every frame. Unchanged
+/// ones aren't allocated either.
+pub(crate) fn update_status(mut inputs: Query<&mut Input>, mut outputs: Query<&mut Output>) {
+    for mut input in inputs.iter_mut() {
+        let streaming = input.is_active() && !input.is_disconnected();
+        if !input.status().describes(&input.device, streaming) {
+            input.status = DeviceStatus::new(&input.device, streaming);
+        }
+    }
+
+    for mut output in outputs.iter_mut() {
+        if !output.1.describes(&output.0, true) {
+            output.1 = DeviceStatus::new(&output.0, true);
+        }
+    }
+}
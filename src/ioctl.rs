@@ -0,0 +1,200 @@
+//! Ioctls of linux/videodev2.h the v4l crate has no codes for, with the structs they
+//! take. Codes are built like the _IOR, _IOW and _IOWR macros of the kernel headers.
+
+use std::mem;
+
+use v4l::v4l2::vidioc::_IOC_TYPE;
+
+pub(crate) const VIDIOC_S_EXT_CTRLS: _IOC_TYPE = iowr(b'V', 72, mem::size_of::<ExtControls>());
+pub(crate) const VIDIOC_S_DV_TIMINGS: _IOC_TYPE = iowr(b'V', 87, mem::size_of::<RawTimings>());
+pub(crate) const VIDIOC_G_SELECTION: _IOC_TYPE = iowr(b'V', 94, mem::size_of::<Selection>());
+pub(crate) const VIDIOC_S_SELECTION: _IOC_TYPE = iowr(b'V', 95, mem::size_of::<Selection>());
+pub(crate) const VIDIOC_DQEVENT: _IOC_TYPE = ior(b'V', 89, mem::size_of::<Event>());
+pub(crate) const VIDIOC_SUBSCRIBE_EVENT: _IOC_TYPE =
+    iow(b'V', 90, mem::size_of::<EventSubscription>());
+pub(crate) const VIDIOC_QUERY_DV_TIMINGS: _IOC_TYPE = ior(b'V', 99, mem::size_of::<RawTimings>());
+
+const fn ioc(dir: usize, typ: u8, nr: u8, size: usize) -> _IOC_TYPE {
+    ((dir << 30) | (size << 16) | ((typ as usize) << 8) | nr as usize) as _IOC_TYPE
+}
+
+/// _IOR of the kernel headers
+pub(crate) const fn ior(typ: u8, nr: u8, size: usize) -> _IOC_TYPE {
+    ioc(2, typ, nr, size)
+}
+
+/// _IOW of the kernel headers
+pub(crate) const fn iow(typ: u8, nr: u8, size: usize) -> _IOC_TYPE {
+    ioc(1, typ, nr, size)
+}
+
+/// _IOWR of the kernel headers
+pub(crate) const fn iowr(typ: u8, nr: u8, size: usize) -> _IOC_TYPE {
+    ioc(3, typ, nr, size)
+}
+
+/// struct v4l2_rect
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct Rect {
+    pub(crate) left: i32,
+    pub(crate) top: i32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// struct v4l2_selection
+#[repr(C)]
+pub(crate) struct Selection {
+    pub(crate) typ: u32,
+    pub(crate) target: u32,
+    pub(crate) flags: u32,
+    pub(crate) r: Rect,
+    pub(crate) reserved: [u32; 9],
+}
+
+/// struct v4l2_ext_controls
+#[repr(C)]
+pub(crate) struct ExtControls {
+    /// `ctrl_class` or `which`, the class all the controls belong to
+    pub(crate) which: u32,
+    pub(crate) count: u32,
+    /// Index of the control the driver failed on, `count` when it failed before
+    /// setting any
+    pub(crate) error_idx: u32,
+    pub(crate) request_fd: i32,
+    pub(crate) reserved: [u32; 1],
+    pub(crate) controls: *mut ExtControl,
+}
+
+/// struct v4l2_ext_control with the 8 byte union of values as its 64 bit member
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub(crate) struct ExtControl {
+    pub(crate) id: u32,
+    pub(crate) size: u32,
+    pub(crate) reserved2: [u32; 1],
+    pub(crate) value64: i64,
+}
+
+impl ExtControl {
+    /// An integer or menu control, which drivers read from the 32 bit member of the
+    /// union at its start
+    pub(crate) fn integer(id: u32, value: i32) -> Self {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&value.to_ne_bytes());
+        Self {
+            id,
+            size: 0,
+            reserved2: [0],
+            value64: i64::from_ne_bytes(bytes),
+        }
+    }
+}
+
+/// struct v4l2_event_subscription
+#[repr(C)]
+pub(crate) struct EventSubscription {
+    pub(crate) typ: u32,
+    pub(crate) id: u32,
+    pub(crate) flags: u32,
+    pub(crate) reserved: [u32; 5],
+}
+
+/// struct v4l2_event, the payload union is 64 bytes aligned like its 64 bit members
+#[repr(C)]
+pub(crate) struct Event {
+    pub(crate) typ: u32,
+    pub(crate) u: [u64; 8],
+    pub(crate) pending: u32,
+    pub(crate) sequence: u32,
+    pub(crate) timestamp: Timespec,
+    pub(crate) id: u32,
+    pub(crate) reserved: [u32; 8],
+}
+
+impl Event {
+    /// `changes` of struct v4l2_event_src_change, the payload of V4L2_EVENT_SOURCE_CHANGE
+    pub(crate) fn src_change(&self) -> u32 {
+        self.u[0] as u32
+    }
+}
+
+/// struct timespec
+#[repr(C)]
+pub(crate) struct Timespec {
+    pub(crate) tv_sec: std::os::raw::c_long,
+    pub(crate) tv_nsec: std::os::raw::c_long,
+}
+
+/// struct v4l2_bt_timings
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub(crate) struct BtTimings {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) interlaced: u32,
+    pub(crate) polarities: u32,
+    pub(crate) pixelclock: u64,
+    pub(crate) hfrontporch: u32,
+    pub(crate) hsync: u32,
+    pub(crate) hbackporch: u32,
+    pub(crate) vfrontporch: u32,
+    pub(crate) vsync: u32,
+    pub(crate) vbackporch: u32,
+    pub(crate) il_vfrontporch: u32,
+    pub(crate) il_vsync: u32,
+    pub(crate) il_vbackporch: u32,
+    pub(crate) standards: u32,
+    pub(crate) flags: u32,
+    pub(crate) picture_aspect: [u32; 2],
+    pub(crate) cea861_vic: u8,
+    pub(crate) hdmi_vic: u8,
+    pub(crate) reserved: [u8; 46],
+}
+
+/// struct v4l2_dv_timings, the union is padded to 32 words
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub(crate) struct RawTimings {
+    pub(crate) typ: u32,
+    pub(crate) bt: BtTimings,
+    pub(crate) reserved: [u32; 1],
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use v4l::v4l_sys::{
+        v4l2_dv_timings, v4l2_event, v4l2_event_subscription, v4l2_ext_control, v4l2_ext_controls,
+        v4l2_selection,
+    };
+
+    use super::*;
+
+    #[test]
+    fn structs_match_the_kernel_headers() {
+        assert_eq!(size_of::<Selection>(), size_of::<v4l2_selection>());
+        assert_eq!(size_of::<Event>(), size_of::<v4l2_event>());
+        assert_eq!(
+            size_of::<EventSubscription>(),
+            size_of::<v4l2_event_subscription>()
+        );
+        assert_eq!(size_of::<RawTimings>(), size_of::<v4l2_dv_timings>());
+        assert_eq!(size_of::<ExtControls>(), size_of::<v4l2_ext_controls>());
+        assert_eq!(size_of::<ExtControl>(), size_of::<v4l2_ext_control>());
+    }
+
+    #[test]
+    fn codes_match_the_kernel_headers() {
+        // as printed by a C program including linux/videodev2.h on x86_64
+        assert_eq!(VIDIOC_S_EXT_CTRLS, 0xc0205648);
+        assert_eq!(VIDIOC_S_DV_TIMINGS, 0xc0845657);
+        assert_eq!(VIDIOC_DQEVENT, 0x80885659);
+        assert_eq!(VIDIOC_SUBSCRIBE_EVENT, 0x4020565a);
+        assert_eq!(VIDIOC_QUERY_DV_TIMINGS, 0x80845663);
+        assert_eq!(VIDIOC_G_SELECTION, 0xc040565e);
+        assert_eq!(VIDIOC_S_SELECTION, 0xc040565f);
+    }
+}
@@ -0,0 +1,46 @@
+use jpeg_encoder::{ColorType, Encoder};
+
+use crate::encode::{self, FrameEncoder};
+use crate::scale::ScaledFrame;
+use crate::{Error, Result};
+
+/// Compresses frames of MJPEG outputs, the buffers are kept between frames
+pub(crate) struct JpegEncoder {
+    quality: u8,
+    /// The scaled frame as packed rgba
+    rgba: Vec<u8>,
+    jpeg: Vec<u8>,
+}
+
+impl JpegEncoder {
+    pub(crate) fn new(quality: u8) -> Self {
+        Self {
+            quality: quality.clamp(1, 100),
+            rgba: Vec::new(),
+            jpeg: Vec::new(),
+        }
+    }
+}
+
+impl FrameEncoder for JpegEncoder {
+    fn encode(&mut self, src: &ScaledFrame, dst: &mut [u8]) -> Result<usize> {
+        let (width, height) = (src.width(), src.height());
+        self.rgba.resize(width * height * 4, 0);
+        encode::encode_rgba(src, &mut self.rgba);
+
+        self.jpeg.clear();
+        Encoder::new(&mut self.jpeg, self.quality)
+            .encode(&self.rgba, width as u16, height as u16, ColorType::Rgba)
+            .map_err(|err| Error::Encode(err.to_string()))?;
+
+        let Some(dst) = dst.get_mut(..self.jpeg.len()) else {
+            return Err(Error::Encode(format!(
+                "jpeg of {} bytes doesn't fit the {} bytes of the buffer",
+                self.jpeg.len(),
+                dst.len()
+            )));
+        };
+        dst.copy_from_slice(&self.jpeg);
+        Ok(self.jpeg.len())
+    }
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
+use bevy::render::render_resource::{
+    Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, TextureAspect,
+};
+use bevy::render::renderer::RenderQueue;
+use bevy::render::Extract;
+
+use crate::{Decoder, Input};
+
+/// Usage of the image of an input, late uploads skip the main world copy
+pub(crate) fn image_usage(late_upload: bool) -> RenderAssetUsages {
+    match late_upload {
+        true => RenderAssetUsages::RENDER_WORLD,
+        false => RenderAssetUsages::all(),
+    }
+}
+
+/// Writes the latest frame of inputs built with
+/// [`InputBuilder::late_upload`](crate::InputBuilder::late_upload) to their texture.
+///
+/// Frames are read from the exchange of the input, so a task converting the next frame
+/// meanwhile doesn't make the upload skip this one.
+pub(crate) fn upload_late_frames(
+    inputs: Extract<Query<&Input>>,
+    gpu_images: Res<RenderAssets<Image>>,
+    queue: Res<RenderQueue>,
+) {
+    // gpu converted inputs are drawn into their texture instead
+    let inputs = inputs
+        .iter()
+        .filter(|input| input.late_upload && input.decoder != Decoder::Gpu);
+    for input in inputs {
+        let device = &input.device;
+        // prepared after the first extraction
+        let Some(gpu_image) = gpu_images.get(&device.image) else {
+            continue;
+        };
+
+        // the gpu image is replaced when a profile switch resizes it
+        let size = Extent3d {
+            width: gpu_image.size.x as u32,
+            height: gpu_image.size.y as u32,
+            depth_or_array_layers: 1,
+        };
+        let row = size.width as usize * input.encoding.bytes_per_pixel();
+
+        let uploaded = device.exchange.read(|frame| {
+            if frame.len() < row * size.height as usize {
+                return;
+            }
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &gpu_image.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                frame,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(row as u32),
+                    rows_per_image: None,
+                },
+                size,
+            );
+        });
+
+        if let Some(dequeued) = uploaded {
+            // a running task holds the io, the latency of this frame isn't reported then
+            if let Ok(mut io) = device.io.try_lock() {
+                io.upload_latency = Some(dequeued.elapsed());
+            }
+        }
+    }
+}
@@ -1,173 +1,545 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use bevy::asset::load_internal_asset;
+use bevy::diagnostic::DiagnosticsStore;
+use bevy::ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::render::render_asset::RenderAssetUsages;
-use bevy::render::render_resource::{
-    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
-};
-use bevy::tasks::{ComputeTaskPool, Task};
+use bevy::render::render_resource::Extent3d;
+use bevy::render::{ExtractSchedule, Render, RenderApp, RenderSet};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use bevy::utils::futures;
-use ffimage::color::Rgb;
-use ffimage::iter::{BytesExt, ColorConvertExt, PixelsExt};
-use ffimage_yuv::yuv::Yuv;
-use ffimage_yuv::yuv422::Yuv422;
 use thiserror::Error;
-use v4l::io::mmap::Stream;
-use v4l::io::traits::{CaptureStream, OutputStream};
-use v4l::prelude::*;
-use v4l::video::Capture;
+use tracing::{debug, error, trace, warn, Span};
+use v4l::format::FieldOrder;
+use v4l::io::traits::OutputStream;
+
+mod activity;
+mod aspect;
+mod auto;
+mod bayer;
+mod budget;
+mod bundle;
+mod busy;
+mod capabilities;
+mod color;
+mod config;
+mod control;
+mod converter;
+mod crop;
+mod deinterlace;
+mod denoise;
+mod devices;
+mod diagnostics;
+mod dither;
+mod dump;
+#[cfg(feature = "dv-timings")]
+mod dv;
+mod encode;
+mod encoder;
+mod errors;
+mod exchange;
+mod external;
+mod file;
+mod fourcc;
+mod frame;
+mod gpu;
+mod group;
+#[cfg(feature = "h264")]
+mod h264;
+mod headless;
+mod hotplug;
+mod input;
+mod input_builder;
+mod inspect;
+mod ioctl;
+#[cfg(feature = "mjpeg-encode")]
+mod jpeg;
+mod late;
+mod m2m;
+#[cfg(feature = "media")]
+mod media;
+mod memory;
+mod metadata;
+mod mock;
+mod mplane;
+mod open;
+mod orientation;
+mod output;
+mod parallel;
+mod pattern;
+mod pool;
+mod post_process;
+mod preference;
+mod processor;
+mod profile;
+mod raw;
+mod readback;
+mod reconnect;
+mod report;
+mod scale;
+#[cfg(feature = "serde")]
+pub(crate) mod serialize;
+mod signal;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+mod source;
+mod source_change;
+mod stats;
+mod subscribe;
+mod swizzle;
+mod target;
+mod timestamp;
+mod underrun;
+mod validate;
+mod wait;
+mod watchdog;
+
+pub use aspect::PixelAspect;
+pub use auto::{AutoInput, AutoInputPhase};
+pub use bayer::{BayerConfig, Demosaic};
+pub use budget::ConversionThrottled;
+pub use bundle::V4lBareInputBundle;
+#[cfg(feature = "bundles")]
+pub use bundle::{V4lInputBundle, V4lOutputBundle};
+pub use busy::{DeviceBusyWaiting, OpenPolicy};
+pub use capabilities::{
+    enumerate_capabilities, DeviceCapabilities, FormatCapabilities, FrameIntervals, FrameSizes,
+};
+pub use color::{
+    ColorMetadata, Colorimetry, ImageEncoding, YcbcrConversion, YcbcrMatrix, YcbcrRange,
+};
+pub use config::{ErrorPolicy, V4lConfig};
+pub use control::{
+    CameraControls, ControlDescriptor, ControlError, ControlFlags, ControlId, ControlKind,
+    ControlMenuItem, ControlStep, ControlValue, Exposure, ExposureMode, WhiteBalance,
+};
+pub use converter::{BuiltinConverter, PixelConverter};
+pub use crop::Crop;
+pub use deinterlace::Deinterlace;
+pub use devices::{
+    enumerate_devices, Capabilities, DeviceInfo, DeviceSelector, Selection, V4lDevices,
+};
+pub use dither::Dither;
+pub use dump::RecordMode;
+#[cfg(feature = "dv-timings")]
+pub use dv::DvTimings;
+pub use encoder::{EncodedFrame, EncodedOutput, EncoderSettings, H264Profile};
+pub use external::{ExternalInput, ExternalOutput};
+pub use fourcc::FourCC;
+pub use frame::FrameId;
+pub use group::{CaptureGroup, SyncedFrame, SyncedFrames};
+pub use headless::{Frame, FrameLayout, RawInput};
+pub use hotplug::V4lDeviceEvent;
+pub use input::{Decoder, Input};
+pub use input_builder::InputBuilder;
+pub use inspect::DeviceStatus;
+pub use m2m::M2m;
+#[cfg(feature = "media")]
+pub use media::{MediaDevice, MediaPipeline, PadFormat, PadRef};
+pub use memory::MemoryType;
+pub use metadata::{CaptureClock, DeviceTimestamp, MetadataInput};
+pub use mock::{MockFrames, MockStep};
+pub use open::{InputOpenFailed, PendingInput};
+pub use orientation::Orientation;
+pub use output::{Output, OutputBuilder};
+pub use pattern::TestPattern;
+pub use pool::BufferPool;
+pub use post_process::PostProcess;
+pub use preference::FormatRequest;
+pub use processor::{FrameInfo, FrameProcessor};
+pub use profile::{Profile, ProfileError, ProfileStep, ProfileSwitched};
+pub use raw::{RawFrame, RawFrames};
+pub use reconnect::{DeviceLost, DeviceReconnected, ReconnectPolicy};
+pub use report::{NegotiationReport, NegotiationStep};
+pub use scale::{AlphaMode, ScaleFilter, SizePolicy};
+pub use signal::{SignalLost, SignalRestored, SignalState};
+#[cfg(feature = "snapshot")]
+pub use snapshot::{SnapshotFormat, SnapshotSaved};
+pub use source_change::FormatChanged;
+pub use stats::{DroppedFrames, FrameStats, StreamStats};
+pub use subscribe::FrameRef;
+pub use target::TargetOptions;
+pub use timestamp::{Presented, Timestamp, TimestampSource};
+pub use underrun::{OutputUnderrun, UnderrunPolicy};
+pub use wait::WaitStrategy;
+pub use watchdog::{WatchdogAction, WatchdogEscalated, WatchdogPolicy};
+
+use color::ToRgb;
+use orientation::Rows;
+use scale::ScaledFrame;
+use source::IoStream;
+use stats::LumaHistogram;
 
+/// Stream buffers requested from drivers unless configured otherwise, see
+/// [`V4lConfig::default_buffer_count`]
 const BUFFER_COUNT: u32 = 4;
 
+/// Restarts of a stream after transient errors before they are reported
+const MAX_RESTARTS: u32 = 5;
+/// Wait before the first restart, doubled on every attempt after it
+const RESTART_BACKOFF: Duration = Duration::from_millis(50);
+/// Longest an input without a dequeue timeout blocks at a time, see [`Io::cancel`]
+const DEQUEUE_SLICE: Duration = Duration::from_millis(100);
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("v4l device unavailable")]
     Io(#[from] std::io::Error),
+    #[error("no v4l capture device matching \"{name}\" (available: {})", .available.join(", "))]
+    NoMatchingDevice {
+        name: String,
+        available: Vec<String>,
+    },
+    #[error("multiple v4l capture devices match \"{name}\": {}", .candidates.join(", "))]
+    AmbiguousDevice {
+        name: String,
+        candidates: Vec<String>,
+    },
+    #[error("none of the v4l devices could be opened: {}", devices::describe_failures(.failures))]
+    NoDeviceAvailable {
+        failures: Vec<(DeviceSelector, Error)>,
+    },
+    #[error("v4l device took none of the preferred formats, it offers {}", .offered.join(", "))]
+    NoPreferredFormat {
+        /// Every format with its sizes, like "YUYV 640x480 1280x720"
+        offered: Vec<String>,
+    },
+    #[error("v4l control {0:?} has a value type that isn't supported")]
+    UnsupportedControl(ControlId),
+    #[error("v4l control {control:?} is {current:?} after setting it to {requested:?}")]
+    ControlNotApplied {
+        control: ControlId,
+        requested: ControlValue,
+        current: ControlValue,
+    },
+    #[error("unsupported pixel format {fourcc}")]
+    UnsupportedFormat { fourcc: FourCC },
+    #[error("invalid fourcc \"{0}\", it has to be 1 to 4 ascii characters")]
+    InvalidFourcc(String),
+    #[error("v4l device rejected format {requested}, it offered {granted}")]
+    FormatRejected { requested: String, granted: String },
+    #[error("at least 2 stream buffers are needed, {0} were requested")]
+    InvalidBufferCount(u32),
+    #[error("failed to decode frame: {0}")]
+    Decode(String),
+    #[error("failed to encode frame: {0}")]
+    Encode(String),
+    #[error("frame processor panicked: {0}")]
+    ProcessorPanicked(String),
+    #[error("no v4l device at {}, it may be unplugged or numbered differently", .path.display())]
+    DeviceNotFound { path: std::path::PathBuf },
+    #[error("no permission to open {}, the user may have to be in the video group", .path.display())]
+    PermissionDenied {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("v4l stream failed: {source}")]
+    StreamingFailed {
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{} is in use by another process{}", .path.display(), busy::describe_holders(.holders))]
+    DeviceBusy {
+        path: std::path::PathBuf,
+        /// Processes holding the device as "name (pid)", when /proc can be read
+        holders: Vec<String>,
+    },
+    #[error("invalid image target: {0}")]
+    InvalidTarget(String),
+    #[error(
+        "image is {}x{} but the v4l device is {}x{}, see SizePolicy",
+        .image.0, .image.1, .device.0, .device.1
+    )]
+    SizeMismatch {
+        image: (u32, u32),
+        device: (u32, u32),
+    },
+    #[error("v4l device reported invalid format {format}: {reason}")]
+    InvalidFormat { format: String, reason: String },
+    #[error(
+        "can't crop to {}x{} at {},{}: {reason}",
+        .rect.width(), .rect.height(), .rect.min.x, .rect.min.y
+    )]
+    InvalidCrop {
+        rect: bevy::math::URect,
+        reason: String,
+    },
+    #[error("no profile named \"{0}\"")]
+    UnknownProfile(String),
+    #[error("v4l device doesn't support {0} buffers")]
+    UnsupportedMemory(MemoryType),
+    #[error("presentation timestamp {pts:?} is not after the previous one, {previous:?}")]
+    NonMonotonicTimestamp { pts: Duration, previous: Duration },
+    #[error("no frame from the v4l device for {waited:?}, its signal may be lost")]
+    Timeout { waited: Duration },
+    #[error("v4l device has no signal, {reason}")]
+    NoSignal { reason: String },
+    #[cfg(feature = "media")]
+    #[error("media controller: {0}")]
+    Media(String),
+    #[cfg(feature = "snapshot")]
+    #[error("failed to save snapshot: {0}")]
+    Snapshot(String),
 }
 
-#[derive(Component)]
-pub struct Input(Device);
-
-impl Input {
-    /// Creates a V4lDevice for encoding a bevy image into v4l
-    pub fn new(device_id: usize, images: &mut ResMut<Assets<Image>>) -> Result<Self> {
-        let dev = v4l::Device::new(device_id)?;
-        let format = dev.format()?;
-        let stream = MmapStream::with_buffers(&dev, v4l::buffer::Type::VideoCapture, BUFFER_COUNT)?;
-
-        let size = Extent3d {
-            width: format.width,
-            height: format.height,
-            depth_or_array_layers: 1,
-        };
+impl Error {
+    /// The io error behind the error, like ENODEV once a device was unplugged
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        match self {
+            Self::Io(err)
+            | Self::PermissionDenied { source: err, .. }
+            | Self::StreamingFailed { source: err } => Some(err),
+            _ => None,
+        }
+    }
 
-        let buffer1 = vec![255_u8; (size.width * size.height * 4) as usize];
-        let buffer2 = buffer1.clone();
-
-        let image = images.add(Image::new(
-            size,
-            TextureDimension::D2,
-            buffer1,
-            TextureFormat::Rgba8UnormSrgb,
-            RenderAssetUsages::all(),
-        ));
-
-        Ok(Self(crate::Device {
-            id: device_id,
-            format,
-            image,
-            size,
-            io: Arc::new(Mutex::new(Io {
-                buffer: buffer2,
-                stream,
-            })),
-            task: None,
-            dev,
-        }))
-    }
-
-    pub fn clone_image(&mut self, images: &mut ResMut<Assets<Image>>) -> Handle<Image> {
-        let buffer = vec![255_u8; (self.0.size.width * self.0.size.height * 4) as usize];
-        images.add(Image {
-            data: buffer,
-            texture_descriptor: TextureDescriptor {
-                label: None,
-                size: self.0.size,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
-                mip_level_count: 1,
-                sample_count: 1,
-                usage: TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_DST
-                    | TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[],
-            },
-            asset_usage: RenderAssetUsages::all(),
-            ..default()
-        })
+    /// Whether a dequeue gave up waiting for a frame
+    fn timed_out(&self) -> bool {
+        match self {
+            Self::Io(err) => err.kind() == std::io::ErrorKind::TimedOut,
+            Self::Timeout { .. } => true,
+            _ => false,
+        }
     }
 
-    /// Handle to bevy image
-    pub fn image(&self) -> &Handle<Image> {
-        &self.0.image
+    /// Io errors of a running stream become [`Error::StreamingFailed`]
+    fn streaming(self) -> Self {
+        match self {
+            Self::Io(source) => Self::StreamingFailed { source },
+            err => err,
+        }
     }
+}
 
+/// An error from a v4l device that happened after it was opened
+#[derive(Event, Debug)]
+pub struct V4lError {
+    pub entity: Entity,
     /// ID of the v4l video device (/dev/video{id})
-    pub fn id(&self) -> usize {
-        self.0.id
-    }
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Latest frame of the device when the error happened
+    pub frame: Option<FrameId>,
+    pub error: Error,
+    /// Errors with the same message dropped since the last one was sent. A device
+    /// repeating an error sends it at most once a second.
+    pub repeated: u32,
+}
+
+/// Sent when a stream was restarted after a transient error, like EIO after a USB glitch.
+/// The image and components of the device are kept.
+#[derive(Event, Debug)]
+pub struct StreamRestarted {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Restarts since the last frame was read, starting at 1
+    pub attempt: u32,
+    pub error: Error,
+}
+
+/// Sent when the first frame of a device was read or written
+#[derive(Event, Debug, Clone)]
+pub struct StreamStarted {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    pub report: NegotiationReport,
+}
+
+/// Sent when a captured frame was converted into the image of an [`Input`]. Frames
+/// that were dequeued but skipped, like while inactive, aren't sent.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FrameReceived {
+    pub entity: Entity,
+    /// Sequence number the driver gave the frame
+    pub frame: FrameId,
+    pub timestamp: Timestamp,
+    /// Bytes of the buffer the driver filled, 0 for drivers that don't say
+    pub bytesused: u32,
+    /// Older frames that were waiting in the driver's queue with this one, requeued
+    /// without converting them. See [`InputBuilder::every_frame`].
+    pub dropped: u32,
+    /// Frames dequeued since the previous one that the driver flagged as corrupt or
+    /// that were shorter than their format, like after a transfer was cut off. They
+    /// are requeued without converting them, the image keeps the previous frame.
+    pub corrupt: u32,
+    /// V4L2_BUF_FLAG_* flags the driver set on the buffer, like 0x40 for
+    /// V4L2_BUF_FLAG_ERROR, 0 for virtual inputs. See [`Input::signal`] for whether the
+    /// device has a signal.
+    pub flags: u32,
+    /// Frames the driver dropped since the previous one, before they were dequeued,
+    /// the gap in its sequence numbers. Drivers drop frames when no buffer is queued,
+    /// more buffers help, see [`InputBuilder::buffer_count`]. Frames the plugin drops
+    /// are `dropped` and `corrupt`.
+    pub lost: u32,
+    /// Timestamps of the camera, for inputs with a [`MetadataInput`] whose metadata
+    /// of the frame arrived before the event was sent
+    pub device_timestamp: Option<DeviceTimestamp>,
+    /// Which clock [`FrameReceived::capture_time`] is of
+    pub clock: CaptureClock,
+}
 
-    pub fn format(&self) -> Format {
-        Format(self.0.format)
+impl FrameReceived {
+    /// Whether the sequence of the driver skipped frames before this one
+    pub fn gap(&self) -> bool {
+        self.lost > 0
     }
 
-    pub fn size(&self) -> Extent3d {
-        self.0.size
+    /// When the frame was captured, the presentation timestamp of the camera converted
+    /// to CLOCK_MONOTONIC when it is known, the timestamp of the kernel otherwise
+    pub fn capture_time(&self) -> Duration {
+        match self.device_timestamp.and_then(|device| device.time) {
+            Some(time) => time,
+            None => self.timestamp.time,
+        }
     }
 }
 
-#[derive(Component)]
-pub struct Output(Device);
+/// Sent when a frame of the image of an [`Output`] was queued on the device. Images are
+/// only written after they changed, and repeated by [`UnderrunPolicy`] outputs.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FrameSent {
+    pub entity: Entity,
+    pub frame: FrameId,
+    /// Presentation timestamp set on the buffer
+    pub pts: Duration,
+    /// Bytes written to the buffer, of all planes for multi-planar outputs
+    pub bytesused: u32,
+}
+
+/// Sent once the frame requested with [`Input::request_frame`] is in the image
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FrameCaptured {
+    pub entity: Entity,
+    pub frame: FrameId,
+}
+
+/// Format of a v4l device.
+///
+/// Outputs pass every field to the driver when setting the format, multi-planar ones
+/// only width, height and fourcc. Inputs report the format the device streams.
+#[derive(Debug, Clone, Copy)]
+pub struct Format(v4l::Format);
 
-impl Output {
-    /// Creates a V4lDevice for encoding a bevy image into v4l
-    pub fn new(device_id: usize, image: Handle<Image>, format: Format) -> Result<Self> {
-        let format = format.0;
-        let dev = v4l::Device::new(device_id)?;
+impl Format {
+    /// Format to open an [`Output`] with, see [`FormatBuilder::build`]
+    pub fn new(width: u32, height: u32, fourcc: &[u8; 4]) -> Result<Self> {
+        Self::builder()
+            .width(width)
+            .height(height)
+            .fourcc(fourcc)
+            .build()
+    }
 
-        let _ = v4l::video::Output::set_format(&dev, &format)?;
+    /// Builds a format, 640x480 YUYV unless changed
+    pub fn builder() -> FormatBuilder {
+        FormatBuilder {
+            width: 640,
+            height: 480,
+            fourcc: *b"YUYV",
+        }
+    }
 
-        let stream = MmapStream::with_buffers(&dev, v4l::buffer::Type::VideoOutput, BUFFER_COUNT)?;
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
 
-        let size = Extent3d {
-            width: format.width,
-            height: format.height,
-            depth_or_array_layers: 1,
-        };
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
 
-        let buffer1 = vec![255_u8; (size.width * size.height * 4) as usize];
-        let buffer2 = buffer1.clone();
+    /// Four ascii characters, like `*b"YUYV"`
+    pub fn fourcc(&self) -> [u8; 4] {
+        self.0.fourcc.repr
+    }
 
-        Ok(Self(crate::Device {
-            id: device_id,
-            format,
-            image,
-            size,
-            io: Arc::new(Mutex::new(Io {
-                buffer: buffer2,
-                stream,
-            })),
-            task: None,
-            dev,
-        }))
+    /// How the fields of interlaced frames are stored, progressive for most cameras
+    pub fn field_order(&self) -> FieldOrder {
+        self.0.field_order
     }
 
-    /// Handle to bevy image
-    pub fn image(&self) -> &Handle<Image> {
-        &self.0.image
+    /// Bytes the driver pads rows to, of the luma plane of planar formats. 0 for
+    /// formats that weren't set on a driver.
+    pub fn stride(&self) -> u32 {
+        self.0.stride
     }
+}
 
-    /// ID of the v4l video device (/dev/video{id})
-    pub fn id(&self) -> usize {
-        self.0.id
+/// Configures a [`Format`], see [`Format::builder`]. The driver picks the other fields,
+/// like the stride.
+#[derive(Debug, Clone, Copy)]
+pub struct FormatBuilder {
+    width: u32,
+    height: u32,
+    fourcc: [u8; 4],
+}
+
+impl FormatBuilder {
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = height;
+        self
     }
 
-    pub fn format(&self) -> Format {
-        Format(self.0.format)
+    pub fn fourcc(mut self, fourcc: &[u8; 4]) -> Self {
+        self.fourcc = *fourcc;
+        self
     }
 
-    pub fn size(&self) -> Extent3d {
-        self.0.size
+    /// Fails with [`Error::UnsupportedFormat`] for formats outputs can't write
+    /// and [`Error::InvalidFormat`] for sizes no driver takes, or that the chroma
+    /// subsampling of the format doesn't divide, like an odd width for YUYV
+    pub fn build(self) -> Result<Format> {
+        if !encode::can_encode(&self.fourcc) {
+            return Err(Error::UnsupportedFormat {
+                fourcc: self.fourcc.into(),
+            });
+        }
+
+        let format = v4l::Format::new(self.width, self.height, v4l::FourCC::new(&self.fourcc));
+        validate::format(&format)?;
+        validate::subsampling(&format)?;
+        Ok(Format(format))
+    }
+
+    /// Like [`FormatBuilder::build`] for a fourcc the [`PixelConverter`] of the output
+    /// encodes, only the size is checked
+    pub fn build_custom(self) -> Result<Format> {
+        let format = v4l::Format::new(self.width, self.height, v4l::FourCC::new(&self.fourcc));
+        validate::format(&format)?;
+        Ok(Format(format))
     }
 }
 
-//TODO: add a way to construct a format
-pub struct Format(v4l::Format);
+impl From<v4l::Format> for Format {
+    fn from(format: v4l::Format) -> Self {
+        Self(format)
+    }
+}
+
+impl From<Format> for v4l::Format {
+    fn from(format: Format) -> Self {
+        format.0
+    }
+}
 
 /// Handle to a v4l Device
-#[allow(dead_code)]
 #[derive(Component)]
 struct Device {
     id: usize,
@@ -176,8 +548,47 @@ struct Device {
     size: Extent3d,
     task: Option<Task<()>>,
     io: Arc<Mutex<Io>>,
-    /// NOTE: dropping this might panic :)
-    dev: v4l::Device,
+    /// Latest frame read or written, updated when a task finishes
+    frame: Option<FrameId>,
+    report: NegotiationReport,
+    /// Span the logs of the device are emitted in, see [`device_span`]
+    span: Span,
+    /// Path the device was opened with, `None` for virtual inputs
+    path: Option<std::path::PathBuf>,
+    /// Time between frames the driver granted, `None` when it doesn't report one
+    frame_interval: Option<Duration>,
+    /// `None` for virtual inputs, like file replay, and once closed
+    dev: Option<v4l::Device>,
+    /// Set by [`Device::close`], no tasks are spawned after it
+    closed: bool,
+    /// Set by [`Device::pause`], mirrored in [`Io`] for the threads that write to it
+    paused: bool,
+    /// Set while [`Device::close`] and [`Device::pause`] wait for the task, shared with
+    /// [`Io::cancel`]
+    cancel: Arc<AtomicBool>,
+    /// Converted frames of inputs, taken without locking the [`Io`]
+    exchange: Arc<exchange::Exchange>,
+    /// Drops errors that repeat the last one, see [`V4lError::repeated`]
+    errors: errors::ErrorFilter,
+    /// Counters of [`Input::stats`] and [`Output::stats`]
+    health: stats::Health,
+    /// Removes the image from the assets once dropped, see [`InputBuilder::keep_image`]
+    release_image: bool,
+    /// Queue of the app the input was spawned into, for [`Device::release_image`]
+    released: Option<pool::ReleasedImages>,
+}
+
+/// Despawned inputs and outputs release their device when the component is dropped,
+/// not only once the last task holding the [`Io`] is done with it
+impl Drop for Device {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.close();
+        }
+        if let Some(released) = self.released.as_ref().filter(|_| self.release_image) {
+            released.push(self.image.id());
+        }
+    }
 }
 
 /// IO Data used in a bevy task
@@ -187,54 +598,679 @@ struct Io {
     /// - input: double buffered with bevy Image.data
     /// - output: copy of Image.data
     buffer: Vec<u8>,
-    stream: Stream<'static>,
+    stream: IoStream,
+    /// Converts captured frames in hardware, replacing [`stream_read`]'s conversion
+    m2m: Option<m2m::M2mStage>,
+    /// Runs on converted frames before they are swapped into the image
+    processor: Option<FrameProcessor>,
+    /// Error of the last frame, sent as a [`V4lError`] once the task is done
+    error: Option<Error>,
+    /// Frames written, outputs have no sequence from the driver
+    sequence: u32,
+    frames: frame::Sequencer,
+    /// Ignore the timestamps of the driver, see [`InputBuilder::dequeue_timestamps`]
+    dequeue_timestamps: bool,
+    /// Restarts since the last frame was read
+    restarts: u32,
+    /// Last restart, sent as a [`StreamRestarted`] once the task is done
+    restarted: Option<(u32, Error)>,
+    /// Stream stopped by [`Device::pause`], no frames are repeated
+    paused: bool,
+    /// Set for inputs that block dequeuing. They poll in slices of [`DEQUEUE_SLICE`]
+    /// until a frame is there or the task is cancelled, so closing or pausing them
+    /// doesn't wait out a frame.
+    cancel: Option<Arc<AtomicBool>>,
+    /// Requeues stale frames before dequeuing, unless the input keeps
+    /// [`InputBuilder::every_frame`]
+    drain: bool,
+    /// Frames rejected as corrupt since the last converted one, sent as
+    /// [`FrameReceived::corrupt`]
+    corrupt: u32,
+    /// Frames the driver dropped since the last converted one, sent as
+    /// [`FrameReceived::lost`]
+    lost: u32,
+    /// Set for [`RawInput`]s keeping the native format and inputs converted on the gpu,
+    /// dequeued buffers are copied into `buffer` as they are
+    native: bool,
+    /// Latest frame converted by an input, sent as a [`FrameReceived`] once the task
+    /// is done
+    received: Option<FrameReceived>,
+    /// Latest frame written by an output, sent as a [`FrameSent`] once the task is done
+    sent: Option<FrameSent>,
+    /// How written images are fitted to the device
+    size_policy: SizePolicy,
+    /// How outputs write the alpha of their image, see [`OutputBuilder::alpha_mode`]
+    alpha: AlphaMode,
+    /// How converted frames or written images are turned, see [`Orientation`]
+    orientation: Orientation,
+    /// Region of the frames converted by inputs the driver doesn't crop, see
+    /// [`Input::set_crop`]
+    crop: Option<crop::Region>,
+    /// Frame outputs scale their image into with [`ScaleFilter::Bilinear`]
+    resampler: Option<scale::Resampler>,
+    /// Where outputs looked the pixels of the previous frame up, reused for the next
+    offsets: scale::Offsets,
+    /// Applied to converted frames for linear [`ImageEncoding`]s
+    linearize: Option<color::Linearize>,
+    /// Used when decoding formats with more than 8 bits per sample
+    dither: Dither,
+    /// Of the YCbCr samples of frames read or written, see [`Colorimetry`]
+    colorimetry: Colorimetry,
+    /// Applied to converted frames before the processor
+    denoise: Option<denoise::TemporalFilter>,
+    /// Set for inputs with a [`PostProcess`]
+    post_process: Option<post_process::Lut>,
+    /// Set for inputs streaming a Bayer format
+    bayer: Option<bayer::Bayer>,
+    /// Converter of the app, asked before the built-in conversions of inputs
+    converter: Option<Box<dyn PixelConverter>>,
+    /// Set for inputs that send [`FrameStats`]
+    stats: Option<stats::LumaHistogram>,
+    /// Dequeued and dropped frames since the last task, see [`StreamStats`]
+    counts: stats::Counts,
+    /// Image outputs write instead of `buffer`, shared with the outputs of the same image
+    snapshot: Option<Arc<Vec<u8>>>,
+    /// How converted frames are stored in `buffer`
+    encoding: ImageEncoding,
+    /// Set for inputs with a preview image
+    preview: Option<scale::Preview>,
+    /// Extra images of inputs, see [`Input::add_target`]
+    targets: Vec<target::Target>,
+    /// Delivers dequeued buffers as [`RawFrame`] events
+    raw: Option<raw::RawSink>,
+    /// Set for inputs, sends dequeued buffers to [`Input::subscribe`]rs
+    publisher: Option<subscribe::Publisher>,
+    /// Writes dequeued buffers to a file for debugging
+    dump: Option<dump::Dumper>,
+    /// Set for inputs, decides which frames are converted
+    activity: Option<activity::Activity>,
+    /// Whether `buffer` holds a frame that wasn't published to `exchange` yet
+    fresh: bool,
+    /// When the frame in `buffer` was dequeued, for [`FrameStats::upload_latency`]
+    dequeued: Option<std::time::Instant>,
+    /// Shared with [`Device::exchange`], finished frames are swapped into it
+    exchange: Arc<exchange::Exchange>,
+    /// Time from dequeue to upload of the latest frame swapped into the image
+    upload_latency: Option<Duration>,
+    /// Set for inputs, see [`InputBuilder::wait`]
+    wait: Option<wait::Waiter>,
+    /// Timestamps written frames, see [`Output::present`]
+    presenter: timestamp::Presenter,
+    /// Set for outputs that repeat frames, see [`OutputBuilder::repeat_on_underrun`]
+    underruns: Option<underrun::Underruns>,
+    /// Bytes per row of captured frames reported by the driver, padded rows are
+    /// copied into `unpadded` before they are converted on the cpu
+    stride: u32,
+    unpadded: Vec<u8>,
+    /// Set for inputs whose driver mislabels frames, see [`InputBuilder::interpret_as`]
+    overrides: swizzle::Overrides,
+    /// Set for inputs with a watchdog, see [`InputBuilder::watchdog`]
+    watchdog: Option<watchdog::Watchdog>,
+    /// Set for inputs with [`InputBuilder::conversion_budget`]
+    budget: Option<budget::Budget>,
+    /// Set for inputs of devices, see [`InputBuilder::signal_timeout`]
+    signal: Option<signal::Signal>,
+    /// Set for inputs, see [`InputBuilder::deinterlace`]
+    deinterlace: Option<deinterlace::Deinterlacer>,
+    /// Set for inputs of devices that report source changes, see [`FormatChanged`]
+    source_change: Option<source_change::SourceChange>,
+    /// Set while [`V4lConfig::diagnostics`] is on
+    diagnostics: Option<diagnostics::Recorder>,
+    /// Set for inputs streaming H264 without an m2m decoder
+    #[cfg(feature = "h264")]
+    h264: Option<h264::H264>,
+    /// Set for outputs in a format [`stream_write`] converts to
+    frame_encoder: Option<Box<dyn encode::FrameEncoder>>,
+}
+
+/// Streams the [`Input`]s and [`Output`]s of the app. The defaults of devices are
+/// inserted as the [`V4lConfig`] resource, see it for what they do.
+pub struct V4lPlugin {
+    /// Schedule of [`V4lSet::SpawnIo`], [`PreUpdate`] by default
+    pub spawn_schedule: InternedScheduleLabel,
+    /// Schedule of [`V4lSet::Poll`], [`Update`] by default. Polling in the same
+    /// schedule as spawning, like [`First`], shows frames in the frame they arrived.
+    pub poll_schedule: InternedScheduleLabel,
+    pub default_buffer_count: u32,
+    pub error_policy: ErrorPolicy,
+    pub auto_negotiate: bool,
+    pub log_frames: bool,
+    pub diagnostics: bool,
+}
+
+impl V4lPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for V4lPlugin {
+    fn default() -> Self {
+        let config = V4lConfig::default();
+        Self {
+            spawn_schedule: PreUpdate.intern(),
+            poll_schedule: Update.intern(),
+            default_buffer_count: config.default_buffer_count,
+            error_policy: config.error_policy,
+            auto_negotiate: config.auto_negotiate,
+            log_frames: config.log_frames,
+            diagnostics: config.diagnostics,
+        }
+    }
+}
+
+/// Systems of [`V4lPlugin`], to order systems of the app around them
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum V4lSet {
+    /// Opens pending inputs, applies controls and profiles and spawns the io tasks
+    SpawnIo,
+    /// Swaps finished frames into the images and sends the events of the devices
+    Poll,
 }
 
-pub struct V4lPlugin;
 impl Plugin for V4lPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(PreUpdate, spawn_io_tasks)
-            .add_systems(Update, poll_io_tasks);
+        let config = V4lConfig {
+            default_buffer_count: self.default_buffer_count,
+            error_policy: self.error_policy,
+            auto_negotiate: self.auto_negotiate,
+            log_frames: self.log_frames,
+            diagnostics: self.diagnostics,
+        };
+        config::set(config.clone());
+        app.insert_resource(config);
+
+        let devices = V4lDevices::scan();
+        if let Some(hotplug) = hotplug::Hotplug::spawn(&devices) {
+            app.insert_resource(hotplug);
+        }
+
+        app.insert_resource(devices)
+            .register_type::<Input>()
+            .register_type::<Output>()
+            .register_type::<DeviceStatus>()
+            .register_type::<CameraControls>()
+            .register_type::<PostProcess>()
+            .add_event::<V4lDeviceEvent>()
+            .add_event::<EncodedFrame>()
+            .add_event::<V4lError>()
+            .add_event::<InputOpenFailed>()
+            .add_event::<DeviceBusyWaiting>()
+            .add_event::<RawFrame>()
+            .add_event::<StreamRestarted>()
+            .add_event::<StreamStarted>()
+            .add_event::<FrameCaptured>()
+            .add_event::<FrameReceived>()
+            .add_event::<SyncedFrames>()
+            .add_event::<FrameSent>()
+            .add_event::<FrameStats>()
+            .add_event::<OutputUnderrun>()
+            .add_event::<ProfileSwitched>()
+            .add_event::<WatchdogEscalated>()
+            .add_event::<ConversionThrottled>()
+            .add_event::<DeviceLost>()
+            .add_event::<DeviceReconnected>()
+            .add_event::<SignalLost>()
+            .add_event::<SignalRestored>()
+            .add_event::<FormatChanged>()
+            .init_resource::<output::Snapshots>()
+            .insert_resource(BufferPool::global().clone())
+            .init_resource::<pool::ReleasedImages>()
+            .add_systems(
+                self.spawn_schedule,
+                (
+                    (
+                        config::sync_config,
+                        pool::track_released_images,
+                        hotplug::send_device_events,
+                        poll_pending_inputs,
+                        group::start_capture_groups,
+                        auto::drive_auto_inputs,
+                        activity::sync_visibility,
+                        profile::switch_profiles,
+                        source_change::follow_source_changes,
+                        control::sync_camera_controls,
+                        post_process::sync_post_process,
+                        reconnect::reconnect_inputs,
+                        spawn_input_tasks,
+                    )
+                        .chain(),
+                    spawn_output_tasks,
+                    headless::spawn_raw_tasks,
+                )
+                    .in_set(V4lSet::SpawnIo),
+            )
+            .add_systems(
+                self.poll_schedule,
+                (
+                    (
+                        output::track_images,
+                        poll_io_tasks,
+                        group::pair_capture_groups,
+                    )
+                        .chain(),
+                    headless::poll_raw_inputs,
+                    send_encoded_frames,
+                    inspect::update_status,
+                    pool::release_images,
+                )
+                    .in_set(V4lSet::Poll),
+            );
+
+        #[cfg(feature = "snapshot")]
+        app.add_event::<SnapshotSaved>().add_systems(
+            self.poll_schedule,
+            snapshot::save_snapshots
+                .after(poll_io_tasks)
+                .in_set(V4lSet::Poll),
+        );
+
+        // finished tasks are polled before new ones are spawned
+        if self.spawn_schedule == self.poll_schedule {
+            app.configure_sets(self.poll_schedule, V4lSet::Poll.before(V4lSet::SpawnIo));
+        }
+
+        load_internal_asset!(app, gpu::SHADER, "gpu.wgsl", Shader::from_wgsl);
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<gpu::GpuFrames>()
+                .init_resource::<readback::Readbacks>()
+                .add_systems(
+                    ExtractSchedule,
+                    (
+                        late::upload_late_frames,
+                        gpu::extract_frames,
+                        readback::extract_outputs,
+                    ),
+                )
+                .add_systems(
+                    Render,
+                    (
+                        gpu::convert_frames.in_set(RenderSet::Queue),
+                        // after the frame rendered into the images
+                        readback::read_back_images.in_set(RenderSet::Cleanup),
+                    ),
+                );
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        // needs the render device, which is created after the plugins are built
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<gpu::GpuConversion>();
+        }
+    }
+}
+
+fn poll_pending_inputs(
+    mut commands: Commands,
+    mut pending: Query<(Entity, &mut PendingInput)>,
+    mut images: ResMut<Assets<Image>>,
+    mut failed: EventWriter<InputOpenFailed>,
+    mut waiting: EventWriter<DeviceBusyWaiting>,
+) {
+    for (entity, mut pending) in pending.iter_mut() {
+        let attempts = pending
+            .waiting
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        waiting.send_batch(
+            attempts
+                .try_iter()
+                .map(|attempt| DeviceBusyWaiting { entity, ..attempt }),
+        );
+        let Some(result) = futures::check_ready(&mut pending.task) else {
+            continue;
+        };
+
+        commands.entity(entity).remove::<PendingInput>();
+
+        match result {
+            Ok(opened) => {
+                let input = opened.into_input(&mut images);
+                debug!("opened v4l device {}", input.selection().selector);
+                commands
+                    .entity(entity)
+                    .insert((input.image().clone(), input));
+            }
+            Err(error) => {
+                error!("failed to open v4l input: {error}");
+                failed.send(InputOpenFailed { entity, error });
+            }
+        }
     }
 }
 
+/// The events [`poll_io_tasks`] sends, and the config filtering its errors
+#[derive(SystemParam)]
+struct IoEvents<'w> {
+    started: EventWriter<'w, StreamStarted>,
+    errors: EventWriter<'w, V4lError>,
+    raw_frames: EventWriter<'w, RawFrame>,
+    frame_stats: EventWriter<'w, FrameStats>,
+    restarts: EventWriter<'w, StreamRestarted>,
+    underruns: EventWriter<'w, OutputUnderrun>,
+    escalated: EventWriter<'w, WatchdogEscalated>,
+    throttled: EventWriter<'w, ConversionThrottled>,
+    lost: EventWriter<'w, DeviceLost>,
+    captured: EventWriter<'w, FrameCaptured>,
+    received: EventWriter<'w, FrameReceived>,
+    sent: EventWriter<'w, FrameSent>,
+    signal_lost: EventWriter<'w, SignalLost>,
+    signal_restored: EventWriter<'w, SignalRestored>,
+    config: Res<'w, V4lConfig>,
+}
+
 fn poll_io_tasks(
-    mut inputs: Query<&mut Input>,
-    mut outputs: Query<&mut Output>,
+    mut inputs: Query<(Entity, &mut Input, Option<&MetadataInput>)>,
+    mut outputs: Query<(Entity, &mut Output)>,
+    mut encoded: Query<(Entity, &mut EncodedOutput)>,
     mut images: ResMut<Assets<Image>>,
+    mut events: IoEvents,
+    mut diagnostics: Option<ResMut<DiagnosticsStore>>,
+    mut snapshots: ResMut<output::Snapshots>,
 ) {
-    for mut input in inputs.iter_mut() {
-        let device = &mut input.0;
+    for (entity, mut input, metadata) in inputs.iter_mut() {
+        let Input {
+            device,
+            preview,
+            late_upload,
+            connection,
+            single_shot,
+            frame_requested,
+            signal,
+            ..
+        } = &mut *input;
         let Some(mut task_status) = device.task.as_mut() else {
             continue;
         };
 
         if let Some(()) = futures::check_ready(&mut task_status) {
-            let Some(image) = images.get_mut(device.image.clone()) else {
+            // late uploads leave no image in the main world. The task is done either way,
+            // polling it again panics.
+            if !*late_upload && !images.contains(&device.image) {
+                device.task = None;
                 continue;
-            };
+            }
 
             if let Ok(mut io) = device.io.lock() {
-                std::mem::swap(&mut image.data, &mut io.buffer);
+                if let Some(frame) = io.raw.as_mut().and_then(|raw| raw.frame.take()) {
+                    events.raw_frames.send(RawFrame { entity, ..frame });
+                }
+                if !*late_upload {
+                    swap_images(&mut io, &device.image, preview.as_ref(), &mut images);
+                }
+
+                events
+                    .started
+                    .send_batch(device.started(io.frames.last(), entity));
+                let previous = device.frame;
+                device.frame = io.frames.last();
+                let counts = std::mem::take(&mut io.counts);
+                device.health.add(counts, io.received.is_some() as u64);
+                if let Some(frame) = io.received.take() {
+                    if let Some(recorder) = io.diagnostics.as_mut() {
+                        recorder.frame(frame.dropped + frame.corrupt);
+                    }
+                    let frame = match metadata {
+                        Some(metadata) => metadata.correlate(frame),
+                        None => frame,
+                    };
+                    events.received.send(FrameReceived { entity, ..frame });
+                }
+                if let Some((store, recorder)) = diagnostics.as_mut().zip(io.diagnostics.as_mut()) {
+                    recorder.record(entity, store);
+                }
+
+                // failed captures leave the request for the next task
+                let shot = device.frame.filter(|&frame| Some(frame) != previous);
+                if let Some(frame) = shot.filter(|_| *single_shot && *frame_requested) {
+                    *frame_requested = false;
+                    if let Err(err) = io.stream.restart() {
+                        warn!(%err, "failed to stop v4l stream after a single shot");
+                    }
+                    events.captured.send(FrameCaptured { entity, frame });
+                }
+
+                if let Some(stats) = io.stats.as_mut().and_then(|stats| stats.latest.take()) {
+                    let wait = io.wait.as_ref();
+                    events.frame_stats.send(FrameStats {
+                        entity,
+                        wait: wait.map_or_else(WaitStrategy::default, |wait| wait.strategy),
+                        wake_latency: wait.and_then(|wait| wait.latency),
+                        upload_latency: io.upload_latency,
+                        escalations: io
+                            .watchdog
+                            .as_ref()
+                            .map_or(0, |watchdog| watchdog.escalations),
+                        conversion: io.budget.as_ref().and_then(|budget| budget.average),
+                        ..stats
+                    });
+                }
+
+                if let Some(watchdog) = io.watchdog.as_mut() {
+                    if let Some(dev) = watchdog.reopened.take() {
+                        device.dev = Some(dev);
+                    }
+                    if let Some((action, failures, error)) = watchdog.pending.take() {
+                        events.escalated.send(WatchdogEscalated {
+                            entity,
+                            device: device.id,
+                            label: device.label().to_string(),
+                            action,
+                            failures,
+                            escalations: watchdog.escalations,
+                            error,
+                        });
+                    }
+                }
+
+                if let Some(budget) = io.budget.as_mut() {
+                    if let Some(interval) = budget.throttled.take() {
+                        events.throttled.send(ConversionThrottled {
+                            entity,
+                            device: device.id,
+                            label: device.label().to_string(),
+                            interval,
+                            conversion: budget.average.unwrap_or_default(),
+                            budget: budget.budget(),
+                        });
+                    }
+                }
+
+                // virtual inputs deliver every frame
+                *signal = match io.signal.as_ref() {
+                    Some(io_signal) => io_signal.state,
+                    None if device.frame.is_some() => SignalState::Present,
+                    None => SignalState::Unknown,
+                };
+                if let Some(signal) = io.signal.as_mut() {
+                    if let Some(error) = signal.pending_lost.take() {
+                        events.signal_lost.send(SignalLost {
+                            entity,
+                            device: device.id,
+                            label: device.label().to_string(),
+                            error,
+                        });
+                    }
+                    if let Some(lost) = signal.pending_restored.take() {
+                        events.signal_restored.send(SignalRestored {
+                            entity,
+                            device: device.id,
+                            label: device.label().to_string(),
+                            lost,
+                        });
+                    }
+                }
+
+                if let Some((attempt, error)) = io.restarted.take() {
+                    device.health.error(&error);
+                    events.restarts.send(StreamRestarted {
+                        entity,
+                        device: device.id,
+                        label: device.label().to_string(),
+                        attempt,
+                        error,
+                    });
+                }
+
+                if let Some(error) = &io.error {
+                    device.health.error(error);
+                }
+                match io.error.take() {
+                    Some(error) if reconnect::is_lost(&error, device.path.as_deref()) => {
+                        warn!(%error, "v4l device disappeared");
+                        // the buffers are freed before the device
+                        io.stream = IoStream::Closed;
+                        device.dev = None;
+                        connection.lose();
+                        events.lost.send(DeviceLost {
+                            entity,
+                            device: device.id,
+                            label: device.label().to_string(),
+                            error,
+                        });
+                    }
+                    Some(error) => {
+                        let event = device.error_event(entity, error);
+                        config::report(
+                            &events.config,
+                            &mut events.errors,
+                            device.errors.pass(event),
+                        );
+                    }
+                    None => {}
+                }
             }
 
             device.task = None;
         }
     }
 
-    for mut output in outputs.iter_mut() {
-        let device = &mut output.0;
+    for (entity, mut output) in outputs.iter_mut() {
+        let Output(device, _, pacing) = &mut *output;
+        let finished = match device.task.as_mut() {
+            Some(task) => futures::check_ready(task).is_some(),
+            None => false,
+        };
+        // the buffer is being written
+        if device.task.is_some() && !finished {
+            continue;
+        }
+        // rendering doesn't modify the image, frames read back from its texture do
+        if pacing.readback && device.exchange.is_fresh() {
+            pacing.changed = true;
+        }
+        // throttled outputs copy the image once the write is due
+        let copy = pacing.changed && !pacing.throttled();
+        if !copy && !finished {
+            continue;
+        }
+
+        let Ok(mut io) = device.io.lock() else {
+            continue;
+        };
+        let copied = match (copy, pacing.readback) {
+            (false, _) => false,
+            (true, true) => {
+                io.snapshot = None;
+                device.exchange.take(&mut io.buffer).is_some()
+            }
+            // read only, a mutable borrow would mark the image modified and upload it
+            // again. Outputs of the same image share the copy, processors run on one
+            // of their own taken by the io task, the image is left untouched. The
+            // previous copy is let go first, so the new one can reuse it.
+            (true, false) => {
+                io.snapshot = None;
+                images
+                    .get(&device.image)
+                    .map(|image| io.snapshot = Some(snapshots.get(device.image.id(), image)))
+                    .is_some()
+            }
+        };
+        if copied {
+            pacing.changed = false;
+            pacing.pending = true;
+        }
+        if !finished {
+            continue;
+        }
+
+        events
+            .started
+            .send_batch(device.started(io.frames.last(), entity));
+        device.frame = io.frames.last();
+        let written = io.sent.is_some() as u64;
+        let counts = stats::Counts {
+            dequeued: written,
+            ..std::mem::take(&mut io.counts)
+        };
+        device.health.add(counts, written);
+        if let Some(frame) = io.sent.take() {
+            if let Some(recorder) = io.diagnostics.as_mut() {
+                recorder.frame(0);
+            }
+            events.sent.send(FrameSent { entity, ..frame });
+        }
+        if let Some((store, recorder)) = diagnostics.as_mut().zip(io.diagnostics.as_mut()) {
+            recorder.target_fps = pacing.fps();
+            recorder.record(entity, store);
+        }
+
+        let underrun = io
+            .underruns
+            .as_mut()
+            .and_then(|underruns| underruns.pending.take());
+        if let Some((repeated, total)) = underrun {
+            events.underruns.send(OutputUnderrun {
+                entity,
+                device: device.id,
+                label: device.label().to_string(),
+                repeated,
+                total,
+            });
+        }
+
+        if let Some(error) = io.error.take() {
+            device.health.error(&error);
+            let event = device.error_event(entity, error);
+            config::report(
+                &events.config,
+                &mut events.errors,
+                device.errors.pass(event),
+            );
+        }
+        device.task = None;
+    }
+
+    for (entity, mut output) in encoded.iter_mut() {
+        let device = &mut output.device;
         let Some(mut task_status) = device.task.as_mut() else {
             continue;
         };
 
         if let Some(()) = futures::check_ready(&mut task_status) {
-            let Some(image) = images.get_mut(device.image.clone()) else {
+            // read only, a mutable borrow would mark the image modified and upload it again
+            let Some(image) = images.get(&device.image) else {
+                device.task = None;
                 continue;
             };
 
             if let Ok(mut io) = device.io.lock() {
-                io.buffer = image.data.clone();
+                io.buffer.clone_from(&image.data);
+                events
+                    .started
+                    .send_batch(device.started(io.frames.last(), entity));
+                device.frame = io.frames.last();
+
+                if let Some(error) = io.error.take() {
+                    let event = device.error_event(entity, error);
+                    config::report(
+                        &events.config,
+                        &mut events.errors,
+                        device.errors.pass(event),
+                    );
+                }
             }
 
             device.task = None;
@@ -242,52 +1278,175 @@ fn poll_io_tasks(
     }
 }
 
-fn spawn_io_tasks(
-    mut inputs: Query<&mut Input>,
-    mut outputs: Query<&mut Output>,
-    mut images: ResMut<Assets<Image>>,
+/// Swaps the frame converted by the last task into the images of an input,
+/// returns whether there was one
+fn swap_images(
+    io: &mut Io,
+    image: &Handle<Image>,
+    preview: Option<&Handle<Image>>,
+    images: &mut Assets<Image>,
+) -> bool {
+    // inactive inputs leave the images at the last converted frame, a mutable borrow
+    // would upload them again
+    if !io.exchange.is_fresh() {
+        return false;
+    }
+    let Some(image) = images.get_mut(image) else {
+        return false;
+    };
+    let Some(dequeued) = io.exchange.take(&mut image.data) else {
+        return false;
+    };
+    io.upload_latency = Some(dequeued.elapsed());
+
+    let preview = preview.and_then(|preview| images.get_mut(preview));
+    if let Some((image, preview)) = preview.zip(io.preview.as_mut()) {
+        std::mem::swap(&mut image.data, &mut preview.buffer);
+    }
+
+    // targets whose handles were all dropped aren't converted anymore
+    io.targets.retain(|target| images.contains(target.image));
+    for target in io.targets.iter_mut() {
+        if let Some(image) = images.get_mut(target.image) {
+            std::mem::swap(&mut image.data, &mut target.buffer);
+        }
+    }
+
+    true
+}
+
+fn send_encoded_frames(
+    encoded: Query<(Entity, &EncodedOutput)>,
+    mut events: EventWriter<EncodedFrame>,
 ) {
+    for (entity, output) in encoded.iter() {
+        events.send_batch(
+            output
+                .drain_frames()
+                .into_iter()
+                .map(|frame| EncodedFrame { entity, ..frame }),
+        );
+    }
+}
+
+/// Every device is handled on its own, one whose image isn't loaded or whose task is
+/// still running doesn't hold up the others
+fn spawn_input_tasks(mut inputs: Query<&mut Input>, images: Res<Assets<Image>>) {
     for mut input in inputs.iter_mut() {
-        let device = &mut input.0;
-        let Some(image) = images.get_mut(device.image.clone()) else {
-            return;
+        // nothing to read until the device is reopened
+        if input.connection.lost || input.device.closed {
+            continue;
+        }
+        // single-shot inputs keep their stream stopped until a frame is requested
+        if input.single_shot && !input.frame_requested {
+            continue;
+        }
+
+        let late_upload = input.late_upload;
+        let orientation = input.orientation;
+        let device = &mut input.device;
+        let (width, height) = match images.get(&device.image) {
+            Some(image) => (image.width(), image.height()),
+            None if late_upload => (device.size.width, device.size.height),
+            None => continue,
         };
+        // the size of the frames before they are turned into the image
+        let (width, height) = orientation.size(width, height);
 
         // task is unfinished
         if device.task.is_some() {
-            return;
+            continue;
         };
 
+        if device.is_paused() {
+            continue;
+        }
+
+        let id = device.id;
         let fourcc = device.format.fourcc.repr;
-        let size = image.width() * image.height() * 4;
         let io = device.io.clone();
-        let task = ComputeTaskPool::get().spawn(async move {
+        let span = device.span.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let _span = span.enter();
             if let Ok(mut io) = io.lock() {
-                stream_read(&mut io, &fourcc, size as usize).unwrap();
+                read_or_restart(&mut io, id, &fourcc, width, height);
             };
         });
 
         device.task = Some(task);
     }
+}
 
+/// Like [`spawn_input_tasks`], in a system of its own so outputs never wait on inputs
+fn spawn_output_tasks(
+    mut outputs: Query<&mut Output>,
+    mut encoded: Query<&mut EncodedOutput>,
+    images: Res<Assets<Image>>,
+) {
     for mut output in outputs.iter_mut() {
-        let device = &mut output.0;
+        let Output(device, _, pacing) = &mut *output;
+        if device.closed {
+            continue;
+        }
 
-        let Some(image) = images.get_mut(device.image.clone()) else {
-            return;
+        let Some(image) = images.get(&device.image) else {
+            continue;
+        };
+
+        // task is unfinished
+        if device.task.is_some() || device.is_paused() {
+            continue;
+        };
+        // unchanged images aren't written again, the repeater of the underrun policy
+        // keeps consumers fed meanwhile
+        if !pacing.write() {
+            continue;
+        }
+
+        let format = device.format;
+        let (width, height) = (image.width(), image.height());
+        let io = device.io.clone();
+        let span = device.span.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let _span = span.enter();
+            if let Ok(mut io) = io.lock() {
+                if let Err(err) = stream_write(&mut io, &format, width, height) {
+                    io.error = Some(err.streaming());
+                }
+                if let Some(underruns) = io.underruns.as_mut() {
+                    underruns.written((width, height));
+                }
+            };
+        });
+
+        device.task = Some(task);
+    }
+
+    for mut output in encoded.iter_mut() {
+        let device = &mut output.device;
+        if device.closed {
+            continue;
+        }
+
+        let Some(image) = images.get(&device.image) else {
+            continue;
         };
 
         // task is unfinished
         if device.task.is_some() {
-            return;
+            continue;
         };
 
-        let fourcc = device.format.fourcc.repr;
-        let size = image.width() * image.height() * 4;
+        let format = device.format;
+        let (width, height) = (image.width(), image.height());
         let io = device.io.clone();
-        let task = ComputeTaskPool::get().spawn(async move {
+        let span = device.span.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let _span = span.enter();
             if let Ok(mut io) = io.lock() {
-                stream_write(&mut io, &fourcc, size as usize).unwrap();
+                if let Err(err) = stream_write(&mut io, &format, width, height) {
+                    io.error = Some(err.streaming());
+                }
             };
         });
 
@@ -295,62 +1454,1923 @@ fn spawn_io_tasks(
     }
 }
 
-fn stream_read(io: &mut Io, fourcc: &[u8; 4], size: usize) -> Result<()> {
-    let (buf, _) = CaptureStream::next(&mut io.stream)?;
+impl Device {
+    /// Waits for the running task and frees the stream and the device, so other
+    /// processes can open it right away instead of failing with EBUSY
+    fn close(&mut self) {
+        if let Some(task) = self.task.take() {
+            self.cancel.store(true, Ordering::Relaxed);
+            bevy::tasks::block_on(task);
+        }
+
+        let mut io = self
+            .io
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        io.stream = IoStream::Closed;
+        io.m2m = None;
+        // nothing is converted or written anymore, devices opened next reuse the buffers
+        let pool = BufferPool::global();
+        pool.give(std::mem::take(&mut io.buffer));
+        pool.give(std::mem::take(&mut io.unpadded));
+        pool.give(self.exchange.recycle());
+        drop(io);
 
-    // TODO: support other formats
-    match fourcc {
-        b"YUYV" => {
-            let rgb = buf
-                .iter()
-                .copied()
-                .pixels::<Yuv422<u8, 0, 2, 1, 3>>()
-                .colorconvert::<[Yuv<u8>; 2]>()
-                .flatten()
-                .colorconvert::<Rgb<u8>>()
-                .bytes()
-                .enumerate();
-
-            for (i, pixel) in rgb {
-                let i = i * 4;
-
-                if i >= size {
-                    break;
-                }
+        self.dev = None;
+        self.closed = true;
+    }
 
-                io.buffer[i..i + 3].clone_from_slice(&pixel);
+    /// Waits for the running task and stops the stream, so the driver turns the camera
+    /// off. The image keeps the last frame.
+    fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        if let Some(task) = self.task.take() {
+            self.cancel.store(true, Ordering::Relaxed);
+            bevy::tasks::block_on(task);
+            self.cancel.store(false, Ordering::Relaxed);
+        }
+
+        if let Ok(mut io) = self.io.lock() {
+            if let Err(err) = io.stream.restart() {
+                warn!(parent: &self.span, %err, "failed to stop v4l stream");
             }
+            io.paused = true;
         }
-        b"IYU2" => {}
-        _ => {}
+        self.paused = true;
+    }
+
+    /// The next task queues the buffers and starts the stream again
+    fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        if let Ok(mut io) = self.io.lock() {
+            io.paused = false;
+            // the pause isn't a stall
+            if let Some(watchdog) = io.watchdog.as_mut() {
+                watchdog.frame();
+            }
+        }
+        self.paused = false;
     }
-    Ok(())
-}
 
-fn stream_write(io: &mut Io, fourcc: &[u8; 4], size: usize) -> Result<()> {
-    let (buf, buf_meta) = OutputStream::next(&mut io.stream)?;
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
 
-    // TODO: support other formats
-    match fourcc {
-        b"YUYV" => {
-            io.buffer
-                .chunks_exact(8)
-                .map(|rgb| {
-                    [
-                        // buffer is rgba, skip alpha channel
-                        Yuv::<u8>::from(Rgb::<u8>(rgb[0..3].try_into().unwrap())),
-                        Yuv::<u8>::from(Rgb::<u8>(rgb[4..7].try_into().unwrap())),
-                    ]
-                })
-                .colorconvert::<Yuv422<u8, 0, 2, 1, 3>>()
-                .bytes()
-                .write(&mut buf.iter_mut());
-
-            buf_meta.field = 0;
-            buf_meta.bytesused = size as u32 * 3;
-        }
-        b"IYU2" => {}
-        _ => {}
+    /// The opened device, fails for virtual and closed devices
+    fn opened(&self) -> Result<&v4l::Device> {
+        self.dev
+            .as_ref()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected).into())
+    }
+
+    /// Capabilities the driver reports, fails for virtual and closed devices
+    fn query_caps(&self) -> Result<Capabilities> {
+        Capabilities::query(self.opened()?)
+    }
+
+    /// Identifies the device in logs and events, like "/dev/video2"
+    fn label(&self) -> &str {
+        &self.report.device
+    }
+
+    /// [`V4lError`] for `error`, sent unless the error filter of the device drops it
+    fn error_event(&self, entity: Entity, error: Error) -> V4lError {
+        V4lError {
+            entity,
+            device: self.id,
+            label: self.label().to_string(),
+            frame: self.frame,
+            error,
+            repeated: 0,
+        }
+    }
+
+    /// [`StreamStarted`] if `frame` is the first one of the device
+    fn started(&self, frame: Option<FrameId>, entity: Entity) -> Option<StreamStarted> {
+        (self.frame.is_none() && frame.is_some()).then(|| StreamStarted {
+            entity,
+            device: self.id,
+            report: self.report.clone(),
+        })
+    }
+}
+
+/// Span for the logs of a device, `device` is its path or the name of a virtual input.
+/// Lets logs be filtered per device, like `RUST_LOG=bevy_v4l[v4l{device=/dev/video2}]=trace`
+fn device_span(device: &str, direction: &'static str) -> Span {
+    tracing::debug_span!("v4l", device, direction)
+}
+
+fn device_path(id: usize) -> std::path::PathBuf {
+    format!("/dev/video{id}").into()
+}
+
+/// Short description of a format for error messages, like "1920x1080 YUYV"
+fn describe_format(format: &v4l::Format) -> String {
+    format!("{}x{} {}", format.width, format.height, format.fourcc)
+}
+
+/// Formats [`stream_read`] converts on the cpu, besides Bayer and compressed ones
+const DECODED_FORMATS: [&[u8; 4]; 13] = [
+    b"YUYV", b"UYVY", b"YVYU", b"IYU2", b"NV12", b"NV21", b"YU12", b"AB24", b"RGBP", b"GREY",
+    b"RGB3", b"BGR3", b"Y16 ",
+];
+
+/// Whether [`stream_read`] can convert frames of this format on the cpu
+fn can_decode(fourcc: &[u8; 4]) -> bool {
+    DECODED_FORMATS.contains(&fourcc)
+        || bayer::is_bayer(fourcc)
+        || cfg!(feature = "mjpeg") && matches!(fourcc, b"MJPG" | b"JPEG")
+        || cfg!(feature = "h264") && fourcc == b"H264"
+}
+
+/// Capture formats inputs convert on the cpu with the enabled features. Inputs of other
+/// formats need an m2m device, see [`InputBuilder::m2m`].
+pub fn supported_capture_formats() -> Vec<[u8; 4]> {
+    let compressed = [b"MJPG", b"JPEG", b"H264"];
+    DECODED_FORMATS
+        .into_iter()
+        .chain(bayer::FOURCCS)
+        .chain(compressed)
+        .filter(|fourcc| can_decode(fourcc))
+        .copied()
+        .collect()
+}
+
+/// Converts an unpadded frame of `fourcc` into the rgba `dst` like inputs do on the cpu,
+/// for frames of a [`RawInput`] or [`Input::subscribe`]. `dst` holds 4 bytes for every
+/// pixel, alpha is left as it is for most formats. YUV formats are taken as limited
+/// range BT.601, the default [`Colorimetry`]. Converts on the calling thread.
+///
+/// Fails for formats that need state across frames, like Bayer and H264, and those
+/// missing from [`supported_capture_formats`].
+pub fn convert_frame(fourcc: [u8; 4], width: u32, src: &[u8], dst: &mut [u8]) -> Result<()> {
+    convert(fourcc, width, src, dst, false)
+}
+
+/// Like [`convert_frame`], but frames from 1280x720 on in 4:2:2 and 4:2:0 formats are
+/// converted in bands of rows across bevy's [`ComputeTaskPool`](bevy::tasks::ComputeTaskPool),
+/// like the frames of inputs. Converts to the same bytes as [`convert_frame`].
+pub fn convert_frame_parallel(
+    fourcc: [u8; 4],
+    width: u32,
+    src: &[u8],
+    dst: &mut [u8],
+) -> Result<()> {
+    convert(fourcc, width, src, dst, true)
+}
+
+/// Like [`convert_frame`] for inputs built with [`ImageEncoding::Luma`]: `dst` holds a
+/// byte for every pixel, the Y samples of YUV formats and the BT.601 luma of rgb ones.
+/// Fails for formats luma isn't extracted from.
+pub fn convert_frame_luma(fourcc: [u8; 4], width: u32, src: &[u8], dst: &mut [u8]) -> Result<()> {
+    if !can_decode_luma(&fourcc) {
+        return Err(Error::UnsupportedFormat {
+            fourcc: fourcc.into(),
+        });
+    }
+    decode_luma(&fourcc, width, src, dst, Dither::default());
+    Ok(())
+}
+
+fn convert(fourcc: [u8; 4], width: u32, src: &[u8], dst: &mut [u8], parallel: bool) -> Result<()> {
+    if bayer::is_bayer(&fourcc) || &fourcc == b"H264" {
+        return Err(Error::UnsupportedFormat {
+            fourcc: fourcc.into(),
+        });
+    }
+    let options = DecodeOptions {
+        parallel,
+        ..default()
+    };
+    decode(&fourcc, width, src, dst, options, None)
+}
+
+/// Names the code converting 4:2:2 and 4:2:0 frames on the cpu, for the logs
+#[cfg(feature = "simd")]
+fn conversion_path() -> &'static str {
+    simd::path().name()
+}
+
+#[cfg(not(feature = "simd"))]
+fn conversion_path() -> &'static str {
+    "scalar"
+}
+
+/// Compressed formats are decoded on an m2m device when one is available
+fn is_compressed(fourcc: &[u8; 4]) -> bool {
+    matches!(fourcc, b"MJPG" | b"JPEG")
+}
+
+/// Bytes per pixel of the packed formats m2m devices are asked for
+fn bytes_per_pixel(fourcc: &[u8; 4]) -> usize {
+    match fourcc {
+        b"AB24" => 4,
+        _ => 2,
+    }
+}
+
+/// Whether the source of the stream of `io` changed its resolution
+fn source_changed(io: &mut Io) -> bool {
+    let Io {
+        source_change,
+        stream,
+        ..
+    } = io;
+    source_change
+        .as_mut()
+        .is_some_and(|change| change.poll(stream))
+}
+
+/// Reads a frame, restarting the stream on transient errors.
+/// Other errors, and transient ones that keep coming back, are reported as [`V4lError`]s.
+fn read_or_restart(io: &mut Io, id: usize, fourcc: &[u8; 4], width: u32, height: u32) {
+    if io
+        .watchdog
+        .as_ref()
+        .is_some_and(|watchdog| watchdog.gave_up)
+    {
+        return;
+    }
+
+    let result = loop {
+        // frames of the changed source don't fit the image, the main world sets the
+        // stream up again before the next task
+        if source_changed(io) {
+            return;
+        }
+        let result = stream_read(io, fourcc, width, height);
+        let cancelled = io
+            .cancel
+            .as_ref()
+            .map(|cancel| cancel.load(Ordering::Relaxed));
+        match result {
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::TimedOut => {
+                // a device that stopped delivering frames ends the task, so the lost
+                // signal is reported and the next task polls it again
+                let lost = io.signal.as_mut().and_then(signal::Signal::timed_out);
+                if cancelled == Some(false) && lost.is_none() {
+                    continue;
+                }
+                break Err(lost.unwrap_or(Error::Io(err)));
+            }
+            result => break result,
+        }
+    };
+
+    // drivers fail dequeues once their source changed
+    if result.is_err() && source_changed(io) {
+        return;
+    }
+
+    let err = match result {
+        Ok(()) => {
+            if std::mem::take(&mut io.fresh) {
+                let dequeued = io.dequeued.unwrap_or_else(std::time::Instant::now);
+                if let Some(recorder) = io.diagnostics.as_mut() {
+                    recorder.conversion = Some(dequeued.elapsed());
+                }
+                let timestamp = io
+                    .received
+                    .as_ref()
+                    .map_or_else(Timestamp::now, |received| received.timestamp);
+                io.exchange.publish(&mut io.buffer, dequeued, timestamp);
+            }
+            io.restarts = 0;
+            if let Some(watchdog) = io.watchdog.as_mut() {
+                watchdog.frame();
+            }
+            let backlog = io.wait.as_ref().and_then(|wait| wait.latency);
+            if let Some(budget) = io.budget.as_mut() {
+                budget.converted(backlog);
+            }
+            return;
+        }
+        // polled without a frame, the next task tries again
+        Err(err) if err.timed_out() => {
+            let _ = watchdog::check(io, id, err);
+            return;
+        }
+        // none of the next frames can be converted either, closing the stream reports
+        // this once
+        Err(err @ Error::UnsupportedFormat { .. }) => {
+            error!(%err, "stopping v4l stream");
+            io.stream = IoStream::Closed;
+            io.error = Some(err);
+            return;
+        }
+        // starting the stream fails with EBUSY while another process streams
+        Err(Error::Io(err)) => busy::check(err, &device_path(id)),
+        Err(err) => err,
+    };
+
+    let Err(err) = watchdog::check(io, id, err) else {
+        return;
+    };
+
+    let transient = matches!(&err, Error::Io(err) if source::is_transient(err));
+    if !transient || io.restarts == MAX_RESTARTS {
+        io.error = Some(err.streaming());
+        return;
+    }
+
+    std::thread::sleep(RESTART_BACKOFF * 2_u32.pow(io.restarts));
+    io.restarts += 1;
+
+    match io.stream.restart() {
+        Ok(()) => {
+            warn!(attempt = io.restarts, %err, "restarting v4l stream");
+            io.restarted = Some((io.restarts, err));
+        }
+        Err(restart) => io.error = Some(Error::from(restart).streaming()),
+    }
+}
+
+fn stream_read(io: &mut Io, fourcc: &[u8; 4], width: u32, height: u32) -> Result<()> {
+    let fourcc = &io.overrides.fourcc(*fourcc);
+    if let Some(wait) = &io.wait {
+        wait.sleep();
+    }
+    // frames queue up in the driver while the app runs slower than the camera, only
+    // the newest is converted so the image doesn't fall further and further behind
+    let mut dropped = 0;
+    if io.drain {
+        while io.stream.waiting() > 1 {
+            // the driver may have dequeued the frames itself since, like on overflow
+            match io.stream.capture() {
+                Ok(_) => dropped += 1,
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    let (buf, mut buf_meta) = io.stream.capture()?;
+    if let Some(signal) = io.signal.as_mut() {
+        signal.frame(&buf_meta);
+    }
+    io.counts.dequeued += dropped as u64 + 1;
+    io.counts.stale += dropped as u64;
+    if let Some(wait) = io.wait.as_mut() {
+        wait.dequeued(buf_meta.timestamp);
+    }
+    if io.dequeue_timestamps {
+        buf_meta.timestamp = Timestamp::now();
+    }
+
+    // buffers dequeued since the previous frame account for part of the gap
+    let lost = io.frames.lost(buf_meta.sequence, dropped);
+    if lost > 0 {
+        debug!(sequence = buf_meta.sequence, lost, "driver dropped frames");
+        io.lost += lost;
+        io.counts.lost += lost as u64;
+    }
+
+    // the image and everything after the conversion see the turned frame
+    let (turned_width, turned_height) = io.orientation.size(width, height);
+    let info = FrameInfo {
+        width: turned_width,
+        height: turned_height,
+        stride: turned_width * io.encoding.bytes_per_pixel().min(4) as u32,
+        frame: io.frames.next(buf_meta.sequence),
+        timestamp: buf_meta.timestamp,
+    };
+    if config::current().log_frames {
+        trace!(
+            sequence = info.frame.sequence,
+            fourcc = %FourCC::new(fourcc),
+            bytesused = buf_meta.bytesused,
+            "captured frame"
+        );
+    }
+
+    // buffers of devices that alternate fields hold one field
+    let lines = io
+        .deinterlace
+        .as_ref()
+        .map_or(height, |deinterlace| deinterlace.lines(height));
+
+    // the image keeps the previous frame, subscribers, dumps and raw frames don't see
+    // corrupt ones either. Frames cut to a region are checked at the size they were
+    // captured.
+    let (source_width, source_lines) = io.crop.map_or((width, lines), |crop| crop.source);
+    if let Some(defect) = validate::frame(fourcc, source_width, source_lines, io.stride, &buf_meta)
+    {
+        debug!(sequence = info.frame.sequence, %defect, "skipping corrupt frame");
+        io.corrupt += 1;
+        io.counts.short += 1;
+        return Ok(());
+    }
+
+    // some drivers leave bytesused at 0 for uncompressed formats
+    let buf = match buf_meta.bytesused as usize {
+        0 => buf,
+        used => &buf[..used.min(buf.len())],
+    };
+
+    if let Some(publisher) = io.publisher.as_mut() {
+        publisher.publish(*fourcc, buf, info.frame, info.timestamp);
+    }
+
+    if let Some(dump) = io.dump.as_mut() {
+        if !dump.push(buf, info.frame.sequence, info.timestamp.time) {
+            io.dump = None;
+        }
+    }
+
+    if let Some(raw) = io.raw.as_mut() {
+        raw.push(*fourcc, buf, info.frame, info.timestamp);
+
+        if raw.mode == RawFrames::Only {
+            return Ok(());
+        }
+    }
+
+    // every access unit is decoded, even those of skipped frames are referenced by later ones
+    #[cfg(feature = "h264")]
+    if let Some(h264) = io.h264.as_mut() {
+        let size = ((width * height * 4) as usize).min(io.buffer.len());
+        if !h264.push(buf, &mut io.buffer[..size])? {
+            return Ok(());
+        }
+    }
+
+    // inactive inputs keep dequeuing so the stream is running when they resume
+    let skipped = match io.activity.as_mut() {
+        Some(activity) => {
+            if !activity.convert() {
+                return Ok(());
+            }
+            std::mem::take(&mut activity.skipped)
+        }
+        None => 0,
+    };
+    // inputs over their budget skip frames until they caught up
+    let skipped = skipped
+        + match io.budget.as_mut() {
+            Some(budget) => {
+                if !budget.convert() {
+                    // sent with the next converted frame
+                    if let Some(activity) = io.activity.as_mut() {
+                        activity.skipped += skipped;
+                    }
+                    return Ok(());
+                }
+                std::mem::take(&mut budget.skipped)
+            }
+            None => 0,
+        };
+    io.fresh = true;
+    io.dequeued = Some(std::time::Instant::now());
+    io.received = Some(FrameReceived {
+        entity: Entity::PLACEHOLDER,
+        frame: info.frame,
+        timestamp: info.timestamp,
+        bytesused: buf_meta.bytesused,
+        dropped,
+        corrupt: std::mem::take(&mut io.corrupt),
+        flags: buf_meta.flags,
+        lost: std::mem::take(&mut io.lost),
+        // set by the MetadataInput of the input once the task is done
+        device_timestamp: None,
+        clock: CaptureClock::Kernel,
+    });
+
+    if io.native {
+        io.buffer.clear();
+        io.buffer.extend_from_slice(buf);
+        return Ok(());
+    }
+
+    // frames converted on an m2m device are padded the way the m2m device expects
+    let buf = match io.crop {
+        // the rows of the region are cut out unpadded
+        Some(crop) => crop.cut(fourcc, buf, io.stride, &mut io.unpadded),
+        None if io.m2m.is_none() => {
+            unpad_frame(fourcc, buf, io.stride, width, lines, &mut io.unpadded)
+        }
+        None => buf,
+    };
+
+    if io.encoding == ImageEncoding::Luma {
+        let size = ((width * height) as usize).min(io.buffer.len());
+        match io.m2m.as_mut() {
+            Some(m2m) => m2m.process_luma(buf, &mut io.buffer[..size])?,
+            None => {
+                let field = ((width * lines) as usize).min(size);
+                decode_luma(fourcc, width, buf, &mut io.buffer[..field], io.dither)
+            }
+        }
+        if let Some(post_process) = &io.post_process {
+            post_process.apply_luma(&mut io.buffer[..size]);
+        }
+        if let Some(deinterlace) = io.deinterlace.as_mut() {
+            deinterlace.apply(&mut io.buffer[..size], width as usize, buf_meta.bottom);
+        }
+        // the unpadded frame was converted, it holds the frame being turned
+        let dst = &mut io.buffer[..size];
+        orientation::turn(dst, width as usize, 1, io.orientation, &mut io.unpadded);
+
+        if let Some(stats) = io.stats.as_mut() {
+            dst.iter().for_each(|&luma| stats.push(luma));
+            stats.finish(info.frame, skipped);
+        }
+        if let Some(processor) = &io.processor {
+            io.error = processor::run(processor, dst, &info).err();
+        }
+        return Ok(());
+    }
+
+    // 16 bit samples skip the 8 bit conversion, like luma they skip the denoiser,
+    // the preview and the targets
+    let wide = io
+        .linearize
+        .as_ref()
+        .filter(|linearize| linearize.is_wide());
+    if let Some(linearize) = wide.filter(|_| fourcc == b"Y16 " && io.m2m.is_none()) {
+        let size = ((width * height * 8) as usize).min(io.buffer.len());
+        linearize.widen_y16(buf, &mut io.buffer[..size]);
+
+        if let Some(stats) = io.stats.as_mut() {
+            for sample in buf.chunks_exact(2) {
+                stats.push(sample[1]);
+            }
+            stats.finish(info.frame, skipped);
+        }
+        let dst = &mut io.buffer[..size];
+        orientation::turn(dst, width as usize, 8, io.orientation, &mut io.unpadded);
+        if let Some(processor) = &io.processor {
+            let info = FrameInfo {
+                stride: turned_width * 8,
+                ..info
+            };
+            io.error = processor::run(processor, &mut io.buffer, &info).err();
+        }
+        return Ok(());
+    }
+
+    // frames are turned while they are converted on the cpu, deinterlacing needs the
+    // rows of the fields as they were captured
+    let deinterlaced = io
+        .deinterlace
+        .as_ref()
+        .is_some_and(deinterlace::Deinterlacer::changes_frames);
+    let mut turned = false;
+    // frames decoded here are mapped row by row, the others once they are converted
+    let mut mapped = false;
+    match io.m2m.as_mut() {
+        Some(m2m) => m2m.process(buf, &mut io.buffer)?,
+        None => {
+            let size = (width * lines * 4) as usize;
+            let size = size.min(io.buffer.len());
+            let dst = &mut io.buffer[..size];
+            let custom = io
+                .converter
+                .as_mut()
+                .filter(|converter| converter.converts(*fourcc));
+            match (io.bayer.as_mut(), custom) {
+                (Some(bayer), _) => {
+                    bayer.demosaic(fourcc, width as usize, lines as usize, buf, dst)
+                }
+                // frames of custom formats are converted as they were dequeued
+                (None, Some(converter)) => {
+                    let format = v4l::Format {
+                        stride: io.stride,
+                        ..v4l::Format::new(width, lines, v4l::FourCC::new(fourcc))
+                    };
+                    converter.decode(buf, &Format(format), dst, &info)?
+                }
+                (None, None) => {
+                    // decoded by h264::H264 before
+                    turned = !deinterlaced && fourcc != b"H264";
+                    mapped = fourcc != b"H264";
+                    let options = DecodeOptions {
+                        dither: io.dither,
+                        colorimetry: io.colorimetry,
+                        parallel: true,
+                        orientation: match turned {
+                            true => io.orientation,
+                            false => Orientation::None,
+                        },
+                        post_process: io.post_process.as_ref(),
+                    };
+                    decode(fourcc, width, buf, dst, options, io.stats.as_mut())?
+                }
+            }
+        }
+    }
+
+    let size = ((width * height * 4) as usize).min(io.buffer.len());
+    io.overrides.apply(&mut io.buffer[..size]);
+    if let Some(post_process) = io.post_process.as_ref().filter(|_| !mapped) {
+        post_process.apply(&mut io.buffer[..size]);
+    }
+    if let Some(deinterlace) = io.deinterlace.as_mut() {
+        let row = width as usize * 4;
+        deinterlace.apply(&mut io.buffer[..size], row, buf_meta.bottom);
+    }
+    if !turned {
+        let dst = &mut io.buffer[..size];
+        orientation::turn(dst, width as usize, 4, io.orientation, &mut io.unpadded);
+    }
+
+    if let Some(stats) = io.stats.as_mut() {
+        let size = ((width * height * 4) as usize).min(io.buffer.len());
+        if stats.is_empty() {
+            stats.push_rgba(&io.buffer[..size]);
+        }
+        stats.finish(info.frame, skipped);
+    }
+
+    if let Some(denoise) = io.denoise.as_mut() {
+        let size = ((width * height * 4) as usize).min(io.buffer.len());
+        denoise.apply(&mut io.buffer[..size]);
+    }
+
+    // reported by poll_io_tasks, so a faulty processor doesn't stop the stream
+    if let Some(processor) = &io.processor {
+        io.error = processor::run(processor, &mut io.buffer, &info).err();
+    }
+
+    if let Some(preview) = io.preview.as_mut() {
+        preview.update(&io.buffer, (turned_width, turned_height));
+    }
+
+    for target in io.targets.iter_mut() {
+        target.update(&io.buffer, turned_width as usize, (width * height) as usize);
+    }
+
+    if let Some(linearize) = &io.linearize {
+        linearize.apply(&mut io.buffer, (width * height) as usize);
+    }
+
+    Ok(())
+}
+
+/// Whether [`decode_luma`] can extract the luma of this format
+fn can_decode_luma(fourcc: &[u8; 4]) -> bool {
+    matches!(
+        fourcc,
+        b"YUYV"
+            | b"UYVY"
+            | b"YVYU"
+            | b"IYU2"
+            | b"NV12"
+            | b"NV21"
+            | b"AB24"
+            | b"RGB3"
+            | b"BGR3"
+            | b"GREY"
+            | b"Y16 "
+    )
+}
+
+/// Drops the padding at the end of every row of `stride` bytes, the converters expect
+/// rows of `row` bytes one after the other. Frames without padding aren't copied.
+///
+/// Frames larger than their rows, like from drivers whose sizeimage is larger than the
+/// frame, are cut to the rows the converters read.
+fn unpad<'a>(src: &'a [u8], stride: usize, row: usize, unpadded: &'a mut Vec<u8>) -> &'a [u8] {
+    if stride <= row {
+        return src;
+    }
+
+    unpadded.clear();
+    for line in src.chunks(stride) {
+        unpadded.extend_from_slice(&line[..row.min(line.len())]);
+    }
+    unpadded
+}
+
+/// Rows of a frame of `fourcc` without the padding of rows of `stride` bytes, of the
+/// chroma planes of 4:2:0 formats too. Frames of formats without a known layout are
+/// left as they are.
+fn unpad_frame<'a>(
+    fourcc: &[u8; 4],
+    src: &'a [u8],
+    stride: u32,
+    width: u32,
+    lines: u32,
+    unpadded: &'a mut Vec<u8>,
+) -> &'a [u8] {
+    let Some(row) = validate::row_bytes(fourcc, width) else {
+        return src;
+    };
+    let stride = stride as usize;
+    match fourcc {
+        // chroma rows of odd widths hold the samples of half a block at their end
+        b"YU12" => {
+            let chroma = (validate::chroma_stride(fourcc, stride), row.div_ceil(2));
+            unpad_planar(src, (stride, row), lines as usize, chroma, unpadded)
+        }
+        b"NV12" | b"NV21" => {
+            let chroma = (
+                validate::chroma_stride(fourcc, stride),
+                row.next_multiple_of(2),
+            );
+            unpad_planar(src, (stride, row), lines as usize, chroma, unpadded)
+        }
+        _ => unpad(src, stride, row, unpadded),
+    }
+}
+
+/// Like [`unpad`] for 4:2:0 frames, whose chroma planes after `height` rows of luma
+/// have rows of `chroma_row` bytes every `chroma_stride` bytes
+fn unpad_planar<'a>(
+    src: &'a [u8],
+    (stride, row): (usize, usize),
+    height: usize,
+    (chroma_stride, chroma_row): (usize, usize),
+    unpadded: &'a mut Vec<u8>,
+) -> &'a [u8] {
+    if stride <= row && chroma_stride <= chroma_row {
+        return src;
+    }
+
+    unpadded.clear();
+    let (luma, chroma) = src.split_at((stride * height).min(src.len()));
+    for line in luma.chunks(stride) {
+        unpadded.extend_from_slice(&line[..row.min(line.len())]);
+    }
+    for line in chroma.chunks(chroma_stride.max(1)) {
+        unpadded.extend_from_slice(&line[..chroma_row.min(line.len())]);
+    }
+    unpadded
+}
+
+/// Converts a frame of `fourcc` into one byte of luma per pixel
+fn decode_luma(fourcc: &[u8; 4], width: u32, src: &[u8], dst: &mut [u8], dither: Dither) {
+    match fourcc {
+        // the Y samples as they are, rows of odd widths end on half a macropixel
+        b"YUYV" | b"YVYU" | b"UYVY" => {
+            let offset = (fourcc == b"UYVY") as usize;
+            let width = (width as usize).max(1);
+            let rows = dst.chunks_mut(width).zip(src.chunks(width.div_ceil(2) * 4));
+            for (dst, src) in rows {
+                for (dst, src) in dst.iter_mut().zip(src.iter().skip(offset).step_by(2)) {
+                    *dst = *src;
+                }
+            }
+        }
+        b"IYU2" => {
+            for (dst, src) in dst.iter_mut().zip(src.iter().skip(1).step_by(3)) {
+                *dst = *src;
+            }
+        }
+        // the Y plane as it is
+        b"NV12" | b"NV21" | b"GREY" => {
+            for (dst, src) in dst.iter_mut().zip(src) {
+                *dst = *src;
+            }
+        }
+        b"AB24" => {
+            for (dst, rgba) in dst.iter_mut().zip(src.chunks_exact(4)) {
+                let [r, g, b] = [rgba[0] as u32, rgba[1] as u32, rgba[2] as u32];
+                *dst = ((77 * r + 150 * g + 29 * b) >> 8) as u8;
+            }
+        }
+        // same weights, in the byte order of the format
+        b"RGB3" | b"BGR3" => {
+            let (r, b) = match fourcc {
+                b"RGB3" => (0, 2),
+                _ => (2, 0),
+            };
+            for (dst, rgb) in dst.iter_mut().zip(src.chunks_exact(3)) {
+                let [r, g, b] = [rgb[r] as u32, rgb[1] as u32, rgb[b] as u32];
+                *dst = ((77 * r + 150 * g + 29 * b) >> 8) as u8;
+            }
+        }
+        b"Y16 " => {
+            let width = (width as usize).max(1);
+            for (i, (dst, src)) in dst.iter_mut().zip(src.chunks_exact(2)).enumerate() {
+                let value = u16::from_le_bytes([src[0], src[1]]);
+                *dst = dither.reduce(value, i % width, i / width);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// How [`decode`] converts frames
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DecodeOptions<'a> {
+    /// For formats with more than 8 bits per sample
+    pub(crate) dither: Dither,
+    /// Of the YCbCr samples of YUV formats
+    pub(crate) colorimetry: Colorimetry,
+    /// Converts large 4:2:2 and 4:2:0 frames in bands across the compute pool
+    pub(crate) parallel: bool,
+    /// Of the rgba frame, turned while it is converted
+    pub(crate) orientation: Orientation,
+    /// Applied while the frame is converted, see [`PostProcess`]
+    pub(crate) post_process: Option<&'a post_process::Lut>,
+}
+
+/// Converts a frame of `fourcc` into the rgba `dst`. Formats with a luma channel push
+/// it into `luma` while converting.
+fn decode(
+    fourcc: &[u8; 4],
+    width: u32,
+    src: &[u8],
+    dst: &mut [u8],
+    options: DecodeOptions,
+    mut luma: Option<&mut LumaHistogram>,
+) -> Result<()> {
+    let (mut k, parallel) = (options.colorimetry.to_rgb(), options.parallel);
+    let mut table = None;
+    if let Some(post_process) = options.post_process {
+        // in the YUV domain, the chroma terms are scaled
+        k = k.saturate(post_process.saturation);
+        table = post_process.table();
+    }
+    let pixels = width as usize;
+    let mut dst = Rows::new(dst, pixels, 4, options.orientation).mapped(table);
+    match fourcc {
+        b"YUYV" => decode_yuv422::<0, 2, 1, 3>(width, src, dst, luma, &k, parallel),
+        b"UYVY" => decode_yuv422::<1, 3, 0, 2>(width, src, dst, luma, &k, parallel),
+        b"YVYU" => decode_yuv422::<0, 2, 3, 1>(width, src, dst, luma, &k, parallel),
+        b"NV12" => decode_semi_planar::<0, 1>(width, src, dst, luma, &k, parallel),
+        b"NV21" => decode_semi_planar::<1, 0>(width, src, dst, luma, &k, parallel),
+        b"YU12" => decode_yu12(width, src, dst, luma, &k, parallel),
+        // rgba as it is, m2m devices are asked for opaque alpha
+        b"AB24" => packed_rows(src, pixels * 4, &mut dst, |_, dst, src| {
+            let len = dst.len().min(src.len());
+            dst[..len].copy_from_slice(&src[..len]);
+        }),
+        b"RGB3" => packed_rows(src, pixels * 3, &mut dst, |_, dst, src| {
+            for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(3)) {
+                dst[..3].copy_from_slice(src);
+                dst[3] = 255;
+            }
+        }),
+        b"BGR3" => packed_rows(src, pixels * 3, &mut dst, |_, dst, src| {
+            for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(3)) {
+                dst[..3].copy_from_slice(&[src[2], src[1], src[0]]);
+                dst[3] = 255;
+            }
+        }),
+        // RGB565 little endian, the high bits are repeated in the low ones so
+        // full intensity is 255
+        b"RGBP" => packed_rows(src, pixels * 2, &mut dst, |_, dst, src| {
+            for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(2)) {
+                let value = u16::from_le_bytes([src[0], src[1]]);
+                let (r, g, b) = (
+                    (value >> 11) as u8,
+                    (value >> 5) as u8 & 0x3f,
+                    value as u8 & 0x1f,
+                );
+                dst.copy_from_slice(&[r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]);
+            }
+        }),
+        // infrared sensors stream this at hundreds of frames per second, so every pixel
+        // is a single store
+        b"GREY" => {
+            packed_rows(src, pixels, &mut dst, |_, dst, src| {
+                for (dst, &value) in dst.chunks_exact_mut(4).zip(src) {
+                    dst.copy_from_slice(&[value, value, value, 255]);
+                }
+            });
+            if let Some(luma) = luma.as_mut() {
+                let pixels = pixels * dst.height();
+                src.iter().take(pixels).for_each(|&value| luma.push(value));
+            }
+        }
+        // 16 bit little endian grey
+        b"Y16 " => packed_rows(src, pixels * 2, &mut dst, |y, dst, src| {
+            for (x, (dst, src)) in dst.chunks_exact_mut(4).zip(src.chunks_exact(2)).enumerate() {
+                let value = u16::from_le_bytes([src[0], src[1]]);
+                let value = options.dither.reduce(value, x, y);
+                dst[..3].fill(value);
+
+                if let Some(luma) = luma.as_mut() {
+                    luma.push(value);
+                }
+            }
+        }),
+        #[cfg(feature = "mjpeg")]
+        b"MJPG" | b"JPEG" => decode_jpeg(src, pixels, &mut dst)?,
+        b"IYU2" => decode_iyu2(src, pixels, &mut dst, luma, &k),
+        // decoded by h264::H264 before the frame got here
+        #[cfg(feature = "h264")]
+        b"H264" => {}
+        _ => {
+            return Err(Error::UnsupportedFormat {
+                fourcc: (*fourcc).into(),
+            })
+        }
+    }
+    Ok(())
+}
+
+/// Converts the rows of `src_row` bytes of formats without subsampling, `convert` is
+/// given the index of the row, the row to fill and the one to convert
+fn packed_rows(
+    src: &[u8],
+    src_row: usize,
+    dst: &mut Rows,
+    mut convert: impl FnMut(usize, &mut [u8], &[u8]),
+) {
+    for (y, src) in src.chunks(src_row.max(1)).take(dst.height()).enumerate() {
+        dst.write(y, |dst| convert(y, dst, src));
+    }
+}
+
+/// Converts IYU2, packed 4:4:4 with the U, Y and V samples of every pixel
+fn decode_iyu2(
+    src: &[u8],
+    width: usize,
+    dst: &mut Rows,
+    mut luma: Option<&mut LumaHistogram>,
+    k: &ToRgb,
+) {
+    packed_rows(src, width * 3, dst, |_, dst, src| {
+        for (dst, uyv) in dst.chunks_exact_mut(4).zip(src.chunks_exact(3)) {
+            dst.copy_from_slice(&k.rgb(uyv[1], uyv[0], uyv[2]));
+
+            if let Some(luma) = luma.as_mut() {
+                luma.push(uyv[1]);
+            }
+        }
+    });
+}
+
+/// Converts packed 4:2:2 with the samples of two pixels at the given byte offsets.
+/// Only the pixels both `src` and `dst` hold are converted.
+fn decode_yuv422<const Y0: usize, const Y1: usize, const U: usize, const V: usize>(
+    width: u32,
+    src: &[u8],
+    mut dst: Rows,
+    luma: Option<&mut LumaHistogram>,
+    k: &ToRgb,
+    parallel: bool,
+) {
+    let width = width as usize;
+    if width == 0 {
+        return;
+    }
+    let height = dst.height();
+    let src_row = width.div_ceil(2) * 4;
+    if let Some(luma) = luma {
+        for src in src.chunks_exact(4).take(height * src_row / 4) {
+            luma.push(src[Y0]);
+            luma.push(src[Y1]);
+        }
+    }
+
+    match parallel::band_rows(width, height).filter(|_| parallel && dst.splits()) {
+        Some(rows) => parallel::run(
+            dst.bands(rows).into_iter().zip(src.chunks(rows * src_row)),
+            |(mut dst, src)| yuv422_rows::<Y0, Y1, U, V>(width, src, &mut dst, k),
+        ),
+        None => yuv422_rows::<Y0, Y1, U, V>(width, src, &mut dst, k),
+    }
+}
+
+/// Converts the rows of a band of a packed 4:2:2 frame. The vector paths of the `simd`
+/// feature convert whole blocks, [`ToRgb::pair`] does the same math for what is left
+/// after their last block.
+fn yuv422_rows<const Y0: usize, const Y1: usize, const U: usize, const V: usize>(
+    width: usize,
+    src: &[u8],
+    dst: &mut Rows,
+    k: &ToRgb,
+) {
+    let rows = src.chunks(width.div_ceil(2) * 4).take(dst.height());
+    for (y, src) in rows.enumerate() {
+        dst.write(y, |dst| {
+            #[cfg(feature = "simd")]
+            let (src, dst) = simd::yuv422::<Y0, Y1, U, V>(src, dst, k);
+            let pairs = dst.len() / 8;
+            let (dst, last) = dst.split_at_mut(pairs * 8);
+            for (dst, src) in dst.chunks_exact_mut(8).zip(src.chunks_exact(4)) {
+                k.pair(dst, src[Y0], src[Y1], src[U], src[V]);
+            }
+            odd_pixel(last, src.get(pairs * 4..pairs * 4 + 4), |pair, src| {
+                k.pair(pair, src[Y0], src[Y1], src[U], src[V])
+            });
+        });
+    }
+}
+
+/// Converts the trailing pixel of a row of an odd width, `last`, with the chroma of the
+/// pair it is the first pixel of. `convert` writes that pair from `src`.
+fn odd_pixel<T>(last: &mut [u8], src: Option<T>, convert: impl FnOnce(&mut [u8], T)) {
+    let Some(src) = src.filter(|_| last.len() == 4) else {
+        return;
+    };
+    let mut pair = [0; 8];
+    convert(&mut pair, src);
+    last.copy_from_slice(&pair[..4]);
+}
+
+/// Converts NV12 and NV21, a Y plane followed by a plane with a Cb and Cr sample for
+/// every 2x2 pixels, at offsets `U` and `V` of every pair. Every row is converted like
+/// YUYV. Odd rows share the chroma of the row above.
+fn decode_semi_planar<const U: usize, const V: usize>(
+    width: u32,
+    src: &[u8],
+    mut dst: Rows,
+    mut luma: Option<&mut LumaHistogram>,
+    k: &ToRgb,
+    parallel: bool,
+) {
+    let width = width as usize;
+    if width == 0 {
+        return;
+    }
+    let height = dst.height();
+    let (luma_plane, chroma_plane) = src.split_at((width * height).min(src.len()));
+    let chroma_stride = width.next_multiple_of(2);
+    if let Some(luma) = luma.as_mut() {
+        // rows without chroma aren't converted
+        let rows = height.min(chroma_plane.len().div_ceil(chroma_stride) * 2);
+        let pushed = luma_plane.chunks_exact(width).take(rows).flatten();
+        pushed.for_each(|&value| luma.push(value));
+    }
+
+    match parallel::band_rows(width, height).filter(|_| parallel && dst.splits()) {
+        Some(rows) => parallel::run(
+            dst.bands(rows)
+                .into_iter()
+                .zip(luma_plane.chunks(rows * width))
+                .zip(chroma_plane.chunks(rows / 2 * chroma_stride)),
+            |((mut dst, luma_plane), chroma_plane)| {
+                semi_planar_rows::<U, V>(width, luma_plane, chroma_plane, &mut dst, k)
+            },
+        ),
+        None => semi_planar_rows::<U, V>(width, luma_plane, chroma_plane, &mut dst, k),
+    }
+}
+
+/// Converts the rows of a band of a semi-planar frame, starting at an even row. Chroma
+/// rows of odd widths end on the samples of a block of a single column.
+fn semi_planar_rows<const U: usize, const V: usize>(
+    width: usize,
+    luma_plane: &[u8],
+    chroma_plane: &[u8],
+    dst: &mut Rows,
+    k: &ToRgb,
+) {
+    let chroma_stride = width.next_multiple_of(2);
+    let rows = luma_plane.chunks_exact(width).take(dst.height());
+    for (y, luma_row) in rows.enumerate() {
+        let chroma_row = &chroma_plane[(y / 2 * chroma_stride).min(chroma_plane.len())..];
+        if chroma_row.is_empty() {
+            break;
+        }
+
+        dst.write(y, |dst| {
+            #[cfg(feature = "simd")]
+            let (dst, luma_row, chroma_row) =
+                simd::semi_planar_row::<U, V>(dst, luma_row, chroma_row, k);
+            let pairs = dst.len() / 8;
+            let (dst, last) = dst.split_at_mut(pairs * 8);
+            let pixels = dst
+                .chunks_exact_mut(8)
+                .zip(luma_row.chunks_exact(2))
+                .zip(chroma_row.chunks_exact(2));
+            for ((dst, pair), chroma) in pixels {
+                k.pair(dst, pair[0], pair[1], chroma[U], chroma[V]);
+            }
+            let src = luma_row
+                .get(pairs * 2)
+                .zip(chroma_row.get(pairs * 2..pairs * 2 + 2));
+            odd_pixel(last, src, |pair, (&y, chroma)| {
+                k.pair(pair, y, y, chroma[U], chroma[V])
+            });
+        });
+    }
+}
+
+/// Converts YU12, also known as I420: a Y plane followed by a Cb and a Cr plane with
+/// a sample for every 2x2 pixels, like [`decode_semi_planar`]
+fn decode_yu12(
+    width: u32,
+    src: &[u8],
+    mut dst: Rows,
+    mut luma: Option<&mut LumaHistogram>,
+    k: &ToRgb,
+    parallel: bool,
+) {
+    let width = width as usize;
+    if width == 0 {
+        return;
+    }
+    let height = dst.height();
+    let (luma_plane, chroma) = src.split_at((width * height).min(src.len()));
+    let chroma_stride = width.div_ceil(2);
+    let (cb_plane, cr_plane) =
+        chroma.split_at((chroma_stride * height.div_ceil(2)).min(chroma.len()));
+    if let Some(luma) = luma.as_mut() {
+        // rows without chroma aren't converted
+        let chroma_rows = cb_plane.len().min(cr_plane.len()).div_ceil(chroma_stride);
+        let rows = height.min(chroma_rows * 2);
+        let pushed = luma_plane.chunks_exact(width).take(rows).flatten();
+        pushed.for_each(|&value| luma.push(value));
+    }
+
+    match parallel::band_rows(width, height).filter(|_| parallel && dst.splits()) {
+        Some(rows) => {
+            let chroma = rows / 2 * chroma_stride;
+            parallel::run(
+                dst.bands(rows)
+                    .into_iter()
+                    .zip(luma_plane.chunks(rows * width))
+                    .zip(cb_plane.chunks(chroma).zip(cr_plane.chunks(chroma))),
+                |((mut dst, luma_plane), (cb_plane, cr_plane))| {
+                    yu12_rows(width, luma_plane, [cb_plane, cr_plane], &mut dst, k)
+                },
+            )
+        }
+        None => yu12_rows(width, luma_plane, [cb_plane, cr_plane], &mut dst, k),
+    }
+}
+
+/// Converts the rows of a band of a YU12 frame, starting at an even row
+fn yu12_rows(
+    width: usize,
+    luma_plane: &[u8],
+    [cb_plane, cr_plane]: [&[u8]; 2],
+    dst: &mut Rows,
+    k: &ToRgb,
+) {
+    let rows = luma_plane.chunks_exact(width).take(dst.height());
+    for (y, luma_row) in rows.enumerate() {
+        let start = y / 2 * width.div_ceil(2);
+        let cb_row = &cb_plane[start.min(cb_plane.len())..];
+        let cr_row = &cr_plane[start.min(cr_plane.len())..];
+        if cb_row.is_empty() || cr_row.is_empty() {
+            break;
+        }
+
+        dst.write(y, |dst| {
+            #[cfg(feature = "simd")]
+            let (dst, luma_row, cb_row, cr_row) = simd::yu12_row(dst, luma_row, cb_row, cr_row, k);
+            let pairs = dst.len() / 8;
+            let (dst, last) = dst.split_at_mut(pairs * 8);
+            let pixels = dst
+                .chunks_exact_mut(8)
+                .zip(luma_row.chunks_exact(2))
+                .zip(cb_row.iter().zip(cr_row));
+            for ((dst, pair), (&cb, &cr)) in pixels {
+                k.pair(dst, pair[0], pair[1], cb, cr);
+            }
+            let chroma = cb_row.get(pairs).zip(cr_row.get(pairs));
+            let src = luma_row.get(pairs * 2).zip(chroma);
+            odd_pixel(last, src, |pair, (&y, (&cb, &cr))| {
+                k.pair(pair, y, y, cb, cr)
+            });
+        });
+    }
+}
+
+#[cfg(feature = "mjpeg")]
+fn decode_jpeg(src: &[u8], width: usize, dst: &mut Rows) -> Result<()> {
+    use jpeg_decoder::PixelFormat;
+
+    let mut decoder = jpeg_decoder::Decoder::new(src);
+    let pixels = decoder
+        .decode()
+        .map_err(|err| Error::Decode(err.to_string()))?;
+
+    let Some(info) = decoder.info() else {
+        return Ok(());
+    };
+
+    match info.pixel_format {
+        PixelFormat::RGB24 => packed_rows(&pixels, width * 3, dst, |_, dst, src| {
+            for (dst, rgb) in dst.chunks_exact_mut(4).zip(src.chunks_exact(3)) {
+                dst[..3].copy_from_slice(rgb);
+            }
+        }),
+        PixelFormat::L8 => packed_rows(&pixels, width, dst, |_, dst, src| {
+            for (dst, &luma) in dst.chunks_exact_mut(4).zip(src) {
+                dst[..3].fill(luma);
+            }
+        }),
+        format => {
+            return Err(Error::Decode(format!(
+                "unsupported jpeg pixel format {format:?}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn stream_write(io: &mut Io, format: &v4l::Format, width: u32, height: u32) -> Result<()> {
+    let fourcc = &format.fourcc.repr;
+    let frame = io.frames.next(io.sequence);
+    if config::current().log_frames {
+        trace!(
+            sequence = frame.sequence,
+            fourcc = %FourCC::new(fourcc),
+            "writing frame"
+        );
+    }
+
+    if let Some(processor) = &io.processor {
+        // the shared copy of the image stays as it is for the other outputs
+        if let Some(snapshot) = io.snapshot.take() {
+            io.buffer.clone_from(&snapshot);
+        }
+        let info = FrameInfo {
+            width,
+            height,
+            stride: width * 4,
+            frame,
+            timestamp: Timestamp::now(),
+        };
+        io.error = processor::run(processor, &mut io.buffer, &info).err();
+    }
+    let image = io
+        .snapshot
+        .as_deref()
+        .map_or(&io.buffer[..], |snapshot| snapshot);
+    io.sequence = io.sequence.wrapping_add(1);
+
+    let (pts, rejected) = io.presenter.next();
+    if let Some(err) = rejected {
+        io.error = Some(err);
+    }
+
+    let size = (format.width, format.height);
+    let resampled = match io.resampler.as_mut() {
+        Some(resampler) => {
+            resampler.resample(image, (width, height), io.orientation, size, io.size_policy)?
+        }
+        None => None,
+    };
+    let offsets = std::mem::take(&mut io.offsets);
+    let (src, src_size, orientation, policy) = match resampled {
+        Some(frame) => (frame, size, Orientation::None, SizePolicy::Error),
+        None => (image, (width, height), io.orientation, io.size_policy),
+    };
+    let src = ScaledFrame::reusing(offsets, src, src_size, orientation, size, policy)?
+        .with_alpha(io.alpha);
+
+    // closing the stream reports this once, instead of for every frame
+    if !matches!(io.stream, IoStream::Mplane(_)) && io.frame_encoder.is_none() {
+        error!(fourcc = %FourCC::new(fourcc), "stopping v4l output stream");
+        io.stream = IoStream::Closed;
+        return Err(Error::UnsupportedFormat {
+            fourcc: (*fourcc).into(),
+        });
+    }
+
+    let stream = match &mut io.stream {
+        IoStream::Mmap(stream) => stream,
+        IoStream::Mplane(stream) => {
+            let mut bytesused = 0;
+            let k = io.colorimetry.to_ycbcr();
+            stream.write(pts, |format, planes| {
+                let used = mplane::encode(format, &src, planes, &k);
+                bytesused = used.iter().sum();
+                used
+            })?;
+            io.sent = Some(FrameSent {
+                entity: Entity::PLACEHOLDER,
+                frame,
+                pts,
+                bytesused,
+            });
+            io.offsets = src.into_offsets();
+            return Ok(());
+        }
+        IoStream::Sink(sink) => {
+            let Some(encoder) = io.frame_encoder.as_mut() else {
+                return Ok(());
+            };
+            // room for the largest frame of the format, formats of mocks leave the size 0.
+            // Outputs don't unpad frames, the scratch of inputs is reused for it.
+            let len = (format.size as usize).max((format.width * format.height * 4) as usize);
+            io.unpadded.resize(len, 0);
+            let bytesused = encoder.encode(&src, &mut io.unpadded)?.min(len);
+            sink.write(&io.unpadded[..bytesused], pts)?;
+            io.sent = Some(FrameSent {
+                entity: Entity::PLACEHOLDER,
+                frame,
+                pts,
+                bytesused: bytesused as u32,
+            });
+            io.offsets = src.into_offsets();
+            return Ok(());
+        }
+        // outputs always write to a mmap, multi-planar or mock stream
+        IoStream::UserPtr(_) | IoStream::Virtual(_) | IoStream::Closed => return Ok(()),
+    };
+    let (buf, buf_meta) = OutputStream::next(stream)?;
+    buf_meta.timestamp =
+        v4l::timestamp::Timestamp::new(pts.as_secs() as _, pts.subsec_micros() as _);
+
+    if let Some(encoder) = io.frame_encoder.as_mut() {
+        buf_meta.field = FieldOrder::Progressive as u32;
+        // encoders report the size of the format, drivers may allocate less
+        buf_meta.bytesused = encoder.encode(&src, buf)?.min(buf.len()) as u32;
+    }
+    io.sent = Some(FrameSent {
+        entity: Entity::PLACEHOLDER,
+        frame,
+        pts,
+        bytesused: buf_meta.bytesused,
+    });
+    io.offsets = src.into_offsets();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Limited range BT.601 in floating point, the fixed point math stays within 1 of it
+    fn reference(y: u8, u: u8, v: u8) -> [u8; 4] {
+        let (kr, kb) = (0.299, 0.114);
+        let kg = 1.0 - kr - kb;
+        let y = (y as f32 - 16.0) * 255.0 / 219.0;
+        let [u, v] = [u, v].map(|c| (c as f32 - 128.0) * 255.0 / 224.0);
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let g = y - 2.0 * (1.0 - kb) * kb / kg * u - 2.0 * (1.0 - kr) * kr / kg * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+        [channel(r), channel(g), channel(b), 255]
+    }
+
+    /// Pixels of a frame of `fourcc` of `width` and `height` converted into rgba
+    fn converted(fourcc: &[u8; 4], (width, height): (u32, u32), src: &[u8]) -> Vec<[u8; 4]> {
+        let mut dst = vec![0; (width * height * 4) as usize];
+        convert_frame(*fourcc, width, src, &mut dst).unwrap();
+        dst.chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect()
+    }
+
+    #[track_caller]
+    fn assert_close(actual: [u8; 4], expected: [u8; 4], tolerance: u8) {
+        let close = actual
+            .iter()
+            .zip(expected)
+            .all(|(&actual, expected)| actual.abs_diff(expected) <= tolerance);
+        assert!(close, "{actual:?} isn't within {tolerance} of {expected:?}");
+    }
+
+    /// YCbCr of white, black, red and blue
+    const WHITE: [u8; 3] = [235, 128, 128];
+    const BLACK: [u8; 3] = [16, 128, 128];
+    const RED: [u8; 3] = [81, 90, 240];
+    const BLUE: [u8; 3] = [41, 240, 110];
+
+    /// YCbCr of the pixels of a 4x4 frame of 2x2 blocks of `blocks`, in rows of blocks
+    fn blocks(blocks: [[u8; 3]; 4]) -> Vec<[u8; 3]> {
+        let mut pixels = Vec::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                pixels.push(blocks[y / 2 * 2 + x / 2]);
+            }
+        }
+        pixels
+    }
+
+    /// Y plane and interleaved chroma plane of `pixels`, with the chroma of the top left
+    /// pixel of every block, CrCb when `vu`
+    fn semi_planar(pixels: &[[u8; 3]], width: usize, vu: bool) -> Vec<u8> {
+        let mut frame: Vec<_> = pixels.iter().map(|&[y, _, _]| y).collect();
+        for row in pixels.chunks(width).step_by(2) {
+            for &[_, u, v] in row.iter().step_by(2) {
+                frame.extend_from_slice(&if vu { [v, u] } else { [u, v] });
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn nv12_converts_known_colors() {
+        let pixels = blocks([WHITE, BLACK, RED, BLUE]);
+        let frame = semi_planar(&pixels, 4, false);
+        let rgba = converted(b"NV12", (4, 4), &frame);
+
+        for (i, expected) in [0, 2, 8, 10].into_iter().zip([
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+        ]) {
+            // every pixel of the 2x2 block, odd rows share the chroma of the row above
+            for offset in [0, 1, 4, 5] {
+                assert_close(rgba[i + offset], expected, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn nv12_upsamples_the_chroma_of_every_block() {
+        // distinct luma in every pixel, the chroma of the block applies to all four
+        let mut pixels = blocks([[0, 64, 192], [0, 200, 80], [0, 110, 150], [0, 30, 30]]);
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            pixel[0] = 40 + i as u8 * 12;
+        }
+        let frame = semi_planar(&pixels, 4, false);
+        let rgba = converted(b"NV12", (4, 4), &frame);
+
+        for (rgba, &[y, u, v]) in rgba.into_iter().zip(&pixels) {
+            assert_close(rgba, reference(y, u, v), 1);
+        }
+    }
+
+    #[test]
+    fn nv12_of_odd_sizes_ends_on_single_rows_and_columns() {
+        // 3x3, the last chroma row holds the blocks of the last luma row alone
+        let pixels = [
+            [WHITE, WHITE, RED],
+            [WHITE, WHITE, RED],
+            [BLUE, BLUE, BLACK],
+        ]
+        .concat();
+        let mut frame: Vec<_> = pixels.iter().map(|&[y, _, _]| y).collect();
+        for [_, u, v] in [WHITE, RED, BLUE, BLACK] {
+            frame.extend_from_slice(&[u, v]);
+        }
+        let rgba = converted(b"NV12", (3, 3), &frame);
+
+        for (rgba, &[y, u, v]) in rgba.into_iter().zip(&pixels) {
+            assert_close(rgba, reference(y, u, v), 1);
+        }
+    }
+
+    #[test]
+    fn nv21_is_nv12_with_the_chroma_swapped() {
+        let pixels = blocks([WHITE, [128, 60, 200], RED, BLUE]);
+        let k = Colorimetry::default().to_rgb();
+        for (fourcc, vu) in [(b"NV12", false), (b"NV21", true)] {
+            let rgba = converted(fourcc, (4, 4), &semi_planar(&pixels, 4, vu));
+            for (rgba, &[y, u, v]) in rgba.into_iter().zip(&pixels) {
+                assert_eq!(rgba, k.rgb(y, u, v), "{}", FourCC::from(*fourcc));
+            }
+        }
+    }
+
+    #[test]
+    fn nv21_of_nv12_chroma_swaps_red_and_blue() {
+        let pixels = blocks([RED, RED, BLUE, BLUE]);
+        let rgba = converted(b"NV21", (4, 4), &semi_planar(&pixels, 4, false));
+        let [r, _, b, _] = rgba[0];
+        assert!(r < 32 && b > 240, "red read as {:?}", rgba[0]);
+        let [r, _, b, _] = rgba[8];
+        assert!(r > 96 && b < 32, "blue read as {:?}", rgba[8]);
+    }
+
+    /// Macropixels of a row of 35 pixels, as Y0, Y1, Cb and Cr. Rows this long go through
+    /// the vector paths of the `simd` feature too.
+    fn macropixels() -> Vec<[u8; 4]> {
+        (0..18_u32)
+            .map(|i| [16 + i * 11, 30 + i * 9, 20 + i * 13, 240 - i * 12].map(|v| v as u8))
+            .collect()
+    }
+
+    /// Lays the samples of a macropixel out in the byte order of a format
+    type Pack = fn([u8; 4]) -> [u8; 4];
+
+    #[test]
+    fn packed_422_orders_match_their_fourcc() {
+        let k = Colorimetry::default().to_rgb();
+        let pairs = macropixels();
+        let mut expected = vec![0; pairs.len() * 8];
+        for (dst, &[y0, y1, u, v]) in expected.chunks_exact_mut(8).zip(&pairs) {
+            k.pair(dst, y0, y1, u, v);
+        }
+        // the row ends on half a macropixel
+        expected.truncate(35 * 4);
+
+        let orders: [(&[u8; 4], Pack); 3] = [
+            (b"YUYV", |[y0, y1, u, v]| [y0, u, y1, v]),
+            (b"UYVY", |[y0, y1, u, v]| [u, y0, v, y1]),
+            (b"YVYU", |[y0, y1, u, v]| [y0, v, y1, u]),
+        ];
+        for (fourcc, pack) in orders {
+            let frame: Vec<_> = pairs.iter().flat_map(|&pair| pack(pair)).collect();
+            let rgba = converted(fourcc, (35, 1), &frame);
+            assert_eq!(rgba.concat(), expected, "{}", FourCC::from(*fourcc));
+
+            let mut luma = [0; 35];
+            convert_frame_luma(*fourcc, 35, &frame, &mut luma).unwrap();
+            let samples = pairs.iter().flat_map(|&[y0, y1, ..]| [y0, y1]);
+            assert!(luma.into_iter().eq(samples.take(35)));
+        }
+        let mjpg = convert_frame_luma(*b"MJPG", 35, &[0; 70], &mut [0; 35]);
+        assert!(matches!(mjpg, Err(Error::UnsupportedFormat { .. })));
+    }
+
+    /// A 4x4 rgba frame of primaries, greys and a ramp of mixed colors
+    fn rgba_frame() -> Vec<u8> {
+        let mut frame = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+            [128, 128, 128, 255],
+            [255, 255, 0, 255],
+            [0, 255, 255, 255],
+        ]
+        .concat();
+        for i in 0..8_u8 {
+            frame.extend_from_slice(&[i * 32, 200 - i * 20, 60 + i * 24, 255]);
+        }
+        frame
+    }
+
+    /// Encodes `src` of `size` into buffers of `fourcc` like outputs do, returning the
+    /// bytes used
+    fn encoded(fourcc: &[u8; 4], src: &[u8], size: (u32, u32)) -> Vec<u8> {
+        let format = v4l::Format::new(size.0, size.1, v4l::FourCC::new(fourcc));
+        let mut encoder = encode::for_format(&format, Colorimetry::default()).unwrap();
+        let frame = ScaledFrame::new(src, size, size, SizePolicy::Error).unwrap();
+        let mut dst = vec![0; src.len() * 2];
+        let used = encoder.encode(&frame, &mut dst).unwrap();
+        dst.truncate(used);
+        dst
+    }
+
+    #[test]
+    fn iyu2_round_trips_through_the_encoder() {
+        let src = rgba_frame();
+        let frame = encoded(b"IYU2", &src, (4, 4));
+        assert_eq!(frame.len(), 4 * 4 * 3);
+
+        let rgba = converted(b"IYU2", (4, 4), &frame);
+        for (rgba, src) in rgba.into_iter().zip(src.chunks_exact(4)) {
+            assert_close(rgba, src.try_into().unwrap(), 2);
+        }
+        // UYV, the luma of white is in the middle
+        assert_eq!(frame[9..12], [128, 235, 128]);
+    }
+
+    #[test]
+    fn rgb24_orders_round_trip() {
+        let src = rgba_frame();
+        let rgb = encoded(b"RGB3", &src, (4, 4));
+        let bgr: Vec<_> = rgb
+            .chunks_exact(3)
+            .flat_map(|p| [p[2], p[1], p[0]])
+            .collect();
+        assert_eq!(rgb[..3], [255, 0, 0]);
+        assert_eq!(bgr[..3], [0, 0, 255]);
+
+        for (fourcc, frame) in [(b"RGB3", rgb), (b"BGR3", bgr)] {
+            let rgba = converted(fourcc, (4, 4), &frame);
+            assert_eq!(rgba.concat(), src, "{}", FourCC::from(*fourcc));
+        }
+    }
+
+    #[test]
+    fn rgb24_rows_skip_the_padding_of_the_stride() {
+        let src = rgba_frame();
+        let mut format = v4l::Format::new(4, 4, v4l::FourCC::new(b"RGB3"));
+        format.stride = 16;
+        let mut encoder = encode::for_format(&format, Colorimetry::default()).unwrap();
+        let frame = ScaledFrame::new(&src, (4, 4), (4, 4), SizePolicy::Error).unwrap();
+        let mut padded = vec![0xaa; 16 * 4];
+        assert_eq!(encoder.encode(&frame, &mut padded).unwrap(), 16 * 4);
+
+        let mut unpadded = Vec::new();
+        let frame = unpad_frame(b"RGB3", &padded, 16, 4, 4, &mut unpadded);
+        assert_eq!(frame.len(), 4 * 4 * 3);
+        assert_eq!(converted(b"RGB3", (4, 4), frame).concat(), src);
+    }
+
+    #[test]
+    fn rgb565_color_bars_replicate_their_high_bits() {
+        // white, yellow, cyan, green, magenta, red, blue and black
+        let bars: [u16; 8] = [
+            0xffff, 0xffe0, 0x07ff, 0x07e0, 0xf81f, 0xf800, 0x001f, 0x0000,
+        ];
+        let frame: Vec<_> = bars.iter().flat_map(|bar| bar.to_le_bytes()).collect();
+        assert_eq!(frame[10..12], [0x00, 0xf8], "red is little endian");
+
+        let rgba = converted(b"RGBP", (8, 1), &frame);
+        assert_eq!(
+            rgba,
+            [
+                [255, 255, 255, 255],
+                [255, 255, 0, 255],
+                [0, 255, 255, 255],
+                [0, 255, 0, 255],
+                [255, 0, 255, 255],
+                [255, 0, 0, 255],
+                [0, 0, 255, 255],
+                [0, 0, 0, 255],
+            ]
+        );
+
+        // halves of every channel, 0x10 of 5 bits and 0x20 of 6 bits
+        let half = converted(b"RGBP", (1, 1), &0x8410_u16.to_le_bytes());
+        assert_eq!(half, [[132, 130, 132, 255]]);
+    }
+
+    /// Metadata of a dequeued buffer with `bytesused` bytes
+    fn meta(bytesused: u32) -> source::FrameMeta {
+        source::FrameMeta {
+            bytesused,
+            error: false,
+            flags: 0,
+            bottom: false,
+            sequence: 0,
+            timestamp: Timestamp::now(),
+        }
+    }
+
+    #[test]
+    fn inflated_sizeimage_is_neither_rejected_nor_read() {
+        // 4x2 YUYV in rows padded to 16 bytes, in a buffer of 4 KiB the driver fills
+        let mut format = v4l::Format::new(4, 2, v4l::FourCC::new(b"YUYV"));
+        (format.stride, format.size) = (16, 4096);
+        validate::format(&format).unwrap();
+
+        let rows = [
+            [81, 90, 81, 240, 41, 240, 41, 110],
+            [235, 128, 235, 128, 16, 128, 16, 128],
+        ];
+        let mut buffer = vec![0xff; 4096];
+        for (dst, row) in buffer.chunks_mut(16).zip(rows) {
+            dst[..8].copy_from_slice(&row);
+        }
+        for used in [0, 32, 4096] {
+            let rejected = validate::frame(b"YUYV", 4, 2, 16, &meta(used));
+            assert_eq!(rejected, None, "{used} bytes used");
+        }
+        assert!(validate::frame(b"YUYV", 4, 2, 16, &meta(24)).is_some());
+
+        // the tail after the last row never reaches the image
+        let mut unpadded = Vec::new();
+        let frame = unpad_frame(b"YUYV", &buffer, 16, 4, 2, &mut unpadded);
+        let rgba = converted(b"YUYV", (4, 2), frame);
+        assert_eq!(rgba, converted(b"YUYV", (4, 2), &rows.concat()));
+        assert_close(rgba[0], [255, 0, 0, 255], 1);
+        assert_close(rgba[7], [0, 0, 0, 255], 1);
+    }
+
+    #[test]
+    fn inflated_sizeimage_of_planar_frames_is_ignored() {
+        let pixels = blocks([WHITE, BLACK, RED, BLUE]);
+        let frame = semi_planar(&pixels, 4, false);
+        let mut buffer = frame.clone();
+        buffer.resize(4096, 0xff);
+
+        let mut format = v4l::Format::new(4, 4, v4l::FourCC::new(b"NV12"));
+        (format.stride, format.size) = (4, 4096);
+        validate::format(&format).unwrap();
+        assert_eq!(validate::frame(b"NV12", 4, 4, 4, &meta(4096)), None);
+
+        let mut unpadded = Vec::new();
+        let unpadded = unpad_frame(b"NV12", &buffer, 4, 4, 4, &mut unpadded);
+        assert_eq!(
+            converted(b"NV12", (4, 4), unpadded),
+            converted(b"NV12", (4, 4), &frame)
+        );
+    }
+
+    /// YU12 planes of `pixels` of `width`, with luma rows padded to `stride` bytes and
+    /// chroma rows to half of it
+    fn padded_yu12(pixels: &[[u8; 3]], width: usize, stride: usize) -> Vec<u8> {
+        let mut frame = Vec::new();
+        for row in pixels.chunks(width) {
+            frame.extend(row.iter().map(|&[y, _, _]| y));
+            frame.resize(frame.len() + stride - width, 0xee);
+        }
+        for plane in [1, 2] {
+            for row in pixels.chunks(width).step_by(2) {
+                frame.extend(row.iter().step_by(2).map(|pixel| pixel[plane]));
+                frame.resize(frame.len() + stride.div_ceil(2) - width.div_ceil(2), 0xee);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn yu12_rows_skip_the_padding_of_every_plane() {
+        let k = Colorimetry::default().to_rgb();
+        let mut pixels = blocks([WHITE, [90, 60, 200], RED, BLUE]);
+        // distinct luma in every pixel, so rows read at the wrong offset show
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            pixel[0] = 40 + i as u8 * 12;
+        }
+        let frame = padded_yu12(&pixels, 4, 32);
+        assert_eq!(frame.len(), 32 * 4 + 16 * 2 * 2);
+
+        let mut unpadded = Vec::new();
+        let unpadded = unpad_frame(b"YU12", &frame, 32, 4, 4, &mut unpadded);
+        assert_eq!(unpadded.len(), 4 * 4 * 3 / 2);
+        for (rgba, &[y, u, v]) in converted(b"YU12", (4, 4), unpadded)
+            .into_iter()
+            .zip(&pixels)
+        {
+            assert_eq!(rgba, k.rgb(y, u, v));
+        }
+    }
+
+    #[test]
+    fn yu12_of_odd_widths_keeps_the_last_chroma_column() {
+        let k = Colorimetry::default().to_rgb();
+        let pixels = [
+            [WHITE, WHITE, RED],
+            [WHITE, WHITE, RED],
+            [BLUE, BLUE, BLACK],
+            [BLUE, BLUE, BLACK],
+        ]
+        .concat();
+        let frame = padded_yu12(&pixels, 3, 16);
+
+        let mut unpadded = Vec::new();
+        let unpadded = unpad_frame(b"YU12", &frame, 16, 3, 4, &mut unpadded);
+        for (rgba, &[y, u, v]) in converted(b"YU12", (3, 4), unpadded)
+            .into_iter()
+            .zip(&pixels)
+        {
+            assert_eq!(rgba, k.rgb(y, u, v));
+        }
+    }
+
+    #[test]
+    fn padded_yuyv_rows_start_at_their_stride() {
+        // 6 pixels of 12 bytes in rows of 16, rows read tightly packed would shear
+        let pairs = macropixels();
+        let rows: Vec<Vec<u8>> = pairs
+            .chunks_exact(3)
+            .take(4)
+            .map(|row| {
+                row.iter()
+                    .flat_map(|&[y0, y1, u, v]| [y0, u, y1, v])
+                    .collect()
+            })
+            .collect();
+        let mut padded = Vec::new();
+        for row in &rows {
+            padded.extend_from_slice(row);
+            padded.extend_from_slice(&[0xee; 4]);
+        }
+
+        let mut unpadded = Vec::new();
+        let frame = unpad_frame(b"YUYV", &padded, 16, 6, 4, &mut unpadded);
+        assert_eq!(
+            converted(b"YUYV", (6, 4), frame),
+            converted(b"YUYV", (6, 4), &rows.concat())
+        );
+    }
+
+    #[test]
+    fn packed_422_encoders_write_rows_at_the_stride() {
+        let src = rgba_frame();
+        for fourcc in [b"YUYV", b"UYVY", b"YVYU"] {
+            let tight = encoded(fourcc, &src, (4, 4));
+            let mut format = v4l::Format::new(4, 4, v4l::FourCC::new(fourcc));
+            format.stride = 12;
+            let mut encoder = encode::for_format(&format, Colorimetry::default()).unwrap();
+            let frame = ScaledFrame::new(&src, (4, 4), (4, 4), SizePolicy::Error).unwrap();
+            let mut padded = vec![0xee; 12 * 4];
+            assert_eq!(encoder.encode(&frame, &mut padded).unwrap(), 12 * 4);
+
+            for (padded, tight) in padded.chunks(12).zip(tight.chunks(8)) {
+                assert_eq!(padded[..8], *tight, "{}", FourCC::from(*fourcc));
+                assert_eq!(
+                    padded[8..],
+                    [0xee; 4],
+                    "padding of {}",
+                    FourCC::from(*fourcc)
+                );
+            }
+        }
+    }
+
+    /// Size of the frames of VGA sensors that report their active area, odd both ways
+    const ODD: (u32, u32) = (639, 479);
+
+    /// YCbCr of a frame of `size` whose luma changes on every pixel and chroma on every
+    /// 2x2 block, the blocks of the last column and row have a single pixel of them
+    fn odd_pixels((width, height): (u32, u32)) -> Vec<[u8; 3]> {
+        let mut pixels = Vec::new();
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let (column, row) = (x / 2, y / 2);
+                pixels.push([
+                    (16 + (x + y * 3) % 220) as u8,
+                    (16 + (column * 7 + row * 3) % 225) as u8,
+                    (16 + (column * 5 + row * 11) % 225) as u8,
+                ]);
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn odd_frames_convert_with_the_chroma_of_their_last_block() {
+        let k = Colorimetry::default().to_rgb();
+        let (width, height) = ODD;
+        let pixels = odd_pixels(ODD);
+        let mut yuyv = Vec::new();
+        for row in pixels.chunks(width as usize) {
+            for pair in row.chunks(2) {
+                let ([y0, u, v], y1) = (pair[0], pair.last().unwrap()[0]);
+                yuyv.extend_from_slice(&[y0, u, y1, v]);
+            }
+        }
+        let frames: [(&[u8; 4], Vec<u8>); 4] = [
+            (b"YUYV", yuyv),
+            (b"NV12", semi_planar(&pixels, width as usize, false)),
+            (b"NV21", semi_planar(&pixels, width as usize, true)),
+            (
+                b"YU12",
+                padded_yu12(&pixels, width as usize, width as usize),
+            ),
+        ];
+
+        for (fourcc, frame) in frames {
+            let name = FourCC::from(*fourcc);
+            let row = validate::row_bytes(fourcc, width).unwrap() as u32;
+            let complete = validate::frame(fourcc, width, height, row, &meta(frame.len() as u32));
+            assert_eq!(complete, None, "{name}");
+            let short = meta(frame.len() as u32 - 1);
+            assert!(validate::frame(fourcc, width, height, row, &short).is_some());
+
+            let mut unpadded = Vec::new();
+            let frame = unpad_frame(fourcc, &frame, row, width, height, &mut unpadded);
+            let rgba = converted(fourcc, ODD, frame);
+            for (i, (rgba, &[y, u, v])) in rgba.into_iter().zip(&pixels).enumerate() {
+                let (x, y_) = (i % width as usize, i / width as usize);
+                assert_eq!(rgba, k.rgb(y, u, v), "{name} at {x}, {y_}");
+            }
+        }
+    }
+
+    #[test]
+    fn odd_frames_round_trip_through_the_encoders() {
+        let (width, height) = ODD;
+        let (w, h) = (width as usize, height as usize);
+        // every 2x2 block has a color of its own, averaging its pixels loses nothing
+        let mut pixels = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                let (column, row) = (x / 2, y / 2);
+                let [r, g, b] = [column * 3, row * 5, (column + row) * 7].map(|c| (c % 256) as u8);
+                pixels.push([r, g, b, 255]);
+            }
+        }
+        let src = pixels.concat();
+        let chroma_blocks = w.div_ceil(2) * h.div_ceil(2);
+        let sizes: [(&[u8; 4], usize); 5] = [
+            (b"YUYV", w.div_ceil(2) * 4 * h),
+            (b"UYVY", w.div_ceil(2) * 4 * h),
+            (b"YVYU", w.div_ceil(2) * 4 * h),
+            (b"NV12", w * h + chroma_blocks * 2),
+            (b"YU12", w * h + chroma_blocks * 2),
+        ];
+
+        for (fourcc, size) in sizes {
+            let name = FourCC::from(*fourcc);
+            let frame = encoded(fourcc, &src, ODD);
+            assert_eq!(frame.len(), size, "bytes used of {name}");
+            let row = validate::row_bytes(fourcc, width).unwrap() as u32;
+            let complete = validate::frame(fourcc, width, height, row, &meta(size as u32));
+            assert_eq!(complete, None, "{name}");
+
+            let decoded = converted(fourcc, ODD, &frame);
+            for (i, (&actual, &expected)) in decoded.iter().zip(&pixels).enumerate() {
+                let (x, y) = (i % w, i / w);
+                if actual.iter().zip(expected).any(|(&a, e)| a.abs_diff(e) > 2) {
+                    panic!("{name} at {x}, {y}: {actual:?} isn't within 2 of {expected:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_sizes_of_subsampled_formats_are_rejected_when_requested() {
+        let [width, height] = [ODD.0, ODD.1];
+        for (fourcc, size) in [
+            (b"YUYV", (width, 480)),
+            (b"UYVY", (width, 480)),
+            (b"NV12", (640, height)),
+            (b"YU12", (width, 480)),
+        ] {
+            let built = Format::builder()
+                .fourcc(fourcc)
+                .width(size.0)
+                .height(size.1)
+                .build();
+            assert!(
+                matches!(built, Err(Error::InvalidFormat { .. })),
+                "{} of {size:?}",
+                FourCC::from(*fourcc)
+            );
+        }
+        // packed 4:2:2 has a chroma sample for every row
+        let yuyv = Format::builder().fourcc(b"YUYV").width(640).height(height);
+        assert!(yuyv.build().is_ok());
+        let rgb = Format::builder()
+            .fourcc(b"RGB3")
+            .width(width)
+            .height(height);
+        assert!(rgb.build().is_ok());
     }
-    Ok(())
 }
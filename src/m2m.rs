@@ -0,0 +1,408 @@
+use std::time::Duration;
+
+use tracing::{debug, warn};
+use v4l::buffer::{Flags, Type};
+use v4l::control::{Control, Value};
+use v4l::format::FieldOrder;
+use v4l::io::mmap::Stream;
+use v4l::io::traits::{CaptureStream, OutputStream};
+use v4l::prelude::*;
+use v4l::video::Capture;
+use v4l::FourCC;
+
+use crate::devices::{enumerate_devices, DeviceSelector};
+use crate::{
+    bytes_per_pixel, describe_format, Colorimetry, DecodeOptions, Dither, Error, Orientation,
+    Result, BUFFER_COUNT,
+};
+
+/// Formats requested from the capture side of m2m devices, in order of preference.
+/// RGBA needs no further conversion, anything else is converted on the cpu.
+const CAPTURE_FOURCCS: [&[u8; 4]; 2] = [b"AB24", b"YUYV"];
+
+/// Returned when dequeuing from a decoder after the last buffer of a resolution
+const EPIPE: i32 = 32;
+
+/// Longest the io task waits for the device to convert a frame, the previous one is
+/// shown when it takes longer
+const CONVERT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// V4L2_CID_ALPHA_COMPONENT, the alpha of the pixels of formats with an alpha channel
+const CID_ALPHA_COMPONENT: u32 = 0x00980929;
+
+/// Memory-to-memory device that converts, and optionally scales, captured frames
+/// in hardware, like a color space converter or a JPEG decoder.
+/// See [`InputBuilder::m2m`](crate::InputBuilder::m2m).
+///
+/// Only index and path selectors are useful here, name selectors match capture devices.
+#[derive(Debug, Clone, Default)]
+pub struct M2m {
+    device: Option<DeviceSelector>,
+    size: Option<(u32, u32)>,
+}
+
+impl M2m {
+    /// Uses the first m2m device that can convert the capture format
+    pub fn auto() -> Self {
+        Self::default()
+    }
+
+    pub fn device(selector: impl Into<DeviceSelector>) -> Self {
+        Self {
+            device: Some(selector.into()),
+            size: None,
+        }
+    }
+
+    /// Scales frames to `width`x`height` during conversion.
+    /// The driver may adjust the size, the image uses whatever it grants.
+    pub fn scaled(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Negotiates both ends of an m2m device for frames of `source` format.
+    /// Returns `None` when no device can do the conversion.
+    pub(crate) fn open(&self, source: &v4l::Format) -> Option<M2mStage> {
+        let result = match &self.device {
+            Some(selector) => selector
+                .open()
+                .and_then(|(dev, id)| M2mStage::new(dev, id, source, self.size)),
+            None => self.open_any(source),
+        };
+
+        match result {
+            Ok(stage) => {
+                debug!(
+                    "converting {} to {} on m2m device {}",
+                    describe_format(source),
+                    describe_format(&stage.format),
+                    stage.id
+                );
+                Some(stage)
+            }
+            Err(err) => {
+                let source = describe_format(source);
+                warn!("no m2m device can convert {source}, converting on the cpu: {err}");
+                None
+            }
+        }
+    }
+
+    fn open_any(&self, source: &v4l::Format) -> Result<M2mStage> {
+        let mut failures = Vec::new();
+
+        for info in enumerate_devices().into_iter().filter(|info| info.m2m) {
+            let stage = v4l::Device::with_path(&info.path)
+                .map_err(Error::from)
+                .and_then(|dev| M2mStage::new(dev, info.id, source, self.size));
+
+            match stage {
+                Ok(stage) => return Ok(stage),
+                Err(err) => failures.push((DeviceSelector::Path(info.path), err)),
+            }
+        }
+
+        Err(Error::NoDeviceAvailable { failures })
+    }
+}
+
+/// An open m2m device.
+/// Captured frames are queued to its output side, and the converted frames dequeued
+/// from its capture side, by the io task of the input.
+pub(crate) struct M2mStage {
+    pub(crate) id: usize,
+    /// Format of the converted frames when the stream started
+    pub(crate) format: v4l::Format,
+    output: Stream<'static>,
+    /// `None` after it failed, until it is negotiated again
+    capture: Option<CaptureQueue>,
+    /// Set once a frame waits in the output stream, it hands it to the driver on its
+    /// next call
+    filled: bool,
+    /// Latest frame dequeued from the capture side
+    frame: Frame,
+    /// Set once a resolution change has been reported
+    resized: bool,
+    /// Converted frames of another size than `format`, before they are stretched
+    scratch: Vec<u8>,
+    dev: v4l::Device,
+}
+
+/// Capture side of the device, streaming once the first frame was dequeued
+struct CaptureQueue {
+    stream: Stream<'static>,
+    started: bool,
+}
+
+/// Latest frame dequeued from the capture side, rows tightly packed
+struct Frame {
+    data: Vec<u8>,
+    format: v4l::Format,
+}
+
+impl M2mStage {
+    fn new(
+        dev: v4l::Device,
+        id: usize,
+        source: &v4l::Format,
+        size: Option<(u32, u32)>,
+    ) -> Result<Self> {
+        let granted = v4l::video::Output::set_format(&dev, source)?;
+        if granted.fourcc.repr != source.fourcc.repr
+            || granted.width != source.width
+            || granted.height != source.height
+        {
+            return Err(Error::FormatRejected {
+                requested: describe_format(source),
+                granted: describe_format(&granted),
+            });
+        }
+
+        let (width, height) = size.unwrap_or((source.width, source.height));
+        let (format, capture) = reconfigure(&dev, (width, height))?;
+        let output = MmapStream::with_buffers(&dev, Type::VideoOutput, BUFFER_COUNT)?;
+
+        Ok(Self {
+            id,
+            format,
+            output,
+            capture: Some(capture),
+            filled: false,
+            frame: Frame {
+                data: Vec::new(),
+                format,
+            },
+            resized: false,
+            scratch: Vec::new(),
+            dev,
+        })
+    }
+
+    /// Queues a captured frame for conversion and converts the latest frame
+    /// from the m2m device into the rgba `dst`.
+    ///
+    /// The output stream only hands a filled buffer to the driver on its next call,
+    /// so converted frames trail the capture by one frame.
+    pub(crate) fn process(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.convert(src, dst, 4, |format, data, dst| {
+            // packed YUV from the m2m device, with the colorimetry it reports
+            let options = DecodeOptions {
+                dither: Dither::None,
+                colorimetry: Colorimetry::detect(format),
+                parallel: true,
+                // turned once the frame is deinterlaced
+                orientation: Orientation::None,
+                // mapped once the frame is converted
+                post_process: None,
+            };
+            crate::decode(&format.fourcc.repr, format.width, data, dst, options, None)
+        })
+    }
+
+    /// Like [`M2mStage::process`], but only writes the luma of the converted frame
+    pub(crate) fn process_luma(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.convert(src, dst, 1, |format, data, dst| {
+            crate::decode_luma(&format.fourcc.repr, format.width, data, dst, Dither::None);
+            Ok(())
+        })
+    }
+
+    /// Feeds `src` to the device and runs `convert` on the latest converted frame.
+    /// Frames the device converted in another size than the image, when it couldn't
+    /// keep the size after a resolution change, are stretched into `dst` with `bytes`
+    /// per pixel.
+    fn convert(
+        &mut self,
+        src: &[u8],
+        dst: &mut [u8],
+        bytes: usize,
+        convert: impl FnOnce(&v4l::Format, &[u8], &mut [u8]) -> Result<()>,
+    ) -> Result<()> {
+        let (buf, buf_meta) = OutputStream::next(&mut self.output)?;
+
+        let len = src.len().min(buf.len());
+        buf[..len].copy_from_slice(&src[..len]);
+        buf_meta.field = FieldOrder::Progressive as u32;
+        buf_meta.bytesused = len as u32;
+
+        // the first frame is only queued with the next one
+        if std::mem::replace(&mut self.filled, true) {
+            self.dequeue()?;
+        }
+        let frame = &self.frame;
+        if frame.data.is_empty() {
+            return Ok(());
+        }
+
+        let size = (self.format.width, self.format.height);
+        let frame_size = (frame.format.width, frame.format.height);
+        if frame_size == size {
+            self.resized = false;
+            return convert(&frame.format, &frame.data, dst);
+        }
+        if !self.resized {
+            warn!(
+                "m2m device {} changed resolution from {} to {}, frames are stretched",
+                self.id,
+                describe_format(&self.format),
+                describe_format(&frame.format)
+            );
+            self.resized = true;
+        }
+        let len = frame_size.0 as usize * frame_size.1 as usize * bytes;
+        self.scratch.resize(len, 0);
+        convert(&frame.format, &frame.data, &mut self.scratch)?;
+        stretch(&self.scratch, frame_size, dst, size, bytes);
+        Ok(())
+    }
+
+    /// Copies the frames converted since the last call into `frame`, waiting up to
+    /// [`CONVERT_TIMEOUT`] for the one queued last. Later ones than it are taken as well,
+    /// so frames never trail by more than one.
+    ///
+    /// Decoders signal a resolution change of the stream by flagging the last buffer of
+    /// the old resolution, after which the capture format is negotiated again for frames
+    /// of the size of the image.
+    fn dequeue(&mut self) -> Result<()> {
+        let size = (self.format.width, self.format.height);
+        if self.capture.is_none() {
+            let (format, capture) = reconfigure(&self.dev, size)?;
+            self.frame.format = format;
+            self.capture = Some(capture);
+        }
+        let Some(capture) = self.capture.as_mut() else {
+            return Ok(());
+        };
+
+        let mut timeout = CONVERT_TIMEOUT.as_millis() as i32;
+        loop {
+            // the first dequeue starts the stream, it waits for the timeout of the stream
+            if capture.started && capture.stream.handle().poll(libc::POLLIN, timeout)? == 0 {
+                return Ok(());
+            }
+            capture.started = true;
+            timeout = 0;
+
+            let source_change = match CaptureStream::next(&mut capture.stream) {
+                Ok((buf, buf_meta)) => {
+                    pack_rows(&mut self.frame, buf);
+                    buf_meta.flags.contains(Flags::LAST)
+                }
+                Err(err) if err.raw_os_error() == Some(EPIPE) => true,
+                Err(err) => {
+                    // the buffer of the failed dequeue was queued again already
+                    self.capture = None;
+                    return Err(err.into());
+                }
+            };
+
+            if source_change {
+                // the old buffers have to be released before new ones are requested
+                self.capture = None;
+                let (format, capture) = reconfigure(&self.dev, size)?;
+                debug!("m2m source changed to {}", describe_format(&format));
+                self.frame.format = format;
+                self.capture = Some(capture);
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Picks the first capture format of the m2m device in [`CAPTURE_FOURCCS`]. RGBA is
+/// only taken from devices that fill in opaque alpha, the frames are copied as they are.
+fn negotiate_capture(dev: &v4l::Device, width: u32, height: u32) -> Result<v4l::Format> {
+    let mut granted = None;
+
+    for fourcc in CAPTURE_FOURCCS {
+        let requested = v4l::Format::new(width, height, FourCC::new(fourcc));
+        let format = Capture::set_format(dev, &requested)?;
+
+        if format.fourcc.repr == *fourcc && fourcc != b"AB24" {
+            return Ok(format);
+        }
+        if format.fourcc.repr == *fourcc {
+            match opaque_alpha(dev) {
+                Ok(()) => return Ok(format),
+                Err(err) => debug!("m2m device can't fill in opaque alpha: {err}"),
+            }
+        }
+
+        granted = Some((requested, format));
+    }
+
+    let (requested, format) = granted.expect("CAPTURE_FOURCCS is not empty");
+    Err(Error::FormatRejected {
+        requested: describe_format(&requested),
+        granted: describe_format(&format),
+    })
+}
+
+/// Asks `dev` to fill in opaque alpha in frames of formats with an alpha channel, which
+/// are converted as they are
+pub(crate) fn opaque_alpha(dev: &v4l::Device) -> std::io::Result<()> {
+    dev.set_control(Control {
+        id: CID_ALPHA_COMPONENT,
+        value: Value::Integer(255),
+    })
+}
+
+/// Stops streaming on a queue of `dev` that is owned by another thread,
+/// waking it from a blocking dequeue
+pub(crate) fn stream_off(dev: &v4l::Device, typ: Type) -> std::io::Result<()> {
+    let mut typ = typ as u32;
+    unsafe {
+        v4l::v4l2::ioctl(
+            dev.handle().fd(),
+            v4l::v4l2::vidioc::VIDIOC_STREAMOFF,
+            &mut typ as *mut _ as *mut std::os::raw::c_void,
+        )
+    }
+}
+
+/// Negotiates the capture side for frames of `size`, also after a source change.
+/// Scalers keep converting to `size`, decoders that can't scale grant the size of the
+/// source.
+fn reconfigure(dev: &v4l::Device, size: (u32, u32)) -> Result<(v4l::Format, CaptureQueue)> {
+    let format = negotiate_capture(dev, size.0, size.1)?;
+    let mut stream = MmapStream::with_buffers(dev, Type::VideoCapture, BUFFER_COUNT)?;
+    stream.set_timeout(CONVERT_TIMEOUT);
+    let capture = CaptureQueue {
+        stream,
+        started: false,
+    };
+    Ok((format, capture))
+}
+
+/// Copies a dequeued buffer into `frame`, dropping the row padding
+fn pack_rows(frame: &mut Frame, buf: &[u8]) {
+    let format = &frame.format;
+    let row = format.width as usize * bytes_per_pixel(&format.fourcc.repr);
+    let stride = (format.stride as usize).max(row);
+    let len = row * format.height as usize;
+
+    frame.data.resize(len, 0);
+    for (dst, src) in frame.data.chunks_exact_mut(row).zip(buf.chunks(stride)) {
+        let len = row.min(src.len());
+        dst[..len].copy_from_slice(&src[..len]);
+    }
+}
+
+/// Stretches the frame of `src_size` in `src` over `dst` of `size`, looking up the
+/// closest pixel of `bytes`
+fn stretch(src: &[u8], src_size: (u32, u32), dst: &mut [u8], size: (u32, u32), bytes: usize) {
+    let (src_width, src_height) = (src_size.0 as usize, src_size.1 as usize);
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let columns: Vec<_> = (0..width).map(|x| x * src_width / width * bytes).collect();
+
+    for (y, row) in dst.chunks_exact_mut(width * bytes).take(height).enumerate() {
+        let start = y * src_height / height * src_width * bytes;
+        let Some(src_row) = src.get(start..start + src_width * bytes) else {
+            break;
+        };
+        for (pixel, &column) in row.chunks_exact_mut(bytes).zip(&columns) {
+            pixel.copy_from_slice(&src_row[column..column + bytes]);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! Media controller configuration for cameras behind a graph of subdevices,
+//! like CSI cameras on embedded boards. Does what `media-ctl --links` and
+//! `media-ctl --set-v4l2` would before opening the [`Input`](crate::Input).
+
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+
+use v4l::v4l2::vidioc::_IOC_TYPE;
+
+use crate::ioctl::iowr;
+use crate::{describe_format, Error, Input, Result};
+
+const MEDIA_IOC_ENUM_ENTITIES: _IOC_TYPE = iowr(b'|', 0x01, mem::size_of::<EntityDesc>());
+const MEDIA_IOC_ENUM_LINKS: _IOC_TYPE = iowr(b'|', 0x02, mem::size_of::<LinksEnum>());
+const MEDIA_IOC_SETUP_LINK: _IOC_TYPE = iowr(b'|', 0x03, mem::size_of::<LinkDesc>());
+const VIDIOC_SUBDEV_S_FMT: _IOC_TYPE = iowr(b'V', 5, mem::size_of::<SubdevFormat>());
+
+const MEDIA_ENT_ID_FLAG_NEXT: u32 = 1 << 31;
+const MEDIA_LNK_FL_ENABLED: u32 = 1;
+const MEDIA_LNK_FL_IMMUTABLE: u32 = 1 << 1;
+const V4L2_SUBDEV_FORMAT_ACTIVE: u32 = 1;
+
+/// struct media_entity_desc
+#[allow(dead_code)]
+#[repr(C)]
+struct EntityDesc {
+    id: u32,
+    name: [u8; 32],
+    typ: u32,
+    revision: u32,
+    flags: u32,
+    group_id: u32,
+    pads: u16,
+    links: u16,
+    reserved: [u32; 4],
+    /// Starts with the major and minor number of the device node
+    dev: [u32; 46],
+}
+
+/// struct media_pad_desc
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PadDesc {
+    entity: u32,
+    index: u16,
+    flags: u32,
+    reserved: [u32; 2],
+}
+
+/// struct media_link_desc
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LinkDesc {
+    source: PadDesc,
+    sink: PadDesc,
+    flags: u32,
+    reserved: [u32; 2],
+}
+
+/// struct media_links_enum
+#[allow(dead_code)]
+#[repr(C)]
+struct LinksEnum {
+    entity: u32,
+    pads: *mut PadDesc,
+    links: *mut LinkDesc,
+    reserved: [u32; 4],
+}
+
+/// struct v4l2_subdev_format with struct v4l2_mbus_framefmt inlined
+#[allow(dead_code)]
+#[repr(C)]
+struct SubdevFormat {
+    which: u32,
+    pad: u32,
+    width: u32,
+    height: u32,
+    code: u32,
+    field: u32,
+    colorspace: u32,
+    ycbcr_enc: u16,
+    quantization: u16,
+    xfer_func: u16,
+    flags: u16,
+    format_reserved: [u16; 10],
+    stream: u32,
+    reserved: [u32; 7],
+}
+
+/// A pad of an entity, by the entity's name like "imx219 10-0010"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PadRef {
+    pub entity: String,
+    pub pad: u16,
+}
+
+impl PadRef {
+    pub fn new(entity: impl Into<String>, pad: u16) -> Self {
+        Self {
+            entity: entity.into(),
+            pad,
+        }
+    }
+}
+
+/// Links to enable, in order, and the formats of subdevice pads, in order.
+/// Links that aren't listed are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct MediaPipeline {
+    pub links: Vec<(PadRef, PadRef)>,
+    pub formats: Vec<PadFormat>,
+}
+
+/// Media bus format of a subdevice pad
+#[derive(Debug, Clone)]
+pub struct PadFormat {
+    pub pad: PadRef,
+    pub width: u32,
+    pub height: u32,
+    /// MEDIA_BUS_FMT_* code, like 0x3007 for MEDIA_BUS_FMT_SRGGB10_1X10
+    pub code: u32,
+}
+
+impl MediaPipeline {
+    pub fn link(mut self, source: PadRef, sink: PadRef) -> Self {
+        self.links.push((source, sink));
+        self
+    }
+
+    pub fn format(mut self, pad: PadRef, width: u32, height: u32, code: u32) -> Self {
+        self.formats.push(PadFormat {
+            pad,
+            width,
+            height,
+            code,
+        });
+        self
+    }
+
+    /// Applies the pipeline to the media device of /dev/video{video_id}, before the
+    /// input is opened
+    pub fn apply_for(&self, video_id: usize) -> Result<()> {
+        MediaDevice::for_video(video_id)?.apply(self)
+    }
+
+    /// Checks that the last pad format is the size `input` streams, a mismatch
+    /// usually means the driver adjusted a pad format or the input picked another format
+    pub fn verify(&self, input: &Input) -> Result<()> {
+        let format = v4l::Format::from(input.format());
+        let Some(last) = self.formats.last() else {
+            return Ok(());
+        };
+
+        if (last.width, last.height) != (format.width, format.height) {
+            return Err(media_error(format!(
+                "pad {} of \"{}\" is {}x{}, but the input streams {}",
+                last.pad.pad,
+                last.pad.entity,
+                last.width,
+                last.height,
+                describe_format(&format)
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct Entity {
+    id: u32,
+    name: String,
+    /// Device node of the entity, like /dev/v4l-subdev2
+    node: Option<PathBuf>,
+}
+
+/// An open media device (/dev/mediaN)
+pub struct MediaDevice {
+    file: File,
+    path: PathBuf,
+    entities: Vec<Entity>,
+}
+
+impl MediaDevice {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut device = Self {
+            file,
+            path,
+            entities: Vec::new(),
+        };
+        device.entities = device.enumerate()?;
+        Ok(device)
+    }
+
+    /// Opens the media device /dev/video{video_id} belongs to, found through sysfs
+    pub fn for_video(video_id: usize) -> Result<Self> {
+        let dir = format!("/sys/class/video4linux/video{video_id}/device");
+        let media = fs::read_dir(&dir)?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .find(|name| name.starts_with("media"))
+            .ok_or_else(|| media_error(format!("no media device in {dir}")))?;
+
+        Self::open(Path::new("/dev").join(media))
+    }
+
+    /// Opens the media device of an opened input, for reconfiguring it while streaming
+    /// is stopped
+    pub fn for_input(input: &Input) -> Result<Self> {
+        Self::for_video(input.id())
+    }
+
+    /// Names of the entities in the graph, for writing a [`MediaPipeline`]
+    pub fn entities(&self) -> impl Iterator<Item = &str> {
+        self.entities.iter().map(|entity| entity.name.as_str())
+    }
+
+    pub fn apply(&self, pipeline: &MediaPipeline) -> Result<()> {
+        for (source, sink) in &pipeline.links {
+            self.enable_link(source, sink)?;
+        }
+
+        for format in &pipeline.formats {
+            self.set_format(format)?;
+        }
+        Ok(())
+    }
+
+    fn entity(&self, name: &str) -> Result<&Entity> {
+        self.entities
+            .iter()
+            .find(|entity| entity.name == name)
+            .ok_or_else(|| {
+                media_error(format!(
+                    "no entity \"{name}\" in {}, it has: {}",
+                    self.path.display(),
+                    self.entities().collect::<Vec<_>>().join(", ")
+                ))
+            })
+    }
+
+    fn enumerate(&self) -> Result<Vec<Entity>> {
+        let mut entities = Vec::new();
+        let mut id = 0;
+
+        loop {
+            let mut desc: EntityDesc = unsafe { mem::zeroed() };
+            desc.id = id | MEDIA_ENT_ID_FLAG_NEXT;
+            // ENOTTY is the only error, after the last entity
+            if self.ioctl(MEDIA_IOC_ENUM_ENTITIES, &mut desc).is_err() {
+                break;
+            }
+
+            let name = CStr::from_bytes_until_nul(&desc.name)
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            entities.push(Entity {
+                id: desc.id,
+                name,
+                node: device_node(desc.dev[0], desc.dev[1]),
+            });
+            id = desc.id;
+        }
+
+        if entities.is_empty() {
+            return Err(media_error(format!(
+                "{} has no entities",
+                self.path.display()
+            )));
+        }
+        Ok(entities)
+    }
+
+    fn enable_link(&self, source: &PadRef, sink: &PadRef) -> Result<()> {
+        let (source_entity, sink_entity) =
+            (self.entity(&source.entity)?, self.entity(&sink.entity)?);
+
+        let mut desc: EntityDesc = unsafe { mem::zeroed() };
+        desc.id = source_entity.id;
+        self.ioctl(MEDIA_IOC_ENUM_ENTITIES, &mut desc)?;
+
+        let mut pads = vec![unsafe { mem::zeroed::<PadDesc>() }; desc.pads as usize];
+        let mut links = vec![unsafe { mem::zeroed::<LinkDesc>() }; desc.links as usize];
+        let mut request = LinksEnum {
+            entity: source_entity.id,
+            pads: pads.as_mut_ptr(),
+            links: links.as_mut_ptr(),
+            reserved: [0; 4],
+        };
+        self.ioctl(MEDIA_IOC_ENUM_LINKS, &mut request)?;
+
+        let describe = || {
+            format!(
+                "\"{}\":{} -> \"{}\":{}",
+                source.entity, source.pad, sink.entity, sink.pad
+            )
+        };
+        let mut link = *links
+            .iter()
+            .find(|link| {
+                link.source.index == source.pad
+                    && link.sink.entity == sink_entity.id
+                    && link.sink.index == sink.pad
+            })
+            .ok_or_else(|| media_error(format!("no link {}", describe())))?;
+
+        if link.flags & MEDIA_LNK_FL_ENABLED != 0 || link.flags & MEDIA_LNK_FL_IMMUTABLE != 0 {
+            return Ok(());
+        }
+
+        link.flags |= MEDIA_LNK_FL_ENABLED;
+        self.ioctl(MEDIA_IOC_SETUP_LINK, &mut link)
+            .map_err(|err| media_error(format!("failed to enable link {}: {err}", describe())))
+    }
+
+    fn set_format(&self, format: &PadFormat) -> Result<()> {
+        let entity = self.entity(&format.pad.entity)?;
+        let node = entity.node.as_ref().ok_or_else(|| {
+            media_error(format!("entity \"{}\" has no subdevice node", entity.name))
+        })?;
+        let subdev = OpenOptions::new().read(true).write(true).open(node)?;
+
+        let mut request: SubdevFormat = unsafe { mem::zeroed() };
+        request.which = V4L2_SUBDEV_FORMAT_ACTIVE;
+        request.pad = format.pad.pad as u32;
+        request.width = format.width;
+        request.height = format.height;
+        request.code = format.code;
+        ioctl(&subdev, VIDIOC_SUBDEV_S_FMT, &mut request)?;
+
+        // drivers adjust formats they can't do instead of failing
+        if (request.width, request.height, request.code)
+            != (format.width, format.height, format.code)
+        {
+            return Err(media_error(format!(
+                "pad {} of \"{}\" was set to {}x{} code {:#x}, but it took {}x{} code {:#x}",
+                format.pad.pad,
+                entity.name,
+                format.width,
+                format.height,
+                format.code,
+                request.width,
+                request.height,
+                request.code
+            )));
+        }
+        Ok(())
+    }
+
+    fn ioctl<T>(&self, request: _IOC_TYPE, arg: &mut T) -> io::Result<()> {
+        ioctl(&self.file, request, arg)
+    }
+}
+
+fn ioctl<T>(file: &File, request: u64, arg: &mut T) -> io::Result<()> {
+    let result =
+        unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T as *mut c_void) };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Device node of a character device, from the DEVNAME in its uevent
+fn device_node(major: u32, minor: u32) -> Option<PathBuf> {
+    if major == 0 && minor == 0 {
+        return None;
+    }
+
+    let uevent = fs::read_to_string(format!("/sys/dev/char/{major}:{minor}/uevent")).ok()?;
+    uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .map(|name| Path::new("/dev").join(name))
+}
+
+fn media_error(message: String) -> Error {
+    Error::Media(message)
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use v4l::buffer::Type;
+use v4l::device::Handle;
+use v4l::prelude::*;
+use v4l::v4l2;
+use v4l::v4l2::vidioc;
+use v4l::v4l_sys::{v4l2_buffer, v4l2_requestbuffers};
+
+use crate::source::IoStream;
+use crate::{Error, Result};
+
+/// V4L2_BUF_CAP_SUPPORTS_*, reported by VIDIOC_REQBUFS since Linux 4.20
+const BUF_CAP_SUPPORTS_MMAP: u32 = 1 << 0;
+const BUF_CAP_SUPPORTS_USERPTR: u32 = 1 << 1;
+const BUF_CAP_SUPPORTS_DMABUF: u32 = 1 << 2;
+
+/// V4L2_BUF_FLAG_DONE, set on buffers the driver filled that weren't dequeued yet
+const BUF_FLAG_DONE: u32 = 0x4;
+
+const MEMORY_MMAP: u32 = 1;
+const MEMORY_USERPTR: u32 = 2;
+
+/// VIDEO_MAX_FRAME, no driver allocates more buffers than this
+const MAX_BUFFERS: u32 = 64;
+
+/// How stream buffers are shared with the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryType {
+    /// The cheapest type the device supports, mmap unless it only supports userptr
+    #[default]
+    Auto,
+    /// Buffers allocated by the driver and mapped into the process
+    Mmap,
+    /// Buffers allocated by the process, for drivers without mmap support or that copy
+    /// mmap buffers. Capture only.
+    ///
+    /// The buffers are heap allocations, drivers that need them page aligned reject
+    /// them. Inputs fall back to mmap with a warning when the driver rejects the
+    /// buffers it is asked for, which is noted in the
+    /// [`NegotiationReport`](crate::NegotiationReport).
+    UserPtr,
+    /// Buffers shared as dma-buf fds. Not supported yet, always rejected with
+    /// [`Error::UnsupportedMemory`].
+    DmaBuf,
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Mmap => "mmap",
+            Self::UserPtr => "userptr",
+            Self::DmaBuf => "dmabuf",
+        })
+    }
+}
+
+impl MemoryType {
+    /// Resolves [`MemoryType::Auto`] for buffers of `typ` on `dev`, and rejects types
+    /// the device or the crate don't support. Drivers that don't report buffer
+    /// capabilities, like those on kernels before 4.20, are assumed to support mmap
+    /// only, userptr buffers asked for are still tried and fall back to mmap.
+    pub(crate) fn resolve(self, dev: &Device, typ: Type) -> Result<Self> {
+        let reported = capabilities(dev, typ);
+        let capabilities = reported.unwrap_or(BUF_CAP_SUPPORTS_MMAP);
+        let supports = |memory| match memory {
+            Self::Mmap => capabilities & BUF_CAP_SUPPORTS_MMAP != 0,
+            Self::UserPtr => capabilities & BUF_CAP_SUPPORTS_USERPTR != 0,
+            Self::DmaBuf => capabilities & BUF_CAP_SUPPORTS_DMABUF != 0,
+            Self::Auto => true,
+        };
+
+        let memory = match self {
+            Self::Auto if !supports(Self::Mmap) && supports(Self::UserPtr) => Self::UserPtr,
+            Self::Auto => Self::Mmap,
+            memory => memory,
+        };
+
+        // userptr outputs and dma-buf aren't implemented
+        let implemented = match memory {
+            Self::UserPtr => matches!(typ, Type::VideoCapture),
+            Self::DmaBuf => false,
+            Self::Auto | Self::Mmap => true,
+        };
+        let tried = reported.is_none() && self == Self::UserPtr;
+        match implemented && (supports(memory) || tried) {
+            true => Ok(memory),
+            false => Err(Error::UnsupportedMemory(memory)),
+        }
+    }
+
+    /// Allocates a capture stream of `count` buffers of a resolved memory type
+    pub(crate) fn capture_stream(self, dev: &Device, count: u32) -> io::Result<IoStream> {
+        match self {
+            Self::UserPtr => {
+                let stream = UserptrStream::with_buffers(dev, Type::VideoCapture, count)?;
+                Ok(IoStream::UserPtr(stream))
+            }
+            // resolved types are never auto or dma-buf
+            Self::Auto | Self::Mmap | Self::DmaBuf => {
+                let stream = MmapStream::with_buffers(dev, Type::VideoCapture, count)?;
+                Ok(IoStream::Mmap(stream))
+            }
+        }
+    }
+}
+
+/// Memory type and number of the stream buffers to allocate for a device
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Buffers {
+    pub(crate) memory: MemoryType,
+    pub(crate) count: u32,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            memory: MemoryType::Auto,
+            count: crate::config::current().default_buffer_count,
+        }
+    }
+}
+
+impl Buffers {
+    /// Fails for counts the streaming model can't work with, one buffer is always
+    /// with the driver while another one is read
+    pub(crate) fn new(memory: MemoryType, count: u32) -> Result<Self> {
+        match count {
+            0 | 1 => Err(Error::InvalidBufferCount(count)),
+            count => Ok(Self { memory, count }),
+        }
+    }
+}
+
+/// Stream buffers of `typ` the driver allocated, drivers may allocate fewer or more
+/// than requested
+pub(crate) fn allocated(dev: &Device, typ: Type, memory: MemoryType) -> u32 {
+    query(&dev.handle(), typ, memory).count() as u32
+}
+
+/// Captured buffers the driver holds ready to be dequeued
+pub(crate) fn done(handle: &Handle, memory: MemoryType) -> u32 {
+    query(handle, Type::VideoCapture, memory)
+        .filter(|buffer| buffer.flags & BUF_FLAG_DONE != 0)
+        .count() as u32
+}
+
+/// Queries the stream buffers of `typ` by index until it is out of range
+fn query(handle: &Handle, typ: Type, memory: MemoryType) -> impl Iterator<Item = v4l2_buffer> {
+    let memory = match memory {
+        MemoryType::UserPtr => MEMORY_USERPTR,
+        _ => MEMORY_MMAP,
+    };
+    let fd = handle.fd() as c_int;
+
+    (0..MAX_BUFFERS).map_while(move |index| unsafe {
+        let mut buffer: v4l2_buffer = mem::zeroed();
+        buffer.index = index;
+        buffer.type_ = typ as u32;
+        buffer.memory = memory;
+        v4l2::ioctl(
+            fd,
+            vidioc::VIDIOC_QUERYBUF,
+            &mut buffer as *mut v4l2_buffer as *mut c_void,
+        )
+        .is_ok()
+        .then_some(buffer)
+    })
+}
+
+/// Buffer capabilities of the device, `None` when the kernel doesn't report them
+fn capabilities(dev: &Device, typ: Type) -> Option<u32> {
+    unsafe {
+        // a request for no buffers only reports capabilities
+        let mut request: v4l2_requestbuffers = mem::zeroed();
+        request.type_ = typ as u32;
+        request.memory = MEMORY_MMAP;
+        v4l2::ioctl(
+            dev.handle().fd() as c_int,
+            vidioc::VIDIOC_REQBUFS,
+            &mut request as *mut v4l2_requestbuffers as *mut c_void,
+        )
+        .ok()?;
+
+        Some(request.capabilities).filter(|&capabilities| capabilities != 0)
+    }
+}
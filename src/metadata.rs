@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use tracing::{debug, info, warn};
+use v4l::buffer::Type;
+use v4l::io::mmap::Stream as MmapStream;
+use v4l::io::traits::CaptureStream;
+
+use crate::devices::{self, enumerate_devices};
+use crate::{FrameReceived, Input, Result};
+
+/// Metadata buffers of the stream, UVC fills one for every video frame
+const BUFFER_COUNT: u32 = 4;
+/// How long a dequeue waits before the thread checks whether it should stop
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+/// Entries kept for frames the app didn't receive yet, a few frames of latency
+const ENTRIES: usize = 16;
+/// Source clock references the clock of the camera is fitted over, about a second
+const CLOCK_SAMPLES: usize = 64;
+/// Time the references have to span before PTSs are converted, the fit is too noisy
+/// below it
+const MIN_CLOCK_SPAN: Duration = Duration::from_millis(50);
+
+/// UVC_STREAM_PTS and UVC_STREAM_SCR of bmHeaderInfo
+const HEADER_PTS: u8 = 1 << 2;
+const HEADER_SCR: u8 = 1 << 3;
+/// Bytes of struct uvc_meta_buf before the payload header, the ns and sof
+const BLOCK_PREFIX: usize = 10;
+
+/// Where [`FrameReceived::capture_time`] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CaptureClock {
+    /// The timestamp of the buffer, like without a [`MetadataInput`]
+    #[default]
+    Kernel,
+    /// The presentation timestamp of the camera, see [`DeviceTimestamp::time`]
+    Device,
+}
+
+/// Timestamps of the UVC payload headers of a frame, read from the metadata node of
+/// the camera by a [`MetadataInput`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceTimestamp {
+    /// Presentation timestamp, when the sensor started exposing the frame, in ticks of
+    /// the clock of the camera
+    pub pts: u32,
+    /// Source clock reference of the first header of the frame that had one, the
+    /// clock of the camera and the 11 bit USB frame number when it was sent
+    pub scr: Option<(u32, u16)>,
+    /// CLOCK_MONOTONIC when the kernel received the first header of the frame
+    pub received: Duration,
+    /// The PTS in CLOCK_MONOTONIC, like the timestamps of the kernel. Converted with the
+    /// source clock references of the last second, within the USB latency of their
+    /// headers. `None` until they span enough time, and for cameras that send none.
+    pub time: Option<Duration>,
+}
+
+/// Streams the metadata node UVC creates next to a camera, for the timestamps of its
+/// sensor. Inserted on the entity of the [`Input`] or [`RawInput`](crate::RawInput) of
+/// the camera, the [`FrameReceived`] events of the input carry the [`DeviceTimestamp`]
+/// of their frame.
+/// Frames whose metadata didn't arrive in time, and inputs without this, keep the
+/// timestamps of the kernel, see [`FrameReceived::clock`].
+///
+/// The node is streamed on a thread of its own, which stops when this is dropped.
+#[derive(Component)]
+pub struct MetadataInput {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    entries: Mutex<Entries>,
+    stop: AtomicBool,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Oldest first
+    frames: VecDeque<Entry>,
+    clock: Clock,
+}
+
+struct Entry {
+    sequence: u32,
+    /// Of the video buffer, UVC copies it into the metadata buffer
+    timestamp: Duration,
+    device: DeviceTimestamp,
+}
+
+impl MetadataInput {
+    /// The metadata node of the USB device of `input`, the node with the same bus info.
+    /// `None` when it has none, like cameras that aren't UVC or kernels before 4.16.
+    pub fn discover(input: &Input) -> Result<Option<Self>> {
+        let Some(video) = input.info() else {
+            return Ok(None);
+        };
+        let node = enumerate_devices()
+            .into_iter()
+            .find(|info| info.metadata && info.id != video.id && info.bus_info == video.bus_info);
+        match node {
+            Some(node) => Self::open(node.path).map(Some),
+            None => {
+                debug!(
+                    bus = %video.bus_info,
+                    "no v4l metadata node next to the camera"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Streams the metadata node at `path`, like /dev/video1 of the camera at /dev/video0
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dev = devices::open_path(&path)?;
+        let mut stream = MmapStream::with_buffers(&dev, Type::MetaCapture, BUFFER_COUNT)?;
+        stream.set_timeout(POLL_TIMEOUT);
+
+        let shared = Arc::new(Shared {
+            entries: Mutex::default(),
+            stop: AtomicBool::new(false),
+        });
+        let thread = std::thread::Builder::new()
+            .name("v4l metadata".into())
+            .spawn({
+                let (shared, path) = (shared.clone(), path.clone());
+                move || stream_metadata(stream, &shared, &path)
+            })?;
+        info!(path = %path.display(), "streaming v4l metadata");
+        Ok(Self {
+            path,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Path of the metadata node
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `frame` with the device timestamp of its metadata, when it arrived
+    pub(crate) fn correlate(&self, frame: FrameReceived) -> FrameReceived {
+        let entries = self.shared.lock();
+        // the sequence restarts with the stream, the timestamp tells streams apart
+        let entry = entries.frames.iter().rev().find(|entry| {
+            entry.sequence == frame.frame.sequence || entry.timestamp == frame.timestamp.time
+        });
+        let Some(entry) = entry else {
+            return frame;
+        };
+        let device = DeviceTimestamp {
+            time: entries.clock.convert(entry.device.pts),
+            ..entry.device
+        };
+        FrameReceived {
+            device_timestamp: Some(device),
+            clock: match device.time {
+                Some(_) => CaptureClock::Device,
+                None => CaptureClock::Kernel,
+            },
+            ..frame
+        }
+    }
+}
+
+impl Drop for MetadataInput {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Entries are replaced whole, a panic can't leave one half written
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Dequeues metadata buffers until the [`MetadataInput`] is dropped or the node fails,
+/// like when the camera is unplugged
+fn stream_metadata(mut stream: MmapStream, shared: &Shared, path: &Path) {
+    while !shared.stop.load(Ordering::Relaxed) {
+        let (buf, meta) = match CaptureStream::next(&mut stream) {
+            Ok(dequeued) => dequeued,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                continue
+            }
+            Err(err) => {
+                let path = path.display();
+                warn!(%path, %err, "v4l metadata stream failed, frames get kernel timestamps");
+                return;
+            }
+        };
+        let timestamp = Duration::new(meta.timestamp.sec as u64, meta.timestamp.usec as u32 * 1000);
+        let used = (meta.bytesused as usize).min(buf.len());
+        let (device, references) = parse(&buf[..used]);
+
+        let mut entries = shared.lock();
+        for (stc, received) in references {
+            entries.clock.push(stc, received);
+        }
+        let Some(device) = device else {
+            continue;
+        };
+        if entries.frames.len() == ENTRIES {
+            entries.frames.pop_front();
+        }
+        entries.frames.push_back(Entry {
+            sequence: meta.sequence,
+            timestamp,
+            device,
+        });
+    }
+}
+
+/// Reads the blocks of a buffer of V4L2_META_FMT_UVC, a struct uvc_meta_buf for every
+/// payload header the driver kept. Returns the timestamp of the frame, `None` without a
+/// PTS, and the source clock references with the time they were received.
+fn parse(mut buf: &[u8]) -> (Option<DeviceTimestamp>, Vec<(u32, Duration)>) {
+    let mut device: Option<DeviceTimestamp> = None;
+    let mut references = Vec::new();
+    while buf.len() >= BLOCK_PREFIX + 2 {
+        let mut ns = [0; 8];
+        ns.copy_from_slice(&buf[..8]);
+        let received = Duration::from_nanos(u64::from_ne_bytes(ns));
+        // bLength covers itself and bmHeaderInfo
+        let length = buf[10] as usize;
+        let flags = buf[11];
+        let Some(header) = buf.get(BLOCK_PREFIX..BLOCK_PREFIX + length.max(2)) else {
+            break;
+        };
+        buf = &buf[BLOCK_PREFIX + length.max(2)..];
+
+        // the PTS comes first, then the SCR
+        let mut fields = &header[2..];
+        let mut pts = None;
+        if flags & HEADER_PTS != 0 && fields.len() >= 4 {
+            pts = Some(u32::from_le_bytes([
+                fields[0], fields[1], fields[2], fields[3],
+            ]));
+            fields = &fields[4..];
+        }
+        let scr = (flags & HEADER_SCR != 0 && fields.len() >= 6).then(|| {
+            let stc = u32::from_le_bytes([fields[0], fields[1], fields[2], fields[3]]);
+            (stc, u16::from_le_bytes([fields[4], fields[5]]) & 0x7ff)
+        });
+
+        if let Some((stc, _)) = scr {
+            references.push((stc, received));
+        }
+        if let Some(device) = device.as_mut() {
+            device.scr = device.scr.or(scr);
+        } else if let Some(pts) = pts {
+            device = Some(DeviceTimestamp {
+                pts,
+                scr,
+                received,
+                time: None,
+            });
+        }
+    }
+    (device, references)
+}
+
+/// Fits the clock of the camera to CLOCK_MONOTONIC with its source clock references
+#[derive(Default)]
+struct Clock {
+    /// Ticks of the camera, unwrapped, and when they were received, oldest first
+    samples: VecDeque<(i64, Duration)>,
+}
+
+impl Clock {
+    fn push(&mut self, stc: u32, received: Duration) {
+        let ticks = match self.samples.back() {
+            // the 32 bit counter wraps every minute or two
+            Some(&(last, _)) => last + stc.wrapping_sub(last as u32) as i32 as i64,
+            None => stc as i64,
+        };
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((ticks, received));
+    }
+
+    /// The time of `pts`, a tick of the camera near the latest reference
+    fn convert(&self, pts: u32) -> Option<Duration> {
+        let (&(first, first_time), &(last, last_time)) =
+            (self.samples.front()?, self.samples.back()?);
+        let span = last_time.checked_sub(first_time)?;
+        if span < MIN_CLOCK_SPAN || last <= first {
+            return None;
+        }
+        let nanos_per_tick = span.as_nanos() as f64 / (last - first) as f64;
+        let ticks = pts.wrapping_sub(last as u32) as i32 as f64;
+        let nanos = last_time.as_nanos() as f64 + ticks * nanos_per_tick;
+        (nanos >= 0.0).then(|| Duration::from_nanos(nanos as u64))
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::source::{FrameMeta, Pacer, VirtualSink, VirtualSource};
+
+/// What a [`mock input`](crate::Input::mock) dequeues next, frames or the failures of a
+/// device, for testing without one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockStep {
+    /// A frame of these bytes in the fourcc of the input, shorter than the format for a
+    /// short buffer
+    Frame(Vec<u8>),
+    /// A frame the driver flagged as corrupt, with V4L2_BUF_FLAG_ERROR
+    Corrupt(Vec<u8>),
+    /// A dequeue without a frame, like EAGAIN of a device opened non-blocking
+    WouldBlock,
+    /// A dequeue failing with the os error `errno`, like 5 (EIO) that restarts the
+    /// stream or 19 (ENODEV) of an unplugged device
+    Error(i32),
+    /// Frames the driver drops before the next step, its sequence number skips them
+    /// like when no buffer was queued. Takes no time.
+    Skip(u32),
+}
+
+/// Dequeues the steps of a script at a frame rate, then nothing once it ran out
+pub(crate) struct MockSource {
+    steps: Box<dyn Iterator<Item = MockStep> + Send>,
+    frame: Vec<u8>,
+    pacer: Pacer,
+    /// Of the next frame, only frames and skips advance it like they do in drivers
+    sequence: u32,
+}
+
+impl MockSource {
+    pub(crate) fn new<I>(steps: I, fps: f32) -> Self
+    where
+        I: IntoIterator<Item = MockStep>,
+        I::IntoIter: Send + 'static,
+    {
+        Self {
+            steps: Box::new(steps.into_iter()),
+            frame: Vec::new(),
+            pacer: Pacer::new(fps),
+            sequence: 0,
+        }
+    }
+}
+
+impl VirtualSource for MockSource {
+    fn next(&mut self) -> io::Result<(&[u8], FrameMeta)> {
+        let (_, timestamp) = self.pacer.wait();
+        let error = loop {
+            match self.steps.next() {
+                Some(MockStep::Frame(frame)) => {
+                    self.frame = frame;
+                    break false;
+                }
+                Some(MockStep::Corrupt(frame)) => {
+                    self.frame = frame;
+                    break true;
+                }
+                Some(MockStep::Skip(frames)) => {
+                    self.sequence = self.sequence.wrapping_add(frames);
+                }
+                Some(MockStep::Error(errno)) => return Err(io::Error::from_raw_os_error(errno)),
+                // like a non-blocking mmap stream, see IoStream::capture
+                Some(MockStep::WouldBlock) | None => return Err(io::ErrorKind::TimedOut.into()),
+            }
+        };
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let meta = FrameMeta {
+            bytesused: self.frame.len() as u32,
+            error,
+            // V4L2_BUF_FLAG_ERROR
+            flags: if error { 0x40 } else { 0 },
+            bottom: false,
+            sequence,
+            timestamp,
+        };
+        Ok((&self.frame, meta))
+    }
+}
+
+/// Frames written to a [`mock output`](crate::Output::mock), encoded in the format of
+/// the output. Clones share the frames.
+#[derive(Clone, Default)]
+pub struct MockFrames(Arc<Mutex<Written>>);
+
+#[derive(Default)]
+struct Written {
+    frames: VecDeque<(Vec<u8>, Duration)>,
+    /// Os errors the next writes fail with, oldest first
+    errors: VecDeque<i32>,
+    /// Buffers of discarded frames, the next writes copy into them
+    spare: Vec<Vec<u8>>,
+}
+
+impl MockFrames {
+    /// Takes the frames written since the last call, oldest first, with their
+    /// presentation timestamps
+    pub fn drain(&self) -> Vec<(Vec<u8>, Duration)> {
+        self.lock().frames.drain(..).collect()
+    }
+
+    /// Drops the frames written since the last call and returns how many there were.
+    /// Their buffers are reused by the next writes, like the buffers of a device.
+    pub fn discard(&self) -> usize {
+        let written = &mut *self.lock();
+        let count = written.frames.len();
+        let frames = written.frames.drain(..).map(|(frame, _)| frame);
+        written.spare.extend(frames);
+        count
+    }
+
+    /// Frames written and not drained yet
+    pub fn len(&self) -> usize {
+        self.lock().frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fails the next write with the os error `errno`, like 19 (ENODEV) of a device
+    /// that went away. Failures queue up, one write fails for each.
+    pub fn fail_next(&self, errno: i32) {
+        self.lock().errors.push_back(errno);
+    }
+
+    /// A panicking writer leaves whole frames, nothing half written
+    fn lock(&self) -> MutexGuard<'_, Written> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Keeps the frames written to a mock output in its [`MockFrames`]
+pub(crate) struct MockSink(pub(crate) MockFrames);
+
+impl VirtualSink for MockSink {
+    fn write(&mut self, frame: &[u8], pts: Duration) -> io::Result<()> {
+        let mut written = self.0.lock();
+        if let Some(errno) = written.errors.pop_front() {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        let mut buffer = written.spare.pop().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+        written.frames.push_back((buffer, pts));
+        Ok(())
+    }
+}
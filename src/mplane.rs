@@ -0,0 +1,520 @@
+//! Multi-planar streaming, which the v4l crate doesn't wrap.
+//! Drivers of many SoCs only offer the `*_MPLANE` buffer types. Devices that offer both
+//! are streamed with the single planar api, only devices without it use these.
+
+use std::os::raw::{c_int, c_void};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, mem, ptr, slice};
+
+use v4l::capability::Flags;
+use v4l::device::Handle;
+use v4l::format::FieldOrder;
+use v4l::v4l2;
+use v4l::v4l2::vidioc;
+use v4l::v4l_sys::{v4l2_buffer, v4l2_format, v4l2_plane, v4l2_requestbuffers};
+use v4l::FourCC;
+
+use crate::color::ToYcbcr;
+use crate::scale::ScaledFrame;
+use crate::source::FrameMeta;
+use crate::Timestamp;
+
+const MEMORY_MMAP: u32 = 1;
+pub(crate) const BUF_TYPE_VIDEO_CAPTURE_MPLANE: u32 = 9;
+pub(crate) const BUF_TYPE_VIDEO_OUTPUT_MPLANE: u32 = 10;
+/// V4L2_BUF_FLAG_ERROR
+const BUF_FLAG_ERROR: u32 = 0x40;
+
+/// Planes a buffer can have, VIDEO_MAX_PLANES
+const MAX_PLANES: usize = 8;
+
+/// Format of a multi-planar queue
+#[derive(Debug, Clone)]
+pub(crate) struct MplaneFormat {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) fourcc: FourCC,
+    /// Bytes per row and size of every plane
+    pub(crate) planes: Vec<PlaneFormat>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PlaneFormat {
+    pub(crate) stride: u32,
+    pub(crate) size: u32,
+}
+
+impl MplaneFormat {
+    /// The single planar view of this format, used where the crate expects a [`v4l::Format`]
+    pub(crate) fn to_format(&self) -> v4l::Format {
+        let mut format = v4l::Format::new(self.width, self.height, self.fourcc);
+        format.stride = self.planes.first().map_or(0, |plane| plane.stride);
+        format.size = self.planes.iter().map(|plane| plane.size).sum();
+        format
+    }
+
+    /// Like [`MplaneFormat::to_format`], with the fourcc of the frames
+    /// [`MplaneStream::capture`] packs the planes of buffers into
+    pub(crate) fn to_capture_format(&self) -> v4l::Format {
+        let mut format = self.to_format();
+        format.fourcc = contiguous(self.fourcc);
+        format
+    }
+}
+
+/// Formats with a plane per component, like NV12M and YUV420M, become their single
+/// planar variant, with the planes one after the other
+fn contiguous(fourcc: FourCC) -> FourCC {
+    match &fourcc.repr {
+        b"NM12" => FourCC::new(b"NV12"),
+        b"NM21" => FourCC::new(b"NV21"),
+        b"YM12" => FourCC::new(b"YU12"),
+        _ => fourcc,
+    }
+}
+
+/// Whether `dev` only captures with the multi-planar api
+pub(crate) fn captures_mplane(dev: &v4l::Device) -> bool {
+    dev.query_caps().is_ok_and(|caps| {
+        let flags = caps.capabilities;
+        !flags.contains(Flags::VIDEO_CAPTURE) && flags.contains(Flags::VIDEO_CAPTURE_MPLANE)
+    })
+}
+
+/// Sets `requested` on the multi-planar capture queue, or gets its current format
+pub(crate) fn capture_format(
+    handle: &Handle,
+    requested: Option<&v4l::Format>,
+) -> io::Result<MplaneFormat> {
+    let Some(requested) = requested else {
+        unsafe {
+            let mut format: v4l2_format = mem::zeroed();
+            format.type_ = BUF_TYPE_VIDEO_CAPTURE_MPLANE;
+            ioctl(handle, vidioc::VIDIOC_G_FMT, &mut format)?;
+            return Ok(granted(&format));
+        }
+    };
+    set_format(
+        handle,
+        BUF_TYPE_VIDEO_CAPTURE_MPLANE,
+        requested.width,
+        requested.height,
+        requested.fourcc,
+    )
+}
+
+/// Sets the format of the multi-planar queue `typ`, returning what the driver granted
+pub(crate) fn set_format(
+    handle: &Handle,
+    typ: u32,
+    width: u32,
+    height: u32,
+    fourcc: FourCC,
+) -> io::Result<MplaneFormat> {
+    unsafe {
+        let mut format: v4l2_format = mem::zeroed();
+        format.type_ = typ;
+        format.fmt.pix_mp.width = width;
+        format.fmt.pix_mp.height = height;
+        format.fmt.pix_mp.pixelformat = u32::from_le_bytes(fourcc.repr);
+
+        ioctl(handle, vidioc::VIDIOC_S_FMT, &mut format)?;
+        Ok(granted(&format))
+    }
+}
+
+/// The format a driver filled in
+unsafe fn granted(format: &v4l2_format) -> MplaneFormat {
+    let pix = format.fmt.pix_mp;
+    let planes = pix.plane_fmt[..(pix.num_planes as usize).min(MAX_PLANES)]
+        .iter()
+        .map(|plane| PlaneFormat {
+            stride: plane.bytesperline,
+            size: plane.sizeimage,
+        })
+        .collect();
+
+    MplaneFormat {
+        width: pix.width,
+        height: pix.height,
+        fourcc: FourCC::new(&pix.pixelformat.to_le_bytes()),
+        planes,
+    }
+}
+
+/// A mapped plane of a buffer
+struct Plane {
+    ptr: *mut u8,
+    len: usize,
+}
+
+/// Memory mapped multi-planar capture or output stream
+pub(crate) struct MplaneStream {
+    pub(crate) format: MplaneFormat,
+    handle: Arc<Handle>,
+    typ: u32,
+    /// Mapped planes of every buffer
+    buffers: Vec<Vec<Plane>>,
+    /// Buffers handed to the driver that haven't been dequeued
+    queued: usize,
+    next: usize,
+    streaming: bool,
+    /// Capture buffer returned last, queued again with the next dequeue
+    dequeued: Option<u32>,
+    /// Planes of the last captured buffer one after the other, for formats with more
+    /// than one
+    frame: Vec<u8>,
+    /// See [`MplaneStream::set_timeout`]
+    timeout: Option<Duration>,
+}
+
+// the mappings are only accessed through &mut self
+unsafe impl Send for MplaneStream {}
+
+impl MplaneStream {
+    pub(crate) fn new(
+        handle: Arc<Handle>,
+        typ: u32,
+        format: MplaneFormat,
+        count: u32,
+    ) -> io::Result<Self> {
+        let mut stream = Self {
+            format,
+            handle,
+            typ,
+            buffers: Vec::new(),
+            queued: 0,
+            next: 0,
+            streaming: false,
+            dequeued: None,
+            frame: Vec::new(),
+            timeout: None,
+        };
+
+        unsafe {
+            let mut request: v4l2_requestbuffers = mem::zeroed();
+            request.count = count;
+            request.type_ = typ;
+            request.memory = MEMORY_MMAP;
+            ioctl(&stream.handle, vidioc::VIDIOC_REQBUFS, &mut request)?;
+
+            for index in 0..request.count {
+                let mut planes: [v4l2_plane; MAX_PLANES] = mem::zeroed();
+                let mut buffer = stream.buffer(index, &mut planes);
+                ioctl(&stream.handle, vidioc::VIDIOC_QUERYBUF, &mut buffer)?;
+
+                let mut mapped = Vec::new();
+                for plane in &planes[..buffer.length as usize] {
+                    let ptr = v4l2::mmap(
+                        ptr::null_mut(),
+                        plane.length as usize,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                        libc::MAP_SHARED,
+                        stream.handle.fd(),
+                        plane.m.mem_offset as libc::off_t,
+                    )?;
+                    mapped.push(Plane {
+                        ptr: ptr as *mut u8,
+                        len: plane.length as usize,
+                    });
+                }
+
+                // pushed before checking the next buffer, so drop unmaps it on errors
+                stream.buffers.push(mapped);
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Buffers the driver allocated
+    pub(crate) fn buffer_count(&self) -> u32 {
+        self.buffers.len() as u32
+    }
+
+    /// Lets `fill` write the planes of the next free buffer and queues it.
+    /// `fill` returns the bytes used of every plane.
+    pub(crate) fn write(
+        &mut self,
+        timestamp: Duration,
+        fill: impl FnOnce(&MplaneFormat, &mut [&mut [u8]]) -> Vec<u32>,
+    ) -> io::Result<()> {
+        if self.queued == self.buffers.len() {
+            self.dequeue()?;
+        }
+
+        let index = self.next;
+        let mut planes: Vec<&mut [u8]> = self.buffers[index]
+            .iter()
+            .map(|plane| unsafe { slice::from_raw_parts_mut(plane.ptr, plane.len) })
+            .collect();
+        let used = fill(&self.format, &mut planes);
+
+        unsafe {
+            let mut v4l2_planes: [v4l2_plane; MAX_PLANES] = mem::zeroed();
+            let lens = self.buffers[index].iter().map(|plane| plane.len as u32);
+            for ((plane, used), len) in v4l2_planes.iter_mut().zip(used).zip(lens) {
+                plane.bytesused = used.min(len);
+            }
+
+            let mut buffer = self.buffer(index as u32, &mut v4l2_planes);
+            buffer.field = FieldOrder::Progressive as u32;
+            buffer.timestamp.tv_sec = timestamp.as_secs() as _;
+            buffer.timestamp.tv_usec = timestamp.subsec_micros() as _;
+            ioctl(&self.handle, vidioc::VIDIOC_QBUF, &mut buffer)?;
+        }
+
+        self.queued += 1;
+        self.next = (self.next + 1) % self.buffers.len();
+
+        if !self.streaming {
+            let mut typ = self.typ;
+            unsafe { ioctl(&self.handle, vidioc::VIDIOC_STREAMON, &mut typ)? };
+            self.streaming = true;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn handle(&self) -> Arc<Handle> {
+        self.handle.clone()
+    }
+
+    /// Makes [`MplaneStream::capture`] fail with [`io::ErrorKind::TimedOut`] when no
+    /// frame arrives within `timeout`, `None` blocks
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Dequeues the next captured buffer, queueing all of them and starting the stream
+    /// first. The buffer dequeued before is queued again, frames stay valid until the
+    /// next capture. Formats with more than one plane are packed into one frame, every
+    /// plane after the first with the stride the single planar variant has.
+    pub(crate) fn capture(&mut self) -> io::Result<(&[u8], FrameMeta)> {
+        if !self.streaming {
+            for index in 0..self.buffers.len() {
+                self.queue(index as u32)?;
+            }
+            let mut typ = self.typ;
+            unsafe { ioctl(&self.handle, vidioc::VIDIOC_STREAMON, &mut typ)? };
+            self.streaming = true;
+        } else if let Some(index) = self.dequeued.take() {
+            self.queue(index)?;
+        }
+        self.poll()?;
+
+        let mut planes: [v4l2_plane; MAX_PLANES] = unsafe { mem::zeroed() };
+        let buffer = unsafe {
+            let mut buffer = self.buffer(0, &mut planes);
+            ioctl(&self.handle, vidioc::VIDIOC_DQBUF, &mut buffer)?;
+            buffer
+        };
+        self.queued -= 1;
+        self.dequeued = Some(buffer.index);
+
+        let mapped = &self.buffers[buffer.index as usize];
+        let data: Vec<&[u8]> = mapped
+            .iter()
+            .zip(&planes[..(buffer.length as usize).min(mapped.len())])
+            .map(|(mapped, plane)| {
+                // some drivers leave bytesused at 0 for uncompressed formats
+                let end = match plane.bytesused as usize {
+                    0 => mapped.len,
+                    used => used.min(mapped.len),
+                };
+                let start = (plane.data_offset as usize).min(end);
+                unsafe { slice::from_raw_parts(mapped.ptr.add(start), end - start) }
+            })
+            .collect();
+
+        let frame = match data[..] {
+            [plane] => plane,
+            _ => {
+                pack(&self.format, &data, &mut self.frame);
+                &self.frame[..]
+            }
+        };
+        let meta = FrameMeta {
+            bytesused: frame.len() as u32,
+            error: buffer.flags & BUF_FLAG_ERROR != 0,
+            flags: buffer.flags,
+            bottom: buffer.field == FieldOrder::Bottom as u32,
+            sequence: buffer.sequence,
+            timestamp: Timestamp::from_buffer(
+                buffer.flags,
+                Duration::new(
+                    buffer.timestamp.tv_sec as u64,
+                    buffer.timestamp.tv_usec as u32 * 1000,
+                ),
+            ),
+        };
+        Ok((frame, meta))
+    }
+
+    /// Stops the stream and takes every buffer back, the next capture or write starts it
+    /// again
+    pub(crate) fn stop(&mut self) -> io::Result<()> {
+        if self.streaming {
+            let mut typ = self.typ;
+            unsafe { ioctl(&self.handle, vidioc::VIDIOC_STREAMOFF, &mut typ)? };
+        }
+        self.streaming = false;
+        self.queued = 0;
+        self.next = 0;
+        self.dequeued = None;
+        Ok(())
+    }
+
+    /// Hands the capture buffer `index` to the driver
+    fn queue(&mut self, index: u32) -> io::Result<()> {
+        unsafe {
+            let mut planes: [v4l2_plane; MAX_PLANES] = mem::zeroed();
+            let mut buffer = self.buffer(index, &mut planes);
+            ioctl(&self.handle, vidioc::VIDIOC_QBUF, &mut buffer)?;
+        }
+        self.queued += 1;
+        Ok(())
+    }
+
+    /// Waits for a captured buffer, devices are opened non-blocking
+    fn poll(&self) -> io::Result<()> {
+        let timeout = self.timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(c_int::MAX as u128) as c_int
+        });
+        let mut fd = libc::pollfd {
+            fd: self.handle.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Err(io::ErrorKind::TimedOut.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for the driver to return a buffer
+    fn dequeue(&mut self) -> io::Result<()> {
+        unsafe {
+            let mut planes: [v4l2_plane; MAX_PLANES] = mem::zeroed();
+            let mut buffer = self.buffer(0, &mut planes);
+            ioctl(&self.handle, vidioc::VIDIOC_DQBUF, &mut buffer)?;
+        }
+
+        self.queued -= 1;
+        Ok(())
+    }
+
+    unsafe fn buffer(&self, index: u32, planes: &mut [v4l2_plane; MAX_PLANES]) -> v4l2_buffer {
+        let mut buffer: v4l2_buffer = mem::zeroed();
+        buffer.index = index;
+        buffer.type_ = self.typ;
+        buffer.memory = MEMORY_MMAP;
+        buffer.length = MAX_PLANES as u32;
+        buffer.m.planes = planes.as_mut_ptr();
+        buffer
+    }
+}
+
+impl Drop for MplaneStream {
+    fn drop(&mut self) {
+        unsafe {
+            if self.streaming {
+                let mut typ = self.typ;
+                let _ = ioctl(&self.handle, vidioc::VIDIOC_STREAMOFF, &mut typ);
+            }
+
+            for plane in self.buffers.iter().flatten() {
+                let _ = v4l2::munmap(plane.ptr as *mut c_void, plane.len);
+            }
+
+            // frees the buffers in the driver
+            let mut request: v4l2_requestbuffers = mem::zeroed();
+            request.type_ = self.typ;
+            request.memory = MEMORY_MMAP;
+            let _ = ioctl(&self.handle, vidioc::VIDIOC_REQBUFS, &mut request);
+        }
+    }
+}
+
+/// Packs the planes of a buffer in `format` into `frame`. Chroma planes get the stride the
+/// converters expect after the luma plane, the one of the luma plane for interleaved
+/// chroma, half of it for a plane per component.
+fn pack(format: &MplaneFormat, planes: &[&[u8]], frame: &mut Vec<u8>) {
+    let stride = |index: usize| {
+        format
+            .planes
+            .get(index)
+            .map_or(0, |plane| plane.stride as usize)
+    };
+    let luma = stride(0);
+    let chroma = match &format.fourcc.repr {
+        b"YM12" => luma / 2,
+        _ => luma,
+    };
+
+    frame.clear();
+    for (index, plane) in planes.iter().enumerate() {
+        let (stride, packed) = (stride(index), if index == 0 { luma } else { chroma });
+        if stride == packed || stride == 0 {
+            frame.extend_from_slice(plane);
+            continue;
+        }
+        for row in plane.chunks(stride) {
+            let start = frame.len();
+            frame.extend_from_slice(&row[..row.len().min(packed)]);
+            frame.resize(start + packed, 0);
+        }
+    }
+}
+
+/// Converts the rgba `src` into the planes of a buffer in `format` with YCbCr samples
+/// of `k`, returning the bytes used of every plane
+pub(crate) fn encode(
+    format: &MplaneFormat,
+    src: &ScaledFrame,
+    planes: &mut [&mut [u8]],
+    k: &ToYcbcr,
+) -> Vec<u32> {
+    let (width, height) = (src.width(), src.height());
+    let stride = |index: usize| {
+        format
+            .planes
+            .get(index)
+            .map_or(0, |plane| plane.stride as usize)
+    };
+
+    match (&format.fourcc.repr, planes) {
+        (b"NV12", [plane]) => {
+            let y_len = stride(0) * height;
+            let uv_stride = crate::validate::chroma_stride(b"NV12", stride(0));
+            let (y, uv) = plane.split_at_mut(y_len.min(plane.len()));
+            crate::encode::encode_nv12(src, (y, stride(0)), (uv, uv_stride), k);
+            vec![(y_len + uv_stride * height.div_ceil(2)) as u32]
+        }
+        (b"NM12", [y, uv]) => {
+            crate::encode::encode_nv12(src, (y, stride(0)), (uv, stride(1)), k);
+            let uv_len = stride(1) * height.div_ceil(2);
+            vec![(stride(0) * height) as u32, uv_len as u32]
+        }
+        (b"YUYV", [plane]) => {
+            let stride = stride(0).max(width.next_multiple_of(2) * 2);
+            crate::encode::encode_yuyv(src, plane, stride, k);
+            vec![(stride * height) as u32]
+        }
+        (b"AB24", [plane]) => {
+            vec![crate::encode::encode_rgba(src, plane) as u32]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Whether [`encode`] can write frames of this format
+pub(crate) fn can_encode(fourcc: &[u8; 4]) -> bool {
+    matches!(fourcc, b"NV12" | b"NM12" | b"YUYV" | b"AB24")
+}
+
+unsafe fn ioctl<T>(handle: &Handle, request: vidioc::_IOC_TYPE, arg: &mut T) -> io::Result<()> {
+    v4l2::ioctl(handle.fd() as c_int, request, arg as *mut T as *mut c_void)
+}
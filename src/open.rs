@@ -0,0 +1,749 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::tasks::Task;
+use tracing::{debug, warn, Span};
+use v4l::framesize::FrameSizeEnum;
+use v4l::video::capture::Parameters;
+use v4l::video::Capture;
+use v4l::{FourCC, Fraction};
+
+use crate::activity::Activity;
+use crate::bayer::{self, Bayer};
+use crate::budget::Budget;
+use crate::busy::{self, DeviceBusyWaiting};
+use crate::capabilities;
+use crate::color::Linearize;
+use crate::config;
+use crate::deinterlace::Deinterlacer;
+use crate::denoise::TemporalFilter;
+use crate::devices::{DeviceInfo, DeviceSelector, Selection};
+use crate::diagnostics::Recorder;
+use crate::dump::Dumper;
+use crate::exchange::Exchange;
+use crate::gpu;
+use crate::input::{Decoder, Input};
+use crate::input_builder::InputBuilder;
+use crate::inspect::DeviceStatus;
+use crate::late;
+use crate::m2m::{M2m, M2mStage};
+use crate::memory::{self, Buffers};
+use crate::mplane::{self, MplaneStream};
+use crate::pattern::{PatternSource, TestPattern};
+use crate::pool::BufferPool;
+use crate::profile::Profile;
+use crate::raw::{RawFrames, RawSink};
+use crate::reconnect::{Connection, ReconnectPolicy};
+use crate::scale::Preview;
+use crate::signal::Signal;
+#[cfg(feature = "snapshot")]
+use crate::snapshot::Snapshots;
+use crate::source::{IoStream, VirtualSource};
+use crate::source_change::SourceChange;
+use crate::stats::LumaHistogram;
+use crate::subscribe::{Publisher, Subscribers};
+use crate::swizzle::Overrides;
+use crate::validate;
+use crate::wait::Waiter;
+use crate::watchdog::{Watchdog, WatchdogPolicy};
+use crate::{
+    can_decode, is_compressed, AlphaMode, BayerConfig, Colorimetry, Deinterlace, Device, Dither,
+    Error, FrameProcessor, ImageEncoding, Io, MemoryType, NegotiationReport, Orientation,
+    PixelAspect, PixelConverter, Result, SignalState, SizePolicy, WaitStrategy, BUFFER_COUNT,
+    DEQUEUE_SLICE,
+};
+
+/// A capture device with its stream buffers allocated but no image yet.
+/// Opening is split from image allocation so it can happen off the main thread.
+pub(crate) struct OpenedInput {
+    pub(crate) id: usize,
+    pub(crate) dev: Option<v4l::Device>,
+    pub(crate) format: v4l::Format,
+    pub(crate) stream: IoStream,
+    pub(crate) m2m: Option<M2mStage>,
+    pub(crate) processor: Option<FrameProcessor>,
+    pub(crate) converter: Option<Box<dyn PixelConverter>>,
+    pub(crate) raw: Option<RawFrames>,
+    pub(crate) dump: Option<Dumper>,
+    pub(crate) dequeue_timestamps: bool,
+    pub(crate) encoding: ImageEncoding,
+    pub(crate) dither: Dither,
+    pub(crate) colorimetry: Option<Colorimetry>,
+    pub(crate) denoise: Option<f32>,
+    pub(crate) bayer: Option<BayerConfig>,
+    pub(crate) stats: Option<usize>,
+    pub(crate) preview: Option<(u32, u32)>,
+    pub(crate) throttle_hidden: bool,
+    pub(crate) keep_image: bool,
+    pub(crate) late_upload: bool,
+    pub(crate) gpu: bool,
+    pub(crate) single_shot: bool,
+    pub(crate) every_frame: bool,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) wait: WaitStrategy,
+    pub(crate) profiles: HashMap<String, Profile>,
+    pub(crate) overrides: Overrides,
+    pub(crate) watchdog: Option<WatchdogPolicy>,
+    pub(crate) budget: Option<Duration>,
+    pub(crate) signal_timeout: Option<Duration>,
+    pub(crate) deinterlace: Deinterlace,
+    pub(crate) orientation: Orientation,
+    pub(crate) info: Option<DeviceInfo>,
+    pub(crate) frame_interval: Option<Duration>,
+    pub(crate) reconnect: Option<ReconnectPolicy>,
+    pub(crate) report: NegotiationReport,
+    pub(crate) span: Span,
+    pub(crate) selection: Selection,
+}
+
+impl OpenedInput {
+    /// Opens the stream of a capture device in the `requested` format, or the current one.
+    /// Inputs that `convert` their frames switch devices whose current format can't be
+    /// converted to one that can, see [`negotiate`].
+    pub(crate) fn new(
+        dev: v4l::Device,
+        device_id: usize,
+        selector: DeviceSelector,
+        m2m: Option<&M2m>,
+        requested: Option<&v4l::Format>,
+        buffers: Buffers,
+        convert: bool,
+    ) -> Result<Self> {
+        let path = selector.node_path(device_id);
+        let info = DeviceInfo::from_device(device_id, &path, &dev)?;
+
+        let mut report = NegotiationReport::new(path.display());
+        if let Ok(formats) = dev.enum_formats() {
+            report.offered(formats.iter().map(|format| &format.fourcc));
+        }
+        // devices that offer both apis are captured single planar
+        let mut mplane_format = None;
+        if mplane::captures_mplane(&dev) {
+            report.note("device only supports the multi-planar api");
+            let granted = mplane::capture_format(&dev.handle(), requested)
+                .map_err(|err| busy::check(err, &path))?;
+            let step = match requested {
+                Some(_) => "set multi-planar capture format",
+                None => "current multi-planar capture format",
+            };
+            report.step(step, requested, &granted.to_format());
+            mplane_format = Some(granted);
+        }
+        // drivers adjust requested formats to the closest one they support
+        let mut format = match (&mplane_format, requested) {
+            // planes of separate components are packed like the single planar variant
+            (Some(mplane_format), _) => mplane_format.to_capture_format(),
+            (None, Some(requested)) => {
+                let granted =
+                    Capture::set_format(&dev, requested).map_err(|err| busy::check(err, &path))?;
+                report.step("set capture format", Some(requested), &granted);
+                granted
+            }
+            (None, None) => {
+                let format = dev.format().map_err(|err| busy::check(err, &path))?;
+                report.step("current capture format", None, &format);
+                format
+            }
+        };
+        validate::format(&format)?;
+        match PixelAspect::query(&dev) {
+            Some(aspect) => report.pixel_aspect = aspect,
+            None => report.note("pixel aspect unknown, assuming square pixels"),
+        }
+
+        // the m2m device is negotiated in the span of the input
+        let span = crate::device_span(&report.device, "input");
+        let m2m = span.in_scope(|| match m2m {
+            Some(m2m) => m2m.open(&format),
+            None if is_compressed(&format.fourcc.repr) => M2m::auto().open(&format),
+            None => None,
+        });
+        if convert && m2m.is_none() && !can_decode(&format.fourcc.repr) {
+            let fourcc = format.fourcc.repr;
+            // the app asked for this one, it isn't replaced. Formats are only negotiated
+            // with the single planar api.
+            if requested.is_some() || mplane_format.is_some() || !config::current().auto_negotiate {
+                return Err(Error::UnsupportedFormat {
+                    fourcc: fourcc.into(),
+                });
+            }
+            format = negotiate(&dev, &format, &mut report)
+                .map_err(|err| busy::check(err, &path))?
+                .ok_or(Error::UnsupportedFormat {
+                    fourcc: fourcc.into(),
+                })?;
+            validate::format(&format)?;
+        }
+        match &m2m {
+            Some(m2m) => report.step(
+                &format!("converting on m2m device {}", m2m.id),
+                Some(&format),
+                &m2m.format,
+            ),
+            None if is_compressed(&format.fourcc.repr) => {
+                report.note("no m2m decoder, decoding on the cpu")
+            }
+            None => {}
+        }
+        if let Some(m2m) = &m2m {
+            validate::format(&m2m.format)?;
+        } else if format.fourcc.repr == *b"AB24" {
+            if let Err(err) = crate::m2m::opaque_alpha(&dev) {
+                report.note(format!(
+                    "alpha is the driver's, it can't be made opaque: {err}"
+                ));
+            }
+        }
+
+        let (stream, memory, allocated) = match mplane_format {
+            Some(format) => {
+                if !matches!(buffers.memory, MemoryType::Auto | MemoryType::Mmap) {
+                    report.note("multi-planar streams only support mmap");
+                }
+                let stream = MplaneStream::new(
+                    dev.handle(),
+                    mplane::BUF_TYPE_VIDEO_CAPTURE_MPLANE,
+                    format,
+                    buffers.count,
+                )
+                .map_err(|err| busy::check(err, &path))?;
+                let allocated = stream.buffer_count();
+                (IoStream::Mplane(stream), MemoryType::Mmap, allocated)
+            }
+            None => {
+                let mut memory = buffers
+                    .memory
+                    .resolve(&dev, v4l::buffer::Type::VideoCapture)?;
+                let stream = match memory.capture_stream(&dev, buffers.count) {
+                    // drivers that report userptr support can still reject the buffers
+                    Err(err) if memory == MemoryType::UserPtr && !busy::is_busy(&err) => {
+                        warn!(%err, "driver rejected userptr buffers, falling back to mmap");
+                        report.note(format!(
+                            "userptr buffers rejected ({err}), streaming with mmap"
+                        ));
+                        memory = MemoryType::Mmap;
+                        memory.capture_stream(&dev, buffers.count)
+                    }
+                    stream => stream,
+                };
+                let stream = stream.map_err(|err| busy::check(err, &path))?;
+                let allocated = memory::allocated(&dev, v4l::buffer::Type::VideoCapture, memory);
+                (stream, memory, allocated)
+            }
+        };
+        report.memory = Some(memory);
+        if allocated != buffers.count {
+            report.note(format!(
+                "requested {} stream buffers, the driver allocated {allocated}",
+                buffers.count
+            ));
+        }
+        report.buffers = Some(allocated);
+        let frame_interval = Capture::params(&dev)
+            .ok()
+            .map(|params| capabilities::duration(params.interval));
+
+        Ok(Self {
+            id: device_id,
+            dev: Some(dev),
+            format,
+            stream,
+            m2m,
+            processor: None,
+            converter: None,
+            raw: None,
+            dump: None,
+            dequeue_timestamps: false,
+            encoding: ImageEncoding::default(),
+            dither: Dither::default(),
+            colorimetry: None,
+            denoise: None,
+            bayer: None,
+            stats: None,
+            preview: None,
+            throttle_hidden: false,
+            keep_image: false,
+            late_upload: false,
+            gpu: false,
+            every_frame: false,
+            single_shot: false,
+            keepalive: None,
+            wait: WaitStrategy::default(),
+            profiles: HashMap::new(),
+            overrides: Overrides::default(),
+            watchdog: None,
+            budget: None,
+            signal_timeout: None,
+            deinterlace: Deinterlace::default(),
+            orientation: Orientation::default(),
+            reconnect: None,
+            info: Some(info),
+            frame_interval,
+            report,
+            span,
+            selection: Selection {
+                selector,
+                skipped: Vec::new(),
+            },
+        })
+    }
+
+    /// See [`Input::test_pattern`]
+    pub(crate) fn test_pattern(
+        pattern: TestPattern,
+        width: u32,
+        height: u32,
+        fourcc: [u8; 4],
+        fps: f32,
+    ) -> Result<Self> {
+        let format = v4l::Format::new(width, height, FourCC::new(&fourcc));
+        let source = PatternSource::new(pattern, format, fps)?;
+        let selector = DeviceSelector::name(format!("{pattern:?} test pattern"));
+        Self::virtual_source(source, format, selector)
+    }
+
+    /// Wraps a virtual source, frames are converted on the cpu
+    pub(crate) fn virtual_source(
+        source: impl VirtualSource + 'static,
+        format: v4l::Format,
+        selector: DeviceSelector,
+    ) -> Result<Self> {
+        validate::format(&format)?;
+        let fourcc = format.fourcc.repr;
+        if !can_decode(&fourcc) {
+            return Err(Error::UnsupportedFormat {
+                fourcc: fourcc.into(),
+            });
+        }
+
+        let mut report = NegotiationReport::new(&selector);
+        report.step("virtual source format", None, &format);
+        let span = crate::device_span(&report.device, "input");
+
+        Ok(Self {
+            id: Input::VIRTUAL_ID,
+            dev: None,
+            format,
+            stream: IoStream::Virtual(Box::new(source)),
+            m2m: None,
+            processor: None,
+            converter: None,
+            raw: None,
+            dump: None,
+            dequeue_timestamps: false,
+            encoding: ImageEncoding::default(),
+            dither: Dither::default(),
+            colorimetry: None,
+            denoise: None,
+            bayer: None,
+            stats: None,
+            preview: None,
+            throttle_hidden: false,
+            keep_image: false,
+            late_upload: false,
+            gpu: false,
+            every_frame: false,
+            single_shot: false,
+            keepalive: None,
+            wait: WaitStrategy::default(),
+            profiles: HashMap::new(),
+            overrides: Overrides::default(),
+            watchdog: None,
+            budget: None,
+            signal_timeout: None,
+            deinterlace: Deinterlace::default(),
+            orientation: Orientation::default(),
+            reconnect: None,
+            info: None,
+            frame_interval: None,
+            report,
+            span,
+            selection: Selection {
+                selector,
+                skipped: Vec::new(),
+            },
+        })
+    }
+
+    pub(crate) fn first_available(
+        selectors: &[DeviceSelector],
+        m2m: Option<&M2m>,
+        format: Option<&v4l::Format>,
+        buffers: Buffers,
+        convert: bool,
+        auto_dv_timings: bool,
+    ) -> Result<Self> {
+        let mut skipped = Vec::new();
+
+        for selector in selectors {
+            match Self::probe(selector, m2m, format, buffers, convert, auto_dv_timings) {
+                Ok(mut opened) => {
+                    opened.selection.skipped = skipped;
+                    return Ok(opened);
+                }
+                Err(err) => {
+                    debug!("skipping v4l device {selector}: {err}");
+                    skipped.push((selector.clone(), err));
+                }
+            }
+        }
+
+        Err(Error::NoDeviceAvailable { failures: skipped })
+    }
+
+    fn probe(
+        selector: &DeviceSelector,
+        m2m: Option<&M2m>,
+        format: Option<&v4l::Format>,
+        buffers: Buffers,
+        convert: bool,
+        auto_dv_timings: bool,
+    ) -> Result<Self> {
+        let (dev, id) = selector.open()?;
+        // before the format is negotiated, drivers report the format of the timings set
+        #[cfg(feature = "dv-timings")]
+        let timings = auto_dv_timings.then(|| crate::dv::detect(&dev)).flatten();
+        #[cfg(not(feature = "dv-timings"))]
+        let _ = auto_dv_timings;
+
+        #[allow(unused_mut)]
+        let mut opened = Self::new(dev, id, selector.clone(), m2m, format, buffers, convert)?;
+        #[cfg(feature = "dv-timings")]
+        if let Some(timings) = timings {
+            opened.report.note(format!(
+                "set the dv timings the receiver detected, {timings}"
+            ));
+        }
+        Ok(opened)
+    }
+
+    /// Asks the driver for a frame interval before the stream starts, virtual sources
+    /// keep theirs
+    pub(crate) fn set_frame_interval(&mut self, interval: Fraction) -> Result<()> {
+        let Some(dev) = &self.dev else {
+            return Ok(());
+        };
+
+        let granted = Capture::set_params(dev, &Parameters::new(interval))?.interval;
+        self.report.note(format!(
+            "requested {}/{}s between frames, the driver granted {}/{}s",
+            interval.numerator, interval.denominator, granted.numerator, granted.denominator
+        ));
+        self.frame_interval = Some(capabilities::duration(granted));
+        Ok(())
+    }
+
+    pub(crate) fn into_input(self, images: &mut Assets<Image>) -> Input {
+        let image = images.reserve_handle();
+        self.into_input_at(image, images)
+    }
+
+    /// Like [`OpenedInput::into_input`], but replaces the image behind an existing handle
+    pub(crate) fn into_input_at(self, image: Handle<Image>, images: &mut Assets<Image>) -> Input {
+        let size = self.size();
+        let preview = self.preview.map(|(width, height)| {
+            images.add(Image::new(
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                vec![255; (width * height * 4) as usize],
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::all(),
+            ))
+        });
+
+        let len = (size.width * size.height) as usize * self.encoding.bytes_per_pixel();
+        let texture = Image::new(
+            size,
+            TextureDimension::D2,
+            vec![255_u8; len],
+            self.encoding.texture_format(),
+            late::image_usage(self.late_upload),
+        );
+        images.insert(&image, gpu::render_target(texture, self.gpu));
+
+        self.into_input_with(image, preview, false)
+    }
+
+    /// Input of a [`RawInput`], its handle points at no image. `native` inputs copy
+    /// dequeued buffers instead of converting them.
+    pub(crate) fn into_headless(mut self, native: bool) -> Input {
+        self.preview = None;
+        if native {
+            self.orientation = Orientation::None;
+        }
+        self.into_input_with(Handle::default(), None, native)
+    }
+
+    /// Size of converted frames, frames converted by an m2m device may have been scaled.
+    /// Turned by the orientation.
+    fn size(&self) -> Extent3d {
+        let frame = self.m2m.as_ref().map_or(&self.format, |m2m| &m2m.format);
+        let (width, height) = self.orientation.size(frame.width, frame.height);
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    fn into_input_with(
+        mut self,
+        image: Handle<Image>,
+        preview: Option<Handle<Image>>,
+        native: bool,
+    ) -> Input {
+        let size = self.size();
+        let decoder = match &self.m2m {
+            Some(m2m) => Decoder::M2m { id: m2m.id },
+            None if self.gpu => Decoder::Gpu,
+            None => Decoder::Cpu,
+        };
+        self.report.decoder = Some(decoder);
+        if decoder == Decoder::Cpu {
+            let conversion = crate::conversion_path();
+            debug!(parent: &self.span, conversion, "converting frames on the cpu");
+        }
+
+        let active = Arc::new(AtomicBool::new(true));
+        let subscribers = Subscribers::default();
+        let wait = Waiter::new(self.wait);
+        // virtual inputs have no device to reopen
+        let watchdog_policy = self.watchdog.filter(|_| self.dev.is_some());
+        let timeout = wait
+            .timeout()
+            .or(watchdog_policy.map(|policy| policy.timeout));
+        // blocking dequeues poll in slices, see Io::cancel
+        let cancel = Arc::new(AtomicBool::new(false));
+        let sliced = timeout.is_none().then(|| cancel.clone());
+        let timeout = timeout.or(sliced.as_ref().map(|_| DEQUEUE_SLICE));
+        let mut stream = self.stream;
+        stream.set_timeout(timeout);
+        let buffers = Buffers {
+            memory: self.report.memory.unwrap_or(MemoryType::Mmap),
+            count: self.report.buffers.unwrap_or(BUFFER_COUNT),
+        };
+        let watchdog = watchdog_policy.map(|policy| Watchdog::new(policy, buffers, timeout));
+        let reconnect = self.reconnect.filter(|_| self.dev.is_some());
+        let connection = Connection::new(reconnect, buffers, timeout);
+
+        let len = (size.width * size.height) as usize * self.encoding.bytes_per_pixel();
+        let exchange = Arc::new(Exchange::new(len));
+        // frames converted on an m2m device skip the software decoder
+        #[cfg(feature = "h264")]
+        let h264 = !native
+            && self.m2m.is_none()
+            && self.overrides.fourcc(self.format.fourcc.repr) == *b"H264";
+
+        Input {
+            device: Device {
+                id: self.id,
+                format: self.format,
+                image,
+                size,
+                io: Arc::new(Mutex::new(Io {
+                    buffer: BufferPool::global().take(len, 255),
+                    stream,
+                    m2m: self.m2m,
+                    processor: self.processor,
+                    error: None,
+                    sequence: 0,
+                    frames: Default::default(),
+                    dequeue_timestamps: self.dequeue_timestamps,
+                    restarts: 0,
+                    restarted: None,
+                    paused: false,
+                    cancel: sliced,
+                    drain: !self.every_frame,
+                    corrupt: 0,
+                    lost: 0,
+                    // the shader converts the frames as they were dequeued
+                    native: native || self.gpu,
+                    received: None,
+                    sent: None,
+                    size_policy: SizePolicy::default(),
+                    alpha: AlphaMode::default(),
+                    orientation: self.orientation,
+                    crop: None,
+                    offsets: Default::default(),
+                    resampler: None,
+                    linearize: Linearize::new(self.encoding),
+                    encoding: self.encoding,
+                    preview: self.preview.map(Preview::new),
+                    targets: Vec::new(),
+                    dither: self.dither,
+                    colorimetry: Colorimetry::resolve(&self.format, self.colorimetry),
+                    denoise: self.denoise.map(TemporalFilter::new),
+                    // set by sync_post_process
+                    post_process: None,
+                    stats: self.stats.map(LumaHistogram::new),
+                    counts: Default::default(),
+                    snapshot: None,
+                    bayer: bayer::is_bayer(&self.overrides.fourcc(self.format.fourcc.repr))
+                        .then(|| Bayer::new(self.bayer.unwrap_or_default())),
+                    converter: self.converter,
+                    raw: self.raw.map(RawSink::new),
+                    publisher: Some(Publisher::new(subscribers.clone())),
+                    dump: self.dump,
+                    activity: Some(Activity::new(active.clone(), self.keepalive)),
+                    fresh: false,
+                    dequeued: None,
+                    exchange: exchange.clone(),
+                    upload_latency: None,
+                    presenter: Default::default(),
+                    underruns: None,
+                    stride: self.format.stride,
+                    // holds a whole frame once rows are unpadded
+                    unpadded: Vec::with_capacity(self.format.size as usize),
+                    overrides: self.overrides,
+                    watchdog,
+                    budget: self.budget.map(Budget::new),
+                    deinterlace: Some(Deinterlacer::new(self.deinterlace, self.format.field_order)),
+                    // virtual sources deliver every frame
+                    signal: self
+                        .dev
+                        .is_some()
+                        .then(|| Signal::new(self.signal_timeout, self.frame_interval)),
+                    source_change: self.dev.as_ref().and_then(SourceChange::subscribe),
+                    diagnostics: Recorder::input(self.id),
+                    #[cfg(feature = "h264")]
+                    h264: h264.then(crate::h264::H264::new),
+                    frame_encoder: None,
+                    wait: Some(wait),
+                })),
+                task: None,
+                frame: None,
+                report: self.report,
+                span: self.span,
+                path: self.info.as_ref().map(|info| info.path.clone()),
+                frame_interval: self.frame_interval,
+                dev: self.dev,
+                closed: false,
+                cancel,
+                exchange,
+                paused: false,
+                errors: Default::default(),
+                health: Default::default(),
+                release_image: !self.keep_image,
+                released: None,
+            },
+            selection: self.selection,
+            info: self.info,
+            decoder,
+            encoding: self.encoding,
+            colorimetry: self.colorimetry,
+            preview,
+            profiles: self.profiles,
+            pending_profile: None,
+            format_changed: None,
+            active,
+            subscribers,
+            throttle_hidden: self.throttle_hidden,
+            late_upload: self.late_upload,
+            single_shot: self.single_shot,
+            orientation: self.orientation,
+            crop: None,
+            frame_requested: false,
+            signal: SignalState::Unknown,
+            #[cfg(feature = "snapshot")]
+            snapshots: Snapshots::default(),
+            connection,
+            status: DeviceStatus::default(),
+        }
+    }
+}
+
+/// Switches a device to the first of the
+/// [`supported_capture_formats`](crate::supported_capture_formats) it offers, YUYV
+/// before the others. The current size is kept when the format
+/// offers it, otherwise the largest one is taken.
+fn negotiate(
+    dev: &v4l::Device,
+    current: &v4l::Format,
+    report: &mut NegotiationReport,
+) -> std::io::Result<Option<v4l::Format>> {
+    let offered: Vec<FourCC> = dev
+        .enum_formats()?
+        .iter()
+        .map(|format| format.fourcc)
+        .collect();
+
+    for fourcc in crate::supported_capture_formats().iter().map(FourCC::new) {
+        if !offered.contains(&fourcc) {
+            continue;
+        }
+
+        let sizes: Vec<(u32, u32)> = dev
+            .enum_framesizes(fourcc)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|size| match size.size {
+                FrameSizeEnum::Discrete(size) => (size.width, size.height),
+                FrameSizeEnum::Stepwise(size) => (size.max_width, size.max_height),
+            })
+            .collect();
+        let (width, height) = match sizes.contains(&(current.width, current.height)) {
+            true => (current.width, current.height),
+            false => sizes
+                .into_iter()
+                .max_by_key(|(width, height)| width * height)
+                .unwrap_or((current.width, current.height)),
+        };
+
+        let requested = v4l::Format::new(width, height, fourcc);
+        let granted = Capture::set_format(dev, &requested)?;
+        report.step("negotiated capture format", Some(&requested), &granted);
+        if granted.fourcc == fourcc {
+            return Ok(Some(granted));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Opens an [`Input`] from a list of [`DeviceSelector`]s on the async compute pool,
+/// so slow device probing doesn't block startup.
+///
+/// Once the device is open the plugin replaces this component with the [`Input`] and
+/// the `Handle<Image>` of its image on the same entity, so spawning it with a sprite
+/// shows the input without allocating the image up front. Failures are sent as
+/// [`InputOpenFailed`], and the attempts of
+/// [`OpenPolicy::WaitUntilFree`](crate::OpenPolicy::WaitUntilFree) as [`DeviceBusyWaiting`].
+#[derive(Component)]
+pub struct PendingInput {
+    pub(crate) task: Task<Result<OpenedInput>>,
+    pub(crate) waiting: Mutex<Receiver<DeviceBusyWaiting>>,
+}
+
+/// Sent when the device of a [`PendingInput`] couldn't be opened, the component is
+/// removed from the entity
+#[derive(Event, Debug)]
+pub struct InputOpenFailed {
+    pub entity: Entity,
+    pub error: Error,
+}
+
+impl PendingInput {
+    /// Opens the device with the default settings, like [`Input::new`] without
+    /// needing the image assets
+    pub fn open(device: impl Into<DeviceSelector>) -> Self {
+        Self::new(vec![device.into()])
+    }
+
+    pub fn new(selectors: Vec<DeviceSelector>) -> Self {
+        InputBuilder {
+            selectors,
+            ..default()
+        }
+        .pending()
+    }
+}
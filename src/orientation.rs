@@ -0,0 +1,202 @@
+/// How the frames of an [`Input`](crate::Input) are turned before they reach its image,
+/// or how the image of an [`Output`](crate::Output) is turned before it is written,
+/// like for a webcam mounted upside down. Rotations are clockwise and swap the width
+/// and height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Orientation {
+    #[default]
+    None,
+    /// Mirrors left and right
+    FlipH,
+    /// Mirrors top and bottom
+    FlipV,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Orientation {
+    /// Whether frames turned this way are as wide as they were high
+    pub fn swaps_size(self) -> bool {
+        matches!(self, Self::Rotate90 | Self::Rotate270)
+    }
+
+    /// Size of a frame of `width` x `height` turned this way. Turning back is the same
+    /// swap, the size of the turned frame gives the size it was turned from.
+    pub fn size(self, width: u32, height: u32) -> (u32, u32) {
+        match self.swaps_size() {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    /// Offset in an rgba frame of `size` with rows of `stride` bytes of the pixel shown in
+    /// row `y` of the turned frame, summed with [`Orientation::column_offset`]
+    pub(crate) fn row_offset(self, y: usize, size: (usize, usize), stride: usize) -> usize {
+        let (width, height) = size;
+        match self {
+            Self::None | Self::FlipH => y * stride,
+            Self::FlipV | Self::Rotate180 => (height - 1 - y) * stride,
+            Self::Rotate90 => y * 4,
+            Self::Rotate270 => (width - 1 - y) * 4,
+        }
+    }
+
+    /// Like [`Orientation::row_offset`], for column `x` of the turned frame
+    pub(crate) fn column_offset(self, x: usize, size: (usize, usize), stride: usize) -> usize {
+        let (width, height) = size;
+        match self {
+            Self::None | Self::FlipV => x * 4,
+            Self::FlipH | Self::Rotate180 => (width - 1 - x) * 4,
+            Self::Rotate90 => (height - 1 - x) * stride,
+            Self::Rotate270 => x * stride,
+        }
+    }
+}
+
+/// Frame that converted rows are written into one at a time, turned on the way.
+/// Flips write every row where it ends up, rotations convert it aside and spread it
+/// over a column, so no orientation takes a pass over the frame of its own.
+pub(crate) struct Rows<'a> {
+    dst: &'a mut [u8],
+    orientation: Orientation,
+    /// Pixels in a row as it is converted
+    width: usize,
+    /// Rows of the frame, of the band for frames converted in bands
+    height: usize,
+    /// Bytes per pixel
+    bytes: usize,
+    /// The row converted last, for rotations
+    scratch: Vec<u8>,
+    /// Maps the rgb channels of converted rows, see [`PostProcess`](crate::PostProcess)
+    table: Option<&'a [u8; 256]>,
+}
+
+impl<'a> Rows<'a> {
+    /// Rows of `width` pixels of `bytes` each, as many as `dst` holds
+    pub(crate) fn new(
+        dst: &'a mut [u8],
+        width: usize,
+        bytes: usize,
+        orientation: Orientation,
+    ) -> Self {
+        let row = width * bytes;
+        let height = dst.len() / row.max(1);
+        Self {
+            dst: &mut dst[..height * row],
+            orientation,
+            width,
+            height,
+            bytes,
+            scratch: Vec::new(),
+            table: None,
+        }
+    }
+
+    /// Maps the rgb channels of every rgba row through `table` once it is converted,
+    /// while the row is still in the cache
+    pub(crate) fn mapped(mut self, table: Option<&'a [u8; 256]>) -> Self {
+        self.table = table;
+        self
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the rows can be converted in [`Rows::bands`], rows of rotated frames
+    /// end up spread over all of it
+    pub(crate) fn splits(&self) -> bool {
+        !self.orientation.swaps_size()
+    }
+
+    /// Splits the rows into bands of `rows` each, in the order they are converted
+    pub(crate) fn bands(self, rows: usize) -> Vec<Rows<'a>> {
+        let (width, bytes, orientation) = (self.width, self.bytes, self.orientation);
+        let table = self.table;
+        let band = |dst: &'a mut [u8]| Rows::new(dst, width, bytes, orientation).mapped(table);
+        let len = rows * width * bytes;
+        match orientation {
+            // the first rows are converted into the last ones
+            Orientation::FlipV | Orientation::Rotate180 => {
+                self.dst.rchunks_mut(len.max(1)).map(band).collect()
+            }
+            _ => self.dst.chunks_mut(len.max(1)).map(band).collect(),
+        }
+    }
+
+    /// Converts row `y` with `convert`, which is given the row to fill
+    pub(crate) fn write(&mut self, y: usize, convert: impl FnOnce(&mut [u8])) {
+        if y >= self.height {
+            return;
+        }
+        let (width, height, bytes) = (self.width, self.height, self.bytes);
+        let row = width * bytes;
+        let flipped = height - 1 - y;
+        let table = self.table;
+        let convert = |dst: &mut [u8]| {
+            convert(dst);
+            if let Some(table) = table {
+                crate::post_process::map(table, dst);
+            }
+        };
+
+        match self.orientation {
+            Orientation::None => convert(&mut self.dst[y * row..(y + 1) * row]),
+            Orientation::FlipV => convert(&mut self.dst[flipped * row..(flipped + 1) * row]),
+            Orientation::FlipH => {
+                let dst = &mut self.dst[y * row..(y + 1) * row];
+                convert(dst);
+                mirror(dst, bytes);
+            }
+            Orientation::Rotate180 => {
+                let dst = &mut self.dst[flipped * row..(flipped + 1) * row];
+                convert(dst);
+                mirror(dst, bytes);
+            }
+            Orientation::Rotate90 | Orientation::Rotate270 => {
+                // formats without alpha leave it as it is, opaque
+                self.scratch.resize(row, 255);
+                convert(&mut self.scratch);
+                for (x, pixel) in self.scratch.chunks_exact(bytes).enumerate() {
+                    let index = match self.orientation {
+                        Orientation::Rotate90 => x * height + flipped,
+                        _ => (width - 1 - x) * height + y,
+                    };
+                    self.dst[index * bytes..(index + 1) * bytes].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+}
+
+/// Turns a whole frame of rows of `width` pixels of `bytes` each, for frames converted
+/// elsewhere or changed after they were converted, like deinterlaced ones. `scratch`
+/// holds a copy of the frame.
+pub(crate) fn turn(
+    frame: &mut [u8],
+    width: usize,
+    bytes: usize,
+    orientation: Orientation,
+    scratch: &mut Vec<u8>,
+) {
+    if orientation == Orientation::None || width == 0 {
+        return;
+    }
+    scratch.clear();
+    scratch.extend_from_slice(frame);
+    let mut rows = Rows::new(frame, width, bytes, orientation);
+    for (y, src) in scratch.chunks_exact(width * bytes).enumerate() {
+        rows.write(y, |dst| dst.copy_from_slice(src));
+    }
+}
+
+/// Reverses the order of the pixels of `row`
+pub(crate) fn mirror(row: &mut [u8], bytes: usize) {
+    let pixels = row.len() / bytes;
+    for x in 0..pixels / 2 {
+        let (left, right) = row.split_at_mut((pixels - 1 - x) * bytes);
+        left[x * bytes..(x + 1) * bytes].swap_with_slice(&mut right[..bytes]);
+    }
+}
@@ -0,0 +1,1157 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::asset::AssetId;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::utils::{HashMap, HashSet};
+use v4l::capability::Flags;
+use v4l::prelude::*;
+use v4l::video::output::Parameters;
+use v4l::{FourCC, Fraction};
+
+use crate::capabilities;
+use crate::converter;
+use crate::devices::{Capabilities, DeviceSelector};
+use crate::diagnostics::Recorder;
+use crate::encode;
+use crate::inspect::DeviceStatus;
+use crate::memory::{self, Buffers};
+use crate::mock::{MockFrames, MockSink};
+use crate::mplane::{self, MplaneFormat, MplaneStream};
+use crate::pool::BufferPool;
+use crate::scale::Resampler;
+use crate::source::IoStream;
+use crate::underrun::{self, Underruns};
+use crate::validate;
+use crate::{
+    describe_format, AlphaMode, Colorimetry, Device, Dither, Error, ExternalOutput, Format,
+    FrameId, FrameInfo, FrameProcessor, ImageEncoding, Input, Io, MemoryType, NegotiationReport,
+    Orientation, PixelConverter, Result, ScaleFilter, SizePolicy, StreamStats, UnderrunPolicy,
+};
+
+/// Reflected for inspectors, which see the [`DeviceStatus`] of the device
+#[derive(Component, Reflect)]
+#[reflect(from_reflect = false)]
+pub struct Output(
+    #[reflect(ignore)] pub(crate) Device,
+    pub(crate) DeviceStatus,
+    #[reflect(ignore)] pub(crate) Pacing,
+);
+
+/// When the image of an [`Output`] is copied and written. Images are only written
+/// after they changed, at most once per frame interval the device negotiated or
+/// [`Output::set_max_fps`] times a second.
+pub(crate) struct Pacing {
+    /// The image was modified since it was last copied
+    pub(crate) changed: bool,
+    /// The copy of the image wasn't written yet
+    pub(crate) pending: bool,
+    /// Frames are read back from the texture of the image, see
+    /// [`OutputBuilder::gpu_readback`]
+    pub(crate) readback: bool,
+    /// Interval of the max fps, overrides the one of the device
+    max: Option<Duration>,
+    /// Interval the driver negotiated, `None` when the output isn't paced to it
+    device: Option<Duration>,
+    /// When the next write is due
+    due: Option<Instant>,
+}
+
+impl Pacing {
+    fn new(max_fps: Option<f32>, device: Option<Duration>, readback: bool) -> Self {
+        Self {
+            // the image is written once whatever it holds
+            changed: true,
+            pending: false,
+            readback,
+            max: max_fps.map(fps_interval),
+            device,
+            due: None,
+        }
+    }
+
+    /// Time between writes, `None` writes every change
+    fn interval(&self) -> Option<Duration> {
+        self.max.or(self.device)
+    }
+
+    /// Frames written a second at most
+    pub(crate) fn fps(&self) -> Option<f32> {
+        self.interval()
+            .map(|interval| 1.0 / interval.as_secs_f32().max(f32::EPSILON))
+    }
+
+    /// Whether the next write isn't due yet
+    pub(crate) fn throttled(&self) -> bool {
+        self.interval().is_some() && self.due.is_some_and(|due| Instant::now() < due)
+    }
+
+    /// Whether a write is due, records it if so. The next one is due an interval after
+    /// this one was, not after it happened, so the writes average the rate without
+    /// drifting by the time the app took to get to them.
+    pub(crate) fn write(&mut self) -> bool {
+        if !self.pending || self.throttled() {
+            return false;
+        }
+
+        self.pending = false;
+        let now = Instant::now();
+        self.due = self.interval().map(|interval| match self.due {
+            Some(due) if now.saturating_duration_since(due) < interval => due + interval,
+            // writes after the image didn't change for a while start over instead of
+            // catching up in a burst
+            _ => now + interval,
+        });
+        true
+    }
+}
+
+fn fps_interval(fps: f32) -> Duration {
+    Duration::from_secs_f32(1.0 / fps.max(f32::EPSILON))
+}
+
+/// Copies of the images of outputs as they were last modified, shared by the outputs of
+/// the same image, so an image fanned out to several devices is copied once per change.
+/// The copy of an image only one output writes is taken into the allocation of the
+/// previous one.
+#[derive(Resource, Default)]
+pub(crate) struct Snapshots(HashMap<AssetId<Image>, Snapshot>);
+
+struct Snapshot {
+    frame: Arc<Vec<u8>>,
+    /// The image was modified since the copy was taken
+    stale: bool,
+}
+
+impl Snapshots {
+    /// The copy of `image`, taken now unless one was since it was last modified
+    pub(crate) fn get(&mut self, id: AssetId<Image>, image: &Image) -> Arc<Vec<u8>> {
+        let snapshot = self.0.entry(id).or_insert_with(|| Snapshot {
+            frame: Arc::new(image.data.clone()),
+            stale: false,
+        });
+        if snapshot.stale {
+            match Arc::get_mut(&mut snapshot.frame) {
+                Some(frame) => frame.clone_from(&image.data),
+                // outputs that didn't copy the change yet still write the previous one
+                None => snapshot.frame = Arc::new(image.data.clone()),
+            }
+            snapshot.stale = false;
+        }
+        snapshot.frame.clone()
+    }
+}
+
+/// Marks outputs whose image was modified, the next poll copies it. The sets of images
+/// are kept across frames, so they don't allocate every frame.
+pub(crate) fn track_images(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut outputs: Query<&mut Output>,
+    mut snapshots: ResMut<Snapshots>,
+    mut modified: Local<HashSet<AssetId<Image>>>,
+    mut written: Local<HashSet<AssetId<Image>>>,
+) {
+    modified.clear();
+    modified.extend(events.read().filter_map(|event| match event {
+        AssetEvent::Added { id } | AssetEvent::Modified { id } | AssetEvent::Removed { id } => {
+            Some(*id)
+        }
+        _ => None,
+    }));
+    // copies of images no output writes anymore, like of despawned ones
+    written.clear();
+    written.extend(outputs.iter().map(|output| output.0.image.id()));
+    snapshots.0.retain(|id, _| written.contains(id));
+    if modified.is_empty() {
+        return;
+    }
+    for id in modified.iter() {
+        if let Some(snapshot) = snapshots.0.get_mut(id) {
+            snapshot.stale = true;
+        }
+    }
+
+    let outputs = outputs.iter_mut();
+    for mut output in outputs.filter(|output| modified.contains(&output.0.image.id())) {
+        output.2.changed = true;
+    }
+}
+
+impl Output {
+    /// Creates a V4lDevice for encoding a bevy image into v4l
+    pub fn new(device_id: usize, image: Handle<Image>, format: Format) -> Result<Self> {
+        Self::builder(device_id).format(format).build(image)
+    }
+
+    /// Like [`Output::new`], but opens the device at `path`, which may be a stable udev
+    /// symlink under /dev/v4l/by-id or /dev/v4l/by-path
+    pub fn from_path(path: impl AsRef<Path>, image: Handle<Image>, format: Format) -> Result<Self> {
+        Self::builder_for(DeviceSelector::from(path.as_ref()))
+            .format(format)
+            .build(image)
+    }
+
+    /// An output that keeps the frames it writes in memory instead of writing them to a
+    /// device, for testing apps and conversions without v4l2loopback. Frames are
+    /// encoded in `format` like for a device, starting with the
+    /// [`initial frame`](OutputBuilder::initial_frame).
+    pub fn mock(image: Handle<Image>, format: Format) -> Result<(Self, MockFrames)> {
+        Self::builder_for(DeviceSelector::name("mock output"))
+            .format(format)
+            .build_mock(image)
+    }
+
+    /// Configures an Output for the v4l video device (/dev/video{id}) before opening it
+    pub fn builder(device_id: usize) -> OutputBuilder {
+        Self::builder_for(DeviceSelector::Index(device_id))
+    }
+
+    fn builder_for(device: DeviceSelector) -> OutputBuilder {
+        OutputBuilder {
+            device,
+            format: None,
+            processor: None,
+            size_policy: SizePolicy::default(),
+            scale_filter: ScaleFilter::default(),
+            alpha: AlphaMode::default(),
+            orientation: Orientation::default(),
+            underrun: None,
+            initial_frame: Some([0, 0, 0, 255]),
+            memory: MemoryType::default(),
+            buffer_count: None,
+            frame_interval: None,
+            max_fps: None,
+            pace_to_device: true,
+            colorimetry: None,
+            converter: None,
+            readback: false,
+            #[cfg(feature = "mjpeg-encode")]
+            jpeg_quality: 85,
+        }
+    }
+
+    /// Handle to bevy image
+    pub fn image(&self) -> &Handle<Image> {
+        &self.0.image
+    }
+
+    /// ID of the v4l video device (/dev/video{id})
+    pub fn id(&self) -> usize {
+        self.0.id
+    }
+
+    /// Queries the capabilities of the device, like whether it is a v4l2loopback node.
+    /// Fails after [`Output::close`].
+    pub fn query_caps(&self) -> Result<Capabilities> {
+        self.0.query_caps()
+    }
+
+    /// Calls `f` with the device, for ioctls the crate doesn't wrap. `None` after
+    /// [`Output::close`].
+    ///
+    /// The stream is running, so `f` must not change the format or the buffers.
+    pub fn with_raw_device<R>(&self, f: impl FnOnce(&v4l::Device) -> R) -> Option<R> {
+        self.0.dev.as_ref().map(f)
+    }
+
+    /// Stops the stream without closing the device, readers see no new frames until
+    /// [`Output::resume`]. Multi-planar streams keep running.
+    pub fn pause(&mut self) {
+        self.0.pause();
+    }
+
+    /// Starts the stream stopped by [`Output::pause`] again
+    pub fn resume(&mut self) {
+        self.0.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.is_paused()
+    }
+
+    /// Writes at most `fps` frames a second instead of once per frame interval of the
+    /// device, `None` goes back to the device's. Changes in between are written with
+    /// the next frame.
+    pub fn set_max_fps(&mut self, fps: Option<f32>) {
+        self.2.max = fps.map(fps_interval);
+    }
+
+    /// Frames written a second at most, `None` when every change of the image is
+    /// written. The rate actually written is the `v4l/output{id}/fps` diagnostic.
+    pub fn pacing_fps(&self) -> Option<f32> {
+        self.2.fps()
+    }
+
+    /// Frames written and the errors of the stream, see [`Input::stats`].
+    /// Outputs drop no frames, a written frame is a presented one.
+    pub fn stats(&self) -> StreamStats {
+        self.0.health.stats()
+    }
+
+    /// Stops streaming and releases the device without despawning the output, so
+    /// another process can open it
+    pub fn close(&mut self) {
+        self.0.close();
+    }
+
+    /// Path the device was opened with, see [`Output::from_path`]
+    pub fn path(&self) -> Option<&Path> {
+        self.0.path.as_deref()
+    }
+
+    pub fn format(&self) -> Format {
+        Format(self.0.format)
+    }
+
+    pub fn size(&self) -> Extent3d {
+        self.0.size
+    }
+
+    /// Stream buffers the driver allocated, see [`OutputBuilder::buffer_count`]
+    pub fn buffer_count(&self) -> Option<u32> {
+        self.0.report.buffers
+    }
+
+    /// Time between frames the driver granted, see [`OutputBuilder::frame_interval`].
+    /// `None` for drivers that don't report one and multi-planar outputs.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        self.0.frame_interval
+    }
+
+    /// Id, format and state of the device, as inspectors show them. Updated once per
+    /// frame by the plugin.
+    pub fn status(&self) -> &DeviceStatus {
+        &self.1
+    }
+
+    /// How the output format was arrived at
+    pub fn negotiation(&self) -> &NegotiationReport {
+        &self.0.report
+    }
+
+    /// Latest frame written, updated once per frame
+    pub fn last_frame(&self) -> Option<FrameId> {
+        self.0.frame
+    }
+
+    /// Sets what happens when the image and the device differ in size, see
+    /// [`OutputBuilder::size_policy`]
+    pub fn set_size_policy(&self, policy: SizePolicy) {
+        if let Ok(mut io) = self.0.io.lock() {
+            io.size_policy = policy;
+        }
+    }
+
+    /// Sets how the image is sampled when it is scaled, see
+    /// [`OutputBuilder::scale_filter`]
+    pub fn set_scale_filter(&self, filter: ScaleFilter) {
+        let size = (self.0.format.width, self.0.format.height);
+        if let Ok(mut io) = self.0.io.lock() {
+            io.resampler = match filter {
+                ScaleFilter::Nearest => None,
+                ScaleFilter::Bilinear => {
+                    Some(io.resampler.take().unwrap_or_else(|| Resampler::new(size)))
+                }
+            };
+        }
+    }
+
+    /// Sets what happens with the alpha of the image, see [`OutputBuilder::alpha_mode`]
+    pub fn set_alpha_mode(&self, mode: AlphaMode) {
+        if let Ok(mut io) = self.0.io.lock() {
+            io.alpha = mode;
+        }
+    }
+
+    /// Sets how the image is turned, see [`OutputBuilder::orientation`]
+    pub fn set_orientation(&self, orientation: Orientation) {
+        if let Ok(mut io) = self.0.io.lock() {
+            io.orientation = orientation;
+        }
+    }
+
+    /// Timestamps the next frame written with `pts` instead of the time it is written,
+    /// like a time from the app's audio clock for consumers syncing on buffer timestamps.
+    ///
+    /// Timestamps that aren't after the previous one are sent as a
+    /// [`V4lError`](crate::V4lError) and the frame is written with the current time.
+    pub fn present(&self, pts: Duration) {
+        if let Ok(mut io) = self.0.io.lock() {
+            io.presenter.present(pts);
+        }
+    }
+
+    /// Frames repeated since the output was opened, see [`OutputBuilder::repeat_on_underrun`]
+    pub fn underruns(&self) -> u32 {
+        let Ok(io) = self.0.io.lock() else {
+            return 0;
+        };
+        io.underruns.as_ref().map_or(0, |underruns| underruns.total)
+    }
+
+    /// Replaces the [`FrameProcessor`] run on written frames
+    pub fn set_processor(&self, processor: Option<FrameProcessor>) {
+        if let Ok(mut io) = self.0.io.lock() {
+            io.processor = processor;
+        }
+    }
+}
+
+/// Configures how an [`Output`] is opened, see [`Output::builder`]
+pub struct OutputBuilder {
+    device: DeviceSelector,
+    format: Option<Format>,
+    processor: Option<FrameProcessor>,
+    size_policy: SizePolicy,
+    scale_filter: ScaleFilter,
+    alpha: AlphaMode,
+    orientation: Orientation,
+    underrun: Option<UnderrunPolicy>,
+    initial_frame: Option<[u8; 4]>,
+    memory: MemoryType,
+    buffer_count: Option<u32>,
+    frame_interval: Option<(u32, u32)>,
+    max_fps: Option<f32>,
+    pace_to_device: bool,
+    colorimetry: Option<Colorimetry>,
+    converter: Option<Box<dyn PixelConverter>>,
+    readback: bool,
+    #[cfg(feature = "mjpeg-encode")]
+    jpeg_quality: u8,
+}
+
+impl OutputBuilder {
+    /// Format to set on the device, defaults to its current format.
+    /// Required for devices that only support the multi-planar api.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Runs `processor` on a copy of every frame before it is written,
+    /// the image itself is not changed.
+    ///
+    /// It runs off the main thread and has to be fast. A panicking processor is
+    /// sent as a [`V4lError`](crate::V4lError) and the frame is written as is.
+    pub fn processor(
+        mut self,
+        processor: impl Fn(&mut [u8], &FrameInfo) + Send + Sync + 'static,
+    ) -> Self {
+        self.processor = Some(Box::new(processor));
+        self
+    }
+
+    /// What happens when the image and the device differ in size, see [`SizePolicy`]
+    pub fn size_policy(mut self, policy: SizePolicy) -> Self {
+        self.size_policy = policy;
+        self
+    }
+
+    /// How the image is sampled when the [`SizePolicy`] scales it, nearest neighbor by
+    /// default. [`ScaleFilter::Bilinear`] keeps a frame of the size of the device for it.
+    pub fn scale_filter(mut self, filter: ScaleFilter) -> Self {
+        self.scale_filter = filter;
+        self
+    }
+
+    /// What happens with the alpha of the image, dropped by default, see [`AlphaMode`].
+    /// Compositing happens while the frame is converted to the format of the device.
+    pub fn alpha_mode(mut self, mode: AlphaMode) -> Self {
+        self.alpha = mode;
+        self
+    }
+
+    /// Turns the image with `orientation` before it is fitted to the device with the
+    /// [`SizePolicy`], rotated images are fitted with their width and height swapped.
+    /// Like scaling, turning happens while the frame is converted.
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// How stream buffers are shared with the device, [`MemoryType::Auto`] by default.
+    /// Outputs only support mmap.
+    pub fn memory(mut self, memory: MemoryType) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Stream buffers to ask the driver for, 4 unless the [`V4lConfig`](crate::V4lConfig)
+    /// says otherwise. More let the plugin get further ahead of the reader. Drivers may
+    /// allocate a different number, see [`Output::buffer_count`]. Building fails for
+    /// fewer than 2.
+    pub fn buffer_count(mut self, count: u32) -> Self {
+        self.buffer_count = Some(count);
+        self
+    }
+
+    /// Quality from 1 to 100 of the frames of MJPEG outputs, 85 by default
+    #[cfg(feature = "mjpeg-encode")]
+    pub fn jpeg_quality(mut self, quality: u8) -> Self {
+        self.jpeg_quality = quality;
+        self
+    }
+
+    /// Tells the driver `numerator / denominator` seconds pass between frames, like 1/30
+    /// for 30 fps. Consumers of loopback devices see it as the frame rate.
+    pub fn frame_interval(mut self, numerator: u32, denominator: u32) -> Self {
+        self.frame_interval = Some((numerator, denominator));
+        self
+    }
+
+    /// Writes the last frame again, with a new sequence and timestamp, whenever the app
+    /// doesn't write one for a frame interval of `policy.fps`. Keeps consumers that give up
+    /// on gaps, like some browsers, streaming through hitches.
+    ///
+    /// Runs a thread per output. Repeats are counted in [`Output::underruns`].
+    pub fn repeat_on_underrun(mut self, policy: UnderrunPolicy) -> Self {
+        self.underrun = Some(policy);
+        self
+    }
+
+    /// Writes the last frame again at least `fps` times a second, even while the image
+    /// doesn't change or the app is throttled, like while its window is minimized.
+    /// Like [`OutputBuilder::repeat_on_underrun`] without ever sending an
+    /// [`OutputUnderrun`](crate::OutputUnderrun).
+    ///
+    /// The keepalive is the floor, changes of the image are written as they happen up to
+    /// the [`OutputBuilder::max_fps`] ceiling, which caps the keepalive too.
+    pub fn keepalive_fps(self, fps: f32) -> Self {
+        self.repeat_on_underrun(UnderrunPolicy { fps, threshold: 0 })
+    }
+
+    /// Frame of a solid rgba `color` written when the device is opened, before the app
+    /// wrote one, black by default. Consumers that open the device early, like browsers
+    /// and OBS reading a v4l2loopback device, find its format streaming instead of
+    /// failing to negotiate it. `None` starts streaming with the first frame of the app.
+    pub fn initial_frame(mut self, color: Option<[u8; 4]>) -> Self {
+        self.initial_frame = color;
+        self
+    }
+
+    /// Writes at most `fps` frames a second, overriding the frame interval of the
+    /// device for drivers that report a bogus one, see [`Output::set_max_fps`]
+    pub fn max_fps(mut self, fps: f32) -> Self {
+        self.max_fps = Some(fps);
+        self
+    }
+
+    /// Writes at most one frame per [`Output::frame_interval`] the driver negotiated, on
+    /// by default. Apps rendering faster than consumers read, like at 240 fps into a
+    /// 30 fps loopback device, otherwise write every change and consumers see the rate
+    /// they were written at. Off writes every change unless there is a max fps.
+    pub fn pace_to_device(mut self, pace: bool) -> Self {
+        self.pace_to_device = pace;
+        self
+    }
+
+    /// Writes YUV frames with `colorimetry` instead of the one of the format, which
+    /// is limited range BT.601 unless the driver reports otherwise
+    pub fn colorimetry(mut self, colorimetry: Colorimetry) -> Self {
+        self.colorimetry = Some(colorimetry);
+        self
+    }
+
+    /// Encodes frames of the formats `converter` takes with it instead of the built-in
+    /// encoders, like a vendor specific format. Formats of fourccs the crate can't
+    /// encode are built with [`FormatBuilder::build_custom`](crate::FormatBuilder::build_custom).
+    pub fn converter(mut self, converter: impl PixelConverter + 'static) -> Self {
+        self.converter = Some(Box::new(converter));
+        self
+    }
+
+    /// Writes what the gpu rendered into the image, like a camera targeting it, instead
+    /// of its cpu data, which rendering doesn't change. The texture is copied into a
+    /// staging buffer after every frame rendered and written once the copy finished, a
+    /// frame or two later.
+    ///
+    /// The image needs [`TextureUsages::COPY_SRC`](bevy::render::render_resource::TextureUsages::COPY_SRC)
+    /// and an 8 bit rgba or bgra format, like the images of
+    /// [`Input::clone_image`](crate::Input::clone_image).
+    pub fn gpu_readback(mut self) -> Self {
+        self.readback = true;
+        self
+    }
+
+    /// Like [`OutputBuilder::build`], but the app writes frames with
+    /// [`ExternalOutput::service`] instead of the plugin
+    pub fn build_external(self, image: Handle<Image>) -> Result<ExternalOutput> {
+        Ok(ExternalOutput::new(self.build(image)?))
+    }
+
+    /// Like [`OutputBuilder::build`], but fails with [`Error::SizeMismatch`] when `image`
+    /// doesn't have the size of the format the device negotiated, turned by the
+    /// [`Orientation`], and the [`SizePolicy`] doesn't scale it. Without the check frames
+    /// of another size fail as they are written.
+    pub fn build_checked(self, image: Handle<Image>, images: &Assets<Image>) -> Result<Output> {
+        let (policy, orientation) = (self.size_policy, self.orientation);
+        let output = self.build(image)?;
+        let Some(image) = images.get(&output.0.image) else {
+            return Ok(output);
+        };
+        let image = orientation.size(image.width(), image.height());
+        let device = (output.0.format.width, output.0.format.height);
+        if policy == SizePolicy::Error && image != device {
+            return Err(Error::SizeMismatch { image, device });
+        }
+        Ok(output)
+    }
+
+    /// Opens the device, frames are written from `image`
+    pub fn build(self, image: Handle<Image>) -> Result<Output> {
+        let (dev, device_id) = self.device.open()?;
+        let path = self.device.node_path(device_id);
+
+        let mut report = NegotiationReport::new(path.display());
+        if let Ok(formats) = v4l::video::Output::enum_formats(&dev) {
+            report.offered(formats.iter().map(|format| &format.fourcc));
+        }
+
+        let format = match self.format {
+            Some(format) => format.0,
+            None => v4l::video::Output::format(&dev)?,
+        };
+
+        let count = self
+            .buffer_count
+            .unwrap_or(crate::config::current().default_buffer_count);
+        let buffers = Buffers::new(self.memory, count)?;
+        let memory = buffers
+            .memory
+            .resolve(&dev, v4l::buffer::Type::VideoOutput)?;
+        report.memory = Some(memory);
+
+        let flags = dev.query_caps()?.capabilities;
+        let mut frame_interval = None;
+        let allocated;
+        let mplane =
+            !flags.contains(Flags::VIDEO_OUTPUT) && flags.contains(Flags::VIDEO_OUTPUT_MPLANE);
+        let (format, stream) = if mplane {
+            report.note("device only supports the multi-planar api");
+            let format = negotiate_mplane(&dev, &format, &mut report)?;
+            let stream = MplaneStream::new(
+                dev.handle(),
+                mplane::BUF_TYPE_VIDEO_OUTPUT_MPLANE,
+                format.clone(),
+                buffers.count,
+            )?;
+            allocated = stream.buffer_count();
+            (format.to_format(), IoStream::Mplane(stream))
+        } else {
+            let granted = v4l::video::Output::set_format(&dev, &format)?;
+            report.step("set output format", Some(&format), &granted);
+            if let Some((numerator, denominator)) = self.frame_interval {
+                let requested = Parameters::new(Fraction::new(numerator, denominator));
+                let granted = v4l::video::Output::set_params(&dev, &requested)?.interval;
+                let requested = format!("{numerator}/{denominator}s");
+                let granted = format!("{}/{}s", granted.numerator, granted.denominator);
+                report.note(format!(
+                    "requested {requested} between frames, the driver granted {granted}"
+                ));
+            }
+            frame_interval = v4l::video::Output::params(&dev)
+                .ok()
+                .map(|params| capabilities::duration(params.interval));
+            let stream =
+                MmapStream::with_buffers(&dev, v4l::buffer::Type::VideoOutput, buffers.count)?;
+            allocated = memory::allocated(&dev, v4l::buffer::Type::VideoOutput, memory);
+            (format, IoStream::Mmap(stream))
+        };
+
+        if allocated != buffers.count {
+            report.note(format!(
+                "requested {} stream buffers, the driver allocated {allocated}",
+                buffers.count
+            ));
+        }
+        report.buffers = Some(allocated);
+
+        self.assemble(
+            image,
+            Opened {
+                id: device_id,
+                dev: Some(dev),
+                path: Some(path),
+                format,
+                stream,
+                frame_interval,
+                report,
+            },
+        )
+    }
+
+    /// Like [`OutputBuilder::build`] without a device, frames are kept in memory for
+    /// tests, see [`Output::mock`]. The format defaults to 640x480 YUYV. The mock takes
+    /// what a v4l2loopback device would, the buffers and frame interval asked for.
+    pub fn build_mock(self, image: Handle<Image>) -> Result<(Output, MockFrames)> {
+        let format = self.format.map_or_else(
+            || v4l::Format::new(640, 480, FourCC::new(b"YUYV")),
+            |format| format.0,
+        );
+        let mut report = NegotiationReport::new("mock output");
+        report.step("mock output format", None, &format);
+
+        let count = self
+            .buffer_count
+            .unwrap_or(crate::config::current().default_buffer_count);
+        let buffers = Buffers::new(self.memory, count)?;
+        report.memory = Some(match buffers.memory {
+            MemoryType::Auto | MemoryType::Mmap => MemoryType::Mmap,
+            memory => return Err(Error::UnsupportedMemory(memory)),
+        });
+        report.buffers = Some(buffers.count);
+        let frame_interval = self.frame_interval.map(|(numerator, denominator)| {
+            capabilities::duration(Fraction::new(numerator, denominator))
+        });
+
+        let frames = MockFrames::default();
+        let output = self.assemble(
+            image,
+            Opened {
+                id: Input::VIRTUAL_ID,
+                dev: None,
+                path: None,
+                format,
+                stream: IoStream::Sink(Box::new(MockSink(frames.clone()))),
+                frame_interval,
+                report,
+            },
+        )?;
+        Ok((output, frames))
+    }
+
+    /// Sets up the io of a device whose stream was created, and writes the initial frame
+    fn assemble(self, image: Handle<Image>, opened: Opened) -> Result<Output> {
+        let Opened {
+            id: device_id,
+            dev,
+            path,
+            format,
+            stream,
+            frame_interval,
+            report,
+        } = opened;
+        validate::format(&format)?;
+        let size = Extent3d {
+            width: format.width,
+            height: format.height,
+            depth_or_array_layers: 1,
+        };
+
+        // size of an image written as it is, until the app wrote one
+        let image_size = self.orientation.size(size.width, size.height);
+        let buffer = BufferPool::global().take((size.width * size.height * 4) as usize, 255);
+
+        let colorimetry = Colorimetry::resolve(&format, self.colorimetry);
+        let custom = self
+            .converter
+            .filter(|converter| converter.converts(format.fourcc.repr));
+        let frame_encoder = match &format.fourcc.repr {
+            _ if custom.is_some() => custom.map(|converter| {
+                Box::new(converter::Encoder::new(converter, format))
+                    as Box<dyn encode::FrameEncoder>
+            }),
+            #[cfg(feature = "mjpeg-encode")]
+            b"MJPG" => Some(Box::new(crate::jpeg::JpegEncoder::new(self.jpeg_quality))
+                as Box<dyn encode::FrameEncoder>),
+            _ => encode::for_format(&format, colorimetry),
+        };
+
+        let span = crate::device_span(&report.device, "output");
+
+        let output = Output(
+            Device {
+                id: device_id,
+                format,
+                image,
+                size,
+                io: Arc::new(Mutex::new(Io {
+                    buffer,
+                    stream,
+                    m2m: None,
+                    processor: self.processor,
+                    error: None,
+                    sequence: 0,
+                    frames: Default::default(),
+                    dequeue_timestamps: false,
+                    restarts: 0,
+                    restarted: None,
+                    paused: false,
+                    cancel: None,
+                    drain: false,
+                    corrupt: 0,
+                    lost: 0,
+                    native: false,
+                    received: None,
+                    sent: None,
+                    size_policy: self.size_policy,
+                    alpha: self.alpha,
+                    orientation: self.orientation,
+                    crop: None,
+                    offsets: Default::default(),
+                    resampler: (self.scale_filter == ScaleFilter::Bilinear)
+                        .then(|| Resampler::new((size.width, size.height))),
+                    linearize: None,
+                    dither: Dither::default(),
+                    colorimetry,
+                    denoise: None,
+                    post_process: None,
+                    bayer: None,
+                    converter: None,
+                    stats: None,
+                    counts: Default::default(),
+                    snapshot: None,
+                    encoding: ImageEncoding::default(),
+                    preview: None,
+                    targets: Vec::new(),
+                    raw: None,
+                    publisher: None,
+                    dump: None,
+                    activity: None,
+                    fresh: false,
+                    dequeued: None,
+                    exchange: Default::default(),
+                    upload_latency: None,
+                    presenter: Default::default(),
+                    stride: 0,
+                    unpadded: Vec::new(),
+                    overrides: Default::default(),
+                    watchdog: None,
+                    budget: None,
+                    signal: None,
+                    source_change: None,
+                    diagnostics: Recorder::output(device_id),
+                    deinterlace: None,
+                    #[cfg(feature = "h264")]
+                    h264: None,
+                    frame_encoder,
+                    underruns: self
+                        .underrun
+                        .map(|policy| Underruns::new(policy.threshold, image_size)),
+                    wait: None,
+                })),
+                task: None,
+                frame: None,
+                span,
+                path,
+                report,
+                frame_interval,
+                dev,
+                closed: false,
+                cancel: Default::default(),
+                exchange: Default::default(),
+                paused: false,
+                errors: Default::default(),
+                health: Default::default(),
+                release_image: false,
+                released: None,
+            },
+            DeviceStatus::default(),
+            Pacing::new(
+                self.max_fps,
+                frame_interval.filter(|interval| self.pace_to_device && !interval.is_zero()),
+                self.readback,
+            ),
+        );
+
+        if let Some(color) = self.initial_frame {
+            let device = &output.0;
+            let mut io = device.io.lock().expect("the io was just created");
+            for pixel in io.buffer.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&color);
+            }
+            let (width, height) = image_size;
+            device
+                .span
+                .in_scope(|| crate::stream_write(&mut io, &format, width, height))?;
+        }
+
+        if let Some(policy) = self.underrun {
+            let device = &output.0;
+            let io = Arc::downgrade(&device.io);
+            // repeats never write faster than changes
+            let fps = self.max_fps.map_or(policy.fps, |max| policy.fps.min(max));
+            underrun::spawn_repeater(io, format, fps, device.id, device.span.clone())?;
+        }
+        Ok(output)
+    }
+}
+
+/// A device opened by [`OutputBuilder::build`], or a mock of one
+struct Opened {
+    id: usize,
+    dev: Option<v4l::Device>,
+    path: Option<PathBuf>,
+    format: v4l::Format,
+    stream: IoStream,
+    frame_interval: Option<Duration>,
+    report: NegotiationReport,
+}
+
+/// Sets the format of a multi-planar output, falling back to NV12 when the driver
+/// doesn't take the requested format
+fn negotiate_mplane(
+    dev: &v4l::Device,
+    format: &v4l::Format,
+    report: &mut NegotiationReport,
+) -> Result<MplaneFormat> {
+    let handle = dev.handle();
+    let mut granted = None;
+
+    for fourcc in [format.fourcc, FourCC::new(b"NM12"), FourCC::new(b"NV12")] {
+        let mplane = mplane::set_format(
+            &handle,
+            mplane::BUF_TYPE_VIDEO_OUTPUT_MPLANE,
+            format.width,
+            format.height,
+            fourcc,
+        )?;
+
+        let requested = v4l::Format::new(format.width, format.height, fourcc);
+        report.step(
+            "set multi-planar output format",
+            Some(&requested),
+            &mplane.to_format(),
+        );
+
+        if mplane.fourcc == fourcc && mplane::can_encode(&fourcc.repr) {
+            return Ok(mplane);
+        }
+
+        granted = Some(mplane);
+    }
+
+    let granted = granted.expect("fourccs are not empty");
+    Err(Error::FormatRejected {
+        requested: describe_format(format),
+        granted: describe_format(&granted.to_format()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn builder() -> OutputBuilder {
+        Output::builder_for(DeviceSelector::name("mock output"))
+    }
+
+    /// Mock output of `builder` streaming `width`x`height` frames of `fourcc`
+    fn mock(builder: OutputBuilder, fourcc: &[u8; 4], (width, height): (u32, u32)) -> Output {
+        mocked(builder, fourcc, (width, height)).0
+    }
+
+    fn mocked(
+        builder: OutputBuilder,
+        fourcc: &[u8; 4],
+        (width, height): (u32, u32),
+    ) -> (Output, MockFrames) {
+        let format = Format::new(width, height, fourcc).unwrap();
+        builder
+            .format(format)
+            .build_mock(Handle::default())
+            .unwrap()
+    }
+
+    /// Writes the rgba `image` of `size` like the plugin does, returning the frame the
+    /// output wrote
+    fn write(output: &Output, frames: &MockFrames, image: &[[u8; 4]], size: (u32, u32)) -> Vec<u8> {
+        let mut io = output.0.io.lock().unwrap();
+        io.buffer = image.concat();
+        crate::stream_write(&mut io, &output.0.format, size.0, size.1).unwrap();
+        frames.drain().pop().unwrap().0
+    }
+
+    #[test]
+    fn format_and_initial_frame() {
+        let (output, frames) = mocked(builder(), b"RGB3", (2, 1));
+        assert_eq!(output.format().fourcc(), *b"RGB3");
+        assert_eq!(frames.drain()[0].0, [0; 6], "black by default");
+
+        let red = builder().initial_frame(Some(RED));
+        let (_, frames) = mocked(red, b"RGB3", (2, 1));
+        assert_eq!(frames.drain()[0].0, [255, 0, 0, 255, 0, 0]);
+
+        let (_, frames) = mocked(builder().initial_frame(None), b"RGB3", (2, 1));
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn processor_runs_on_written_frames() {
+        let white = builder().processor(|frame, info| {
+            assert_eq!((info.width, info.height), (2, 1));
+            frame.fill(255);
+        });
+        let (_, frames) = mocked(white, b"RGB3", (2, 1));
+        assert_eq!(frames.drain()[0].0, [255; 6]);
+    }
+
+    #[test]
+    fn size_policy_fits_other_sizes() {
+        let (output, _) = mocked(builder(), b"RGB3", (2, 1));
+        let mut io = output.0.io.lock().unwrap();
+        io.buffer = RED.to_vec();
+        let err = crate::stream_write(&mut io, &output.0.format, 1, 1).unwrap_err();
+        assert!(matches!(err, Error::SizeMismatch { .. }));
+        drop(io);
+
+        let stretch = builder().size_policy(SizePolicy::Stretch);
+        let (output, frames) = mocked(stretch, b"RGB3", (2, 1));
+        assert_eq!(
+            write(&output, &frames, &[RED], (1, 1)),
+            [255, 0, 0, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn scale_filter_blends_neighbors() {
+        let stretch = || builder().size_policy(SizePolicy::Stretch);
+        let (output, frames) = mocked(stretch(), b"RGB3", (4, 1));
+        let nearest = write(&output, &frames, &[RED, BLUE], (2, 1));
+        assert_eq!(nearest[3..6], [255, 0, 0]);
+
+        let (output, frames) = mocked(
+            stretch().scale_filter(ScaleFilter::Bilinear),
+            b"RGB3",
+            (4, 1),
+        );
+        assert!(output.0.io.lock().unwrap().resampler.is_some());
+        let bilinear = write(&output, &frames, &[RED, BLUE], (2, 1));
+        assert_eq!(bilinear[3..6], [191, 0, 64]);
+    }
+
+    #[test]
+    fn alpha_mode_keeps_or_composites_alpha() {
+        let clear = [255, 0, 0, 128];
+        let written = |mode| {
+            let (output, frames) = mocked(builder().alpha_mode(mode), b"AB24", (1, 1));
+            write(&output, &frames, &[clear], (1, 1))
+        };
+        assert_eq!(written(AlphaMode::Ignore), [255, 0, 0, 255]);
+        assert_eq!(written(AlphaMode::Straight), clear);
+        let over_black = written(AlphaMode::PremultiplyOverColor(Color::BLACK));
+        assert!(over_black[0].abs_diff(128) <= 1, "{over_black:?}");
+        assert_eq!(over_black[1..], [0, 0, 255]);
+    }
+
+    #[test]
+    fn orientation_turns_the_image() {
+        let flipped = builder().orientation(Orientation::FlipH);
+        let (output, frames) = mocked(flipped, b"RGB3", (2, 1));
+        assert_eq!(
+            write(&output, &frames, &[RED, BLUE], (2, 1)),
+            [0, 0, 255, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn colorimetry_sets_the_range_of_yuv_frames() {
+        let (_, frames) = mocked(builder().initial_frame(Some([255; 4])), b"YUYV", (2, 1));
+        assert_eq!(frames.drain()[0].0, [235, 128, 235, 128]);
+
+        let jpeg = builder()
+            .initial_frame(Some([255; 4]))
+            .colorimetry(Colorimetry::JPEG);
+        let (output, frames) = mocked(jpeg, b"YUYV", (2, 1));
+        assert_eq!(output.0.io.lock().unwrap().colorimetry, Colorimetry::JPEG);
+        assert_eq!(frames.drain()[0].0, [255, 128, 255, 128]);
+    }
+
+    struct Sevens;
+
+    impl PixelConverter for Sevens {
+        fn converts(&self, fourcc: [u8; 4]) -> bool {
+            &fourcc == b"GREY"
+        }
+
+        fn encode(&mut self, _: &[u8], format: &Format, dst: &mut [u8]) -> Result<usize> {
+            let len = (format.width() * format.height()) as usize;
+            dst[..len].fill(7);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn converter_replaces_the_builtin_encoder() {
+        let (_, frames) = mocked(builder().converter(Sevens), b"GREY", (2, 2));
+        assert_eq!(frames.drain()[0].0, [7; 4]);
+        // formats it doesn't take keep theirs
+        let (_, frames) = mocked(builder().converter(Sevens), b"RGB3", (1, 1));
+        assert_eq!(frames.drain()[0].0, [0; 3]);
+    }
+
+    #[test]
+    fn underrun_policy_repeats_the_last_frame() {
+        let keepalive = builder().keepalive_fps(500.0);
+        let (output, frames) = mocked(keepalive, b"GREY", (1, 1));
+        assert!(output.0.io.lock().unwrap().underruns.is_some());
+
+        let start = Instant::now();
+        while frames.len() < 3 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no repeated frames"
+            );
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    #[test]
+    fn pacing_follows_the_frame_interval_and_max_fps() {
+        let output = mock(builder().frame_interval(1, 30), b"GREY", (1, 1));
+        assert_eq!(
+            output.frame_interval(),
+            Some(Duration::from_secs_f64(1.0 / 30.0))
+        );
+        assert!((output.pacing_fps().unwrap() - 30.0).abs() < 0.01);
+
+        let unpaced = builder().frame_interval(1, 30).pace_to_device(false);
+        assert_eq!(mock(unpaced, b"GREY", (1, 1)).pacing_fps(), None);
+
+        let capped = builder().frame_interval(1, 30).max_fps(10.0);
+        assert!((mock(capped, b"GREY", (1, 1)).pacing_fps().unwrap() - 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn buffers_are_validated_like_for_devices() {
+        let output = mock(builder().buffer_count(6), b"GREY", (1, 1));
+        assert_eq!(output.buffer_count(), Some(6));
+        assert_eq!(output.negotiation().memory, Some(MemoryType::Mmap));
+
+        let format = Format::new(1, 1, b"GREY").unwrap();
+        let one = builder().format(format).buffer_count(1);
+        let err = one.build_mock(Handle::default()).err();
+        assert!(matches!(err, Some(Error::InvalidBufferCount(1))));
+
+        let userptr = builder().format(format).memory(MemoryType::UserPtr);
+        let err = userptr.build_mock(Handle::default()).err();
+        assert!(matches!(
+            err,
+            Some(Error::UnsupportedMemory(MemoryType::UserPtr))
+        ));
+    }
+
+    #[test]
+    fn gpu_readback_reads_the_texture() {
+        assert!(!mock(builder(), b"GREY", (1, 1)).2.readback);
+        assert!(mock(builder().gpu_readback(), b"GREY", (1, 1)).2.readback);
+    }
+
+    #[cfg(feature = "mjpeg-encode")]
+    #[test]
+    fn jpeg_quality_reaches_the_encoder() {
+        let noise: Vec<_> = (0..64 * 64_u32)
+            .map(|i| [i * 7, i * 13, i * 29, 255].map(|value| value as u8))
+            .collect();
+        let size = |quality| {
+            let (output, frames) = mocked(builder().jpeg_quality(quality), b"MJPG", (64, 64));
+            write(&output, &frames, &noise, (64, 64)).len()
+        };
+        assert!(size(10) < size(95));
+    }
+}
@@ -0,0 +1,32 @@
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+/// Frames from this many pixels on are converted in bands of rows across the
+/// [`ComputeTaskPool`], waking its threads costs more than converting smaller ones
+const MIN_PIXELS: usize = 1280 * 720;
+
+/// Rows of the bands a frame is split into, one band per thread of the pool. Even, so
+/// the rows of a band share their chroma with no other band. `None` when the frame is
+/// converted on the calling thread.
+pub(crate) fn band_rows(width: usize, height: usize) -> Option<usize> {
+    let threads = pool().thread_num();
+    if width * height < MIN_PIXELS || threads < 2 {
+        return None;
+    }
+    Some(height.div_ceil(threads).next_multiple_of(2))
+}
+
+/// Converts every band on the pool, returns once all of them are done
+pub(crate) fn run<T: Send>(bands: impl Iterator<Item = T>, convert: impl Fn(T) + Sync) {
+    let convert = &convert;
+    pool().scope(|scope| {
+        for band in bands {
+            scope.spawn(async move { convert(band) });
+        }
+    });
+}
+
+/// Apps without bevy's task pool plugin, like tools calling
+/// [`convert_frame_parallel`](crate::convert_frame_parallel), get a default pool
+fn pool() -> &'static TaskPool {
+    ComputeTaskPool::get_or_init(TaskPool::default)
+}
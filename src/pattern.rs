@@ -0,0 +1,128 @@
+use std::io;
+
+use crate::color::ToYcbcr;
+use crate::encode::encode_yuyv;
+use crate::scale::{ScaledFrame, SizePolicy};
+use crate::source::{FrameMeta, Pacer, VirtualSource};
+use crate::{bytes_per_pixel, Colorimetry, Error, Result};
+
+/// 75% color bars, left to right
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+
+/// Procedural frames for an [`Input::test_pattern`](crate::Input::test_pattern)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// SMPTE style color bars
+    Bars,
+    /// Red increasing to the right, green increasing downwards
+    Gradient,
+    /// A white box moving across a black frame
+    MovingBox,
+}
+
+/// Generates a [`TestPattern`] in a raw format, as a capture device would
+pub(crate) struct PatternSource {
+    pattern: TestPattern,
+    format: v4l::Format,
+    rgba: Vec<u8>,
+    frame: Vec<u8>,
+    /// The input decodes frames with the colorimetry of the format, like of a device
+    k: ToYcbcr,
+    pacer: Pacer,
+}
+
+impl PatternSource {
+    pub(crate) fn new(pattern: TestPattern, format: v4l::Format, fps: f32) -> Result<Self> {
+        let fourcc = format.fourcc.repr;
+        if !matches!(&fourcc, b"YUYV" | b"AB24") {
+            return Err(Error::UnsupportedFormat {
+                fourcc: fourcc.into(),
+            });
+        }
+
+        let pixels = (format.width * format.height) as usize;
+        let mut source = Self {
+            pattern,
+            format,
+            rgba: vec![255; pixels * 4],
+            frame: vec![0; pixels * bytes_per_pixel(&fourcc)],
+            k: Colorimetry::detect(&format).to_ycbcr(),
+            pacer: Pacer::new(fps),
+        };
+
+        // still patterns are only drawn once
+        source.draw(0);
+        Ok(source)
+    }
+
+    fn draw(&mut self, sequence: u32) {
+        let width = self.format.width as usize;
+        let height = self.format.height as usize;
+
+        for (i, pixel) in self.rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % width, i / width);
+
+            let rgb = match self.pattern {
+                TestPattern::Bars => BARS[x * BARS.len() / width],
+                TestPattern::Gradient => [
+                    (x * 255 / width.max(2).saturating_sub(1)) as u8,
+                    (y * 255 / height.max(2).saturating_sub(1)) as u8,
+                    0,
+                ],
+                TestPattern::MovingBox => {
+                    let side = height / 4;
+                    let left = sequence as usize * 4 % width;
+                    let top = (height - side) / 2;
+                    let inside =
+                        (x + width - left) % width < side && (top..top + side).contains(&y);
+                    if inside {
+                        [255; 3]
+                    } else {
+                        [0; 3]
+                    }
+                }
+            };
+
+            pixel[..3].copy_from_slice(&rgb);
+        }
+
+        match &self.format.fourcc.repr {
+            b"YUYV" => {
+                let size = (self.format.width, self.format.height);
+                // the same size never fails
+                if let Ok(rgba) = ScaledFrame::new(&self.rgba, size, size, SizePolicy::Error) {
+                    encode_yuyv(&rgba, &mut self.frame, rgba.width() * 2, &self.k);
+                }
+            }
+            _ => self.frame.copy_from_slice(&self.rgba),
+        }
+    }
+}
+
+impl VirtualSource for PatternSource {
+    fn next(&mut self) -> io::Result<(&[u8], FrameMeta)> {
+        let (sequence, timestamp) = self.pacer.wait();
+
+        if self.pattern == TestPattern::MovingBox {
+            self.draw(sequence);
+        }
+
+        let meta = FrameMeta {
+            error: false,
+            flags: 0,
+            bottom: false,
+            bytesused: self.frame.len() as u32,
+            sequence,
+            timestamp,
+        };
+        Ok((&self.frame, meta))
+    }
+}
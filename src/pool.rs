@@ -0,0 +1,168 @@
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use bevy::prelude::*;
+
+use crate::{ExternalInput, Input, RawInput};
+
+/// Bytes of buffers kept by default, a few 1080p rgba frames
+const DEFAULT_CAPACITY: usize = 64 << 20;
+
+/// Frame buffers of closed inputs and outputs, reused by the ones opened after them
+/// instead of allocating new ones, like when a kiosk cycles through cameras. Buffers of
+/// at least the size asked for and at most twice it are reused, the oldest are freed
+/// once the pool holds more than its capacity.
+///
+/// Inserted by the plugin, all of its clones share the same pool.
+#[derive(Resource, Clone)]
+pub struct BufferPool(Arc<Mutex<Pool>>);
+
+struct Pool {
+    /// Oldest first
+    buffers: Vec<Vec<u8>>,
+    capacity: usize,
+}
+
+/// Images of dropped inputs, removed from the assets by [`release_images`]. Asset ids
+/// are only unique within an app, every app has a queue of its own that inputs are
+/// given once they are spawned into it.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct ReleasedImages(Arc<Mutex<Vec<AssetId<Image>>>>);
+
+impl ReleasedImages {
+    /// Removes `image` from the assets on the next update, see
+    /// [`InputBuilder::keep_image`](crate::InputBuilder::keep_image)
+    pub(crate) fn push(&self, image: AssetId<Image>) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(image);
+    }
+
+    fn take(&self) -> Vec<AssetId<Image>> {
+        std::mem::take(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl BufferPool {
+    /// The pool of the process, devices are built without access to the resource
+    pub(crate) fn global() -> &'static Self {
+        static POOL: OnceLock<BufferPool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            Self(Arc::new(Mutex::new(Pool {
+                buffers: Vec::new(),
+                capacity: DEFAULT_CAPACITY,
+            })))
+        })
+    }
+
+    /// Bytes of buffers kept at most, 64 MiB by default. 0 frees every buffer as its
+    /// device closes.
+    pub fn set_capacity(&self, bytes: usize) {
+        let mut pool = self.lock();
+        pool.capacity = bytes;
+        pool.trim();
+    }
+
+    /// Bytes of the buffers kept for reuse
+    pub fn pooled_bytes(&self) -> usize {
+        self.lock().bytes()
+    }
+
+    /// Buffers kept for reuse
+    pub fn len(&self) -> usize {
+        self.lock().buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frees every buffer kept
+    pub fn clear(&self) {
+        self.lock().buffers.clear();
+    }
+
+    /// A buffer of `len` bytes of `fill`, a pooled one when one fits
+    pub(crate) fn take(&self, len: usize, fill: u8) -> Vec<u8> {
+        let mut pool = self.lock();
+        // the smallest that fits, small frames don't pin the buffers of large ones
+        let fits = pool
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| (len..=len.saturating_mul(2)).contains(&buffer.capacity()))
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+        let Some(index) = fits else {
+            return vec![fill; len];
+        };
+        let mut buffer = pool.buffers.remove(index);
+        drop(pool);
+        buffer.clear();
+        buffer.resize(len, fill);
+        buffer
+    }
+
+    /// Keeps `buffer` for reuse, freeing the oldest buffers beyond the capacity
+    pub(crate) fn give(&self, buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        let mut pool = self.lock();
+        pool.buffers.push(buffer);
+        pool.trim();
+    }
+
+    /// Buffers are swapped in and out whole, a panic can't leave one half pooled
+    fn lock(&self) -> MutexGuard<'_, Pool> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Pool {
+    fn bytes(&self) -> usize {
+        self.buffers.iter().map(Vec::capacity).sum()
+    }
+
+    fn trim(&mut self) {
+        while self.bytes() > self.capacity {
+            self.buffers.remove(0);
+        }
+    }
+}
+
+/// Removes the images of dropped inputs from the assets, even while handles to them
+/// are left, and pools their data
+pub(crate) fn release_images(
+    pool: Res<BufferPool>,
+    released: Res<ReleasedImages>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for id in released.take() {
+        if let Some(image) = images.remove(id) {
+            pool.give(image.data);
+        }
+    }
+}
+
+/// Gives inputs spawned since the last update the queue of the app, those dropped
+/// before they were spawned leave their image to the handles
+pub(crate) fn track_released_images(
+    released: Res<ReleasedImages>,
+    mut inputs: Query<&mut Input, Added<Input>>,
+    mut raw: Query<&mut RawInput, Added<RawInput>>,
+    mut external: Query<&mut ExternalInput, Added<ExternalInput>>,
+) {
+    let inputs = inputs
+        .iter_mut()
+        .map(Mut::into_inner)
+        .chain(raw.iter_mut().map(|raw| &mut **raw.into_inner()))
+        .chain(
+            external
+                .iter_mut()
+                .map(|external| &mut **external.into_inner()),
+        );
+    for input in inputs {
+        input.device.released = Some(released.clone());
+    }
+}
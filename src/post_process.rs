@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+
+use crate::Input;
+
+/// Saturations above this would overflow the fixed point chroma terms of the conversion
+const MAX_SATURATION: f32 = 4.0;
+
+/// Brightness, contrast, gamma and saturation the frames of the [`Input`] on the same
+/// entity are adjusted by, for cameras whose controls don't work. Brightness, contrast
+/// and gamma map every rgb channel through a table of 256 values, built when the
+/// component changes and applied to the rows of frames as they are converted.
+/// Saturation scales the chroma of YUV formats converted on the cpu, other formats keep
+/// theirs.
+///
+/// The default changes nothing, and neither does removing the component. Frames
+/// converted on the gpu and those of native inputs aren't adjusted.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PostProcess {
+    /// Added to every channel, -1 to 1 of its range
+    pub brightness: f32,
+    /// Scales the distance of every channel from mid gray, 1 keeps it
+    pub contrast: f32,
+    /// Channels are raised to `1 / gamma`, above 1 brightens the mid tones
+    pub gamma: f32,
+    /// Scales the chroma, 0 is grayscale and 1 keeps it, up to 4
+    pub saturation: f32,
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl PostProcess {
+    /// Whether frames are converted as if there was no adjustment
+    pub fn is_identity(&self) -> bool {
+        self.maps_identity() && self.saturation == 1.0
+    }
+
+    /// The value every channel value maps to. Contrast is applied around mid gray, then
+    /// the brightness is added and gamma applied last.
+    pub fn table(&self) -> [u8; 256] {
+        let gamma = 1.0 / self.gamma.max(f32::EPSILON);
+        std::array::from_fn(|value| {
+            let value = value as f32 / 255.0;
+            let value = ((value - 0.5) * self.contrast + 0.5 + self.brightness).clamp(0.0, 1.0);
+            (value.powf(gamma) * 255.0).round() as u8
+        })
+    }
+
+    fn maps_identity(&self) -> bool {
+        self.brightness == 0.0 && self.contrast == 1.0 && self.gamma == 1.0
+    }
+}
+
+/// A [`PostProcess`] as io tasks apply it
+#[derive(Debug)]
+pub(crate) struct Lut {
+    /// `None` when only the saturation changes
+    table: Option<[u8; 256]>,
+    pub(crate) saturation: f32,
+}
+
+impl Lut {
+    /// `None` for adjustments that change nothing, frames skip it
+    fn new(post_process: &PostProcess) -> Option<Self> {
+        if post_process.is_identity() {
+            return None;
+        }
+        Some(Self {
+            table: (!post_process.maps_identity()).then(|| post_process.table()),
+            saturation: post_process.saturation.clamp(0.0, MAX_SATURATION),
+        })
+    }
+
+    pub(crate) fn table(&self) -> Option<&[u8; 256]> {
+        self.table.as_ref()
+    }
+
+    /// Maps the rgb channels of rgba pixels, for frames converted elsewhere
+    pub(crate) fn apply(&self, rgba: &mut [u8]) {
+        if let Some(table) = &self.table {
+            map(table, rgba);
+        }
+    }
+
+    /// Maps luma samples, for [`ImageEncoding::Luma`](crate::ImageEncoding::Luma)
+    pub(crate) fn apply_luma(&self, luma: &mut [u8]) {
+        if let Some(table) = &self.table {
+            luma.iter_mut()
+                .for_each(|value| *value = table[*value as usize]);
+        }
+    }
+}
+
+/// Maps the rgb channels of the rgba pixels of `rgba` through `table`, alpha is kept
+pub(crate) fn map(table: &[u8; 256], rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        for value in &mut pixel[..3] {
+            *value = table[*value as usize];
+        }
+    }
+}
+
+/// Inputs whose post processing changed or that just opened
+type PostProcessChanged = Or<(Changed<PostProcess>, Added<Input>)>;
+
+/// Hands changed [`PostProcess`]es to the io tasks of their inputs, from their next
+/// frame on
+pub(crate) fn sync_post_process(
+    inputs: Query<(&Input, &PostProcess), PostProcessChanged>,
+    mut removed: RemovedComponents<PostProcess>,
+    all: Query<&Input>,
+) {
+    let set = |input: &Input, post_process: Option<&PostProcess>| {
+        if let Ok(mut io) = input.device.io.lock() {
+            io.post_process = post_process.and_then(Lut::new);
+        }
+    };
+    for (input, post_process) in inputs.iter() {
+        set(input, Some(post_process));
+    }
+    for entity in removed.read() {
+        if let Ok(input) = all.get(entity) {
+            set(input, None);
+        }
+    }
+}
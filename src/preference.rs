@@ -0,0 +1,151 @@
+use tracing::debug;
+use v4l::framesize::FrameSizeEnum;
+use v4l::video::Capture;
+
+use crate::{describe_format, Error, FourCC, Result};
+
+/// A capture format an [`Input`](crate::Input) accepts, see
+/// [`Input::new_with_preferences`](crate::Input::new_with_preferences)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FormatRequest {
+    pub fourcc: FourCC,
+    /// Smallest width and height accepted
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_size: Option<(u32, u32)>,
+    /// Largest width and height accepted, the largest size the device offers below it
+    /// is picked
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_size: Option<(u32, u32)>,
+}
+
+impl FormatRequest {
+    /// Any size of `fourcc`
+    pub fn new(fourcc: impl Into<FourCC>) -> Self {
+        Self {
+            fourcc: fourcc.into(),
+            min_size: None,
+            max_size: None,
+        }
+    }
+
+    pub fn min_size(mut self, width: u32, height: u32) -> Self {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    pub fn max_size(mut self, width: u32, height: u32) -> Self {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    fn accepts(&self, width: u32, height: u32) -> bool {
+        let (min_width, min_height) = self.min_size.unwrap_or((0, 0));
+        let (max_width, max_height) = self.max_size.unwrap_or((u32::MAX, u32::MAX));
+        (min_width..=max_width).contains(&width) && (min_height..=max_height).contains(&height)
+    }
+
+    /// Largest size within the bounds of the sizes a device offers
+    fn pick(&self, sizes: &[FrameSizeEnum]) -> Option<(u32, u32)> {
+        let (max_width, max_height) = self.max_size.unwrap_or((u32::MAX, u32::MAX));
+
+        sizes
+            .iter()
+            .map(|size| match size {
+                FrameSizeEnum::Discrete(size) => (size.width, size.height),
+                FrameSizeEnum::Stepwise(size) => (
+                    step_below(size.min_width, size.max_width, size.step_width, max_width),
+                    step_below(
+                        size.min_height,
+                        size.max_height,
+                        size.step_height,
+                        max_height,
+                    ),
+                ),
+            })
+            .filter(|&(width, height)| self.accepts(width, height))
+            .max_by_key(|&(width, height)| width as u64 * height as u64)
+    }
+}
+
+/// Largest of `min + n * step` up to `max` that is at most `limit`, `min` if none is
+fn step_below(min: u32, max: u32, step: u32, limit: u32) -> u32 {
+    let limit = max.min(limit);
+    if limit <= min {
+        return min;
+    }
+    min + (limit - min) / step.max(1) * step.max(1)
+}
+
+/// Sets the first of `requests` the device takes, returning its index and the
+/// granted format
+pub(crate) fn select(
+    dev: &v4l::Device,
+    requests: &[FormatRequest],
+) -> Result<(usize, v4l::Format)> {
+    let formats = dev.enum_formats()?;
+    let current = dev.format()?;
+
+    for (index, request) in requests.iter().enumerate() {
+        let fourcc = v4l::FourCC::new(request.fourcc.as_bytes());
+        if !formats.iter().any(|format| format.fourcc == fourcc) {
+            debug!("preferred format {} is not offered", request.fourcc);
+            continue;
+        }
+
+        // drivers that can't enumerate sizes get the bounds or the current size
+        let sizes: Vec<FrameSizeEnum> = dev
+            .enum_framesizes(fourcc)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|size| size.size)
+            .collect();
+        let (width, height) = match request.pick(&sizes) {
+            Some(size) => size,
+            None if sizes.is_empty() => request
+                .max_size
+                .or(request.min_size)
+                .unwrap_or((current.width, current.height)),
+            None => {
+                debug!(
+                    "no size of {} is within the preferred bounds",
+                    request.fourcc
+                );
+                continue;
+            }
+        };
+
+        let granted = Capture::set_format(dev, &v4l::Format::new(width, height, fourcc))?;
+        if granted.fourcc == fourcc && request.accepts(granted.width, granted.height) {
+            return Ok((index, granted));
+        }
+        debug!(
+            "preferred format {} {width}x{height} was adjusted to {}",
+            request.fourcc,
+            describe_format(&granted)
+        );
+    }
+
+    Err(Error::NoPreferredFormat {
+        offered: formats
+            .iter()
+            .map(|format| describe_offer(dev, format.fourcc))
+            .collect(),
+    })
+}
+
+/// A format with the sizes the device offers, like "YUYV 640x480 1280x720"
+fn describe_offer(dev: &v4l::Device, fourcc: v4l::FourCC) -> String {
+    let mut offer = FourCC::new(&fourcc.repr).to_string();
+    for size in dev.enum_framesizes(fourcc).unwrap_or_default() {
+        let size = match size.size {
+            FrameSizeEnum::Discrete(size) => format!(" {}x{}", size.width, size.height),
+            FrameSizeEnum::Stepwise(size) => format!(
+                " {}x{} to {}x{}",
+                size.min_width, size.min_height, size.max_width, size.max_height
+            ),
+        };
+        offer.push_str(&size);
+    }
+    offer
+}
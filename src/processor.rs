@@ -0,0 +1,36 @@
+use crate::{Error, FrameId, Result, Timestamp};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Callback run on every rgba frame, see [`InputBuilder::processor`](crate::InputBuilder::processor)
+/// and [`OutputBuilder::processor`](crate::OutputBuilder::processor).
+///
+/// Processors run on the async compute task pool, off the main thread, while the
+/// device is locked. Keep them fast, a slow processor lowers the frame rate.
+pub type FrameProcessor = Box<dyn Fn(&mut [u8], &FrameInfo) + Send + Sync>;
+
+/// Describes the rgba frame passed to a [`FrameProcessor`]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row
+    pub stride: u32,
+    /// Sequence reported by the driver, or counted by the crate on outputs
+    pub frame: FrameId,
+    /// Capture time reported by the driver, or the time the frame was written on outputs
+    pub timestamp: Timestamp,
+}
+
+/// Runs `processor` on `frame`.
+/// A panic is returned as an error instead of unwinding through the locked [`Io`](crate::Io).
+pub(crate) fn run(processor: &FrameProcessor, frame: &mut [u8], info: &FrameInfo) -> Result<()> {
+    panic::catch_unwind(AssertUnwindSafe(|| processor(frame, info))).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+
+        Error::ProcessorPanicked(message)
+    })
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use std::io;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension};
+use bevy::tasks::block_on;
+use tracing::{error, warn};
+use v4l::control::{Control, Value};
+use v4l::video::Capture;
+
+use crate::source::IoStream;
+use crate::{
+    describe_format, gpu, late, validate, Colorimetry, Decoder, Error, Format, Input, MemoryType,
+    Result, BUFFER_COUNT,
+};
+
+/// A capture format with the controls that go with it, like a "night" profile with
+/// a lower frame rate and longer exposure. See [`Input::switch_profile`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    pub format: Format,
+    /// Control ids and values, set in order after the format
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub controls: Vec<(u32, i64)>,
+}
+
+/// The part of a profile switch that failed, see [`ProfileError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileStep {
+    /// No profile of that name was added
+    Lookup,
+    /// Setting or validating the format
+    Format,
+    /// Setting one of the controls
+    Controls,
+    /// Allocating the stream for the new format
+    Stream,
+}
+
+#[derive(Debug)]
+pub struct ProfileError {
+    pub step: ProfileStep,
+    pub error: Error,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} step failed: {}", self.step, self.error)
+    }
+}
+
+/// Sent once a switch requested with [`Input::switch_profile`] is done
+#[derive(Event, Debug)]
+pub struct ProfileSwitched {
+    pub entity: Entity,
+    pub profile: String,
+    /// On errors after the stream was stopped the previous format is restored,
+    /// failing that the input reports errors until it is switched again
+    pub result: std::result::Result<(), ProfileError>,
+}
+
+/// Switches the profiles of inputs that requested it, before their next task is spawned
+pub(crate) fn switch_profiles(
+    mut inputs: Query<(Entity, &mut Input)>,
+    mut images: ResMut<Assets<Image>>,
+    mut switched: EventWriter<ProfileSwitched>,
+) {
+    for (entity, mut input) in inputs.iter_mut() {
+        let Some(profile) = input.pending_profile.take() else {
+            continue;
+        };
+
+        // the frame of an unfinished task is dropped with the stream
+        if let Some(task) = input.device.task.take() {
+            block_on(task);
+        }
+
+        let span = input.device.span.clone();
+        let result = span.in_scope(|| switch(&mut input, &profile, &mut images));
+        switched.send(ProfileSwitched {
+            entity,
+            profile,
+            result,
+        });
+    }
+}
+
+fn switch(
+    input: &mut Input,
+    name: &str,
+    images: &mut Assets<Image>,
+) -> std::result::Result<(), ProfileError> {
+    let fail = |step| move |error| ProfileError { step, error };
+
+    let profile = input
+        .profiles
+        .get(name)
+        .cloned()
+        .ok_or_else(|| fail(ProfileStep::Lookup)(Error::UnknownProfile(name.to_string())))?;
+
+    apply_or_restore(input, &profile, &format!("profile {name}"), images)
+}
+
+/// Sets the format of `input` right away, see [`Input::set_format`]
+pub(crate) fn set_format(
+    input: &mut Input,
+    format: Format,
+    images: &mut Assets<Image>,
+) -> Result<()> {
+    // the frame of an unfinished task is dropped with the stream
+    if let Some(task) = input.device.task.take() {
+        block_on(task);
+    }
+
+    let span = input.device.span.clone();
+    let _span = span.enter();
+    let previous = UVec2::new(input.device.size.width, input.device.size.height);
+    let profile = Profile {
+        format,
+        controls: Vec::new(),
+    };
+    let name = format!("the requested format {}", describe_format(&format.0));
+    apply_or_restore(input, &profile, &name, images).map_err(|err| err.error)?;
+    input.format_changed = Some(previous);
+    Ok(())
+}
+
+/// Like [`apply`], but sets the previous format up again when that fails, so the input
+/// keeps streaming
+fn apply_or_restore(
+    input: &mut Input,
+    profile: &Profile,
+    name: &str,
+    images: &mut Assets<Image>,
+) -> std::result::Result<(), ProfileError> {
+    let previous = input.device.format;
+    let result = apply(input, profile, name, images);
+    if let Err(err) = &result {
+        warn!(%err, "switching to {name} failed, restoring the previous format");
+        let restored = Profile {
+            format: Format(previous),
+            controls: Vec::new(),
+        };
+        if let Err(err) = apply(input, &restored, "the previous format", images) {
+            error!(%err, "restoring the previous format failed");
+        }
+    }
+    result
+}
+
+/// Replaces the stream of the input with one of the format of `profile`, reported as
+/// the format of `name`. The image is replaced under the same handle.
+pub(crate) fn apply(
+    input: &mut Input,
+    profile: &Profile,
+    name: &str,
+    images: &mut Assets<Image>,
+) -> std::result::Result<(), ProfileError> {
+    let fail = |step| move |error| ProfileError { step, error };
+    let unsupported = || Error::Io(io::ErrorKind::Unsupported.into());
+
+    let encoding = input.encoding;
+    let colorimetry = input.colorimetry;
+    let late_upload = input.late_upload;
+    let orientation = input.orientation;
+    let gpu = input.decoder == Decoder::Gpu;
+    let device = &mut input.device;
+    // virtual inputs have no format to set
+    let Some(dev) = &device.dev else {
+        return Err(fail(ProfileStep::Format)(unsupported()));
+    };
+    let Ok(mut io) = device.io.lock() else {
+        return Err(fail(ProfileStep::Format)(unsupported()));
+    };
+    // the m2m device would have to be renegotiated too
+    if io.m2m.is_some() {
+        return Err(fail(ProfileStep::Format)(unsupported()));
+    }
+
+    // the format can't change while buffers are allocated
+    io.stream = IoStream::Closed;
+
+    let requested = profile.format.0;
+    let granted = Capture::set_format(dev, &requested)
+        .map_err(Error::from)
+        .map_err(fail(ProfileStep::Format))?;
+    device
+        .report
+        .step(&format!("set format of {name}"), Some(&requested), &granted);
+    validate::format(&granted).map_err(fail(ProfileStep::Format))?;
+    if granted.fourcc != requested.fourcc {
+        return Err(fail(ProfileStep::Format)(Error::FormatRejected {
+            requested: describe_format(&requested),
+            granted: describe_format(&granted),
+        }));
+    }
+
+    for &(id, value) in &profile.controls {
+        dev.set_control(Control {
+            id,
+            value: Value::Integer(value),
+        })
+        .map_err(Error::from)
+        .map_err(fail(ProfileStep::Controls))?;
+    }
+
+    let memory = device.report.memory.unwrap_or(MemoryType::Mmap);
+    let count = device.report.buffers.unwrap_or(BUFFER_COUNT);
+    io.stream = memory
+        .capture_stream(dev, count)
+        .map_err(Error::from)
+        .map_err(fail(ProfileStep::Stream))?;
+
+    let (width, height) = orientation.size(granted.width, granted.height);
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let len = (size.width * size.height) as usize * encoding.bytes_per_pixel();
+    io.buffer = vec![255; len];
+    io.stride = granted.stride;
+    io.colorimetry = Colorimetry::resolve(&granted, colorimetry);
+    if let Some(deinterlace) = io.deinterlace.as_mut() {
+        deinterlace.order = granted.field_order;
+    }
+    // sized for the previous format, like regions the driver didn't crop
+    io.crop = None;
+    io.targets.clear();
+    io.fresh = false;
+    device.exchange.reset(len);
+
+    let image = Image::new(
+        size,
+        TextureDimension::D2,
+        vec![255; len],
+        encoding.texture_format(),
+        late::image_usage(late_upload),
+    );
+    images.insert(&device.image, gpu::render_target(image, gpu));
+
+    device.format = granted;
+    device.size = size;
+    if input.crop.is_some_and(|crop| !crop.driver) {
+        input.crop = None;
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use crate::{FrameId, Timestamp};
+
+/// Buffers kept for reuse once every frame holding them is dropped
+const POOL_SIZE: usize = 8;
+
+/// How an [`Input`](crate::Input) delivers the bytes it dequeues as [`RawFrame`] events,
+/// see [`InputBuilder::raw_frames`](crate::InputBuilder::raw_frames)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFrames {
+    /// Send raw frames and keep updating the image
+    Alongside,
+    /// Only send raw frames, frames are neither converted nor copied into the image
+    Only,
+}
+
+/// A frame exactly as it was dequeued from the driver, like an MJPEG or H.264 packet
+#[derive(Event, Debug, Clone)]
+pub struct RawFrame {
+    pub entity: Entity,
+    pub fourcc: [u8; 4],
+    /// The used bytes of the buffer, `bytes_used` long
+    pub data: Arc<[u8]>,
+    pub bytes_used: u32,
+    pub frame: FrameId,
+    /// Capture time reported by the driver
+    pub timestamp: Timestamp,
+}
+
+/// Copies dequeued buffers into pooled allocations until the plugin sends them
+pub(crate) struct RawSink {
+    pub(crate) mode: RawFrames,
+    pool: BufferPool,
+    /// Frame of the last task, waiting to be sent
+    pub(crate) frame: Option<RawFrame>,
+}
+
+impl RawSink {
+    pub(crate) fn new(mode: RawFrames) -> Self {
+        Self {
+            mode,
+            pool: BufferPool::default(),
+            frame: None,
+        }
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        fourcc: [u8; 4],
+        buf: &[u8],
+        frame: FrameId,
+        timestamp: Timestamp,
+    ) {
+        self.frame = Some(RawFrame {
+            // filled in by the plugin when the event is sent
+            entity: Entity::PLACEHOLDER,
+            fourcc,
+            data: self.pool.copy(buf),
+            bytes_used: buf.len() as u32,
+            frame,
+            timestamp,
+        });
+    }
+}
+
+/// Copies of dequeued buffers, handed out as `Arc`s so the driver buffer can be
+/// requeued right away
+#[derive(Default)]
+pub(crate) struct BufferPool(Vec<Arc<[u8]>>);
+
+impl BufferPool {
+    /// Reuses a pooled buffer of the same length that no frame holds anymore
+    pub(crate) fn copy(&mut self, buf: &[u8]) -> Arc<[u8]> {
+        let unused = self
+            .0
+            .iter()
+            .position(|data| data.len() == buf.len() && Arc::strong_count(data) == 1);
+
+        match unused {
+            Some(index) => {
+                let data = &mut self.0[index];
+                Arc::get_mut(data)
+                    .expect("pooled buffer is unused")
+                    .copy_from_slice(buf);
+                data.clone()
+            }
+            None => {
+                // compressed frames vary in size, drop the oldest buffer to make room
+                if self.0.len() == POOL_SIZE {
+                    self.0.remove(0);
+                }
+
+                let data: Arc<[u8]> = Arc::from(buf);
+                self.0.push(data.clone());
+                data
+            }
+        }
+    }
+}
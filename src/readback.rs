@@ -0,0 +1,255 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use bevy::asset::AssetId;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+    ImageDataLayout, Maintain, MapMode, TextureFormat, TextureUsages,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::Extract;
+use bevy::utils::HashMap;
+use tracing::warn;
+
+use crate::exchange::Exchange;
+use crate::{Output, Timestamp};
+
+/// wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, rows copied into a buffer start at multiples of it
+const ROW_ALIGNMENT: u32 = 256;
+
+/// States of a staging buffer, set by its map callback
+const MAPPING: u8 = 0;
+const MAPPED: u8 = 1;
+const FAILED: u8 = 2;
+
+/// Outputs that read their image back from the gpu, by their image,
+/// see [`OutputBuilder::gpu_readback`](crate::OutputBuilder::gpu_readback)
+#[derive(Resource, Default)]
+pub(crate) struct Readbacks(HashMap<AssetId<Image>, Readback>);
+
+/// Staging buffers the texture of an output is copied into on alternate frames, one is
+/// read while the other is copied into, so neither the gpu nor the app waits on the map
+struct Readback {
+    /// The exchange of the output, frames read back are published into it
+    exchange: Arc<Exchange>,
+    slots: Vec<Slot>,
+    /// Slot copied into first, frames are read in the order they were copied
+    oldest: usize,
+    size: UVec2,
+    bgra: bool,
+    /// Frame without the row padding, swapped into the exchange
+    frame: Vec<u8>,
+    /// The texture can't be read back, warned once
+    warned: bool,
+}
+
+struct Slot {
+    buffer: Buffer,
+    state: Arc<AtomicU8>,
+    /// A copy into the buffer was submitted and not read yet
+    busy: bool,
+}
+
+impl Readback {
+    fn new(exchange: Arc<Exchange>) -> Self {
+        Self {
+            exchange,
+            slots: Vec::new(),
+            oldest: 0,
+            size: UVec2::ZERO,
+            bgra: false,
+            frame: Vec::new(),
+            warned: false,
+        }
+    }
+
+    /// Buffers for textures of `size`, copies into the previous ones are dropped
+    fn resize(&mut self, render_device: &RenderDevice, size: UVec2, bgra: bool) {
+        let len = padded(size.x) as u64 * size.y as u64;
+        self.slots = (0..2)
+            .map(|_| Slot {
+                buffer: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("v4l_output_readback"),
+                    size: len,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                state: Default::default(),
+                busy: false,
+            })
+            .collect();
+        self.oldest = 0;
+        self.size = size;
+        self.bgra = bgra;
+    }
+
+    /// Publishes the frames of the buffers mapped since the last frame, oldest first
+    fn receive(&mut self) {
+        for _ in 0..self.slots.len() {
+            let slot = &mut self.slots[self.oldest];
+            if !slot.busy {
+                break;
+            }
+            match slot.state.load(Ordering::Acquire) {
+                MAPPING => break,
+                MAPPED => {
+                    unpad(
+                        &slot.buffer.slice(..).get_mapped_range(),
+                        self.size,
+                        self.bgra,
+                        &mut self.frame,
+                    );
+                    slot.buffer.unmap();
+                    self.exchange
+                        .publish(&mut self.frame, Instant::now(), Timestamp::now());
+                }
+                // the device was lost, the frame is dropped
+                _ => {}
+            }
+            slot.busy = false;
+            self.oldest = (self.oldest + 1) % self.slots.len();
+        }
+    }
+
+    /// The slot to copy the next frame into, `None` while both wait for their copies
+    fn free(&self) -> Option<usize> {
+        let len = self.slots.len();
+        (0..len)
+            .map(|offset| (self.oldest + offset) % len)
+            .find(|&index| !self.slots[index].busy)
+    }
+}
+
+/// Tracks the outputs that read their image back
+pub(crate) fn extract_outputs(outputs: Extract<Query<&Output>>, mut readbacks: ResMut<Readbacks>) {
+    let outputs = || outputs.iter().filter(|output| output.2.readback);
+    readbacks
+        .0
+        .retain(|id, _| outputs().any(|output| output.0.image.id() == *id));
+
+    for output in outputs() {
+        let device = &output.0;
+        readbacks
+            .0
+            .entry(device.image.id())
+            .or_insert_with(|| Readback::new(device.exchange.clone()));
+    }
+}
+
+/// Reads the buffers copied into on earlier frames that were mapped since, and copies
+/// the textures of the outputs into free ones. Runs once the frame rendered into them.
+pub(crate) fn read_back_images(
+    mut readbacks: ResMut<Readbacks>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    if readbacks.0.is_empty() {
+        return;
+    }
+    // runs the callbacks of the buffers whose copies finished
+    render_device.poll(Maintain::Poll);
+
+    let mut encoder = None;
+    let mut copied = Vec::new();
+    for (id, readback) in readbacks.0.iter_mut() {
+        readback.receive();
+        let Some(gpu_image) = gpu_images.get(*id) else {
+            continue;
+        };
+        let bgra = match gpu_image.texture_format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            _ => {
+                warn_once(readback, "an 8 bit rgba or bgra format");
+                continue;
+            }
+        };
+        if !gpu_image.texture.usage().contains(TextureUsages::COPY_SRC) {
+            warn_once(readback, "TextureUsages::COPY_SRC");
+            continue;
+        }
+
+        let size = gpu_image.size.as_uvec2();
+        if readback.slots.is_empty() || (size, bgra) != (readback.size, readback.bgra) {
+            readback.resize(&render_device, size, bgra);
+        }
+        // the gpu is two frames behind, this one isn't read back
+        let Some(index) = readback.free() else {
+            continue;
+        };
+        let slot = &mut readback.slots[index];
+        let encoder = encoder.get_or_insert_with(|| {
+            render_device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("v4l_output_readback"),
+            })
+        });
+        encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &slot.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded(size.x)),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+        slot.busy = true;
+        copied.push((*id, index));
+    }
+
+    let Some(encoder) = encoder else {
+        return;
+    };
+    queue.submit([encoder.finish()]);
+    // buffers can only be mapped once the copies into them were submitted
+    for (id, index) in copied {
+        let slot = &readbacks.0[&id].slots[index];
+        let state = slot.state.clone();
+        state.store(MAPPING, Ordering::Release);
+        slot.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                let mapped = match result {
+                    Ok(()) => MAPPED,
+                    Err(_) => FAILED,
+                };
+                state.store(mapped, Ordering::Release);
+            });
+    }
+}
+
+fn warn_once(readback: &mut Readback, needs: &str) {
+    if !readback.warned {
+        warn!("v4l output can't read its image back from the gpu, it needs {needs}");
+        readback.warned = true;
+    }
+}
+
+/// Bytes of a row of rgba pixels in a buffer copied into
+fn padded(width: u32) -> u32 {
+    (width * 4).next_multiple_of(ROW_ALIGNMENT)
+}
+
+/// Copies the rows of a mapped buffer without their padding, as rgba
+fn unpad(mapped: &[u8], size: UVec2, bgra: bool, frame: &mut Vec<u8>) {
+    let row = size.x as usize * 4;
+    frame.clear();
+    for padded in mapped.chunks(padded(size.x) as usize).take(size.y as usize) {
+        frame.extend_from_slice(&padded[..row]);
+    }
+    if bgra {
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+}
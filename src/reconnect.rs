@@ -0,0 +1,205 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::futures;
+use tracing::{info, warn};
+use v4l::video::Capture;
+
+use crate::memory::Buffers;
+use crate::source::{IoStream, ENODEV};
+use crate::source_change::SourceChange;
+use crate::{describe_format, Error, Input};
+
+/// How an [`Input`] whose device disappeared is reopened, see
+/// [`InputBuilder::reconnect`](crate::InputBuilder::reconnect)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Time between attempts to reopen the device
+    pub interval: Duration,
+    /// Attempts before giving up, `None` keeps trying
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Sent when the device of an [`Input`] disappeared, like when its cable was pulled.
+/// The input stops reading it and its image keeps the last frame.
+#[derive(Event, Debug)]
+pub struct DeviceLost {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    pub error: Error,
+}
+
+/// Sent when an [`Input`] reopened its device after a [`DeviceLost`], frames stream into
+/// the same image again
+#[derive(Event, Debug)]
+pub struct DeviceReconnected {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Attempts it took, starting at 1
+    pub attempts: u32,
+}
+
+/// Whether the device of an input is there, and how it gets reopened when it isn't
+pub(crate) struct Connection {
+    policy: Option<ReconnectPolicy>,
+    /// Buffers and timeout of the stream of the reopened device
+    buffers: Buffers,
+    timeout: Option<Duration>,
+    pub(crate) lost: bool,
+    attempts: u32,
+    next_attempt: Instant,
+    task: Option<Task<io::Result<(v4l::Device, IoStream)>>>,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        policy: Option<ReconnectPolicy>,
+        buffers: Buffers,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            policy,
+            buffers,
+            timeout,
+            lost: false,
+            attempts: 0,
+            next_attempt: Instant::now(),
+            task: None,
+        }
+    }
+
+    /// Marks the device as gone, no tasks are spawned for it until it is reopened
+    pub(crate) fn lose(&mut self) {
+        self.lost = true;
+        self.attempts = 0;
+        self.next_attempt = Instant::now() + self.policy.map_or(Duration::ZERO, |p| p.interval);
+    }
+}
+
+/// Whether an error of a stream means its device is gone, unplugged devices fail with
+/// ENODEV or leave EIO behind once their node is removed
+pub(crate) fn is_lost(err: &Error, path: Option<&Path>) -> bool {
+    let Some(err) = err.io_error() else {
+        return false;
+    };
+    err.raw_os_error() == Some(ENODEV) || path.is_some_and(|path| !path.exists())
+}
+
+/// Reopens the devices of inputs that lost theirs, in the format they streamed
+pub(crate) fn reconnect_inputs(
+    mut inputs: Query<(Entity, &mut Input)>,
+    mut reconnected: EventWriter<DeviceReconnected>,
+) {
+    let now = Instant::now();
+
+    for (entity, mut input) in inputs.iter_mut() {
+        let Input {
+            device, connection, ..
+        } = &mut *input;
+        let Some(policy) = connection
+            .policy
+            .filter(|_| connection.lost && !device.closed)
+        else {
+            continue;
+        };
+
+        let Some(task) = connection.task.as_mut() else {
+            let Some(path) = device.path.clone() else {
+                continue;
+            };
+            let gave_up = policy
+                .max_attempts
+                .is_some_and(|max| connection.attempts >= max);
+            if now < connection.next_attempt || gave_up {
+                continue;
+            }
+
+            connection.attempts += 1;
+            connection.next_attempt = now + policy.interval;
+            let (format, buffers, timeout) =
+                (device.format, connection.buffers, connection.timeout);
+            connection.task = Some(
+                AsyncComputeTaskPool::get()
+                    .spawn(async move { reopen(path, &format, buffers, timeout) }),
+            );
+            continue;
+        };
+
+        let Some(result) = futures::check_ready(task) else {
+            continue;
+        };
+        connection.task = None;
+
+        let _span = device.span.enter();
+        match result {
+            Ok((dev, stream)) => {
+                if let Ok(mut io) = device.io.lock() {
+                    io.stream = stream;
+                    io.restarts = 0;
+                    io.counts = Default::default();
+                    // events are subscribed per open file
+                    io.source_change = SourceChange::subscribe(&dev);
+                }
+                device.dev = Some(dev);
+                device.health.reset();
+                connection.lost = false;
+                info!(attempts = connection.attempts, "reconnected v4l device");
+                reconnected.send(DeviceReconnected {
+                    entity,
+                    device: device.id,
+                    label: device.label().to_string(),
+                    attempts: connection.attempts,
+                });
+            }
+            Err(err) => {
+                warn!(attempt = connection.attempts, %err, "reconnecting v4l device failed");
+            }
+        }
+    }
+}
+
+/// Opens the device at `path` again with new buffers, failing when the driver doesn't
+/// take the format the input streamed, since the image is sized for it
+fn reopen(
+    path: PathBuf,
+    format: &v4l::Format,
+    buffers: Buffers,
+    timeout: Option<Duration>,
+) -> io::Result<(v4l::Device, IoStream)> {
+    let dev = v4l::Device::with_path(&path)?;
+    let granted = Capture::set_format(&dev, format)?;
+    if (granted.width, granted.height, granted.fourcc)
+        != (format.width, format.height, format.fourcc)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "device came back with {} instead of {}",
+                describe_format(&granted),
+                describe_format(format)
+            ),
+        ));
+    }
+
+    let mut stream = buffers.memory.capture_stream(&dev, buffers.count)?;
+    stream.set_timeout(timeout);
+    Ok((dev, stream))
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use crate::{describe_format, Decoder, MemoryType, PixelAspect};
+
+/// What was asked of a device while setting it up and what it granted,
+/// for finding out why a device ended up with an unexpected format.
+///
+/// `Display` prints a multi-line summary.
+#[derive(Debug, Clone, Default)]
+pub struct NegotiationReport {
+    /// Device that was negotiated with
+    pub device: String,
+    /// Pixel formats the device offered
+    pub offered: Vec<String>,
+    pub steps: Vec<NegotiationStep>,
+    /// Fallbacks and conversions that were chosen along the way
+    pub notes: Vec<String>,
+    /// How stream buffers are shared with the device, `None` for virtual inputs
+    pub memory: Option<MemoryType>,
+    /// Stream buffers the driver allocated, `None` for virtual inputs
+    pub buffers: Option<u32>,
+    /// Reported by capture devices that implement VIDIOC_CROPCAP, square otherwise
+    pub pixel_aspect: PixelAspect,
+    /// Where the frames of an input are converted, `None` for outputs
+    pub decoder: Option<Decoder>,
+}
+
+/// A format that was read from, or set on, a device
+#[derive(Debug, Clone)]
+pub struct NegotiationStep {
+    /// What was done, like "set output format"
+    pub action: String,
+    pub requested: Option<String>,
+    pub granted: String,
+}
+
+impl NegotiationReport {
+    pub(crate) fn new(device: impl fmt::Display) -> Self {
+        Self {
+            device: device.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn offered<'a>(&mut self, fourccs: impl IntoIterator<Item = &'a v4l::FourCC>) {
+        self.offered = fourccs.into_iter().map(ToString::to_string).collect();
+    }
+
+    pub(crate) fn step(
+        &mut self,
+        action: &str,
+        requested: Option<&v4l::Format>,
+        granted: &v4l::Format,
+    ) {
+        self.steps.push(NegotiationStep {
+            action: action.to_string(),
+            requested: requested.map(describe_format),
+            granted: describe_format(granted),
+        });
+    }
+
+    pub(crate) fn note(&mut self, note: impl Into<String>) {
+        self.notes.push(note.into());
+    }
+
+    /// Format granted by the last step
+    pub fn granted(&self) -> Option<&str> {
+        self.steps.last().map(|step| step.granted.as_str())
+    }
+}
+
+impl fmt::Display for NegotiationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "negotiation with {}", self.device)?;
+
+        if !self.offered.is_empty() {
+            writeln!(f, "  offered: {}", self.offered.join(", "))?;
+        }
+
+        for step in &self.steps {
+            match &step.requested {
+                Some(requested) => writeln!(
+                    f,
+                    "  {}: requested {requested}, granted {}",
+                    step.action, step.granted
+                )?,
+                None => writeln!(f, "  {}: {}", step.action, step.granted)?,
+            }
+        }
+
+        if let Some(memory) = self.memory {
+            writeln!(f, "  memory: {memory}")?;
+        }
+        if let Some(buffers) = self.buffers {
+            writeln!(f, "  buffers: {buffers}")?;
+        }
+        match self.decoder {
+            Some(Decoder::Cpu) => writeln!(f, "  decoder: cpu")?,
+            Some(Decoder::M2m { id }) => writeln!(f, "  decoder: m2m device {id}")?,
+            Some(Decoder::Gpu) => writeln!(f, "  decoder: gpu")?,
+            None => {}
+        }
+        if self.pixel_aspect != PixelAspect::SQUARE {
+            writeln!(f, "  pixel aspect: {}", self.pixel_aspect)?;
+        }
+
+        for note in &self.notes {
+            writeln!(f, "  {note}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use bevy::render::color::Color;
+
+use crate::{Error, Orientation, Result};
+
+/// What an [`Output`](crate::Output) does when its image and the device differ in size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SizePolicy {
+    /// Don't write the frame and send a [`V4lError`](crate::V4lError)
+    #[default]
+    Error,
+    /// Scale to the size of the device with the [`ScaleFilter`] of the output, ignoring
+    /// the aspect ratio
+    Stretch,
+    /// Scale preserving the aspect ratio and center, filling the bars above and below
+    /// (letterbox) or left and right (pillarbox) of the image with `border`
+    Letterbox {
+        /// rgba, alpha is ignored by most formats
+        border: [u8; 4],
+    },
+}
+
+/// How an [`Output`](crate::Output) samples its image when the [`SizePolicy`] scales it,
+/// see [`OutputBuilder::scale_filter`](crate::OutputBuilder::scale_filter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScaleFilter {
+    /// The closest pixel, looked up while the frame is converted. Blocky scaled up and
+    /// aliased scaled down a lot.
+    #[default]
+    Nearest,
+    /// The four closest pixels blended, scaled into a frame of the size of the device
+    /// before it is converted
+    Bilinear,
+}
+
+/// What an [`Output`](crate::Output) does with the alpha of its image, see
+/// [`OutputBuilder::alpha_mode`](crate::OutputBuilder::alpha_mode)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    /// Writes the colors as they are, formats with alpha get opaque pixels
+    #[default]
+    Ignore,
+    /// Composites the pixels over a solid color, like a scene rendered over a
+    /// transparent clear color shown over black instead of with bright fringes
+    PremultiplyOverColor(Color),
+    /// Writes the colors as they are, formats with alpha, like AB24, keep it
+    Straight,
+}
+
+/// An rgba image as seen through a [`SizePolicy`] and an [`AlphaMode`], for encoding
+/// frames of a different size than the image. Every pixel is looked up while encoding,
+/// so neither scaling nor compositing take a pass over the frame of their own.
+pub(crate) struct ScaledFrame<'a> {
+    src: &'a [u8],
+    offsets: Offsets,
+    border: [u8; 4],
+    alpha: Alpha,
+}
+
+/// Where the pixels of a [`ScaledFrame`] are looked up. Outputs keep them between frames,
+/// so frames of the same size don't allocate them again.
+#[derive(Default)]
+pub(crate) struct Offsets {
+    /// Offset in `src` of the row shown on every row of the frame, `None` in a border
+    rows: Vec<Option<usize>>,
+    /// Offset in a row of `src` of the pixel shown in every column, `None` in a border
+    columns: Vec<Option<usize>>,
+}
+
+/// [`AlphaMode`] with the background as the bytes it is composited with
+#[derive(Clone, Copy)]
+enum Alpha {
+    Opaque,
+    Over([u32; 3]),
+    Straight,
+}
+
+impl<'a> ScaledFrame<'a> {
+    /// Fits the `src` image of `src_size` into a frame of `size`, keeping its alpha
+    pub(crate) fn new(
+        src: &'a [u8],
+        src_size: (u32, u32),
+        size: (u32, u32),
+        policy: SizePolicy,
+    ) -> Result<Self> {
+        Self::turned(src, src_size, Orientation::None, size, policy)
+    }
+
+    /// Like [`ScaledFrame::new`], with the image turned by `orientation` before it is
+    /// fitted. Turning only changes which pixel is looked up, like scaling.
+    pub(crate) fn turned(
+        src: &'a [u8],
+        src_size: (u32, u32),
+        orientation: Orientation,
+        size: (u32, u32),
+        policy: SizePolicy,
+    ) -> Result<Self> {
+        Self::reusing(Offsets::default(), src, src_size, orientation, size, policy)
+    }
+
+    /// Like [`ScaledFrame::turned`], looking pixels up in `offsets` of a previous frame,
+    /// which [`ScaledFrame::into_offsets`] gives back
+    pub(crate) fn reusing(
+        mut offsets: Offsets,
+        src: &'a [u8],
+        src_size: (u32, u32),
+        orientation: Orientation,
+        size: (u32, u32),
+        policy: SizePolicy,
+    ) -> Result<Self> {
+        let image = (src_size.0 as usize, src_size.1 as usize);
+        let src_size = orientation.size(src_size.0, src_size.1);
+        let (src_width, src_height) = (src_size.0 as usize, src_size.1 as usize);
+        let (width, height) = (size.0 as usize, size.1 as usize);
+        let (content, border) = fit(src_size, size, policy)?;
+
+        let stride = image.0 * 4;
+        fill(&mut offsets.rows, height, content.1, src_height, |y| {
+            orientation.row_offset(y, image, stride)
+        });
+        fill(&mut offsets.columns, width, content.0, src_width, |x| {
+            orientation.column_offset(x, image, stride)
+        });
+        Ok(Self {
+            src,
+            offsets,
+            border,
+            alpha: Alpha::Straight,
+        })
+    }
+
+    /// The offsets of the frame, for the next one
+    pub(crate) fn into_offsets(self) -> Offsets {
+        self.offsets
+    }
+
+    /// Handles the alpha of the pixels with `mode` as they are looked up
+    pub(crate) fn with_alpha(mut self, mode: AlphaMode) -> Self {
+        self.alpha = match mode {
+            AlphaMode::Ignore => Alpha::Opaque,
+            AlphaMode::PremultiplyOverColor(color) => {
+                let [r, g, b, _] = color.as_rgba_u8();
+                Alpha::Over([r, g, b].map(u32::from))
+            }
+            AlphaMode::Straight => Alpha::Straight,
+        };
+        self
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.offsets.columns.len()
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.offsets.rows.len()
+    }
+
+    /// rgba of the pixel in column `x` of `row`, see [`ScaledFrame::row`]
+    pub(crate) fn pixel(&self, row: Option<usize>, x: usize) -> [u8; 4] {
+        let offset = row
+            .zip(self.offsets.columns[x])
+            .map(|(row, column)| row + column);
+        let rgba = offset
+            .and_then(|offset| self.src.get(offset..offset + 4))
+            .unwrap_or(&self.border);
+        let [r, g, b, a] = [rgba[0], rgba[1], rgba[2], rgba[3]];
+
+        match self.alpha {
+            Alpha::Opaque => [r, g, b, 255],
+            Alpha::Over(background) => {
+                let a = a as u32;
+                let over = |value: u8, background: u32| {
+                    ((value as u32 * a + background * (255 - a) + 127) / 255) as u8
+                };
+                [
+                    over(r, background[0]),
+                    over(g, background[1]),
+                    over(b, background[2]),
+                    255,
+                ]
+            }
+            Alpha::Straight => [r, g, b, a],
+        }
+    }
+
+    /// Looks up row `y` once for the [`ScaledFrame::pixel`]s in it
+    pub(crate) fn row(&self, y: usize) -> Option<usize> {
+        self.offsets.rows[y]
+    }
+}
+
+/// Size of the part of a frame of `size` showing an image of `src_size`, and the color
+/// of the rest
+fn fit(
+    src_size: (u32, u32),
+    size: (u32, u32),
+    policy: SizePolicy,
+) -> Result<((usize, usize), [u8; 4])> {
+    let (src_width, src_height) = (src_size.0 as usize, src_size.1 as usize);
+    let (width, height) = (size.0 as usize, size.1 as usize);
+
+    Ok(match policy {
+        _ if src_size == size => ((width, height), [0; 4]),
+        SizePolicy::Error => {
+            return Err(Error::SizeMismatch {
+                image: src_size,
+                device: size,
+            })
+        }
+        SizePolicy::Stretch => ((width, height), [0; 4]),
+        // the smaller of the two scales, without floats
+        SizePolicy::Letterbox { border } if width * src_height <= height * src_width => {
+            ((width, src_height * width / src_width.max(1)), border)
+        }
+        SizePolicy::Letterbox { border } => {
+            ((src_width * height / src_height.max(1), height), border)
+        }
+    })
+}
+
+/// Fills `offsets` with the nearest neighbor offsets for `len` pixels showing `src_len`
+/// pixels scaled to `content` pixels and centered, `offset` gives the offset of a source
+/// pixel
+fn fill(
+    offsets: &mut Vec<Option<usize>>,
+    len: usize,
+    content: usize,
+    src_len: usize,
+    offset: impl Fn(usize) -> usize,
+) {
+    let start = (len - content.min(len)) / 2;
+
+    offsets.clear();
+    offsets.extend((0..len).map(|i| {
+        let i = i.checked_sub(start).filter(|&i| i < content)?;
+        Some(offset(i * src_len / content))
+    }));
+}
+
+/// Frame of the size of the device the image of an output is scaled into with
+/// [`ScaleFilter::Bilinear`], allocated once for every frame written
+pub(crate) struct Resampler {
+    frame: Vec<u8>,
+}
+
+/// The two pixels a pixel is blended from along a row or column, and the weight of the
+/// second in 1/256
+#[derive(Clone, Copy)]
+struct Tap {
+    offsets: [usize; 2],
+    weight: u32,
+}
+
+impl Resampler {
+    pub(crate) fn new(size: (u32, u32)) -> Self {
+        Self {
+            frame: vec![255; (size.0 * size.1 * 4) as usize],
+        }
+    }
+
+    /// Scales the rgba `src` of `src_size`, turned by `orientation`, into a frame of
+    /// `size` like [`ScaledFrame::turned`] looks it up. `None` when the turned image
+    /// already has the size, it is written as it is.
+    pub(crate) fn resample(
+        &mut self,
+        src: &[u8],
+        src_size: (u32, u32),
+        orientation: Orientation,
+        size: (u32, u32),
+        policy: SizePolicy,
+    ) -> Result<Option<&[u8]>> {
+        let image = (src_size.0 as usize, src_size.1 as usize);
+        let src_size = orientation.size(src_size.0, src_size.1);
+        if src_size == size {
+            return Ok(None);
+        }
+        let (content, border) = fit(src_size, size, policy)?;
+        let (width, height) = (size.0 as usize, size.1 as usize);
+
+        let stride = image.0 * 4;
+        let rows = taps(height, content.1, src_size.1 as usize, |y| {
+            orientation.row_offset(y, image, stride)
+        });
+        let columns = taps(width, content.0, src_size.0 as usize, |x| {
+            orientation.column_offset(x, image, stride)
+        });
+
+        self.frame.resize(width * height * 4, 255);
+        for (row, dst) in rows.iter().zip(self.frame.chunks_exact_mut(width * 4)) {
+            for (column, dst) in columns.iter().zip(dst.chunks_exact_mut(4)) {
+                let rgba = row
+                    .zip(*column)
+                    .and_then(|(row, column)| blend(src, row, column));
+                dst.copy_from_slice(&rgba.unwrap_or(border));
+            }
+        }
+        Ok(Some(&self.frame))
+    }
+}
+
+/// Like [`offsets`], with the two pixels around the center of every pixel
+fn taps(
+    len: usize,
+    content: usize,
+    src_len: usize,
+    offset: impl Fn(usize) -> usize,
+) -> Vec<Option<Tap>> {
+    let start = (len - content.min(len)) / 2;
+
+    (0..len)
+        .map(|i| {
+            let i = i
+                .checked_sub(start)
+                .filter(|&i| i < content && src_len > 0)?;
+            // the centers of the first and last pixels line up, in 1/256 of a pixel of src
+            let position = ((2 * i + 1) * src_len * 256 / (2 * content)).saturating_sub(128);
+            let first = (position / 256).min(src_len - 1);
+            Some(Tap {
+                offsets: [offset(first), offset((first + 1).min(src_len - 1))],
+                weight: (position % 256) as u32,
+            })
+        })
+        .collect()
+}
+
+/// The four pixels of `src` at the taps of `row` and `column` blended, `None` past the
+/// end of `src`
+fn blend(src: &[u8], row: Tap, column: Tap) -> Option<[u8; 4]> {
+    let pixel = |y: usize, x: usize| {
+        let offset = row.offsets[y] + column.offsets[x];
+        src.get(offset..offset + 4)
+    };
+    let (top, bottom) = ((pixel(0, 0)?, pixel(0, 1)?), (pixel(1, 0)?, pixel(1, 1)?));
+    let lerp = |a: u32, b: u32, weight: u32| a * (256 - weight) + b * weight;
+
+    let mut rgba = [0; 4];
+    for (i, value) in rgba.iter_mut().enumerate() {
+        let top = lerp(top.0[i] as u32, top.1[i] as u32, column.weight);
+        let bottom = lerp(bottom.0[i] as u32, bottom.1[i] as u32, column.weight);
+        *value = ((lerp(top, bottom, row.weight) + (1 << 15)) >> 16) as u8;
+    }
+    Some(rgba)
+}
+
+/// Smaller copy of every captured frame, see
+/// [`InputBuilder::preview`](crate::InputBuilder::preview)
+pub(crate) struct Preview {
+    pub(crate) size: (u32, u32),
+    /// rgba, swapped with the preview image like the frame buffer
+    pub(crate) buffer: Vec<u8>,
+}
+
+impl Preview {
+    pub(crate) fn new(size: (u32, u32)) -> Self {
+        Self {
+            size,
+            buffer: vec![255; (size.0 * size.1 * 4) as usize],
+        }
+    }
+
+    /// Scales the rgba frame `src` of `src_size` into the buffer, keeping its aspect ratio
+    pub(crate) fn update(&mut self, src: &[u8], src_size: (u32, u32)) {
+        let policy = SizePolicy::Letterbox {
+            border: [0, 0, 0, 255],
+        };
+        if let Ok(frame) = ScaledFrame::new(src, src_size, self.size, policy) {
+            crate::encode::encode_rgba(&frame, &mut self.buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+    /// Every pixel of `src` of `src_size` looked up in a frame of `size`
+    fn scaled(
+        src: &[[u8; 4]],
+        src_size: (u32, u32),
+        size: (u32, u32),
+        policy: SizePolicy,
+    ) -> Vec<[u8; 4]> {
+        let src = src.concat();
+        let frame = ScaledFrame::new(&src, src_size, size, policy).unwrap();
+        (0..frame.height())
+            .flat_map(|y| {
+                let row = frame.row(y);
+                (0..frame.width()).map(move |x| (row, x))
+            })
+            .map(|(row, x)| frame.pixel(row, x))
+            .collect()
+    }
+
+    #[test]
+    fn error_rejects_other_sizes() {
+        let src = [RED, BLUE].concat();
+        let err = ScaledFrame::new(&src, (2, 1), (4, 2), SizePolicy::Error).err();
+        assert!(matches!(
+            err,
+            Some(Error::SizeMismatch {
+                image: (2, 1),
+                device: (4, 2)
+            })
+        ));
+        let mut resampler = Resampler::new((4, 2));
+        let resampled =
+            resampler.resample(&src, (2, 1), Orientation::None, (4, 2), SizePolicy::Error);
+        assert!(resampled.is_err());
+
+        // frames of the size of the image are written as they are
+        assert_eq!(
+            scaled(&[RED, BLUE], (2, 1), (2, 1), SizePolicy::Error),
+            [RED, BLUE]
+        );
+    }
+
+    #[test]
+    fn stretch_fills_the_frame() {
+        let frame = scaled(&[RED, BLUE], (2, 1), (4, 2), SizePolicy::Stretch);
+        assert_eq!(frame, [RED, RED, BLUE, BLUE, RED, RED, BLUE, BLUE]);
+
+        // the centers of the outer pixels line up, the ones between are blended
+        let src = [RED, BLUE].concat();
+        let mut resampler = Resampler::new((4, 1));
+        let resampled = resampler
+            .resample(&src, (2, 1), Orientation::None, (4, 1), SizePolicy::Stretch)
+            .unwrap()
+            .unwrap();
+        let blended: Vec<_> = resampled.chunks_exact(4).collect();
+        assert_eq!(
+            blended,
+            [&RED[..], &[191, 0, 64, 255], &[64, 0, 191, 255], &BLUE]
+        );
+    }
+
+    #[test]
+    fn letterbox_centers_the_image_between_borders() {
+        let policy = SizePolicy::Letterbox { border: GREEN };
+        // bars above and below a wide image
+        let frame = scaled(&[RED, BLUE], (2, 1), (4, 4), policy);
+        let bar = [GREEN; 4];
+        let row = [RED, RED, BLUE, BLUE];
+        assert_eq!(frame, [bar, row, row, bar].concat());
+
+        // bars left and right of a tall image
+        let frame = scaled(&[RED, BLUE], (1, 2), (4, 2), policy);
+        assert_eq!(
+            frame,
+            [[GREEN, RED, GREEN, GREEN], [GREEN, BLUE, GREEN, GREEN]].concat()
+        );
+
+        let src = [RED, BLUE].concat();
+        let mut resampler = Resampler::new((4, 4));
+        let resampled = resampler
+            .resample(&src, (2, 1), Orientation::None, (4, 4), policy)
+            .unwrap()
+            .unwrap();
+        let rows: Vec<_> = resampled.chunks_exact(16).collect();
+        assert!(rows[0].chunks_exact(4).all(|pixel| pixel == GREEN));
+        assert!(rows[3].chunks_exact(4).all(|pixel| pixel == GREEN));
+        assert_eq!(&rows[1][..4], RED);
+        assert_eq!(&rows[2][12..], BLUE);
+    }
+}
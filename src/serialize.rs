@@ -0,0 +1,180 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use v4l::format::{Colorspace, FieldOrder, Flags, Quantization, TransferFunction};
+
+use crate::{validate, Format, FourCC};
+
+/// How a [`Format`] is serialized. Omitted fields default to 0, which lets the driver
+/// pick them like [`v4l::Format::new`] does, so a deserialized format is set exactly
+/// like the original.
+#[derive(Serialize, Deserialize)]
+struct FormatRepr {
+    width: u32,
+    height: u32,
+    fourcc: FourCC,
+    #[serde(default)]
+    stride: u32,
+    #[serde(default)]
+    size: u32,
+    /// Raw v4l2 values of the enums below
+    #[serde(default)]
+    field_order: u32,
+    #[serde(default)]
+    flags: u32,
+    #[serde(default)]
+    colorspace: u32,
+    #[serde(default)]
+    quantization: u32,
+    #[serde(default)]
+    transfer: u32,
+}
+
+impl Serialize for Format {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let format = &self.0;
+        FormatRepr {
+            width: format.width,
+            height: format.height,
+            fourcc: format.fourcc.repr.into(),
+            stride: format.stride,
+            size: format.size,
+            field_order: format.field_order as u32,
+            flags: format.flags.bits(),
+            colorspace: format.colorspace as u32,
+            quantization: format.quantization as u32,
+            transfer: format.transfer as u32,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Format {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = FormatRepr::deserialize(deserializer)?;
+        let invalid =
+            |field: &str, value: u32| D::Error::custom(format!("invalid {field} {value}"));
+
+        let mut format = v4l::Format::new(
+            repr.width,
+            repr.height,
+            v4l::FourCC::new(repr.fourcc.as_bytes()),
+        );
+        format.stride = repr.stride;
+        format.size = repr.size;
+        format.field_order = FieldOrder::try_from(repr.field_order)
+            .map_err(|_| invalid("field order", repr.field_order))?;
+        format.flags = Flags::from(repr.flags);
+        format.colorspace = Colorspace::try_from(repr.colorspace)
+            .map_err(|_| invalid("colorspace", repr.colorspace))?;
+        format.quantization = Quantization::try_from(repr.quantization)
+            .map_err(|_| invalid("quantization", repr.quantization))?;
+        format.transfer = TransferFunction::try_from(repr.transfer)
+            .map_err(|_| invalid("transfer function", repr.transfer))?;
+
+        // caught here instead of when the format is set
+        validate::format(&format).map_err(D::Error::custom)?;
+        Ok(Self(format))
+    }
+}
+
+/// A [`FourCC`] is serialized as its characters, like "YUYV"
+impl Serialize for FourCC {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FourCC {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// For `#[serde(with)]` on fourccs stored as `[u8; 4]`, serialized like a [`FourCC`]
+pub(crate) mod fourcc {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        fourcc: &[u8; 4],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        FourCC::new(fourcc).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; 4], D::Error> {
+        FourCC::deserialize(deserializer).map(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use v4l::v4l_sys::v4l2_pix_format;
+
+    use super::*;
+
+    /// The fields setting `format` passes to the driver
+    fn set_format(format: &Format) -> [u32; 11] {
+        let pix = v4l2_pix_format::from(format.0);
+        [
+            pix.width,
+            pix.height,
+            pix.pixelformat,
+            pix.field,
+            pix.bytesperline,
+            pix.sizeimage,
+            pix.colorspace,
+            pix.priv_,
+            pix.flags,
+            pix.quantization,
+            pix.xfer_func,
+        ]
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let mut set = v4l::Format::new(1280, 720, v4l::FourCC::new(b"NV12"));
+        set.stride = 1344;
+        set.size = 1344 * 720 * 3 / 2;
+        set.field_order = FieldOrder::Interlaced;
+        set.colorspace = Colorspace::Rec709;
+        set.quantization = Quantization::LimitedRange;
+        set.transfer = TransferFunction::Rec709;
+        for format in [Format(set), Format::new(640, 480, b"YUYV").unwrap()] {
+            let text = ron::to_string(&format).unwrap();
+            let parsed: Format = ron::from_str(&text).unwrap();
+            assert_eq!(set_format(&parsed), set_format(&format), "{text}");
+        }
+    }
+
+    #[test]
+    fn fourccs_are_their_characters() {
+        // padded with a space, which parses back
+        let format = Format(v4l::Format::new(320, 240, v4l::FourCC::new(b"Y16 ")));
+        let text = ron::to_string(&format).unwrap();
+        assert!(text.contains(r#"fourcc:"Y16 ""#), "{text}");
+        let parsed: Format = ron::from_str(&text).unwrap();
+        assert_eq!(parsed.fourcc(), *b"Y16 ");
+    }
+
+    #[test]
+    fn omitted_fields_are_left_to_the_driver() {
+        let parsed: Format = ron::from_str(r#"(width: 640, height: 480, fourcc: "MJPG")"#).unwrap();
+        let new = Format(v4l::Format::new(640, 480, v4l::FourCC::new(b"MJPG")));
+        assert_eq!(set_format(&parsed), set_format(&new));
+    }
+
+    #[test]
+    fn invalid_formats_fail_to_deserialize() {
+        for text in [
+            r#"(width: 640, height: 480, fourcc: "YUYV2")"#,
+            r#"(width: 0, height: 480, fourcc: "YUYV")"#,
+            r#"(width: 640, height: 480, fourcc: "YUYV", colorspace: 99)"#,
+        ] {
+            assert!(ron::from_str::<Format>(text).is_err(), "{text}");
+        }
+    }
+}
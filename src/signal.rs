@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use tracing::{info, warn};
+
+use crate::source::FrameMeta;
+use crate::Error;
+
+/// Signal timeout of inputs whose driver doesn't report a frame interval
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Frames in a row that have to be empty or flagged by the driver before the signal is
+/// lost, or fine before it is restored, so a single bad frame doesn't flap it
+const HYSTERESIS: u32 = 3;
+
+/// Whether the source of an [`Input`](crate::Input) delivers a picture, like the
+/// cable of a capture card being plugged in, see [`Input::signal`](crate::Input::signal).
+/// A camera filming a black scene still has a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SignalState {
+    /// No frame arrived yet
+    #[default]
+    Unknown,
+    Present,
+    /// No frames arrived for the signal timeout, several in a row were empty or
+    /// flagged as corrupt by the driver, or the receiver of the device detects no signal
+    Absent,
+}
+
+/// Sent when the [`SignalState`] of an [`Input`](crate::Input) that was delivering frames
+/// became absent, like after the cable of a capture card was pulled: it got none for
+/// longer than its signal timeout, see
+/// [`InputBuilder::signal_timeout`](crate::InputBuilder::signal_timeout), a few in a row
+/// were empty or flagged as corrupt, or its receiver detects no signal. The device
+/// stays open and is polled every update, the image keeps the last frame until a
+/// [`SignalRestored`] is sent.
+#[derive(Event, Debug)]
+pub struct SignalLost {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// [`Error::Timeout`] with the time since the last frame, or [`Error::NoSignal`]
+    pub error: Error,
+}
+
+/// Sent once a few frames in a row were fine after a [`SignalLost`]
+#[derive(Event, Debug, Clone)]
+pub struct SignalRestored {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    /// Time since the last fine frame before the signal was lost
+    pub lost: Duration,
+}
+
+/// Signal state of an input, updated by its io task
+pub(crate) struct Signal {
+    timeout: Duration,
+    /// `None` until the first frame, devices that are slow to start don't lose a signal
+    last_frame: Option<Instant>,
+    last_good: Option<Instant>,
+    pub(crate) state: SignalState,
+    /// Bad and fine frames in a row
+    bad: u32,
+    good: u32,
+    /// Set once the driver filled in bytesused, some leave it at 0 for every frame of
+    /// uncompressed formats, others only for the empty ones of a missing signal
+    fills_bytesused: bool,
+    /// The last fine frame when the signal was lost
+    lost_since: Option<Instant>,
+    /// Sent as a [`SignalLost`] and a [`SignalRestored`] once the task is done
+    pub(crate) pending_lost: Option<Error>,
+    pub(crate) pending_restored: Option<Duration>,
+}
+
+impl Signal {
+    /// Signal of an input that is given `timeout`, or twice its frame interval
+    pub(crate) fn new(timeout: Option<Duration>, frame_interval: Option<Duration>) -> Self {
+        let timeout = timeout
+            .or(frame_interval.map(|interval| interval * 2))
+            .unwrap_or(DEFAULT_TIMEOUT);
+        Self {
+            timeout,
+            last_frame: None,
+            last_good: None,
+            state: SignalState::Unknown,
+            bad: 0,
+            good: 0,
+            fills_bytesused: false,
+            lost_since: None,
+            pending_lost: None,
+            pending_restored: None,
+        }
+    }
+
+    /// Records a dequeued frame with the metadata the driver gave it
+    pub(crate) fn frame(&mut self, meta: &FrameMeta) {
+        let now = Instant::now();
+        self.last_frame = Some(now);
+        self.fills_bytesused |= meta.bytesused != 0;
+        let bad = meta.error || (self.fills_bytesused && meta.bytesused == 0);
+        if bad {
+            self.good = 0;
+            self.bad += 1;
+        } else {
+            self.bad = 0;
+            self.good += 1;
+        }
+
+        match self.state {
+            SignalState::Absent if self.good >= HYSTERESIS => {
+                let lost = self
+                    .lost_since
+                    .map_or_else(Duration::default, |since| now - since);
+                info!(?lost, "v4l signal restored");
+                self.state = SignalState::Present;
+                self.pending_restored = Some(lost);
+            }
+            SignalState::Absent => {}
+            _ if self.bad >= HYSTERESIS => self.lose(Error::NoSignal {
+                reason: format!("{} frames in a row were empty or corrupt", self.bad),
+            }),
+            _ if !bad => self.state = SignalState::Present,
+            _ => {}
+        }
+        if !bad {
+            self.last_good = Some(now);
+        }
+    }
+
+    /// Records a dequeue that timed out, returns an [`Error::Timeout`] once the signal
+    /// timeout passed since the last frame
+    pub(crate) fn timed_out(&mut self) -> Option<Error> {
+        let waited = self.last_frame?.elapsed();
+        if waited < self.timeout {
+            return None;
+        }
+
+        self.lose(Error::Timeout { waited });
+        Some(Error::Timeout { waited })
+    }
+
+    /// Records a receiver that detects no signal, like the hdmi receiver of a capture
+    /// card reporting ENOLINK once the cable was pulled
+    pub(crate) fn no_link(&mut self) {
+        self.lose(Error::NoSignal {
+            reason: "the receiver detects none".into(),
+        });
+    }
+
+    fn lose(&mut self, error: Error) {
+        if self.state == SignalState::Absent {
+            return;
+        }
+        warn!(%error, "v4l signal lost");
+        self.state = SignalState::Absent;
+        self.good = 0;
+        self.lost_since = self.last_good;
+        self.pending_lost = Some(error);
+    }
+}
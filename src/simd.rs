@@ -0,0 +1,459 @@
+use std::sync::OnceLock;
+
+use crate::color::ToRgb;
+
+/// Code converting 4:2:2 and 4:2:0 frames, picked once for the cpu the app runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Path {
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+    Scalar,
+}
+
+impl Path {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Self::Sse2 => "sse2",
+            #[cfg(target_arch = "aarch64")]
+            Self::Neon => "neon",
+            Self::Scalar => "scalar",
+        }
+    }
+
+    /// Pixels converted by one iteration, `None` for the scalar path
+    fn block(self) -> Option<usize> {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Self::Sse2 => Some(8),
+            #[cfg(target_arch = "aarch64")]
+            Self::Neon => Some(16),
+            Self::Scalar => None,
+        }
+    }
+
+    /// The most of `pixels` converted in whole blocks
+    fn blocks(self, pixels: usize) -> usize {
+        self.block().map_or(0, |block| pixels / block * block)
+    }
+}
+
+pub(crate) fn path() -> Path {
+    static PATH: OnceLock<Path> = OnceLock::new();
+    *PATH.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("sse2") {
+            return Path::Sse2;
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Path::Neon;
+        }
+        Path::Scalar
+    })
+}
+
+/// Converts the leading blocks of a packed 4:2:2 frame with the coefficients `k`,
+/// returns the rest of `src` and `dst` for [`ToRgb::pair`]
+pub(crate) fn yuv422<'a, 'b, const Y0: usize, const Y1: usize, const U: usize, const V: usize>(
+    src: &'a [u8],
+    dst: &'b mut [u8],
+    k: &ToRgb,
+) -> (&'a [u8], &'b mut [u8]) {
+    let path = path();
+    let pixels = path.blocks((src.len() / 2).min(dst.len() / 4));
+    let (src, src_rest) = src.split_at(pixels * 2);
+    let (dst, dst_rest) = dst.split_at_mut(pixels * 4);
+
+    match path {
+        // SAFETY: the cpu supports the path, both slices hold whole blocks
+        #[cfg(target_arch = "x86_64")]
+        Path::Sse2 => unsafe { sse2::yuv422::<Y0, Y1, U, V>(src, dst, k) },
+        #[cfg(target_arch = "aarch64")]
+        Path::Neon => unsafe { neon::yuv422::<Y0, Y1, U, V>(src, dst, k) },
+        Path::Scalar => {}
+    }
+    (src_rest, dst_rest)
+}
+
+/// Like [`yuv422`] for a row of NV12 or NV21 with the chroma row it shares
+pub(crate) fn semi_planar_row<'a, 'b, const U: usize, const V: usize>(
+    dst: &'b mut [u8],
+    luma: &'a [u8],
+    chroma: &'a [u8],
+    k: &ToRgb,
+) -> (&'b mut [u8], &'a [u8], &'a [u8]) {
+    let path = path();
+    let pixels = path.blocks((dst.len() / 4).min(luma.len()).min(chroma.len()));
+    let (dst, dst_rest) = dst.split_at_mut(pixels * 4);
+    let (luma, luma_rest) = luma.split_at(pixels);
+    let (chroma, chroma_rest) = chroma.split_at(pixels);
+
+    match path {
+        // SAFETY: the cpu supports the path, all slices hold whole blocks
+        #[cfg(target_arch = "x86_64")]
+        Path::Sse2 => unsafe { sse2::semi_planar::<U, V>(dst, luma, chroma, k) },
+        #[cfg(target_arch = "aarch64")]
+        Path::Neon => unsafe { neon::semi_planar::<U, V>(dst, luma, chroma, k) },
+        Path::Scalar => {}
+    }
+    (dst_rest, luma_rest, chroma_rest)
+}
+
+/// Like [`yuv422`] for a row of YU12 with the chroma rows it shares
+pub(crate) fn yu12_row<'a, 'b>(
+    dst: &'b mut [u8],
+    luma: &'a [u8],
+    cb: &'a [u8],
+    cr: &'a [u8],
+    k: &ToRgb,
+) -> (&'b mut [u8], &'a [u8], &'a [u8], &'a [u8]) {
+    let path = path();
+    let chroma = cb.len().min(cr.len()) * 2;
+    let pixels = path.blocks((dst.len() / 4).min(luma.len()).min(chroma));
+    let (dst, dst_rest) = dst.split_at_mut(pixels * 4);
+    let (luma, luma_rest) = luma.split_at(pixels);
+    let (cb, cb_rest) = cb.split_at(pixels / 2);
+    let (cr, cr_rest) = cr.split_at(pixels / 2);
+
+    match path {
+        // SAFETY: the cpu supports the path, all slices hold whole blocks
+        #[cfg(target_arch = "x86_64")]
+        Path::Sse2 => unsafe { sse2::yu12(dst, luma, cb, cr, k) },
+        #[cfg(target_arch = "aarch64")]
+        Path::Neon => unsafe { neon::yu12(dst, luma, cb, cr, k) },
+        Path::Scalar => {}
+    }
+    (dst_rest, luma_rest, cb_rest, cr_rest)
+}
+
+/// 8 pixels per block, samples are widened to 16 bit lanes
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    use crate::color::ToRgb;
+
+    pub(super) unsafe fn yuv422<
+        const Y0: usize,
+        const Y1: usize,
+        const U: usize,
+        const V: usize,
+    >(
+        src: &[u8],
+        dst: &mut [u8],
+        k: &ToRgb,
+    ) {
+        let mask = _mm_set1_epi16(0xff);
+        for (src, dst) in src.chunks_exact(16).zip(dst.chunks_exact_mut(32)) {
+            let samples = _mm_loadu_si128(src.as_ptr() as *const __m128i);
+            let (even, odd) = (_mm_and_si128(samples, mask), _mm_srli_epi16(samples, 8));
+            // both lumas are at offsets of the same parity, the chroma at the others
+            let (luma, chroma) = match Y0 % 2 {
+                0 => (even, odd),
+                _ => (odd, even),
+            };
+            let (u, v) = split_chroma::<U, V>(chroma);
+            store(dst, luma, u, v, k);
+        }
+    }
+
+    pub(super) unsafe fn semi_planar<const U: usize, const V: usize>(
+        dst: &mut [u8],
+        luma: &[u8],
+        chroma: &[u8],
+        k: &ToRgb,
+    ) {
+        let blocks = dst
+            .chunks_exact_mut(32)
+            .zip(luma.chunks_exact(8))
+            .zip(chroma.chunks_exact(8));
+        for ((dst, luma), chroma) in blocks {
+            let (u, v) = split_chroma::<U, V>(widen(chroma));
+            store(dst, widen(luma), u, v, k);
+        }
+    }
+
+    pub(super) unsafe fn yu12(dst: &mut [u8], luma: &[u8], cb: &[u8], cr: &[u8], k: &ToRgb) {
+        let blocks = dst
+            .chunks_exact_mut(32)
+            .zip(luma.chunks_exact(8))
+            .zip(cb.chunks_exact(4).zip(cr.chunks_exact(4)));
+        for ((dst, luma), (cb, cr)) in blocks {
+            store(
+                dst,
+                widen(luma),
+                duplicate(widen4(cb)),
+                duplicate(widen4(cr)),
+                k,
+            );
+        }
+    }
+
+    /// 8 bytes as 16 bit lanes
+    unsafe fn widen(src: &[u8]) -> __m128i {
+        let bytes = _mm_loadl_epi64(src.as_ptr() as *const __m128i);
+        _mm_unpacklo_epi8(bytes, _mm_setzero_si128())
+    }
+
+    /// 4 bytes as the lower 16 bit lanes
+    unsafe fn widen4(src: &[u8]) -> __m128i {
+        let bytes = _mm_cvtsi32_si128((src.as_ptr() as *const i32).read_unaligned());
+        _mm_unpacklo_epi8(bytes, _mm_setzero_si128())
+    }
+
+    /// Lanes of 4 samples at the given offsets into both samples for every pixel
+    unsafe fn split_chroma<const U: usize, const V: usize>(chroma: __m128i) -> (__m128i, __m128i) {
+        let low = _mm_and_si128(chroma, _mm_set1_epi32(0xffff));
+        let high = _mm_srli_epi32(chroma, 16);
+        let (u, v) = if U < V { (low, high) } else { (high, low) };
+        (
+            _mm_or_si128(u, _mm_slli_epi32(u, 16)),
+            _mm_or_si128(v, _mm_slli_epi32(v, 16)),
+        )
+    }
+
+    /// The lower 4 lanes, every one repeated for two pixels
+    unsafe fn duplicate(samples: __m128i) -> __m128i {
+        _mm_unpacklo_epi16(samples, samples)
+    }
+
+    unsafe fn store(dst: &mut [u8], luma: __m128i, u: __m128i, v: __m128i, k: &ToRgb) {
+        let bias = _mm_set1_epi16(128);
+        let u = _mm_slli_epi16(_mm_sub_epi16(u, bias), 8);
+        let v = _mm_slli_epi16(_mm_sub_epi16(v, bias), 8);
+        let luma = _mm_slli_epi16(_mm_sub_epi16(luma, _mm_set1_epi16(k.luma_offset)), 7);
+        let luma = _mm_mulhi_epi16(luma, _mm_set1_epi16(k.y));
+
+        let r = _mm_add_epi16(luma, _mm_mulhi_epi16(v, _mm_set1_epi16(k.r_v)));
+        let g = _mm_sub_epi16(
+            _mm_sub_epi16(luma, _mm_mulhi_epi16(u, _mm_set1_epi16(k.g_u))),
+            _mm_mulhi_epi16(v, _mm_set1_epi16(k.g_v)),
+        );
+        let b = _mm_add_epi16(luma, _mm_mulhi_epi16(u, _mm_set1_epi16(k.b_u)));
+
+        let rg = _mm_unpacklo_epi8(narrow(r), narrow(g));
+        let ba = _mm_unpacklo_epi8(narrow(b), _mm_set1_epi8(-1));
+        let ptr = dst.as_mut_ptr() as *mut __m128i;
+        _mm_storeu_si128(ptr, _mm_unpacklo_epi16(rg, ba));
+        _mm_storeu_si128(ptr.add(1), _mm_unpackhi_epi16(rg, ba));
+    }
+
+    /// Rounds lanes in 1/4 and saturates them to bytes in the lower half
+    unsafe fn narrow(channel: __m128i) -> __m128i {
+        let channel = _mm_srai_epi16(_mm_add_epi16(channel, _mm_set1_epi16(2)), 2);
+        _mm_packus_epi16(channel, channel)
+    }
+}
+
+/// 16 pixels per block, the lumas of even and odd pixels are converted apart
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use crate::color::ToRgb;
+
+    pub(super) unsafe fn yuv422<
+        const Y0: usize,
+        const Y1: usize,
+        const U: usize,
+        const V: usize,
+    >(
+        src: &[u8],
+        dst: &mut [u8],
+        k: &ToRgb,
+    ) {
+        for (src, dst) in src.chunks_exact(32).zip(dst.chunks_exact_mut(64)) {
+            let samples = vld4_u8(src.as_ptr());
+            let samples = [samples.0, samples.1, samples.2, samples.3];
+            store(dst, samples[Y0], samples[Y1], samples[U], samples[V], k);
+        }
+    }
+
+    pub(super) unsafe fn semi_planar<const U: usize, const V: usize>(
+        dst: &mut [u8],
+        luma: &[u8],
+        chroma: &[u8],
+        k: &ToRgb,
+    ) {
+        let blocks = dst
+            .chunks_exact_mut(64)
+            .zip(luma.chunks_exact(16))
+            .zip(chroma.chunks_exact(16));
+        for ((dst, luma), chroma) in blocks {
+            let luma = vld2_u8(luma.as_ptr());
+            let chroma = vld2_u8(chroma.as_ptr());
+            let chroma = [chroma.0, chroma.1];
+            store(dst, luma.0, luma.1, chroma[U], chroma[V], k);
+        }
+    }
+
+    pub(super) unsafe fn yu12(dst: &mut [u8], luma: &[u8], cb: &[u8], cr: &[u8], k: &ToRgb) {
+        let blocks = dst
+            .chunks_exact_mut(64)
+            .zip(luma.chunks_exact(16))
+            .zip(cb.chunks_exact(8).zip(cr.chunks_exact(8)));
+        for ((dst, luma), (cb, cr)) in blocks {
+            let luma = vld2_u8(luma.as_ptr());
+            store(
+                dst,
+                luma.0,
+                luma.1,
+                vld1_u8(cb.as_ptr()),
+                vld1_u8(cr.as_ptr()),
+                k,
+            );
+        }
+    }
+
+    unsafe fn store(
+        dst: &mut [u8],
+        even: uint8x8_t,
+        odd: uint8x8_t,
+        u: uint8x8_t,
+        v: uint8x8_t,
+        k: &ToRgb,
+    ) {
+        let bias = vdupq_n_s16(128);
+        let u = vshlq_n_s16::<8>(vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(u)), bias));
+        let v = vshlq_n_s16::<8>(vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(v)), bias));
+
+        // the doubling multiply takes half the coefficients for the same product
+        let r = vqdmulhq_n_s16(v, k.r_v / 2);
+        let g = vnegq_s16(vaddq_s16(
+            vqdmulhq_n_s16(u, k.g_u / 2),
+            vqdmulhq_n_s16(v, k.g_v / 2),
+        ));
+        let b = vqdmulhq_n_s16(u, k.b_u / 2);
+        let (even, odd) = (luma(even, k), luma(odd, k));
+
+        let rgba = uint8x16x4_t(
+            channel(even, odd, r),
+            channel(even, odd, g),
+            channel(even, odd, b),
+            vdupq_n_u8(255),
+        );
+        vst4q_u8(dst.as_mut_ptr(), rgba);
+    }
+
+    /// Lumas less their offset and expanded to full range, in 1/4
+    unsafe fn luma(luma: uint8x8_t, k: &ToRgb) -> int16x8_t {
+        let offset = vdupq_n_s16(k.luma_offset);
+        let luma = vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(luma)), offset);
+        vqdmulhq_n_s16(vshlq_n_s16::<7>(luma), k.y / 2)
+    }
+
+    /// A channel of 16 pixels in order
+    unsafe fn channel(even: int16x8_t, odd: int16x8_t, term: int16x8_t) -> uint8x16_t {
+        let pixels = vzip_u8(narrow(even, term), narrow(odd, term));
+        vcombine_u8(pixels.0, pixels.1)
+    }
+
+    /// Adds the term to the lumas in 1/4, rounds and saturates them to bytes
+    unsafe fn narrow(luma: int16x8_t, term: int16x8_t) -> uint8x8_t {
+        vqrshrun_n_s16::<2>(vaddq_s16(luma, term))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Colorimetry, YcbcrMatrix, YcbcrRange};
+
+    /// xorshift64, the same frames on every run
+    struct Random(u64);
+
+    impl Random {
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    self.0 ^= self.0 << 13;
+                    self.0 ^= self.0 >> 7;
+                    self.0 ^= self.0 << 17;
+                    (self.0 >> 32) as u8
+                })
+                .collect()
+        }
+    }
+
+    /// Coefficients of every colorimetry, plain and with the saturations of post
+    /// processing, which scale the chroma terms to the ends of their range
+    fn coefficients() -> Vec<ToRgb> {
+        let mut coefficients = Vec::new();
+        for matrix in [YcbcrMatrix::Bt601, YcbcrMatrix::Bt709] {
+            for range in [YcbcrRange::Limited, YcbcrRange::Full] {
+                let k = Colorimetry { matrix, range }.to_rgb();
+                coefficients.extend([1.0, 0.0, 0.4, 2.0].map(|saturation| k.saturate(saturation)));
+            }
+        }
+        coefficients
+    }
+
+    /// Widths with and without a partial block at the end of the row
+    const WIDTHS: [usize; 4] = [16, 32, 40, 64];
+
+    #[test]
+    fn yuv422_matches_the_scalar_path() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for k in coefficients() {
+            for width in WIDTHS {
+                let src = random.bytes(width * 2);
+                let mut dst = vec![0; width * 4];
+                let (_, rest) = yuv422::<0, 2, 1, 3>(&src, &mut dst, &k);
+                let converted = width * 4 - rest.len();
+                assert_eq!(converted, path().blocks(width) * 4);
+
+                let mut expected = vec![0; width * 4];
+                for (dst, src) in expected.chunks_exact_mut(8).zip(src.chunks_exact(4)) {
+                    k.pair(dst, src[0], src[2], src[1], src[3]);
+                }
+                assert_eq!(dst[..converted], expected[..converted], "{k:?} at {width}");
+            }
+        }
+    }
+
+    #[test]
+    fn semi_planar_matches_the_scalar_path() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        for k in coefficients() {
+            for width in WIDTHS {
+                let (luma, chroma) = (random.bytes(width), random.bytes(width));
+                let mut dst = vec![0; width * 4];
+                let (rest, _, _) = semi_planar_row::<1, 0>(&mut dst, &luma, &chroma, &k);
+                let converted = width * 4 - rest.len();
+                assert_eq!(converted, path().blocks(width) * 4);
+
+                let mut expected = vec![0; width * 4];
+                let pairs = luma.chunks_exact(2).zip(chroma.chunks_exact(2));
+                for (dst, (luma, chroma)) in expected.chunks_exact_mut(8).zip(pairs) {
+                    k.pair(dst, luma[0], luma[1], chroma[1], chroma[0]);
+                }
+                assert_eq!(dst[..converted], expected[..converted], "{k:?} at {width}");
+            }
+        }
+    }
+
+    #[test]
+    fn yu12_matches_the_scalar_path() {
+        let mut random = Random(0xd1b5_4a32_d192_ed03);
+        for k in coefficients() {
+            for width in WIDTHS {
+                let luma = random.bytes(width);
+                let (cb, cr) = (random.bytes(width / 2), random.bytes(width / 2));
+                let mut dst = vec![0; width * 4];
+                let (rest, ..) = yu12_row(&mut dst, &luma, &cb, &cr, &k);
+                let converted = width * 4 - rest.len();
+                assert_eq!(converted, path().blocks(width) * 4);
+
+                let mut expected = vec![0; width * 4];
+                let pairs = luma.chunks_exact(2).zip(cb.iter().zip(&cr));
+                for (dst, (luma, (&u, &v))) in expected.chunks_exact_mut(8).zip(pairs) {
+                    k.pair(dst, luma[0], luma[1], u, v);
+                }
+                assert_eq!(dst[..converted], expected[..converted], "{k:?} at {width}");
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task};
+use bevy::utils::futures;
+use tracing::{debug, warn};
+
+use crate::color::{f16_value, srgb_oetf};
+use crate::{Decoder, Error, ImageEncoding, Input, Result};
+
+/// File format of a snapshot, see [`Input::save_snapshot`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Lossless, tagged as sRGB
+    Png,
+    /// Quality from 1 to 100, sRGB like every jpeg
+    Jpeg { quality: u8 },
+}
+
+/// Sent once a snapshot requested with [`Input::request_snapshot`] was written, or
+/// failed to
+#[derive(Event, Debug)]
+pub struct SnapshotSaved {
+    pub entity: Entity,
+    pub path: PathBuf,
+    pub result: Result<()>,
+}
+
+/// Snapshots of an input waiting for the next poll, and the ones being written
+#[derive(Default)]
+pub(crate) struct Snapshots {
+    pub(crate) requested: Vec<(PathBuf, SnapshotFormat)>,
+    tasks: Vec<Task<(PathBuf, Result<()>)>>,
+}
+
+/// The image of an input, copied as it was between two swaps
+pub(crate) struct Frame {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    encoding: ImageEncoding,
+}
+
+impl Frame {
+    /// Copies the frame in the image of `input`. Images of late uploaded and gpu
+    /// converted inputs only hold their frames on the gpu.
+    pub(crate) fn copy(input: &Input, images: &Assets<Image>) -> Result<Self> {
+        if input.late_upload || input.decoder == Decoder::Gpu {
+            return Err(Error::Snapshot(
+                "the frames of late uploaded and gpu converted inputs aren't on the cpu"
+                    .to_string(),
+            ));
+        }
+        let Some(image) = images.get(input.image()) else {
+            return Err(Error::Snapshot(
+                "the image of the input was removed".to_string(),
+            ));
+        };
+        let size = image.texture_descriptor.size;
+        Ok(Self {
+            data: image.data.clone(),
+            width: size.width,
+            height: size.height,
+            encoding: input.encoding,
+        })
+    }
+
+    /// Encodes the frame into a file at `path`
+    pub(crate) fn save(&self, path: &Path, format: SnapshotFormat) -> Result<()> {
+        let luma = self.encoding == ImageEncoding::Luma;
+        let data = self.gamma_encoded();
+        let failed = |err: &dyn std::fmt::Display| Error::Snapshot(err.to_string());
+
+        match format {
+            SnapshotFormat::Png => {
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = png::Encoder::new(file, self.width, self.height);
+                encoder.set_color(match luma {
+                    true => png::ColorType::Grayscale,
+                    false => png::ColorType::Rgba,
+                });
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+                let mut writer = encoder.write_header().map_err(|err| failed(&err))?;
+                writer.write_image_data(&data).map_err(|err| failed(&err))?;
+            }
+            SnapshotFormat::Jpeg { quality } => {
+                let (Ok(width), Ok(height)) =
+                    (u16::try_from(self.width), u16::try_from(self.height))
+                else {
+                    return Err(Error::Snapshot(format!(
+                        "{}x{} is too large for a jpeg",
+                        self.width, self.height
+                    )));
+                };
+                let color = match luma {
+                    true => jpeg_encoder::ColorType::Luma,
+                    false => jpeg_encoder::ColorType::Rgba,
+                };
+                jpeg_encoder::Encoder::new_file(path, quality.clamp(1, 100))
+                    .map_err(|err| failed(&err))?
+                    .encode(&data, width, height, color)
+                    .map_err(|err| failed(&err))?;
+            }
+        }
+        debug!(path = %path.display(), "saved v4l snapshot");
+        Ok(())
+    }
+
+    /// Gamma encoded bytes of every sample, like the frame was converted before the
+    /// encoding of the image was applied
+    fn gamma_encoded(&self) -> Vec<u8> {
+        let byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let samples = || {
+            self.data
+                .chunks_exact(2)
+                .map(|sample| [sample[0], sample[1]])
+        };
+        // alpha is linear in every encoding
+        let gamma = |index: usize, value: f32| match index % 4 {
+            3 => value,
+            _ => srgb_oetf(value),
+        };
+
+        match self.encoding {
+            ImageEncoding::Srgb | ImageEncoding::Unorm | ImageEncoding::Luma => self.data.clone(),
+            ImageEncoding::Linear => self
+                .data
+                .iter()
+                .enumerate()
+                .map(|(index, &value)| byte(gamma(index, value as f32 / 255.0)))
+                .collect(),
+            ImageEncoding::Half => samples()
+                .map(|sample| byte(f16_value(u16::from_le_bytes(sample))))
+                .collect(),
+            ImageEncoding::LinearHalf => samples()
+                .enumerate()
+                .map(|(index, sample)| byte(gamma(index, f16_value(u16::from_le_bytes(sample)))))
+                .collect(),
+            ImageEncoding::Unorm16 => samples()
+                .map(|sample| (u16::from_le_bytes(sample) >> 8) as u8)
+                .collect(),
+        }
+    }
+}
+
+/// Copies the images of inputs that requested snapshots once their frames were swapped
+/// in, and sends the snapshots that were written
+pub(crate) fn save_snapshots(
+    mut inputs: Query<(Entity, &mut Input)>,
+    images: Res<Assets<Image>>,
+    mut saved: EventWriter<SnapshotSaved>,
+) {
+    for (entity, mut input) in inputs.iter_mut() {
+        let snapshots = &input.snapshots;
+        if snapshots.requested.is_empty() && snapshots.tasks.is_empty() {
+            continue;
+        }
+
+        for (path, format) in std::mem::take(&mut input.snapshots.requested) {
+            let task = match Frame::copy(&input, &images) {
+                Ok(frame) => IoTaskPool::get().spawn(async move {
+                    let result = frame.save(&path, format);
+                    (path, result)
+                }),
+                Err(err) => {
+                    saved.send(SnapshotSaved {
+                        entity,
+                        path,
+                        result: Err(err),
+                    });
+                    continue;
+                }
+            };
+            input.snapshots.tasks.push(task);
+        }
+
+        input.snapshots.tasks.retain_mut(|task| {
+            let Some((path, result)) = futures::check_ready(task) else {
+                return true;
+            };
+            if let Err(err) = &result {
+                warn!(path = %path.display(), %err, "saving v4l snapshot failed");
+            }
+            saved.send(SnapshotSaved {
+                entity,
+                path,
+                result,
+            });
+            false
+        });
+    }
+}
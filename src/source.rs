@@ -0,0 +1,206 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+use v4l::buffer::{Flags, Metadata};
+use v4l::device::Handle;
+use v4l::format::FieldOrder;
+use v4l::io::mmap::Stream;
+use v4l::io::traits::{CaptureStream, Stream as StreamTrait};
+use v4l::io::userptr::Stream as UserptrStream;
+
+use crate::memory;
+use crate::mplane::MplaneStream;
+use crate::{MemoryType, Timestamp};
+
+const EIO: i32 = 5;
+const EPIPE: i32 = 32;
+pub(crate) const ENODEV: i32 = 19;
+
+/// Where an [`Io`](crate::Io) gets frames from, or writes them to
+pub(crate) enum IoStream {
+    /// Capture or output stream of a v4l device
+    Mmap(Stream<'static>),
+    /// Capture stream with buffers allocated by the process, see [`MemoryType`](crate::MemoryType)
+    UserPtr(UserptrStream),
+    /// Capture or output stream of a device that only supports the multi-planar API
+    Mplane(MplaneStream),
+    /// Frames produced in process, fed through the same conversion as captured ones
+    Virtual(Box<dyn VirtualSource>),
+    /// Output stream kept in process, frames are encoded like for a device
+    Sink(Box<dyn VirtualSink>),
+    /// No buffers allocated, while the format of an input changes
+    Closed,
+}
+
+impl IoStream {
+    /// Blocks until the next captured frame is available. Devices are opened
+    /// non-blocking, mmap streams that woke up without a frame fail with EAGAIN, which
+    /// is reported as [`io::ErrorKind::TimedOut`] like a timed out poll.
+    pub(crate) fn capture(&mut self) -> io::Result<(&[u8], FrameMeta)> {
+        match self {
+            Self::Mmap(stream) => {
+                let (buf, buf_meta) = CaptureStream::next(stream).map_err(would_block)?;
+                Ok((buf, FrameMeta::from_buffer(buf_meta)))
+            }
+            Self::UserPtr(stream) => {
+                let (buf, buf_meta) = CaptureStream::next(stream)?;
+                Ok((buf, FrameMeta::from_buffer(buf_meta)))
+            }
+            Self::Mplane(stream) => stream.capture().map_err(would_block),
+            Self::Closed => Err(io::ErrorKind::NotConnected.into()),
+            Self::Virtual(source) => source.next(),
+            Self::Sink(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// Frames the driver captured that are waiting to be dequeued, 0 for streams that
+    /// don't queue frames or don't count them, like multi-planar ones
+    pub(crate) fn waiting(&self) -> u32 {
+        match self {
+            Self::Mmap(stream) => memory::done(&stream.handle(), MemoryType::Mmap),
+            Self::UserPtr(stream) => memory::done(&stream.handle(), MemoryType::UserPtr),
+            Self::Mplane(_) | Self::Virtual(_) | Self::Sink(_) | Self::Closed => 0,
+        }
+    }
+
+    /// Handle of the device of capture streams, events of the device are dequeued on it
+    pub(crate) fn handle(&self) -> Option<Arc<Handle>> {
+        match self {
+            Self::Mmap(stream) => Some(stream.handle()),
+            Self::UserPtr(stream) => Some(stream.handle()),
+            Self::Mplane(stream) => Some(stream.handle()),
+            Self::Virtual(_) | Self::Sink(_) | Self::Closed => None,
+        }
+    }
+
+    /// Makes [`IoStream::capture`] fail with [`io::ErrorKind::TimedOut`] when no frame
+    /// arrives within `timeout`, `None` blocks. Only mmap streams can time out.
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        match self {
+            Self::Mmap(stream) => match timeout {
+                Some(timeout) => stream.set_timeout(timeout),
+                None => stream.clear_timeout(),
+            },
+            Self::Mplane(stream) => stream.set_timeout(timeout),
+            _ => {}
+        }
+    }
+
+    /// Stops the stream, the next [`IoStream::capture`] queues all buffers and
+    /// starts it again
+    pub(crate) fn restart(&mut self) -> io::Result<()> {
+        match self {
+            Self::Mmap(stream) => StreamTrait::stop(stream),
+            Self::UserPtr(stream) => StreamTrait::stop(stream),
+            Self::Mplane(stream) => stream.stop(),
+            Self::Virtual(_) | Self::Sink(_) | Self::Closed => Ok(()),
+        }
+    }
+}
+
+impl Drop for IoStream {
+    /// v4l streams panic when stopping them fails while they are dropped, unless the
+    /// device is gone. They are stopped here first, a stream that can't be stopped is
+    /// leaked with its buffers instead of taking the app down.
+    fn drop(&mut self) {
+        let Err(err) = self.restart() else {
+            return;
+        };
+        if err.raw_os_error() == Some(ENODEV) {
+            return;
+        }
+
+        warn!(%err, "failed to stop v4l stream, leaking its buffers");
+        std::mem::forget(std::mem::replace(self, Self::Closed));
+    }
+}
+
+/// EAGAIN of a non-blocking dequeue is a dequeue without a frame
+fn would_block(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+        _ => err,
+    }
+}
+
+/// Whether a stream error goes away by restarting the stream, like EIO after
+/// a USB glitch. Anything else, like an unplugged device, is fatal.
+pub(crate) fn is_transient(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(EIO | EPIPE))
+}
+
+/// A source of frames that behaves like a capture device
+pub(crate) trait VirtualSource: Send {
+    /// Blocks until the next frame is due, like dequeuing from a device
+    fn next(&mut self) -> io::Result<(&[u8], FrameMeta)>;
+}
+
+/// Where the frames of an output go instead of a device
+pub(crate) trait VirtualSink: Send {
+    /// Takes a frame encoded in the format of the output, like queueing it to a device
+    fn write(&mut self, frame: &[u8], pts: Duration) -> io::Result<()>;
+}
+
+/// The parts of a dequeued buffer's metadata the crate uses
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameMeta {
+    pub(crate) bytesused: u32,
+    /// Set by drivers for buffers they know are corrupt, V4L2_BUF_FLAG_ERROR
+    pub(crate) error: bool,
+    /// V4L2_BUF_FLAG_* of the buffer, 0 for virtual sources
+    pub(crate) flags: u32,
+    /// Set for buffers holding a bottom field, of devices that alternate fields
+    pub(crate) bottom: bool,
+    pub(crate) sequence: u32,
+    pub(crate) timestamp: Timestamp,
+}
+
+impl FrameMeta {
+    fn from_buffer(buf_meta: &Metadata) -> Self {
+        Self {
+            bytesused: buf_meta.bytesused,
+            error: buf_meta.flags.contains(Flags::ERROR),
+            flags: buf_meta.flags.bits(),
+            bottom: buf_meta.field == FieldOrder::Bottom as u32,
+            sequence: buf_meta.sequence,
+            timestamp: Timestamp::from_buffer(
+                buf_meta.flags.bits(),
+                Duration::new(
+                    buf_meta.timestamp.sec as u64,
+                    buf_meta.timestamp.usec as u32 * 1000,
+                ),
+            ),
+        }
+    }
+}
+
+/// Paces a virtual source to a frame rate and counts its frames
+pub(crate) struct Pacer {
+    interval: Duration,
+    started: Instant,
+    sequence: u32,
+}
+
+impl Pacer {
+    pub(crate) fn new(fps: f32) -> Self {
+        Self {
+            interval: Duration::from_secs_f32(1.0 / fps.max(f32::EPSILON)),
+            started: Instant::now(),
+            sequence: 0,
+        }
+    }
+
+    /// Sleeps until the next frame is due and returns the sequence and timestamp for it
+    pub(crate) fn wait(&mut self) -> (u32, Timestamp) {
+        let due = self.interval * self.sequence;
+        if let Some(remaining) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        (sequence, Timestamp::now())
+    }
+}
@@ -0,0 +1,186 @@
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use bevy::prelude::*;
+use tracing::{info, warn};
+use v4l::v4l2;
+use v4l::video::Capture;
+
+use crate::ioctl::{
+    Event, EventSubscription, RawTimings, VIDIOC_DQEVENT, VIDIOC_QUERY_DV_TIMINGS,
+    VIDIOC_SUBSCRIBE_EVENT, VIDIOC_S_DV_TIMINGS,
+};
+use crate::profile::{self, Profile};
+use crate::source::IoStream;
+use crate::{describe_format, Format, Input};
+
+/// V4L2_EVENT_SOURCE_CHANGE, and the V4L2_EVENT_SRC_CH_RESOLUTION change it reports
+const EVENT_SOURCE_CHANGE: u32 = 5;
+const SRC_CH_RESOLUTION: u32 = 1;
+/// Receivers fail to query the timings of a source without a signal with ENOLINK
+const ENOLINK: i32 = 67;
+
+/// Sent when the source of an [`Input`] changed its resolution, like a capture card
+/// whose hdmi input switched from 720p to 1080p, or after [`Input::set_format`]. The
+/// stream is set up again with the format the driver detected and the image is replaced
+/// in place, handles to it show frames of the new size.
+#[derive(Event, Debug, Clone)]
+pub struct FormatChanged {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    pub previous: UVec2,
+    pub size: UVec2,
+}
+
+/// Source change events of a capture device, updated by its io task
+pub(crate) struct SourceChange {
+    /// Set until the main world set the stream up again
+    changed: bool,
+}
+
+impl SourceChange {
+    /// Subscribes to the source changes of `dev`, `None` for drivers that don't send
+    /// them, like most webcams
+    pub(crate) fn subscribe(dev: &v4l::Device) -> Option<Self> {
+        unsafe {
+            let mut subscription: EventSubscription = mem::zeroed();
+            subscription.typ = EVENT_SOURCE_CHANGE;
+            v4l2::ioctl(
+                dev.handle().fd() as c_int,
+                VIDIOC_SUBSCRIBE_EVENT,
+                &mut subscription as *mut EventSubscription as *mut c_void,
+            )
+            .ok()?;
+        }
+        Some(Self { changed: false })
+    }
+
+    /// Dequeues the events of the device of `stream`, returns whether its resolution
+    /// changed since the stream was set up. Frames dequeued after that don't match
+    /// the image anymore.
+    pub(crate) fn poll(&mut self, stream: &IoStream) -> bool {
+        let Some(handle) = stream.handle() else {
+            return self.changed;
+        };
+        // fails with ENOENT once no event is pending
+        loop {
+            let mut event: Event = unsafe { mem::zeroed() };
+            let dequeued = unsafe {
+                v4l2::ioctl(
+                    handle.fd() as c_int,
+                    VIDIOC_DQEVENT,
+                    &mut event as *mut Event as *mut c_void,
+                )
+            };
+            if dequeued.is_err() {
+                break;
+            }
+            if event.typ == EVENT_SOURCE_CHANGE && event.src_change() & SRC_CH_RESOLUTION != 0 {
+                self.changed = true;
+            }
+        }
+        self.changed
+    }
+}
+
+/// Sets the streams of inputs whose source changed up again, before their next task is
+/// spawned, and reports the formats set with [`Input::set_format`]
+pub(crate) fn follow_source_changes(
+    mut inputs: Query<(Entity, &mut Input)>,
+    mut images: ResMut<Assets<Image>>,
+    mut changed: EventWriter<FormatChanged>,
+) {
+    for (entity, mut input) in inputs.iter_mut() {
+        if let Some(previous) = input.format_changed.take() {
+            changed.send(FormatChanged {
+                entity,
+                device: input.device.id,
+                label: input.device.label().to_string(),
+                previous,
+                size: UVec2::new(input.device.size.width, input.device.size.height),
+            });
+        }
+        // the task that saw the change is polled first
+        if input.device.task.is_some() {
+            continue;
+        }
+        let Some(dev) = input.device.dev.as_ref() else {
+            continue;
+        };
+        let Some(format) = detect(&input, dev) else {
+            continue;
+        };
+
+        let span = input.device.span.clone();
+        let _span = span.enter();
+        let previous = UVec2::new(input.device.size.width, input.device.size.height);
+        let profile = Profile {
+            format: Format(format),
+            controls: Vec::new(),
+        };
+        if let Err(err) = profile::apply(&mut input, &profile, "the changed source", &mut images) {
+            // the closed stream reports errors until the source changes again
+            warn!(%err, "following the source change failed");
+            continue;
+        }
+
+        let size = UVec2::new(input.device.size.width, input.device.size.height);
+        info!(%previous, %size, "v4l source changed resolution");
+        changed.send(FormatChanged {
+            entity,
+            device: input.device.id,
+            label: input.device.label().to_string(),
+            previous,
+            size,
+        });
+    }
+}
+
+/// Format of the signal the device detects after a source change, `None` without one.
+/// The buffers are freed first, receivers of digital video only take the timings they
+/// detected without them, and report the format of those afterwards.
+fn detect(input: &Input, dev: &v4l::Device) -> Option<v4l::Format> {
+    let mut io = input.device.io.lock().ok()?;
+    let change = io.source_change.as_mut()?;
+    if !mem::take(&mut change.changed) {
+        return None;
+    }
+    io.stream = IoStream::Closed;
+    drop(io);
+
+    // devices without dv timings fail with ENOTTY, their format follows the source
+    unsafe {
+        let fd = dev.handle().fd() as c_int;
+        let mut timings: RawTimings = mem::zeroed();
+        let timings = &mut timings as *mut RawTimings as *mut c_void;
+        match v4l2::ioctl(fd, VIDIOC_QUERY_DV_TIMINGS, timings) {
+            Ok(_) => {
+                if let Err(err) = v4l2::ioctl(fd, VIDIOC_S_DV_TIMINGS, timings) {
+                    warn!(%err, "failed to set the detected dv timings");
+                }
+            }
+            // frames that arrive after the signal came back restore it
+            Err(err) if err.raw_os_error() == Some(ENOLINK) => {
+                let mut io = input.device.io.lock().ok()?;
+                if let Some(signal) = io.signal.as_mut() {
+                    signal.no_link();
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    match Capture::format(dev) {
+        Ok(format) => {
+            info!(format = describe_format(&format), "v4l source changed");
+            Some(format)
+        }
+        Err(err) => {
+            warn!(%err, "failed to query the format of the changed source");
+            None
+        }
+    }
+}
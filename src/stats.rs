@@ -0,0 +1,212 @@
+use bevy::prelude::*;
+
+use std::time::{Duration, Instant};
+
+use crate::{Error, FrameId, WaitStrategy};
+
+/// Streaming health of an [`Input`](crate::Input) or [`Output`](crate::Output), see
+/// [`Input::stats`](crate::Input::stats). Counted since the device was opened, or
+/// since it reconnected after a [`DeviceLost`](crate::DeviceLost). Updated once per
+/// frame by the plugin.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamStats {
+    /// Buffers dequeued from the driver, every captured frame of inputs, including
+    /// dropped ones, and every frame written by outputs
+    pub dequeued: u64,
+    /// Frames converted into the image of an input, or written by an output
+    pub presented: u64,
+    /// Frames the plugin dropped, too slow to convert every frame or rejecting them
+    pub dropped: DroppedFrames,
+    /// Frames the driver dropped before they were dequeued, the gaps in its sequence
+    /// numbers, see [`FrameReceived::lost`](crate::FrameReceived::lost). Many of these
+    /// call for more buffers, many `dropped.stale` for faster conversion.
+    pub kernel_dropped: u64,
+    /// Errors since the last frame presented, 0 while streaming fine
+    pub consecutive_errors: u32,
+    /// Message of the latest error, kept after the stream recovered
+    pub last_error: Option<String>,
+    /// Time since the first frame was read or written, `None` before
+    pub uptime: Option<Duration>,
+}
+
+/// Frames of an input that were dequeued but didn't reach its image, by why
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DroppedFrames {
+    /// Older frames waiting in the driver's queue with a newer one, see
+    /// [`FrameReceived::dropped`](crate::FrameReceived::dropped)
+    pub stale: u64,
+    /// Frames the driver flagged as corrupt or that were shorter than their format,
+    /// see [`FrameReceived::corrupt`](crate::FrameReceived::corrupt)
+    pub short: u64,
+    /// Frames that failed to convert
+    pub decode_failed: u64,
+}
+
+impl DroppedFrames {
+    pub fn total(&self) -> u64 {
+        self.stale + self.short + self.decode_failed
+    }
+}
+
+/// Counted by the io task while it holds the io lock anyway, and folded into the
+/// [`Health`] of the device once the task is done, so streaming takes no extra locks
+#[derive(Default)]
+pub(crate) struct Counts {
+    pub(crate) dequeued: u64,
+    pub(crate) stale: u64,
+    pub(crate) short: u64,
+    /// Gaps in the sequence of the driver
+    pub(crate) lost: u64,
+}
+
+/// [`StreamStats`] of a device, only touched by the plugin
+#[derive(Default)]
+pub(crate) struct Health {
+    stats: StreamStats,
+    /// When the first frame was read or written
+    since: Option<Instant>,
+}
+
+impl Health {
+    /// The stats, with the uptime as of now
+    pub(crate) fn stats(&self) -> StreamStats {
+        StreamStats {
+            uptime: self.since.map(|since| since.elapsed()),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Adds the counts of a finished task, and `presented` frames
+    pub(crate) fn add(&mut self, counts: Counts, presented: u64) {
+        let stats = &mut self.stats;
+        stats.dequeued += counts.dequeued;
+        stats.dropped.stale += counts.stale;
+        stats.dropped.short += counts.short;
+        stats.kernel_dropped += counts.lost;
+        if presented > 0 {
+            stats.presented += presented;
+            stats.consecutive_errors = 0;
+            self.since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub(crate) fn error(&mut self, error: &Error) {
+        let stats = &mut self.stats;
+        if matches!(error, Error::Decode(_)) {
+            stats.dropped.decode_failed += 1;
+        }
+        stats.consecutive_errors += 1;
+        stats.last_error = Some(error.to_string());
+    }
+
+    /// Starts over for a reconnected device, the last error is kept
+    pub(crate) fn reset(&mut self) {
+        *self = Self {
+            stats: StreamStats {
+                last_error: self.stats.last_error.take(),
+                ..Default::default()
+            },
+            since: None,
+        };
+    }
+}
+
+/// Luma statistics of a captured frame, sent for inputs with
+/// [`InputBuilder::stats`](crate::InputBuilder::stats)
+#[derive(Event, Debug, Clone)]
+pub struct FrameStats {
+    pub entity: Entity,
+    pub frame: FrameId,
+    /// Pixel counts per luma range, darkest first, evenly splitting 0..=255
+    pub histogram: Vec<u32>,
+    /// Mean luma, 0 to 1
+    pub mean: f32,
+    /// Fraction of pixels at luma 0
+    pub clipped_black: f32,
+    /// Fraction of pixels at luma 255
+    pub clipped_white: f32,
+    /// Frames dequeued but not converted since the previous stats, while the input
+    /// was inactive, see [`Input::set_active`](crate::Input::set_active), or over its
+    /// [`InputBuilder::conversion_budget`](crate::InputBuilder::conversion_budget)
+    pub skipped: u32,
+    /// How the io task waits for frames, see [`InputBuilder::wait`](crate::InputBuilder::wait)
+    pub wait: WaitStrategy,
+    /// Time from capture to dequeue of the frame, for drivers with monotonic timestamps
+    pub wake_latency: Option<Duration>,
+    /// Time from dequeue of the latest frame to its swap into the image, or its write
+    /// to the texture for [`InputBuilder::late_upload`](crate::InputBuilder::late_upload)
+    /// inputs. Late uploads happen after the event is sent, so theirs is the previous frame's.
+    pub upload_latency: Option<Duration>,
+    /// Watchdog steps taken since the input was opened, see
+    /// [`InputBuilder::watchdog`](crate::InputBuilder::watchdog)
+    pub escalations: u32,
+    /// Average time converting a frame, for inputs with a conversion budget
+    pub conversion: Option<Duration>,
+}
+
+/// Counts the luma of every pixel while a frame is converted
+pub(crate) struct LumaHistogram {
+    buckets: usize,
+    counts: [u32; 256],
+    /// Statistics of the latest frame, sent once the task is done
+    pub(crate) latest: Option<FrameStats>,
+}
+
+impl LumaHistogram {
+    pub(crate) fn new(buckets: usize) -> Self {
+        Self {
+            buckets: buckets.clamp(1, 256),
+            counts: [0; 256],
+            latest: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, luma: u8) {
+        self.counts[luma as usize] += 1;
+    }
+
+    /// Whether nothing was pushed since the last [`LumaHistogram::finish`]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+
+    /// Counts an rgba frame, for converters that don't push luma themselves
+    pub(crate) fn push_rgba(&mut self, rgba: &[u8]) {
+        for pixel in rgba.chunks_exact(4) {
+            let [r, g, b] = [pixel[0] as u32, pixel[1] as u32, pixel[2] as u32];
+            self.push(((77 * r + 150 * g + 29 * b) >> 8) as u8);
+        }
+    }
+
+    /// Turns the counts into [`FrameStats`] in `latest` and starts over
+    pub(crate) fn finish(&mut self, frame: FrameId, skipped: u32) {
+        let total: u64 = self.counts.iter().map(|&count| count as u64).sum();
+        let sum: u64 = (0..256)
+            .map(|luma| luma * self.counts[luma as usize] as u64)
+            .sum();
+        let fraction = |count: u32| count as f32 / total.max(1) as f32;
+
+        let mut histogram = vec![0; self.buckets];
+        for (luma, count) in self.counts.iter().enumerate() {
+            histogram[luma * self.buckets / 256] += count;
+        }
+
+        self.latest = Some(FrameStats {
+            // filled in by the plugin when the event is sent, like the wait statistics
+            entity: Entity::PLACEHOLDER,
+            frame,
+            histogram,
+            mean: sum as f32 / total.max(1) as f32 / 255.0,
+            clipped_black: fraction(self.counts[0]),
+            clipped_white: fraction(self.counts[255]),
+            skipped,
+            wait: WaitStrategy::default(),
+            wake_latency: None,
+            upload_latency: None,
+            escalations: 0,
+            conversion: None,
+        });
+        self.counts = [0; 256];
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+use crate::raw::BufferPool;
+use crate::{FrameId, Timestamp};
+
+/// Frames a subscriber can fall behind before new ones are dropped for it
+const CAPACITY: usize = 4;
+
+/// A frame as it was dequeued, sent to the receivers of
+/// [`Input::subscribe`](crate::Input::subscribe) before it is converted
+#[derive(Debug, Clone)]
+pub struct FrameRef {
+    pub fourcc: [u8; 4],
+    /// Copy of the used bytes of the buffer, the driver buffer is requeued without
+    /// waiting for receivers. The copy is reused once every clone of it is dropped.
+    pub data: Arc<[u8]>,
+    pub frame: FrameId,
+    /// Capture time reported by the driver
+    pub timestamp: Timestamp,
+}
+
+/// Senders of the subscribers of an input, shared by the input and its io task.
+/// Only ever locked on its own, never while waiting for the io.
+pub(crate) type Subscribers = Arc<Mutex<Vec<SyncSender<FrameRef>>>>;
+
+pub(crate) fn subscribe(subscribers: &Subscribers) -> Receiver<FrameRef> {
+    let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.push(sender);
+    }
+    receiver
+}
+
+/// Sends dequeued frames to the subscribers of an input from its io task
+pub(crate) struct Publisher {
+    subscribers: Subscribers,
+    pool: BufferPool,
+}
+
+impl Publisher {
+    pub(crate) fn new(subscribers: Subscribers) -> Self {
+        Self {
+            subscribers,
+            pool: BufferPool::default(),
+        }
+    }
+
+    /// Never blocks, full receivers miss the frame and dropped ones are unsubscribed
+    pub(crate) fn publish(
+        &mut self,
+        fourcc: [u8; 4],
+        buf: &[u8],
+        frame: FrameId,
+        timestamp: Timestamp,
+    ) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let frame = FrameRef {
+            fourcc,
+            data: self.pool.copy(buf),
+            frame,
+            timestamp,
+        };
+        subscribers.retain(|sender| match sender.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
@@ -0,0 +1,31 @@
+/// Workarounds for drivers that mislabel their frames, see
+/// [`InputBuilder::interpret_as`](crate::InputBuilder::interpret_as) and
+/// [`InputBuilder::swizzle`](crate::InputBuilder::swizzle)
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Overrides {
+    /// Format the frames are converted as, instead of the one the driver reports
+    pub(crate) fourcc: Option<[u8; 4]>,
+    /// Channel of the converted pixel every rgba channel is taken from
+    pub(crate) channels: Option<[usize; 4]>,
+}
+
+impl Overrides {
+    /// Format to convert frames the driver reports as `fourcc` as
+    pub(crate) fn fourcc(&self, fourcc: [u8; 4]) -> [u8; 4] {
+        self.fourcc.unwrap_or(fourcc)
+    }
+
+    /// Permutes the channels of every rgba8 pixel of a converted frame
+    pub(crate) fn apply(&self, rgba: &mut [u8]) {
+        let Some(channels) = self.channels else {
+            return;
+        };
+
+        for pixel in rgba.chunks_exact_mut(4) {
+            let converted = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            for (dst, &channel) in pixel.iter_mut().zip(&channels) {
+                *dst = converted[channel];
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+
+use crate::color::Linearize;
+use crate::orientation;
+use crate::ImageEncoding;
+
+/// How an extra image target of an [`Input`](crate::Input) stores frames,
+/// see [`Input::add_target`](crate::Input::add_target)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TargetOptions {
+    pub encoding: ImageEncoding,
+    /// Mirrors the frames left to right, see [`Input::mirrored_image`](crate::Input::mirrored_image)
+    pub mirror: bool,
+}
+
+/// An extra image converted from every frame of an input
+pub(crate) struct Target {
+    pub(crate) image: AssetId<Image>,
+    encoding: ImageEncoding,
+    mirror: bool,
+    linearize: Option<Linearize>,
+    /// Swapped with the image data like the frame buffer
+    pub(crate) buffer: Vec<u8>,
+}
+
+impl Target {
+    pub(crate) fn new(image: AssetId<Image>, options: TargetOptions, pixels: usize) -> Self {
+        let encoding = options.encoding;
+        Self {
+            image,
+            encoding,
+            mirror: options.mirror,
+            linearize: Linearize::new(encoding),
+            buffer: vec![255; pixels * encoding.bytes_per_pixel()],
+        }
+    }
+
+    /// Converts the first `pixels` pixels of the sRGB encoded rgba `src`, a frame with
+    /// rows of `width` pixels
+    pub(crate) fn update(&mut self, src: &[u8], width: usize, pixels: usize) {
+        if self.encoding == ImageEncoding::Luma {
+            for (dst, rgba) in self.buffer.iter_mut().zip(src.chunks_exact(4)) {
+                let [r, g, b] = [rgba[0] as u32, rgba[1] as u32, rgba[2] as u32];
+                *dst = ((77 * r + 150 * g + 29 * b) >> 8) as u8;
+            }
+            if self.mirror {
+                let len = pixels.min(self.buffer.len());
+                mirror(&mut self.buffer[..len], width, 1);
+            }
+            return;
+        }
+
+        let len = (pixels * 4).min(src.len()).min(self.buffer.len());
+        self.buffer[..len].copy_from_slice(&src[..len]);
+        // the rgba rows, before wider encodings spread them over the buffer
+        if self.mirror {
+            mirror(&mut self.buffer[..len], width, 4);
+        }
+
+        if let Some(linearize) = &self.linearize {
+            linearize.apply(&mut self.buffer, pixels);
+        }
+    }
+}
+
+/// Reverses the pixels of every row of `width` pixels of `bytes` each
+fn mirror(frame: &mut [u8], width: usize, bytes: usize) {
+    for row in frame.chunks_exact_mut((width * bytes).max(1)) {
+        orientation::mirror(row, bytes);
+    }
+}
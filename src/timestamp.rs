@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use crate::Error;
+
+/// V4L2_BUF_FLAG_TIMESTAMP_MASK and its values
+const TIMESTAMP_MASK: u32 = 0xe000;
+const TIMESTAMP_MONOTONIC: u32 = 0x2000;
+const TIMESTAMP_COPY: u32 = 0x4000;
+
+/// Where the time of a [`Timestamp`] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimestampSource {
+    /// Taken by the driver from CLOCK_MONOTONIC, usually when the frame was captured
+    Monotonic,
+    /// Copied by an m2m device from the buffer it was produced from,
+    /// in whatever clock that buffer used
+    Copy,
+    /// CLOCK_MONOTONIC when the crate dequeued the frame, used when the driver
+    /// doesn't say what its timestamps mean or was told not to be trusted
+    DequeueTime,
+}
+
+/// Time a frame was captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Time since an unspecified point, comparable between timestamps of the same source
+    pub time: Duration,
+    pub source: TimestampSource,
+}
+
+impl Timestamp {
+    /// Reads the timestamp of a dequeued buffer, falling back to the dequeue time
+    /// when the clock of the driver is unknown
+    pub(crate) fn from_buffer(flags: u32, time: Duration) -> Self {
+        let source = match flags & TIMESTAMP_MASK {
+            TIMESTAMP_MONOTONIC => TimestampSource::Monotonic,
+            TIMESTAMP_COPY => TimestampSource::Copy,
+            _ => return Self::now(),
+        };
+
+        Self { time, source }
+    }
+
+    /// CLOCK_MONOTONIC now, the clock drivers use for their timestamps
+    pub(crate) fn now() -> Self {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // can't fail with a valid clock and pointer
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+
+        Self {
+            time: Duration::new(now.tv_sec as u64, now.tv_nsec as u32),
+            source: TimestampSource::DequeueTime,
+        }
+    }
+}
+
+/// The frame an input last swapped into its image, see [`Input::last_presented`](crate::Input::last_presented)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presented {
+    /// When the frame was captured
+    pub timestamp: Timestamp,
+    /// CLOCK_MONOTONIC when the frame was swapped into the image, or written to the
+    /// texture for [`InputBuilder::late_upload`](crate::InputBuilder::late_upload) and
+    /// gpu converted inputs
+    pub at: Duration,
+}
+
+impl Presented {
+    /// Time from capture to presentation, `None` for [`TimestampSource::Copy`]
+    /// timestamps, whose clock isn't known
+    pub fn latency(&self) -> Option<Duration> {
+        match self.timestamp.source {
+            TimestampSource::Copy => None,
+            TimestampSource::Monotonic | TimestampSource::DequeueTime => {
+                Some(self.at.saturating_sub(self.timestamp.time))
+            }
+        }
+    }
+}
+
+/// Presentation timestamps supplied by the app for the frames of an output,
+/// see [`Output::present`](crate::Output::present)
+#[derive(Debug, Default)]
+pub(crate) struct Presenter {
+    next: Option<Duration>,
+    last: Option<Duration>,
+}
+
+impl Presenter {
+    pub(crate) fn present(&mut self, pts: Duration) {
+        self.next = Some(pts);
+    }
+
+    /// Timestamp of the frame being written. Without a timestamp from the app, or when
+    /// it isn't after the previous one, this is CLOCK_MONOTONIC like a capture device's.
+    pub(crate) fn next(&mut self) -> (Duration, Option<Error>) {
+        let now = Timestamp::now().time;
+        let Some(pts) = self.next.take() else {
+            return (now, None);
+        };
+
+        match self.last {
+            Some(previous) if pts <= previous => {
+                (now, Some(Error::NonMonotonicTimestamp { pts, previous }))
+            }
+            _ => {
+                self.last = Some(pts);
+                (pts, None)
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use tracing::{warn, Span};
+
+use crate::{stream_write, Io};
+
+/// Keeps the frames of an [`Output`](crate::Output) coming while the app doesn't write
+/// any, like during a hitch, see [`OutputBuilder::repeat_on_underrun`](crate::OutputBuilder::repeat_on_underrun)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnderrunPolicy {
+    /// Rate the consumer expects frames at
+    pub fps: f32,
+    /// Repeated frames in a row before an [`OutputUnderrun`] is sent, never for 0
+    pub threshold: u32,
+}
+
+/// Sent once an output repeated [`UnderrunPolicy::threshold`] frames in a row
+#[derive(Event, Debug, Clone)]
+pub struct OutputUnderrun {
+    pub entity: Entity,
+    pub device: usize,
+    pub label: String,
+    /// Frames repeated in a row so far
+    pub repeated: u32,
+    /// Frames repeated since the output was opened
+    pub total: u32,
+}
+
+/// Underrun state of an output, updated by regular writes and the repeater thread
+pub(crate) struct Underruns {
+    threshold: u32,
+    last_write: Instant,
+    /// Image size of the last regular write, the size of the frame in the buffer
+    size: (u32, u32),
+    streak: u32,
+    pub(crate) total: u32,
+    /// (repeated, total), sent as an [`OutputUnderrun`] once the task is done
+    pub(crate) pending: Option<(u32, u32)>,
+}
+
+impl Underruns {
+    pub(crate) fn new(threshold: u32, size: (u32, u32)) -> Self {
+        Self {
+            threshold,
+            last_write: Instant::now(),
+            size,
+            streak: 0,
+            total: 0,
+            pending: None,
+        }
+    }
+
+    /// Records a frame written from the image
+    pub(crate) fn written(&mut self, size: (u32, u32)) {
+        self.last_write = Instant::now();
+        self.size = size;
+        self.streak = 0;
+    }
+
+    fn repeated(&mut self) {
+        self.last_write = Instant::now();
+        self.streak += 1;
+        self.total += 1;
+        if self.streak == self.threshold {
+            self.pending = Some((self.streak, self.total));
+        }
+    }
+}
+
+/// Repeats the last frame of the output behind `io` whenever a frame interval passes
+/// without a write, until the output is dropped
+pub(crate) fn spawn_repeater(
+    io: Weak<Mutex<Io>>,
+    format: v4l::Format,
+    fps: f32,
+    id: usize,
+    span: Span,
+) -> std::io::Result<()> {
+    let interval = Duration::from_secs_f32(1.0 / fps.max(f32::EPSILON));
+
+    std::thread::Builder::new()
+        .name(format!("v4l repeater {id}"))
+        .spawn(move || {
+            span.in_scope(|| loop {
+                std::thread::sleep(interval);
+                let Some(io) = io.upgrade() else {
+                    break;
+                };
+                repeat(&io, &format, interval);
+            })
+        })?;
+    Ok(())
+}
+
+fn repeat(io: &Arc<Mutex<Io>>, format: &v4l::Format, interval: Duration) {
+    let Ok(mut io) = io.lock() else {
+        return;
+    };
+    if io.paused {
+        return;
+    }
+    let (width, height) = match io.underruns.as_ref() {
+        Some(underruns) if underruns.last_write.elapsed() >= interval => underruns.size,
+        _ => return,
+    };
+
+    // the buffer was already processed when it was written
+    let processor = io.processor.take();
+    // fresh sequence and timestamp, strict consumers drop duplicates
+    if let Err(err) = stream_write(&mut io, format, width, height) {
+        warn!(%err, "failed to repeat output frame");
+    }
+    io.processor = processor;
+
+    if let Some(underruns) = io.underruns.as_mut() {
+        underruns.repeated();
+    }
+}
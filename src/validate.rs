@@ -0,0 +1,142 @@
+use crate::source::FrameMeta;
+use crate::{bayer, describe_format, Error, FourCC, Result};
+
+/// Largest width or height accepted from a driver
+const MAX_DIMENSION: u32 = 16384;
+
+/// Checks a format reported by a driver before buffers are sized from it,
+/// some drivers briefly report nonsense like a width of 0 while reconfiguring
+pub(crate) fn format(format: &v4l::Format) -> Result<()> {
+    let invalid = |reason: String| {
+        Err(Error::InvalidFormat {
+            format: describe_format(format),
+            reason,
+        })
+    };
+
+    let (width, height) = (format.width, format.height);
+    if width == 0 || height == 0 {
+        return invalid("width and height have to be nonzero".into());
+    }
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return invalid(format!(
+            "width and height have to be at most {MAX_DIMENSION}"
+        ));
+    }
+
+    let Some((bytes_per_pixel, size)) = layout(&format.fourcc.repr) else {
+        return Ok(());
+    };
+
+    // drivers may leave stride and size at 0 for formats they don't pad
+    let stride = match format.stride {
+        0 => width * bytes_per_pixel,
+        stride if stride < width * bytes_per_pixel => {
+            return invalid(format!(
+                "stride {stride} is less than {} bytes per row",
+                width * bytes_per_pixel
+            ))
+        }
+        stride => stride,
+    };
+    let needed = size(stride as u64, height as u64);
+    if format.size != 0 && (format.size as u64) < needed {
+        return invalid(format!("size {} is less than {needed} bytes", format.size));
+    }
+
+    Ok(())
+}
+
+/// Rejects requested sizes the chroma subsampling of the format doesn't divide, like an
+/// odd width for YUYV, whose macropixels hold two pixels
+pub(crate) fn subsampling(format: &v4l::Format) -> Result<()> {
+    let fourcc = &format.fourcc.repr;
+    let (even_width, even_height) = match fourcc {
+        b"YUYV" | b"UYVY" | b"YVYU" => (true, false),
+        b"NV12" | b"NV21" | b"YU12" | b"NM12" => (true, true),
+        _ => return Ok(()),
+    };
+    let odd = match (format.width % 2 == 1, format.height % 2 == 1) {
+        (true, _) if even_width => "width",
+        (_, true) if even_height => "height",
+        _ => return Ok(()),
+    };
+    Err(Error::InvalidFormat {
+        format: describe_format(format),
+        reason: format!("{} needs an even {odd}", FourCC::new(fourcc)),
+    })
+}
+
+/// Why a dequeued buffer can't be converted, `None` for ones that look complete.
+/// Drivers flag buffers they know are corrupt, short ones are left by transfers that
+/// were cut off, which is common on flaky USB hubs.
+pub(crate) fn frame(
+    fourcc: &[u8; 4],
+    width: u32,
+    height: u32,
+    stride: u32,
+    meta: &FrameMeta,
+) -> Option<String> {
+    if meta.error {
+        return Some("flagged by the driver".into());
+    }
+
+    let used = meta.bytesused as u64;
+    let Some((bytes_per_pixel, size)) = layout(fourcc) else {
+        // drivers always say how much of a compressed frame they filled
+        return (used == 0).then(|| "empty".into());
+    };
+    // some drivers leave bytesused at 0 for uncompressed formats, the whole buffer is used
+    let stride = stride.max(padded(fourcc, width) * bytes_per_pixel);
+    let needed = size(stride as u64, height as u64);
+    (used != 0 && used < needed).then(|| format!("{used} bytes, expected {needed}"))
+}
+
+/// Bytes in a row of `width` pixels without padding, `None` for formats whose layout
+/// isn't known
+pub(crate) fn row_bytes(fourcc: &[u8; 4], width: u32) -> Option<usize> {
+    layout(fourcc).map(|(bytes_per_pixel, _)| (padded(fourcc, width) * bytes_per_pixel) as usize)
+}
+
+/// Bytes between the rows of the chroma planes of 4:2:0 frames whose luma rows are
+/// `stride` bytes apart. Chroma rows of odd widths end on the samples of a block of a
+/// single column, which tight luma rows have no room for.
+pub(crate) fn chroma_stride(fourcc: &[u8; 4], stride: usize) -> usize {
+    match fourcc {
+        b"YU12" => stride.div_ceil(2),
+        _ => stride.next_multiple_of(2),
+    }
+}
+
+/// Rows of packed 4:2:2 end on a whole macropixel, odd widths have half a one at the end
+fn padded(fourcc: &[u8; 4], width: u32) -> u32 {
+    match fourcc {
+        b"YUYV" | b"UYVY" | b"YVYU" => width.next_multiple_of(2),
+        _ => width,
+    }
+}
+
+/// The frame size for a first plane of rows of a given stride and a given height
+type FrameSize = fn(u64, u64) -> u64;
+
+/// Bytes per pixel of the first plane and the frame size for its stride and height,
+/// `None` for formats whose layout isn't known, like compressed ones
+fn layout(fourcc: &[u8; 4]) -> Option<(u32, FrameSize)> {
+    match fourcc {
+        b"YUYV" | b"UYVY" | b"YVYU" | b"RGBP" | b"Y16 " => {
+            Some((2, |stride, height| stride * height))
+        }
+        b"AB24" | b"RGB4" => Some((4, |stride, height| stride * height)),
+        b"RGB3" | b"BGR3" | b"IYU2" => Some((3, |stride, height| stride * height)),
+        b"GREY" => Some((1, |stride, height| stride * height)),
+        // chroma planes of half the rows after the luma plane, rounded up for odd sizes
+        b"NV12" | b"NV21" => Some((1, |stride, height| {
+            stride * height + stride.next_multiple_of(2) * height.div_ceil(2)
+        })),
+        b"YU12" => Some((1, |stride, height| {
+            stride * height + stride.div_ceil(2) * height.div_ceil(2) * 2
+        })),
+        fourcc if bayer::is_bayer(fourcc) => Some((1, |stride, height| stride * height)),
+        _ => None,
+    }
+}
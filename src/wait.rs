@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+use crate::{Timestamp, TimestampSource};
+
+/// How the io task of an [`Input`](crate::Input) waits for the next frame,
+/// see [`InputBuilder::wait`](crate::InputBuilder::wait)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+    /// Blocks dequeuing until the frame is there, parking an async compute pool thread
+    /// for most of every frame. The device is polled in slices of 100ms meanwhile, so
+    /// closing or pausing the input doesn't wait out a slow frame.
+    #[default]
+    Blocking,
+    /// Polls the device for at most `timeout`. Without a frame by then the task
+    /// finishes and the next update tries again, a zero timeout spins once per update.
+    Poll { timeout: Duration },
+    /// Sleeps until shortly before the next frame is expected from the measured
+    /// frame interval, then blocks. Fewest wake-ups, at the cost of some latency
+    /// when the frame rate drops.
+    Adaptive,
+}
+
+/// Weight of the previous frame intervals in the measured one, out of 8
+const INTERVAL_WEIGHT: u32 = 7;
+
+/// Implements a [`WaitStrategy`] around dequeuing and measures how long frames
+/// waited to be dequeued
+pub(crate) struct Waiter {
+    pub(crate) strategy: WaitStrategy,
+    /// Average time between dequeued frames, for [`WaitStrategy::Adaptive`]
+    interval: Option<Duration>,
+    last: Option<Instant>,
+    /// Time from capture to dequeue of the latest frame, where the driver's clock is known
+    pub(crate) latency: Option<Duration>,
+}
+
+impl Waiter {
+    pub(crate) fn new(strategy: WaitStrategy) -> Self {
+        Self {
+            strategy,
+            interval: None,
+            last: None,
+            latency: None,
+        }
+    }
+
+    /// Timeout for the dequeue, `None` blocks
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self.strategy {
+            WaitStrategy::Poll { timeout } => Some(timeout),
+            WaitStrategy::Blocking | WaitStrategy::Adaptive => None,
+        }
+    }
+
+    /// Sleeps before dequeuing for [`WaitStrategy::Adaptive`]
+    pub(crate) fn sleep(&self) {
+        if self.strategy != WaitStrategy::Adaptive {
+            return;
+        }
+
+        let Some((interval, last)) = self.interval.zip(self.last) else {
+            return;
+        };
+        // wake up with a quarter of the interval to spare
+        if let Some(remaining) = (interval * 3 / 4).checked_sub(last.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    /// Records a dequeued frame
+    pub(crate) fn dequeued(&mut self, timestamp: Timestamp) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            let elapsed = now - last;
+            self.interval = Some(match self.interval {
+                Some(interval) => (interval * INTERVAL_WEIGHT + elapsed) / 8,
+                None => elapsed,
+            });
+        }
+        self.last = Some(now);
+
+        self.latency = match timestamp.source {
+            TimestampSource::Monotonic => Timestamp::now().time.checked_sub(timestamp.time),
+            TimestampSource::Copy | TimestampSource::DequeueTime => None,
+        };
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use tracing::{error, warn};
+
+use crate::memory::Buffers;
+use crate::source::IoStream;
+use crate::source_change::SourceChange;
+use crate::{Error, Io};
+
+/// When an [`Input`](crate::Input) whose dequeues keep failing is escalated, see
+/// [`InputBuilder::watchdog`](crate::InputBuilder::watchdog). Unlike the restarts of
+/// transient errors this also catches devices that stop delivering frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchdogPolicy {
+    /// Dequeues without a frame for longer than this fail. Inputs that block
+    /// for frames time out after it.
+    pub timeout: Duration,
+    /// Failures in a row within `window` before the next step of the escalation
+    pub failures: u32,
+    pub window: Duration,
+}
+
+impl Default for WatchdogPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            failures: 3,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Steps of the escalation, in order. A frame starts over at the first step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Stops and starts the stream, like after a transient error
+    RestartStream,
+    /// Closes the device and opens it again with new buffers
+    ReopenDevice,
+    /// Stops reading the device, the input shows its last frame from now on
+    GiveUp,
+}
+
+/// Sent when a watchdog escalated a step
+#[derive(Event, Debug)]
+pub struct WatchdogEscalated {
+    pub entity: Entity,
+    /// ID of the v4l video device (/dev/video{id})
+    pub device: usize,
+    /// Names the device like its logs do, like "/dev/video2"
+    pub label: String,
+    pub action: WatchdogAction,
+    /// Failures in a row that led to the step
+    pub failures: u32,
+    /// Steps taken since the input was opened
+    pub escalations: u32,
+    /// Latest failure, a timeout for devices that stopped delivering frames
+    pub error: Error,
+}
+
+/// Watchdog state of an input, updated by its io task
+pub(crate) struct Watchdog {
+    policy: WatchdogPolicy,
+    /// Buffers and timeout of streams of reopened devices
+    buffers: Buffers,
+    timeout: Option<Duration>,
+    /// Last frame or escalation, timeouts only fail once `policy.timeout` passed since
+    last_frame: Instant,
+    streak: u32,
+    streak_start: Instant,
+    next: WatchdogAction,
+    pub(crate) escalations: u32,
+    pub(crate) gave_up: bool,
+    /// (action, failures, error), sent as a [`WatchdogEscalated`] once the task is done
+    pub(crate) pending: Option<(WatchdogAction, u32, Error)>,
+    /// Opened by [`WatchdogAction::ReopenDevice`], replaces the device of the input
+    pub(crate) reopened: Option<v4l::Device>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(policy: WatchdogPolicy, buffers: Buffers, timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            policy,
+            buffers,
+            timeout,
+            last_frame: now,
+            streak: 0,
+            streak_start: now,
+            next: WatchdogAction::RestartStream,
+            escalations: 0,
+            gave_up: false,
+            pending: None,
+            reopened: None,
+        }
+    }
+
+    /// Records a dequeued frame
+    pub(crate) fn frame(&mut self) {
+        self.last_frame = Instant::now();
+        self.streak = 0;
+        self.next = WatchdogAction::RestartStream;
+    }
+
+    /// Records a failed dequeue, returns the step to take once enough failed in a row
+    fn failed(&mut self, timed_out: bool) -> Option<(WatchdogAction, u32)> {
+        let now = Instant::now();
+        // polling inputs time out all the time
+        if timed_out && now - self.last_frame < self.policy.timeout {
+            return None;
+        }
+
+        if self.streak == 0 || now - self.streak_start > self.policy.window {
+            self.streak = 0;
+            self.streak_start = now;
+        }
+        self.streak += 1;
+        if self.streak < self.policy.failures.max(1) {
+            return None;
+        }
+
+        let failures = std::mem::take(&mut self.streak);
+        let action = self.next;
+        self.next = match action {
+            WatchdogAction::RestartStream => WatchdogAction::ReopenDevice,
+            WatchdogAction::ReopenDevice | WatchdogAction::GiveUp => WatchdogAction::GiveUp,
+        };
+        // every step gets a full timeout to recover
+        self.last_frame = now;
+        self.escalations += 1;
+        Some((action, failures))
+    }
+}
+
+/// Counts a failed dequeue of an input with a watchdog and takes the next step of the
+/// escalation when it is due. The error is returned when no step was taken.
+pub(crate) fn check(io: &mut Io, id: usize, err: Error) -> std::result::Result<(), Error> {
+    let timed_out = err.timed_out();
+    let Some(watchdog) = io.watchdog.as_mut() else {
+        return Err(err);
+    };
+    let Some((action, failures)) = watchdog.failed(timed_out) else {
+        return Err(err);
+    };
+
+    warn!(?action, failures, %err, "v4l watchdog escalating");
+    match action {
+        WatchdogAction::RestartStream => {
+            if let Err(restart) = io.stream.restart() {
+                io.error = Some(restart.into());
+            }
+        }
+        WatchdogAction::ReopenDevice => {
+            // the buffers of the old device are freed before the new one allocates its own
+            io.stream = IoStream::Closed;
+            let (buffers, timeout) = (watchdog.buffers, watchdog.timeout);
+            let reopened = v4l::Device::new(id).and_then(|dev| {
+                let mut stream = buffers.memory.capture_stream(&dev, buffers.count)?;
+                stream.set_timeout(timeout);
+                Ok((dev, stream))
+            });
+
+            match reopened {
+                Ok((dev, stream)) => {
+                    io.stream = stream;
+                    // events are subscribed per open file
+                    io.source_change = SourceChange::subscribe(&dev);
+                    watchdog.reopened = Some(dev);
+                }
+                Err(reopen) => {
+                    error!(%reopen, "reopening v4l device failed");
+                    io.error = Some(reopen.into());
+                }
+            }
+        }
+        WatchdogAction::GiveUp => {
+            io.stream = IoStream::Closed;
+            watchdog.gave_up = true;
+        }
+    }
+
+    watchdog.pending = Some((action, failures, err));
+    Ok(())
+}
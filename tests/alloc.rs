@@ -0,0 +1,110 @@
+//! Allocations of the plugin while an output streams. A test binary of its own, the
+//! allocator counts the allocations of the polling systems and of the io tasks.
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use bevy::ecs::schedule::{ExecutorKind, ScheduleLabel};
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, TaskPoolBuilder};
+use bevy_v4l::{Format, Output, V4lSet};
+use common::{app, solid_image, update_until};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// Counts the allocations of the threads that are polling or running io tasks
+struct Counting;
+
+static MEASURING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Set on the main thread while the systems of [`V4lSet::Poll`] run, and on the
+    /// threads of the [`AsyncComputeTaskPool`] the io tasks run on
+    static COUNTED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if MEASURING.load(Ordering::Relaxed) && COUNTED.with(Cell::get) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Spawning the io tasks in [`V4lSet::SpawnIo`] allocates them in the task pool, that
+/// isn't counted. Their work and the polling are.
+#[test]
+fn streaming_outputs_allocate_nothing() {
+    // the plugins keep a pool that is already set up
+    AsyncComputeTaskPool::get_or_init(|| {
+        TaskPoolBuilder::new()
+            .on_thread_spawn(|| COUNTED.with(|counted| counted.set(true)))
+            .build()
+    });
+    let mut app = app();
+    // systems run on the main thread, between the ones marking it
+    app.edit_schedule(Update.intern(), |schedule| {
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+    });
+    app.add_systems(
+        Update,
+        (
+            (|| COUNTED.with(|counted| counted.set(true))).before(V4lSet::Poll),
+            (|| COUNTED.with(|counted| counted.set(false))).after(V4lSet::Poll),
+        ),
+    );
+
+    let image = solid_image(&mut app, WIDTH, HEIGHT, [200, 100, 50, 255]);
+    let format = Format::new(WIDTH, HEIGHT, b"YUYV").unwrap();
+    let (output, frames) = Output::builder(0)
+        .format(format)
+        .initial_frame(None)
+        .pace_to_device(false)
+        .build_mock(image.clone())
+        .unwrap();
+    app.world.spawn(output);
+
+    // every update changes the image, like an app rendering into it
+    let write = |app: &mut App, count: usize| {
+        let mut written = 0;
+        update_until(app, "written frames", |app| {
+            app.world.resource_mut::<Assets<Image>>().get_mut(&image);
+            written += frames.discard();
+            written >= count
+        });
+    };
+    // the first frames allocate the copy of the image, the buffers they are encoded in
+    // and the state kept between polls
+    write(&mut app, 5);
+
+    MEASURING.store(true, Ordering::Relaxed);
+    write(&mut app, 30);
+    MEASURING.store(false, Ordering::Relaxed);
+    let allocated = ALLOCATIONS.load(Ordering::Relaxed);
+    assert_eq!(
+        allocated, 0,
+        "{allocated} allocations for 30 written frames"
+    );
+}
@@ -0,0 +1,60 @@
+//! Shared by the integration tests, each uses some of it
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_v4l::V4lPlugin;
+
+/// Io tasks of mocks paced at 1000 fps finish within a few updates, devices within a few
+/// frames of theirs
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// App with the plugin and the assets it needs, without a window or renderer
+pub fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+        .init_asset::<Image>()
+        .init_asset::<Shader>()
+        .add_plugins(V4lPlugin::default());
+    app
+}
+
+/// Runs updates until `done` is true, fails after [`TIMEOUT`]
+pub fn update_until(app: &mut App, what: &str, mut done: impl FnMut(&mut App) -> bool) {
+    let start = Instant::now();
+    while !done(app) {
+        assert!(start.elapsed() < TIMEOUT, "timed out waiting for {what}");
+        app.update();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// An rgba image of `width`x`height` pixels of `color`
+pub fn solid_image(app: &mut App, width: u32, height: u32, color: [u8; 4]) -> Handle<Image> {
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    images.add(Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &color,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all(),
+    ))
+}
+
+/// Whether every pixel of `image` is within `tolerance` of `color`, alpha aside
+pub fn close_to(image: &Image, color: [u8; 4], tolerance: u8) -> bool {
+    image.data.chunks_exact(4).all(|pixel| {
+        pixel
+            .iter()
+            .zip(color)
+            .take(3)
+            .all(|(&value, expected)| value.abs_diff(expected) <= tolerance)
+    })
+}
@@ -0,0 +1,251 @@
+//! Frames written by an output and captured by an input of the same v4l2loopback
+//! device. Needs the module loaded with `exclusive_caps=0`, like
+//! `modprobe v4l2loopback video_nr=42 exclusive_caps=0`, and the id of the device:
+//!
+//! `V4L_LOOPBACK=42 cargo test --test loopback -- --ignored`
+
+mod common;
+
+use bevy::ecs::event::ManualEventReader;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_v4l::{Error, Format, FrameReceived, FrameSent, Input, MemoryType, Output};
+use common::{app, close_to, solid_image, update_until};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+/// No channel at 255, the value images of inputs start with
+const COLOR: [u8; 4] = [200, 100, 50, 255];
+
+/// Bytes of a frame of 4:2:2, of 4:2:0 and of packed rgb
+const PACKED_422: u32 = WIDTH * HEIGHT * 2;
+const PLANAR_420: u32 = WIDTH * HEIGHT * 3 / 2;
+const RGB24: u32 = WIDTH * HEIGHT * 3;
+
+/// Formats outputs encode and inputs convert, with the error of their round trip and
+/// the bytes of their frames. Chroma of 4:2:0 is subsampled, but the blocks of the
+/// pattern are a single color.
+const FORMATS: [(&[u8; 4], u8, u32); 6] = [
+    (b"YUYV", 6, PACKED_422),
+    (b"UYVY", 6, PACKED_422),
+    (b"YVYU", 6, PACKED_422),
+    (b"NV12", 6, PLANAR_420),
+    (b"YU12", 6, PLANAR_420),
+    (b"RGB3", 0, RGB24),
+];
+
+fn loopback() -> usize {
+    std::env::var("V4L_LOOPBACK")
+        .expect("V4L_LOOPBACK is the id of the v4l2loopback device")
+        .parse()
+        .expect("V4L_LOOPBACK is a device id, like 42 for /dev/video42")
+}
+
+/// Rgba pixels of 8x8 blocks of colors without a channel at 255, so a captured frame
+/// is told apart from the image the input starts with and rows or planes that end up
+/// in the wrong place show
+fn pattern() -> Vec<u8> {
+    const COLORS: [[u8; 4]; 5] = [
+        COLOR,
+        [16, 16, 16, 255],
+        [50, 200, 100, 255],
+        [100, 50, 200, 255],
+        [235, 235, 235, 255],
+    ];
+    let mut pixels = Vec::new();
+    for y in 0..HEIGHT as usize {
+        for x in 0..WIDTH as usize {
+            pixels.extend_from_slice(&COLORS[(x / 8 + y / 8 * 3) % COLORS.len()]);
+        }
+    }
+    pixels
+}
+
+/// Writes the [`pattern`] in every format of [`FORMATS`] and captures it with stream
+/// buffers of `memory`, which the input has to report as one of `chosen`
+fn round_trip(memory: MemoryType, chosen: &[MemoryType]) {
+    let id = loopback();
+    let mut app = app();
+    let pattern = pattern();
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    let image = images.add(Image::new(
+        Extent3d {
+            width: WIDTH,
+            height: HEIGHT,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pattern.clone(),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all(),
+    ));
+
+    for (fourcc, tolerance, bytes) in FORMATS {
+        let name = String::from_utf8_lossy(fourcc).into_owned();
+        let format = Format::new(WIDTH, HEIGHT, fourcc).unwrap();
+        let output = Output::new(id, image.clone(), format).unwrap();
+        // outputs only stream mmap buffers
+        let written = output.negotiation().memory;
+        assert_eq!(written, Some(MemoryType::Mmap), "{name} output");
+        let output = app.world.spawn(output).id();
+        // the device takes the format of the output once it streams
+        let mut sent = ManualEventReader::<FrameSent>::default();
+        update_until(&mut app, &format!("{name} to be written"), |app| {
+            let events = app.world.resource::<Events<FrameSent>>();
+            for event in sent.read(events).filter(|event| event.entity == output) {
+                assert_eq!(event.bytesused, bytes, "bytes written of {name}");
+            }
+            app.world
+                .get::<Output>(output)
+                .unwrap()
+                .last_frame()
+                .is_some()
+        });
+
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        let input = Input::builder()
+            .device(id)
+            .memory(memory)
+            .build(&mut images)
+            .unwrap();
+        assert_eq!(&input.format().fourcc(), fourcc, "the loopback runs {name}");
+        let report = input.negotiation();
+        let streamed = report.memory.unwrap();
+        assert!(
+            chosen.contains(&streamed),
+            "{name} streamed {streamed} buffers for {memory}"
+        );
+        if memory == MemoryType::UserPtr && streamed == MemoryType::Mmap {
+            // drivers that reject userptr buffers stream mmap, noting why
+            let noted = report.notes.iter().any(|note| note.contains("userptr"));
+            assert!(noted, "{name} fell back to mmap without a note");
+        }
+        let captured = input.image().clone();
+        let input = app.world.spawn(input).id();
+
+        // consumers see the bytes the output wrote, not the size of the buffer
+        let mut received = ManualEventReader::<FrameReceived>::default();
+        let mut frames = 0;
+        update_until(&mut app, &format!("{name} to be captured"), |app| {
+            let events = app.world.resource::<Events<FrameReceived>>();
+            for event in received.read(events).filter(|event| event.entity == input) {
+                assert_eq!(event.bytesused, bytes, "bytes captured of {name}");
+                frames += 1;
+            }
+            let images = app.world.resource::<Assets<Image>>();
+            let converted = images
+                .get(&captured)
+                .is_some_and(|image| !close_to(image, [255; 4], 0));
+            converted && frames > 0
+        });
+        let images = app.world.resource::<Assets<Image>>();
+        let captured = images.get(&captured).unwrap();
+        let pixels = captured.data.chunks_exact(4).zip(pattern.chunks_exact(4));
+        for (i, (actual, expected)) in pixels.enumerate() {
+            let (x, y) = (i % WIDTH as usize, i / WIDTH as usize);
+            let close = actual
+                .iter()
+                .zip(expected)
+                .take(3)
+                .all(|(&actual, &expected)| actual.abs_diff(expected) <= tolerance);
+            assert!(
+                close,
+                "{name} round tripped {expected:?} to {actual:?} at {x}, {y}"
+            );
+        }
+
+        // the device takes the next format once both streams stopped
+        app.world.despawn(input);
+        app.world.despawn(output);
+        app.update();
+    }
+}
+
+#[test]
+#[ignore = "needs a v4l2loopback device, see the module docs"]
+fn round_trips_frames() {
+    // v4l2loopback supports mmap, the cheapest type
+    round_trip(MemoryType::Auto, &[MemoryType::Mmap]);
+}
+
+#[test]
+#[ignore = "needs a v4l2loopback device, see the module docs"]
+fn round_trips_frames_with_mmap() {
+    round_trip(MemoryType::Mmap, &[MemoryType::Mmap]);
+}
+
+#[test]
+#[ignore = "needs a v4l2loopback device, see the module docs"]
+fn round_trips_frames_with_userptr() {
+    // versions of v4l2loopback that reject userptr buffers fall back to mmap
+    round_trip(
+        MemoryType::UserPtr,
+        &[MemoryType::UserPtr, MemoryType::Mmap],
+    );
+}
+
+#[test]
+#[ignore = "needs a v4l2loopback device, see the module docs"]
+fn dmabuf_buffers_are_rejected() {
+    let mut app = app();
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    let input = Input::builder()
+        .device(loopback())
+        .memory(MemoryType::DmaBuf)
+        .build(&mut images);
+    assert!(matches!(
+        input,
+        Err(Error::UnsupportedMemory(MemoryType::DmaBuf))
+    ));
+}
+
+/// Resident memory of the process in bytes, assuming 4 KiB pages
+fn resident() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    pages * 4096
+}
+
+#[test]
+#[ignore = "needs a v4l2loopback device, see the module docs"]
+fn reopening_inputs_keeps_memory_flat() {
+    const CYCLES: usize = 300;
+    const WARMUP: usize = 20;
+
+    let id = loopback();
+    let mut app = app();
+    let image = solid_image(&mut app, WIDTH, HEIGHT, COLOR);
+    let format = Format::new(WIDTH, HEIGHT, b"YUYV").unwrap();
+    let output = Output::new(id, image, format).unwrap();
+    app.world.spawn(output);
+
+    let mut baseline = (0, 0);
+    for cycle in 0..CYCLES {
+        if cycle == WARMUP {
+            baseline = (resident(), app.world.resource::<Assets<Image>>().len());
+        }
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        let input = Input::new(id, &mut images).unwrap();
+        // held like a sprite would, the image is removed anyway
+        let captured = input.image().clone();
+        let input = app.world.spawn(input).id();
+        update_until(&mut app, "a captured frame", |app| {
+            let images = app.world.resource::<Assets<Image>>();
+            images
+                .get(&captured)
+                .is_some_and(|image| !close_to(image, [255; 4], 0))
+        });
+        app.world.despawn(input);
+        app.update();
+    }
+
+    let images = app.world.resource::<Assets<Image>>().len();
+    assert_eq!(images, baseline.1, "images of dropped inputs were left");
+    let grown = resident().saturating_sub(baseline.0);
+    assert!(
+        grown < 8 << 20,
+        "{grown} bytes more resident after {CYCLES} cycles"
+    );
+}
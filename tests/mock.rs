@@ -0,0 +1,374 @@
+//! The plugin driving mock inputs and outputs, which runs without devices or kernel
+//! modules
+
+mod common;
+
+use std::time::Duration;
+
+use bevy::ecs::event::ManualEventReader;
+use bevy::prelude::*;
+use bevy_v4l::{
+    CaptureGroup, DeviceLost, Format, FrameReceived, Input, MockStep, Output, PostProcess,
+    StreamRestarted, V4lError,
+};
+use common::{app, close_to, solid_image, update_until};
+
+const WIDTH: u32 = 4;
+const HEIGHT: u32 = 2;
+
+/// Converted from luma 81 of limited range YUV without chroma
+const GRAY: [u8; 4] = [76, 76, 76, 255];
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// A YUYV frame of a single luma, without chroma
+fn yuyv(luma: u8) -> Vec<u8> {
+    [luma, 128].repeat((WIDTH * HEIGHT) as usize)
+}
+
+/// Whether every sample of a YUYV frame is within 2 of the ones of [`yuyv`]
+fn is_yuyv(frame: &[u8], luma: u8) -> bool {
+    frame.len() == (WIDTH * HEIGHT * 2) as usize
+        && frame
+            .chunks_exact(2)
+            .all(|sample| sample[0].abs_diff(luma) <= 2 && sample[1].abs_diff(128) <= 2)
+}
+
+fn spawn_input(app: &mut App, steps: Vec<MockStep>) -> (Entity, Handle<Image>) {
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    let input = Input::mock(WIDTH, HEIGHT, *b"YUYV", 1000.0, steps, &mut images).unwrap();
+    let image = input.image().clone();
+    (app.world.spawn(input).id(), image)
+}
+
+fn image_is(app: &App, image: &Handle<Image>, color: [u8; 4]) -> bool {
+    app.world
+        .resource::<Assets<Image>>()
+        .get(image)
+        .is_some_and(|image| close_to(image, color, 2))
+}
+
+fn errors(app: &App, entity: Entity) -> usize {
+    let events = app.world.resource::<Events<V4lError>>();
+    events
+        .get_reader()
+        .read(events)
+        .filter(|event| event.entity == entity)
+        .count()
+}
+
+#[test]
+fn converts_yuyv() {
+    let near = |dst: &[u8], value: u8| {
+        dst.chunks_exact(4)
+            .all(|pixel| pixel[..3].iter().all(|&sample| sample.abs_diff(value) <= 1))
+    };
+    let mut dst = vec![0; 8];
+    bevy_v4l::convert_frame(*b"YUYV", 2, &[16, 128, 16, 128], &mut dst).unwrap();
+    assert!(near(&dst, 0), "black converted to {dst:?}");
+    bevy_v4l::convert_frame(*b"YUYV", 2, &[235, 128, 235, 128], &mut dst).unwrap();
+    assert!(near(&dst, 255), "white converted to {dst:?}");
+}
+
+#[test]
+fn frames_reach_the_image() {
+    let mut app = app();
+    let (_, image) = spawn_input(&mut app, vec![MockStep::Frame(yuyv(16))]);
+    update_until(&mut app, "a black frame", |app| {
+        image_is(app, &image, BLACK)
+    });
+}
+
+#[test]
+fn short_and_corrupt_frames_are_skipped() {
+    let mut app = app();
+    let mut short = yuyv(126);
+    short.truncate(10);
+    let steps = vec![
+        MockStep::Frame(yuyv(16)),
+        MockStep::Frame(short),
+        MockStep::Corrupt(yuyv(126)),
+        MockStep::WouldBlock,
+        MockStep::Frame(yuyv(81)),
+    ];
+    let (entity, image) = spawn_input(&mut app, steps);
+
+    let mut reader = ManualEventReader::<FrameReceived>::default();
+    let mut corrupt = 0;
+    update_until(&mut app, "the frame after the corrupt ones", |app| {
+        let events = app.world.resource::<Events<FrameReceived>>();
+        corrupt += reader
+            .read(events)
+            .filter(|event| event.entity == entity)
+            .map(|event| event.corrupt)
+            .sum::<u32>();
+        assert!(
+            !image_is(app, &image, [128, 128, 128, 255]),
+            "a corrupt frame was converted"
+        );
+        image_is(app, &image, GRAY)
+    });
+    assert_eq!(corrupt, 2);
+}
+
+#[test]
+fn transient_errors_restart_the_stream() {
+    let mut app = app();
+    let steps = vec![
+        MockStep::Frame(yuyv(16)),
+        // EIO
+        MockStep::Error(5),
+        MockStep::Frame(yuyv(81)),
+    ];
+    let (entity, image) = spawn_input(&mut app, steps);
+
+    let mut reader = ManualEventReader::<StreamRestarted>::default();
+    let mut restarted = false;
+    update_until(&mut app, "the frame after the restart", |app| {
+        let events = app.world.resource::<Events<StreamRestarted>>();
+        restarted |= reader.read(events).any(|event| event.entity == entity);
+        image_is(app, &image, GRAY)
+    });
+    assert!(restarted, "the stream wasn't restarted");
+    assert_eq!(errors(&app, entity), 0);
+}
+
+#[test]
+fn stats_count_frames_and_errors() {
+    let mut app = app();
+    let mut short = yuyv(126);
+    short.truncate(10);
+    let steps = vec![
+        MockStep::Frame(yuyv(16)),
+        MockStep::Frame(short),
+        MockStep::Corrupt(yuyv(126)),
+        // EIO
+        MockStep::Error(5),
+        MockStep::Skip(3),
+        MockStep::Frame(yuyv(81)),
+    ];
+    let (entity, image) = spawn_input(&mut app, steps);
+    let mut reader = ManualEventReader::<FrameReceived>::default();
+    let mut lost = 0;
+    update_until(&mut app, "the last frame", |app| {
+        let events = app.world.resource::<Events<FrameReceived>>();
+        lost += reader.read(events).map(|event| event.lost).sum::<u32>();
+        image_is(app, &image, GRAY)
+    });
+
+    let stats = app.world.get::<Input>(entity).unwrap().stats();
+    assert_eq!(stats.dequeued, 4);
+    assert_eq!(stats.presented, 2);
+    assert_eq!(stats.dropped.short, 2);
+    assert_eq!(stats.dropped.total(), 2);
+    // the driver's drops aren't the plugin's
+    assert_eq!(stats.kernel_dropped, 3);
+    assert_eq!(lost, 3);
+    assert_eq!(stats.consecutive_errors, 0);
+    assert!(stats.last_error.is_some());
+    assert!(stats.uptime.is_some());
+}
+
+#[test]
+fn mirrored_images_reverse_the_rows() {
+    let mut app = app();
+    // black left half, white right half
+    let frame = [16, 128, 16, 128, 235, 128, 235, 128].repeat(HEIGHT as usize);
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    let steps = vec![MockStep::Frame(frame)];
+    let mut input = Input::mock(WIDTH, HEIGHT, *b"YUYV", 1000.0, steps, &mut images).unwrap();
+    let mirrored = input.mirrored_image(&mut images).unwrap();
+    let image = input.image().clone();
+    app.world.spawn(input);
+
+    let red = |app: &App, image: &Handle<Image>, x: u32| {
+        let images = app.world.resource::<Assets<Image>>();
+        images.get(image).map(|image| image.data[x as usize * 4])
+    };
+    // both images start white, and the frame is swapped into them in the same update
+    update_until(&mut app, "a black pixel left in the image", |app| {
+        red(app, &image, 0).is_some_and(|red| red < 5)
+    });
+    assert!(red(&app, &mirrored, 0).is_some_and(|red| red > 250));
+    assert!(red(&app, &mirrored, WIDTH - 1).is_some_and(|red| red < 5));
+}
+
+/// What [`PostProcess`] describes, worked out in f64 for every value
+fn post_processed(post_process: &PostProcess, value: u8) -> u8 {
+    let value = value as f64 / 255.0;
+    let contrasted = (value - 0.5) * post_process.contrast as f64 + 0.5;
+    let value = (contrasted + post_process.brightness as f64).clamp(0.0, 1.0);
+    (value.powf(1.0 / post_process.gamma as f64) * 255.0).round() as u8
+}
+
+#[test]
+fn post_process_matches_the_float_math() {
+    let adjusted = PostProcess {
+        brightness: 0.1,
+        contrast: 1.5,
+        gamma: 0.8,
+        saturation: 1.0,
+    };
+    let cases = [
+        PostProcess::default(),
+        adjusted,
+        PostProcess {
+            brightness: -0.2,
+            contrast: 0.7,
+            gamma: 2.2,
+            saturation: 1.0,
+        },
+    ];
+    for post_process in cases {
+        let table = post_process.table();
+        for value in 0..=255 {
+            let expected = post_processed(&post_process, value);
+            let mapped = table[value as usize];
+            assert!(
+                mapped.abs_diff(expected) <= 1,
+                "{post_process:?} maps {value} to {mapped}, not {expected}"
+            );
+        }
+    }
+    assert!(PostProcess::default().is_identity());
+    assert_eq!(
+        PostProcess::default().table(),
+        std::array::from_fn(|value| value as u8)
+    );
+
+    // mapped while the frames are converted
+    let mut app = app();
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    let steps = vec![MockStep::Frame(yuyv(81))];
+    let input = Input::mock(WIDTH, HEIGHT, *b"YUYV", 1000.0, steps, &mut images).unwrap();
+    let image = input.image().clone();
+    app.world.spawn((input, adjusted));
+    let gray = post_processed(&adjusted, GRAY[0]);
+    update_until(&mut app, "an adjusted frame", |app| {
+        image_is(app, &image, [gray, gray, gray, 255])
+    });
+}
+
+#[test]
+fn capture_groups_pair_the_frames_of_their_members() {
+    let mut app = app();
+    let frames = || vec![MockStep::Frame(yuyv(81)); 500];
+    let (left, _) = spawn_input(&mut app, frames());
+    let (right, _) = spawn_input(&mut app, frames());
+    let tolerance = Duration::from_millis(20);
+    let group = app
+        .world
+        .spawn(CaptureGroup::new([left, right], tolerance))
+        .id();
+    update_until(&mut app, "synced frames", |app| {
+        app.world.get::<CaptureGroup>(group).unwrap().synced() > 0
+    });
+
+    let group = app.world.get::<CaptureGroup>(group).unwrap();
+    assert!(group.is_started());
+    let synced = group.latest_synced_frames().unwrap();
+    let members: Vec<_> = synced.frames.iter().map(|frame| frame.entity).collect();
+    assert_eq!(members, [left, right]);
+    assert!(synced.skew <= tolerance);
+}
+
+#[test]
+fn lost_devices_are_reported() {
+    let mut app = app();
+    // ENODEV
+    let (entity, _) = spawn_input(&mut app, vec![MockStep::Error(19)]);
+    update_until(&mut app, "the lost device", |app| {
+        let events = app.world.resource::<Events<DeviceLost>>();
+        events
+            .get_reader()
+            .read(events)
+            .any(|event| event.entity == entity)
+    });
+    // lost devices aren't reported twice
+    assert_eq!(errors(&app, entity), 0);
+}
+
+#[test]
+fn outputs_encode_the_image() {
+    let mut app = app();
+    let image = solid_image(&mut app, WIDTH, HEIGHT, [255; 4]);
+    let format = Format::new(WIDTH, HEIGHT, b"YUYV").unwrap();
+    let (output, frames) = Output::mock(image, format).unwrap();
+    app.world.spawn(output);
+
+    // the initial frame is written while the output is built
+    let (initial, _) = frames.drain().remove(0);
+    assert!(is_yuyv(&initial, 16), "black encoded as {initial:?}");
+
+    update_until(&mut app, "a written frame", |_| !frames.is_empty());
+    let (frame, _) = frames.drain().remove(0);
+    assert!(is_yuyv(&frame, 235), "white encoded as {frame:?}");
+}
+
+#[test]
+fn failed_writes_are_reported() {
+    let mut app = app();
+    let image = solid_image(&mut app, WIDTH, HEIGHT, [255; 4]);
+    let format = Format::new(WIDTH, HEIGHT, b"YUYV").unwrap();
+    let (output, frames) = Output::builder(0)
+        .format(format)
+        .initial_frame(None)
+        .build_mock(image)
+        .unwrap();
+    // ENODEV
+    frames.fail_next(19);
+    let entity = app.world.spawn(output).id();
+
+    update_until(&mut app, "an error", |app| errors(app, entity) > 0);
+    assert!(frames.is_empty());
+}
+
+#[test]
+fn every_device_advances_while_another_stalls() {
+    let mut app = app();
+    let frames = || vec![MockStep::Frame(yuyv(81)); 1000];
+    let (first, first_image) = spawn_input(&mut app, frames());
+    let (second, _) = spawn_input(&mut app, frames());
+    let image = solid_image(&mut app, WIDTH, HEIGHT, [255; 4]);
+    let format = Format::new(WIDTH, HEIGHT, b"YUYV").unwrap();
+    let (output, written) = Output::builder(0)
+        .format(format)
+        .initial_frame(None)
+        .pace_to_device(false)
+        .build_mock(image.clone())
+        .unwrap();
+    app.world.spawn(output);
+
+    let mut reader = ManualEventReader::<FrameReceived>::default();
+    let mut received = |app: &mut App| {
+        let events = app.world.resource::<Events<FrameReceived>>();
+        let events: Vec<_> = reader.read(events).map(|event| event.entity).collect();
+        let count = |entity| {
+            events
+                .iter()
+                .filter(|&&received| received == entity)
+                .count()
+        };
+        (count(first), count(second))
+    };
+    let (mut both, mut outputs) = ((0, 0), 0);
+    update_until(&mut app, "frames of both inputs and the output", |app| {
+        let (first, second) = received(app);
+        both = (both.0 + first, both.1 + second);
+        // changes of the image are written as they happen
+        app.world.resource_mut::<Assets<Image>>().get_mut(&image);
+        outputs += written.drain().len();
+        both.0 >= 10 && both.1 >= 10 && outputs >= 10
+    });
+
+    // the first input stalls without its image, the others keep going
+    app.world
+        .resource_mut::<Assets<Image>>()
+        .remove(&first_image);
+    let (mut second_frames, mut outputs) = (0, 0);
+    update_until(&mut app, "frames next to a stalled input", |app| {
+        second_frames += received(app).1;
+        app.world.resource_mut::<Assets<Image>>().get_mut(&image);
+        outputs += written.drain().len();
+        second_frames >= 10 && outputs >= 10
+    });
+}